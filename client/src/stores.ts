@@ -7,6 +7,7 @@ export let clientConfig: Writable<any> = writable(null);
 
 export let mediaFileId: Writable<string|null> = writable(null);
 export let curVideo: Writable<Proto3.MediaFile|null> = writable(null);
+export let curVersions: Writable<Proto3.MediaFileVersion[]> = writable([]);
 
 export let videoIsReady: Writable<boolean> = writable(false);
 
@@ -0,0 +1,3 @@
+-- Adaptive bitrate (HLS, optionally also DASH) ladder, stored under <media_file_id>/adaptive/
+ALTER TABLE media_files ADD COLUMN adaptive_done DATETIME DEFAULT NULL;
+ALTER TABLE media_files ADD COLUMN adaptive_has_dash BOOLEAN DEFAULT NULL;
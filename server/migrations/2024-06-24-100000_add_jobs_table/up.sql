@@ -0,0 +1,19 @@
+-- Persistent queue for media processing jobs (transcoding, thumbnails, adaptive ladders).
+-- Survives server restarts, so interrupted jobs can be resumed and failed ones retried.
+CREATE TABLE IF NOT EXISTS "jobs" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    media_file_id VARCHAR(255) NOT NULL REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id VARCHAR(255) NOT NULL,
+    job_type VARCHAR(32) NOT NULL,      -- 'transcode', 'thumbs' or 'ladder'
+    state VARCHAR(16) NOT NULL DEFAULT 'queued',    -- 'queued', 'running', 'done', 'failed' or 'cancelled'
+    payload TEXT NOT NULL,              -- Job arguments, as JSON
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT,                         -- Error from the last failed attempt, if any
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    started DATETIME,                   -- Start of the latest attempt
+    finished DATETIME,
+    next_attempt DATETIME               -- Retry backoff: don't start again before this
+);
+
+CREATE INDEX ix_jobs_state ON jobs (state);
+CREATE INDEX ix_jobs_media_file_id ON jobs (media_file_id);
@@ -0,0 +1,3 @@
+-- SHA-256 of the full original file, for detecting identical uploads (NULL for media added before this)
+ALTER TABLE media_files ADD COLUMN content_hash TEXT DEFAULT NULL;
+CREATE INDEX ix_media_files_content_hash ON media_files (content_hash);
@@ -0,0 +1,13 @@
+-- Time-limited public links to a single media file, for external reviewers.
+-- The access token is signed by the server; this table is for listing, revocation and expiry.
+CREATE TABLE IF NOT EXISTS "share_links" (
+    id VARCHAR(64) NOT NULL PRIMARY KEY,
+    media_file_id VARCHAR(255) NOT NULL REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    created_by VARCHAR(255) NOT NULL,
+    permission VARCHAR(16) NOT NULL,    -- 'view' or 'comment'
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    expires DATETIME NOT NULL,
+    revoked DATETIME                    -- Set when revoked before expiry
+);
+
+CREATE INDEX ix_share_links_media_file_id ON share_links (media_file_id);
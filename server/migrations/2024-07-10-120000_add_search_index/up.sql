@@ -0,0 +1,75 @@
+-- Full-text search over media file titles and original filenames, comment text and subtitle cues.
+-- Media files and comments are kept in sync by the triggers below. Subtitle cues live in .vtt files,
+-- so the server indexes them itself (when a subtitle is added, and on startup for any that are missing).
+CREATE VIRTUAL TABLE search_index USING fts5(
+    kind UNINDEXED,             -- 'media_file', 'comment' or 'subtitle'
+    ref_id UNINDEXED,           -- ID of the media file / comment / subtitle
+    media_file_id UNINDEXED,
+    start_ms UNINDEXED,         -- Start time of a subtitle cue, NULL for others
+    body,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
+
+
+-- Media files
+
+CREATE TRIGGER tr_search_media_files_insert AFTER INSERT ON media_files
+FOR EACH ROW BEGIN
+    INSERT INTO search_index (kind, ref_id, media_file_id, body)
+        VALUES ('media_file', NEW.id, NEW.id, trim(coalesce(NEW.title, '') || ' ' || coalesce(NEW.orig_filename, '')));
+END;
+
+CREATE TRIGGER tr_search_media_files_update AFTER UPDATE OF id, title, orig_filename ON media_files
+FOR EACH ROW BEGIN
+    DELETE FROM search_index WHERE kind = 'media_file' AND ref_id = OLD.id;
+    INSERT INTO search_index (kind, ref_id, media_file_id, body)
+        VALUES ('media_file', NEW.id, NEW.id, trim(coalesce(NEW.title, '') || ' ' || coalesce(NEW.orig_filename, '')));
+END;
+
+CREATE TRIGGER tr_search_media_files_delete AFTER DELETE ON media_files
+FOR EACH ROW BEGIN
+    DELETE FROM search_index WHERE media_file_id = OLD.id;
+END;
+
+
+-- Comments
+
+CREATE TRIGGER tr_search_comments_insert AFTER INSERT ON comments
+FOR EACH ROW BEGIN
+    INSERT INTO search_index (kind, ref_id, media_file_id, body)
+        VALUES ('comment', NEW.id, NEW.media_file_id, NEW.comment);
+END;
+
+CREATE TRIGGER tr_search_comments_update AFTER UPDATE OF id, media_file_id, comment ON comments
+FOR EACH ROW BEGIN
+    DELETE FROM search_index WHERE kind = 'comment' AND ref_id = OLD.id;
+    INSERT INTO search_index (kind, ref_id, media_file_id, body)
+        VALUES ('comment', NEW.id, NEW.media_file_id, NEW.comment);
+END;
+
+CREATE TRIGGER tr_search_comments_delete AFTER DELETE ON comments
+FOR EACH ROW BEGIN
+    DELETE FROM search_index WHERE kind = 'comment' AND ref_id = OLD.id;
+END;
+
+
+-- Subtitles (cues are inserted by the server)
+
+CREATE TRIGGER tr_search_subtitles_update AFTER UPDATE OF media_file_id ON subtitles
+FOR EACH ROW BEGIN
+    UPDATE search_index SET media_file_id = NEW.media_file_id WHERE kind = 'subtitle' AND ref_id = OLD.id;
+END;
+
+CREATE TRIGGER tr_search_subtitles_delete AFTER DELETE ON subtitles
+FOR EACH ROW BEGIN
+    DELETE FROM search_index WHERE kind = 'subtitle' AND ref_id = OLD.id;
+END;
+
+
+-- Index existing data
+
+INSERT INTO search_index (kind, ref_id, media_file_id, body)
+    SELECT 'media_file', id, id, trim(coalesce(title, '') || ' ' || coalesce(orig_filename, '')) FROM media_files;
+
+INSERT INTO search_index (kind, ref_id, media_file_id, body)
+    SELECT 'comment', id, media_file_id, comment FROM comments;
@@ -0,0 +1,7 @@
+-- Embedded start timecode of the source (e.g. "01:00:00:00", or "00:59:59;28" for drop-frame), if any
+ALTER TABLE media_files ADD COLUMN start_timecode TEXT DEFAULT NULL;
+
+-- Comment position as a frame number from the start of the media file, derived from `timecode`.
+-- NULL if the comment has no (valid) timecode. Filled in for existing comments on server start.
+ALTER TABLE comments ADD COLUMN frame INTEGER DEFAULT NULL;
+CREATE INDEX ix_comments_media_file_frame ON comments (media_file_id, frame);
@@ -0,0 +1,11 @@
+-- Version stacks: successive versions (cuts) of the same work, each its own media file.
+-- All versions in a stack share `stack_id`, which is the ID of the first version's media file
+-- (kept as-is even if that media file is later deleted). Media files that were never stacked have no row here.
+CREATE TABLE IF NOT EXISTS "media_file_versions" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    media_file_id VARCHAR(255) NOT NULL UNIQUE REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    stack_id VARCHAR(255) NOT NULL,
+    version INTEGER NOT NULL,           -- 1, 2, 3... in the order versions were added
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    UNIQUE (stack_id, version)
+);
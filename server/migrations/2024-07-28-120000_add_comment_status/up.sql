@@ -0,0 +1,8 @@
+-- Review workflow for comment threads: resolution status, assignee, and who resolved it and when.
+-- Set on top-level comments (thread starters) only; replies stay 'open'.
+ALTER TABLE comments ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'open';    -- 'open', 'resolved' or 'wontfix'
+ALTER TABLE comments ADD COLUMN assignee_id VARCHAR(255) DEFAULT NULL;        -- User ID
+ALTER TABLE comments ADD COLUMN resolved_by VARCHAR(255) DEFAULT NULL;        -- User ID, set when status changes from 'open'
+ALTER TABLE comments ADD COLUMN resolved_at DATETIME DEFAULT NULL;
+CREATE INDEX ix_comments_status ON comments (status);
+CREATE INDEX ix_comments_assignee_id ON comments (assignee_id);
@@ -0,0 +1,12 @@
+-- Per-reviewer sign-off on media files. One row per (media file, reviewer), overwritten when
+-- the reviewer changes their mind. `decision` is 'approved', 'changes_requested' or 'pending'.
+CREATE TABLE IF NOT EXISTS "approvals" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    media_file_id VARCHAR(255) NOT NULL REFERENCES media_files(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id VARCHAR(255) NOT NULL,
+    username VARCHAR(255) NOT NULL,     -- Denormalized display name, at the time of the decision
+    decision VARCHAR(32) NOT NULL,
+    note TEXT,
+    updated DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    UNIQUE (media_file_id, user_id)
+);
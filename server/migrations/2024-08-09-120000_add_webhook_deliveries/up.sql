@@ -0,0 +1,17 @@
+-- Outgoing webhook deliveries: a persistent retry queue, and a log of what was sent where.
+-- Payload is built when the event happens, so deliveries don't depend on the objects still existing.
+CREATE TABLE IF NOT EXISTS "webhook_deliveries" (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    endpoint_url TEXT NOT NULL,
+    event_name VARCHAR(64) NOT NULL,    -- e.g. 'comment_added'
+    payload TEXT NOT NULL,              -- JSON body, as sent
+    state VARCHAR(16) NOT NULL DEFAULT 'queued',    -- 'queued', 'done' or 'failed'
+    attempts INTEGER NOT NULL DEFAULT 0,
+    response_status INTEGER,            -- HTTP status of the last attempt, if it got a response
+    error TEXT,                         -- Error from the last failed attempt, if any
+    created DATETIME DEFAULT (CURRENT_TIMESTAMP) NOT NULL,
+    last_attempt DATETIME,
+    next_attempt DATETIME               -- Retry backoff: don't send again before this
+);
+
+CREATE INDEX ix_webhook_deliveries_state ON webhook_deliveries (state);
@@ -0,0 +1,6 @@
+-- E-mail notifications: users' addresses and how often to mail them unseen messages.
+-- `email_last_msg_id` is a high-water mark of messages already handled, so each one is mailed at most once.
+ALTER TABLE users ADD COLUMN email VARCHAR(255) DEFAULT NULL;
+ALTER TABLE users ADD COLUMN email_frequency VARCHAR(16) NOT NULL DEFAULT 'off';  -- 'off', 'immediate', 'hourly' or 'daily'
+ALTER TABLE users ADD COLUMN email_last_msg_id INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE users ADD COLUMN email_last_sent DATETIME DEFAULT NULL;
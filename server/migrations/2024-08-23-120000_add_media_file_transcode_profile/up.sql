@@ -0,0 +1,3 @@
+-- Name of the transcoding profile picked at upload time, so re-runs use the same one.
+-- NULL (older media files) = server's default profile.
+ALTER TABLE media_files ADD COLUMN transcode_profile VARCHAR DEFAULT NULL;
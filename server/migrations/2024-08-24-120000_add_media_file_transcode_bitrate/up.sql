@@ -0,0 +1,3 @@
+-- Video bitrate (bps) of the finished transcode, so deduplication only shares transcodes
+-- made with the same profile and bitrate. NULL = not transcoded, or transcoded by an older version.
+ALTER TABLE media_files ADD COLUMN transcode_bitrate INTEGER DEFAULT NULL;
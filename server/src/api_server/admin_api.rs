@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::Reply;
+
+use crate::database::{models, DBPaging, DbBasicQuery};
+use super::auth::AuthUser;
+use super::server_state::ServerState;
+
+
+/// Parse `page` and `page_size` query parameters, or make a 400 reply
+fn parse_paging(query: &HashMap<String, String>) -> Result<DBPaging, Box<dyn Reply>> {
+    let mut pg = DBPaging::default();
+    if let Some(n) = query.get("page_size") {
+        match n.parse::<std::num::NonZeroU32>() {
+            Ok(n) => pg.page_size = n,
+            Err(_) => return Err(Box::new(warp::reply::with_status("Bad page_size".to_string(), StatusCode::BAD_REQUEST))),
+        }
+    }
+    if let Some(n) = query.get("page") {
+        match n.parse::<u32>() {
+            Ok(n) => pg.page_num = n,
+            Err(_) => return Err(Box::new(warp::reply::with_status("Bad page".to_string(), StatusCode::BAD_REQUEST))),
+        }
+    }
+    Ok(pg)
+}
+
+/// Warp handler for `GET /api/admin/jobs`: list media processing jobs, newest first.
+/// Only for admins.
+///
+/// # Arguments
+/// * `user` - Authenticated user
+/// * `query` - Optional query parameters: `state` (queued, running, done, failed, cancelled), `page` and `page_size`
+/// * `server` - Server state (for DB access)
+pub async fn handle_list_jobs(
+    user: AuthUser,
+    query: HashMap<String, String>,
+    server: ServerState)
+        -> Result<Box<dyn Reply>, Infallible>
+{
+    if !user.is_admin {
+        tracing::warn!(user=%user.user_id, "Non-admin user tried to list processing jobs.");
+        return Ok(Box::new(warp::reply::with_status("Permission denied".to_string(), StatusCode::FORBIDDEN)));
+    }
+
+    let pg = match parse_paging(&query) {
+        Ok(pg) => pg,
+        Err(e) => return Ok(e),
+    };
+
+    let jobs = server.db.conn().and_then(|mut conn| match query.get("state") {
+        Some(st) => models::Job::get_by_state(&mut conn, st, pg),
+        None => models::Job::get_all(&mut conn, pg),
+    });
+    match jobs {
+        Ok(jobs) => Ok(Box::new(warp::reply::json(&jobs))),
+        Err(e) => {
+            tracing::error!(details=%e, "Failed to get jobs from DB.");
+            Ok(Box::new(warp::reply::with_status("Internal error: failed to get jobs".to_string(), StatusCode::INTERNAL_SERVER_ERROR)))
+        }
+    }
+}
+
+/// Warp handler for `GET /api/admin/webhooks`: list webhook deliveries (the delivery log), newest first.
+/// Only for admins.
+///
+/// # Arguments
+/// * `user` - Authenticated user
+/// * `query` - Optional query parameters: `state` (queued, done, failed), `page` and `page_size`
+/// * `server` - Server state (for DB access)
+pub async fn handle_list_webhook_deliveries(
+    user: AuthUser,
+    query: HashMap<String, String>,
+    server: ServerState)
+        -> Result<Box<dyn Reply>, Infallible>
+{
+    if !user.is_admin {
+        tracing::warn!(user=%user.user_id, "Non-admin user tried to list webhook deliveries.");
+        return Ok(Box::new(warp::reply::with_status("Permission denied".to_string(), StatusCode::FORBIDDEN)));
+    }
+    let pg = match parse_paging(&query) {
+        Ok(pg) => pg,
+        Err(e) => return Ok(e),
+    };
+
+    let deliveries = server.db.conn().and_then(|mut conn| match query.get("state") {
+        Some(st) => models::WebhookDelivery::get_by_state(&mut conn, st, pg),
+        None => models::WebhookDelivery::get_all(&mut conn, pg),
+    });
+    match deliveries {
+        Ok(d) => Ok(Box::new(warp::reply::json(&d))),
+        Err(e) => {
+            tracing::error!(details=%e, "Failed to get webhook deliveries from DB.");
+            Ok(Box::new(warp::reply::with_status("Internal error: failed to get webhook deliveries".to_string(), StatusCode::INTERNAL_SERVER_ERROR)))
+        }
+    }
+}
@@ -0,0 +1,516 @@
+// Export a media file's comment threads for editing and review tools:
+// CMX3600 EDL (one event with locators), DaVinci Resolve marker EDL, Avid Media Composer
+// marker list, Final Cut Pro XML markers, CSV and JSON.
+// Comment positions are frame numbers (`comments.frame`, or parsed from the client's timecode string),
+// clamped to `total_frames`. They are written out as source timecodes (see `timecode::FrameRate::nle_timecode()`):
+// offset by the file's start timecode if it has one, and non-drop-frame at the nominal (rounded) rate unless
+// the start timecode is drop-frame. That's what NLEs use to place markers on a given frame.
+// Replies are attached to their thread: as marker text in timeline formats, as rows / nested objects in CSV / JSON.
+// Timeline formats skip comments without a timecode.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use warp::http::{HeaderMap, StatusCode};
+use warp::Reply;
+use lib_clapshot_grpc::proto::org::CommentExportFormat;
+
+use crate::database::{error::DBResult, models, DbBasicQuery, DbQueryByMediaFile, DBPaging, DB};
+use crate::timecode::FrameRate;
+use super::server_state::ServerState;
+
+const MARKER_COLOR_EDL: &str = "RED";
+const MARKER_COLOR_RESOLVE: &str = "ResolveColorRed";
+const MARKER_COLOR_AVID: &str = "red";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Edl,
+    ResolveEdl,
+    AvidMarkers,
+    FcpXml,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "edl" | "cmx3600" => Ok(ExportFormat::Edl),
+            "resolve" => Ok(ExportFormat::ResolveEdl),
+            "avid" => Ok(ExportFormat::AvidMarkers),
+            "fcpxml" | "fcp" => Ok(ExportFormat::FcpXml),
+            _ => anyhow::bail!("Unknown export format '{}'. Use csv, json, edl, resolve, avid or fcpxml.", s),
+        }
+    }
+}
+
+impl From<CommentExportFormat> for ExportFormat {
+    fn from(f: CommentExportFormat) -> Self {
+        match f {
+            CommentExportFormat::Csv => ExportFormat::Csv,
+            CommentExportFormat::Json => ExportFormat::Json,
+            CommentExportFormat::Cmx3600Edl => ExportFormat::Edl,
+            CommentExportFormat::ResolveEdl => ExportFormat::ResolveEdl,
+            CommentExportFormat::AvidMarkers => ExportFormat::AvidMarkers,
+            CommentExportFormat::Fcpxml => ExportFormat::FcpXml,
+        }
+    }
+}
+
+impl ExportFormat {
+    fn mime_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Edl | ExportFormat::ResolveEdl => "text/plain; charset=utf-8",
+            ExportFormat::AvidMarkers => "text/plain; charset=utf-8",
+            ExportFormat::FcpXml => "application/xml",
+        }
+    }
+
+    fn file_suffix(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => ".csv",
+            ExportFormat::Json => ".json",
+            ExportFormat::Edl => ".edl",
+            ExportFormat::ResolveEdl => ".resolve.edl",
+            ExportFormat::AvidMarkers => ".avid-markers.txt",
+            ExportFormat::FcpXml => ".fcpxml",
+        }
+    }
+}
+
+/// Rendered export, ready for download
+#[derive(Debug)]
+pub struct CommentExport {
+    pub content: String,
+    pub mime_type: String,
+    pub filename: String,
+}
+
+/// Top-level comment with its frame number and all replies, oldest first
+pub(crate) struct Thread<'a> {
+    pub comment: &'a models::Comment,
+    pub frame: Option<u64>,
+    pub replies: Vec<&'a models::Comment>,
+}
+
+/// Group comments into threads, sorted by frame (comments without timecode last), then by creation time.
+/// Replies to replies are flattened into the top-level thread.
+pub(crate) fn build_threads<'a>(comments: &'a [models::Comment], rate: &FrameRate) -> Vec<Thread<'a>> {
+    let by_id: HashMap<i32, &models::Comment> = comments.iter().map(|c| (c.id, c)).collect();
+    let root_of = |c: &'a models::Comment| -> &'a models::Comment {
+        let mut cur = c;
+        for _ in 0..comments.len() {    // Bounded, in case of a parent_id cycle
+            match cur.parent_id.and_then(|p| by_id.get(&p)) {
+                Some(parent) => cur = parent,
+                None => break,
+            }
+        }
+        cur
+    };
+
+    let mut threads: Vec<Thread> = comments.iter()
+        .filter(|c| root_of(c).id == c.id)
+        .map(|c| Thread { comment: c, frame: comment_frame(c, rate), replies: vec![] })
+        .collect();
+    let idx: HashMap<i32, usize> = threads.iter().enumerate().map(|(i, t)| (t.comment.id, i)).collect();
+    for c in comments {
+        let root = root_of(c);
+        if root.id != c.id {
+            if let Some(i) = idx.get(&root.id) {
+                threads[*i].replies.push(c);
+            }
+        }
+    }
+    for t in threads.iter_mut() {
+        t.replies.sort_by_key(|r| (r.created, r.id));
+    }
+    threads.sort_by_key(|t| (t.frame.is_none(), t.frame, t.comment.created, t.comment.id));
+    threads
+}
+
+/// Length of the media file in frames (at least one frame past the last comment)
+fn length(rate: &FrameRate, threads: &[Thread]) -> u64 {
+    let last = threads.iter().filter_map(|t| t.frame).max().map(|f| f + 1).unwrap_or(1);
+    rate.total_frames().unwrap_or(0).max(last)
+}
+
+/// Frame number of a comment: normalized `frame` if stored, otherwise parsed from the timecode
+pub(crate) fn comment_frame(c: &models::Comment, rate: &FrameRate) -> Option<u64> {
+    match c.frame {
+        Some(f) if f >= 0 => Some(rate.clamp(f as u64)),
+        _ => c.timecode.as_deref().and_then(|tc| rate.parse(tc)),
+    }
+}
+
+pub(crate) fn author(c: &models::Comment) -> &str {
+    if c.username_ifnull.is_empty() { c.user_id.as_deref().unwrap_or("Anonymous") } else { &c.username_ifnull }
+}
+
+/// Collapse whitespace, so text fits on one line
+fn one_line(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Thread as single-line marker text: "Author: comment | Replier: reply ..."
+fn marker_text(t: &Thread) -> String {
+    std::iter::once(t.comment).chain(t.replies.iter().copied())
+        .map(|c| format!("{}: {}", one_line(author(c)), one_line(&c.comment)))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+pub(crate) fn media_title(mf: &models::MediaFile) -> String {
+    mf.title.clone().or_else(|| mf.orig_filename.clone()).unwrap_or_else(|| mf.id.clone())
+}
+
+fn edl_line(num: usize, reel: &str, rate: &FrameRate, src_in: u64, src_out: u64) -> String {
+    format!("{:03}  {:<8} {:<5} {:<8} {} {} {} {}\n", num, reel, "V", "C",
+        rate.nle_timecode(src_in), rate.nle_timecode(src_out), rate.nle_timecode(src_in), rate.nle_timecode(src_out))
+}
+
+fn edl_header(mf: &models::MediaFile, rate: &FrameRate) -> String {
+    let fcm = if rate.is_drop_frame() { "DROP FRAME" } else { "NON-DROP FRAME" };
+    format!("TITLE: {}\nFCM: {}\n\n", one_line(&media_title(mf)), fcm)
+}
+
+/// CMX3600: one event covering the whole file, with a `* LOC:` locator per thread
+fn render_edl(mf: &models::MediaFile, rate: &FrameRate, threads: &[Thread]) -> String {
+    let mut out = edl_header(mf, rate);
+    out += &edl_line(1, "AX", rate, 0, length(rate, threads));
+    out += &format!("* FROM CLIP NAME: {}\n", one_line(mf.orig_filename.as_deref().unwrap_or(&mf.id)));
+    for t in threads {
+        if let Some(f) = t.frame {
+            out += &format!("* LOC: {} {:<7} {}\n", rate.nle_timecode(f), MARKER_COLOR_EDL, marker_text(t));
+        }
+    }
+    out
+}
+
+/// DaVinci Resolve's marker EDL (as written by "Export > Timeline Markers to EDL"): one single-frame event per marker
+fn render_resolve_edl(mf: &models::MediaFile, rate: &FrameRate, threads: &[Thread]) -> String {
+    let mut out = edl_header(mf, rate);
+    for (i, (t, f)) in threads.iter().filter_map(|t| t.frame.map(|f| (t, f))).enumerate() {
+        out += &edl_line(i + 1, &format!("{:03}", i + 1), rate, f, f + 1);
+        out += &format!(" |C:{} |M:{} |D:1\n\n", MARKER_COLOR_RESOLVE, marker_text(t).replace('|', "/"));
+    }
+    out
+}
+
+/// Avid Media Composer marker list: `Name<TAB>Timecode<TAB>Track<TAB>Color<TAB>Comment<TAB>Duration`
+fn render_avid_markers(rate: &FrameRate, threads: &[Thread]) -> String {
+    let tsv = |s: &str| one_line(s).replace('\t', " ");
+    threads.iter()
+        .filter_map(|t| t.frame.map(|f| format!("{}\t{}\tV1\t{}\t{}\t1\n", tsv(author(t.comment)), rate.nle_timecode(f), MARKER_COLOR_AVID, tsv(&marker_text(t)))))
+        .collect()
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+/// Final Cut Pro XML (1.9): a project with the media file as a single clip, with a marker per thread
+fn render_fcpxml(mf: &models::MediaFile, rate: &FrameRate, threads: &[Thread]) -> String {
+    let (num, den) = rate.frame_duration();
+    let time = |frames: u64| if frames == 0 { "0s".to_string() } else { format!("{}/{}s", frames * num, den) };
+    let title = xml_escape(&media_title(mf));
+    let dur = time(length(rate, threads));
+    let fname = xml_escape(mf.orig_filename.as_deref().unwrap_or(&mf.id));
+    let src_start = rate.start().map(|tc| tc.frame()).unwrap_or(0);   // Clip and markers are in source time
+    let tc_format = if rate.is_drop_frame() { "DF" } else { "NDF" };
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE fcpxml>\n<fcpxml version=\"1.9\">\n");
+    out += "  <resources>\n";
+    out += &format!("    <format id=\"r1\" name=\"FFVideoFormatRateUndefined\" frameDuration=\"{}/{}s\"/>\n", num, den);
+    out += &format!("    <asset id=\"r2\" name=\"{}\" start=\"{}\" duration=\"{}\" hasVideo=\"1\" format=\"r1\">\n", fname, time(src_start), dur);
+    out += &format!("      <media-rep kind=\"original-media\" src=\"{}\"/>\n", fname);
+    out += "    </asset>\n  </resources>\n";
+    out += &format!("  <library>\n    <event name=\"Clapshot review\">\n      <project name=\"{}\">\n", title);
+    out += &format!("        <sequence format=\"r1\" duration=\"{}\" tcStart=\"0s\" tcFormat=\"{}\">\n          <spine>\n", dur, tc_format);
+    out += &format!("            <asset-clip ref=\"r2\" offset=\"0s\" name=\"{}\" start=\"{}\" duration=\"{}\" tcFormat=\"{}\">\n", title, time(src_start), dur, tc_format);
+    for t in threads {
+        if let Some(f) = t.frame {
+            let note = t.replies.iter().map(|r| format!("{}: {}", one_line(author(r)), one_line(&r.comment))).collect::<Vec<_>>().join(" | ");
+            out += &format!("              <marker start=\"{}\" duration=\"{}\" value=\"{}\" note=\"{}\"/>\n",
+                time(src_start + f), time(1), xml_escape(&format!("{}: {}", one_line(author(t.comment)), one_line(&t.comment.comment))), xml_escape(&note));
+        }
+    }
+    out += "            </asset-clip>\n          </spine>\n        </sequence>\n      </project>\n    </event>\n  </library>\n</fcpxml>\n";
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// CSV: one row per comment, replies right after their thread's top-level comment
+fn render_csv(rate: &FrameRate, threads: &[Thread]) -> String {
+    let mut out = String::from("comment_id,parent_id,timecode,frame,seconds,author,user_id,created,edited,comment\r\n");
+    for t in threads {
+        for c in std::iter::once(t.comment).chain(t.replies.iter().copied()) {
+            let frame = if c.id == t.comment.id { t.frame } else { comment_frame(c, rate) };
+            let row = [
+                c.id.to_string(),
+                c.parent_id.map(|p| p.to_string()).unwrap_or_default(),
+                frame.map(|f| rate.nle_timecode(f)).unwrap_or_default(),
+                frame.map(|f| f.to_string()).unwrap_or_default(),
+                frame.map(|f| format!("{:.3}", rate.seconds(f))).unwrap_or_default(),
+                author(c).to_string(),
+                c.user_id.clone().unwrap_or_default(),
+                c.created.and_utc().to_rfc3339(),
+                c.edited.map(|e| e.and_utc().to_rfc3339()).unwrap_or_default(),
+                c.comment.clone(),
+            ];
+            out += &row.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(",");
+            out += "\r\n";
+        }
+    }
+    out
+}
+
+/// JSON: media file info and an array of threads with nested replies
+fn render_json(mf: &models::MediaFile, rate: &FrameRate, threads: &[Thread]) -> String {
+    let comment_json = |c: &models::Comment, frame: Option<u64>| serde_json::json!({
+        "id": c.id,
+        "parent_id": c.parent_id,
+        "timecode": frame.map(|f| rate.nle_timecode(f)),
+        "frame": frame,
+        "seconds": frame.map(|f| rate.seconds(f)),
+        "author": author(c),
+        "user_id": c.user_id,
+        "created": c.created.and_utc().to_rfc3339(),
+        "edited": c.edited.map(|e| e.and_utc().to_rfc3339()),
+        "comment": c.comment,
+        "drawing": c.drawing.is_some(),
+        "status": c.status,
+        "assignee_id": c.assignee_id,
+    });
+    let comments = threads.iter().map(|t| {
+        let mut obj = comment_json(t.comment, t.frame);
+        obj["replies"] = t.replies.iter().map(|r| comment_json(r, comment_frame(r, rate))).collect();
+        obj
+    }).collect::<Vec<_>>();
+    let doc = serde_json::json!({
+        "media_file": {
+            "id": mf.id,
+            "title": mf.title,
+            "orig_filename": mf.orig_filename,
+            "fps": rate.fps(),
+            "total_frames": rate.total_frames(),
+            "start_timecode": rate.start().map(|tc| tc.to_string()),
+        },
+        "comments": comments,
+    });
+    serde_json::to_string_pretty(&doc).expect("JSON serialization failed")
+}
+
+/// Render comments of a media file in the given format
+///
+/// # Arguments
+/// * `mf` - Media file (for frame rate, length and title)
+/// * `comments` - All comments of the media file
+/// * `format` - Output format
+pub fn render(mf: &models::MediaFile, comments: &[models::Comment], format: ExportFormat) -> String {
+    let rate = FrameRate::of(mf);
+    let threads = build_threads(comments, &rate);
+    match format {
+        ExportFormat::Csv => render_csv(&rate, &threads),
+        ExportFormat::Json => render_json(mf, &rate, &threads),
+        ExportFormat::Edl => render_edl(mf, &rate, &threads),
+        ExportFormat::ResolveEdl => render_resolve_edl(mf, &rate, &threads),
+        ExportFormat::AvidMarkers => render_avid_markers(&rate, &threads),
+        ExportFormat::FcpXml => render_fcpxml(mf, &rate, &threads),
+    }
+}
+
+/// Load a media file and its comments from DB, and render them for download
+///
+/// # Arguments
+/// * `db` - Database
+/// * `media_file_id` - Media file to export comments from
+/// * `format` - Output format
+///
+/// # Returns
+/// * `CommentExport` with content, MIME type and a suggested filename
+pub fn export_comments(db: &DB, media_file_id: &str, format: ExportFormat) -> DBResult<CommentExport> {
+    let conn = &mut db.conn()?;
+    let mf = models::MediaFile::get(conn, &media_file_id.to_string())?;
+    let comments = models::Comment::get_by_media_file(conn, media_file_id, DBPaging::default())?;
+    let base = media_title(&mf).chars()
+        .map(|c| if c.is_alphanumeric() || "-_. ".contains(c) { c } else { '_' })
+        .collect::<String>();
+    let base = base.trim().rsplit_once('.').map(|(b, _)| b.to_string()).filter(|b| !b.is_empty()).unwrap_or(base.trim().to_string());
+    Ok(CommentExport {
+        content: render(&mf, &comments, format),
+        mime_type: format.mime_type().to_string(),
+        filename: format!("{} comments{}", base, format.file_suffix()),
+    })
+}
+
+/// Warp handler for `GET /api/media/<media_file_id>/comments/export?format=<fmt>`.
+/// Requires the same access as viewing the media file.
+///
+/// # Arguments
+/// * `media_file_id` - Media file ID from the URL
+/// * `query` - Query parameters: `format` (csv, json, edl, resolve, avid, fcpxml; default csv)
+/// * `hdrs` - Request headers (for authentication)
+/// * `server` - Server state
+pub async fn handle_export_comments(
+    media_file_id: String,
+    query: HashMap<String, String>,
+    hdrs: HeaderMap,
+    server: ServerState)
+        -> Result<warp::reply::Response, warp::Rejection>
+{
+    let format = match query.get("format").map(|f| f.parse::<ExportFormat>()).unwrap_or(Ok(ExportFormat::Csv)) {
+        Ok(f) => f,
+        Err(e) => return Ok(warp::reply::with_status(e.to_string(), StatusCode::BAD_REQUEST).into_response()),
+    };
+    super::media_access::user_can_view(&server, &hdrs, &media_file_id).await?;
+
+    Ok(match export_comments(&server.db, &media_file_id, format) {
+        Ok(exp) => {
+            let disposition = format!("attachment; filename=\"{}\"; filename*=UTF-8''{}",
+                exp.filename.replace(|c: char| !c.is_ascii() || c == '"', "_"), urlencoding::encode(&exp.filename));
+            warp::reply::with_header(
+                warp::reply::with_header(exp.content, "content-type", exp.mime_type),
+                "content-disposition", disposition).into_response()
+        },
+        Err(e) => {
+            tracing::error!(details=%e, media_file=%media_file_id, "Failed to export comments.");
+            warp::reply::with_status("Internal error: failed to export comments".to_string(), StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    })
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::{make_test_comment, make_test_media_file};
+
+    fn mf(fps: &str, total_frames: i32) -> models::MediaFile {
+        models::MediaFile {
+            orig_filename: Some("cut3.mov".into()),
+            title: Some("Review \"cut\" 3".into()),
+            total_frames: Some(total_frames),
+            fps: Some(fps.into()),
+            ..make_test_media_file()
+        }
+    }
+
+    fn comment(id: i32, parent_id: Option<i32>, tc: Option<&str>, author: &str, text: &str) -> models::Comment {
+        models::Comment {
+            parent_id,
+            user_id: Some(format!("user.{}", author.to_lowercase())),
+            username_ifnull: author.into(),
+            comment: text.into(),
+            timecode: tc.map(|s| s.into()),
+            ..make_test_comment(id)
+        }
+    }
+
+    fn sample_comments() -> Vec<models::Comment> {
+        vec![
+            comment(1, None, Some("00:00:10:00"), "Alice", "Too dark,\nfix \"grade\""),
+            comment(2, Some(1), None, "Bob", "Agreed"),
+            comment(3, None, Some("00:00:02:05"), "Bob", "Jump cut"),
+            comment(4, Some(2), None, "Carol", "Done"),
+            comment(5, None, None, "Carol", "General note"),
+        ]
+    }
+
+    #[test]
+    fn test_threads() {
+        let comments = sample_comments();
+        let rate = FrameRate::of(&mf("25", 1000));
+        let threads = build_threads(&comments, &rate);
+        assert_eq!(threads.iter().map(|t| t.comment.id).collect::<Vec<_>>(), vec![3, 1, 5]);
+        assert_eq!(threads[1].frame, Some(250));
+        assert_eq!(threads[1].replies.iter().map(|c| c.id).collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(marker_text(&threads[1]), "Alice: Too dark, fix \"grade\" | Bob: Agreed | Carol: Done");
+
+        // Stored frame number takes precedence over the timecode string
+        let mut comments = sample_comments();
+        comments[2].frame = Some(300);
+        let threads = build_threads(&comments, &rate);
+        assert_eq!(threads.iter().map(|t| (t.comment.id, t.frame)).collect::<Vec<_>>(), vec![(1, Some(250)), (3, Some(300)), (5, None)]);
+    }
+
+    #[test]
+    fn test_render_start_timecode() {
+        let mut mf = mf("29.97", 1000);
+        mf.start_timecode = Some("00:59:59;28".into());
+        let comments = sample_comments();
+
+        let edl = render(&mf, &comments, ExportFormat::Edl);
+        assert!(edl.contains("FCM: DROP FRAME\n"));
+        assert!(edl.contains("* LOC: 01:00:09;28 RED     Alice:"));     // Frame 300 after start
+        let resolve = render(&mf, &comments, ExportFormat::ResolveEdl);
+        assert!(resolve.contains("001  001      V     C        01:00:02;03 01:00:02;04 "));
+
+        let xml = render(&mf, &comments, ExportFormat::FcpXml);
+        assert!(xml.contains("<asset-clip ref=\"r2\" offset=\"0s\" name=\"Review &quot;cut&quot; 3\" start=\"107997890/30000s\" duration=\"1001000/30000s\" tcFormat=\"DF\">"));
+
+        let json: serde_json::Value = serde_json::from_str(&render(&mf, &comments, ExportFormat::Json)).unwrap();
+        assert_eq!(json["media_file"]["start_timecode"], "00:59:59;28");
+        assert_eq!(json["comments"][0]["frame"], 65);
+        assert_eq!(json["comments"][0]["timecode"], "01:00:02;03");
+
+        // Import maps the source timecodes back
+        let rate = FrameRate::of(&mf);
+        let p = super::super::comment_import::parse(&edl, super::super::comment_import::ImportFormat::Edl, &rate).unwrap();
+        assert_eq!(p.comments.iter().filter_map(|c| c.frame).collect::<Vec<_>>(), vec![65, 300]);
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let mf = mf("25", 1000);
+        let comments = sample_comments();
+
+        let edl = render(&mf, &comments, ExportFormat::Edl);
+        assert!(edl.starts_with("TITLE: Review \"cut\" 3\nFCM: NON-DROP FRAME\n\n"));
+        assert!(edl.contains("001  AX       V     C        00:00:00:00 00:00:40:00 00:00:00:00 00:00:40:00\n"));
+        assert!(edl.contains("* LOC: 00:00:02:05 RED     Bob: Jump cut\n"));
+        assert!(edl.contains("* LOC: 00:00:10:00 RED     Alice: Too dark, fix \"grade\" | Bob: Agreed | Carol: Done\n"));
+        assert!(!edl.contains("General note"));
+
+        let resolve = render(&mf, &comments, ExportFormat::ResolveEdl);
+        assert!(resolve.contains("002  002      V     C        00:00:10:00 00:00:10:01 00:00:10:00 00:00:10:01\n |C:ResolveColorRed |M:Alice: Too dark, fix \"grade\" / Bob: Agreed / Carol: Done |D:1\n"));
+
+        let avid = render(&mf, &comments, ExportFormat::AvidMarkers);
+        assert_eq!(avid.lines().next(), Some("Bob\t00:00:02:05\tV1\tred\tBob: Jump cut\t1"));
+        assert_eq!(avid.lines().count(), 2);
+
+        let xml = render(&mf, &comments, ExportFormat::FcpXml);
+        assert!(xml.contains("frameDuration=\"1/25s\""));
+        assert!(xml.contains("<marker start=\"250/25s\" duration=\"1/25s\" value=\"Alice: Too dark, fix &quot;grade&quot;\" note=\"Bob: Agreed | Carol: Done\"/>"));
+        assert!(xml.contains("<project name=\"Review &quot;cut&quot; 3\">"));
+
+        let csv = render(&mf, &comments, ExportFormat::Csv);
+        let rows = csv.split("\r\n").collect::<Vec<_>>();
+        assert_eq!(rows[0], "comment_id,parent_id,timecode,frame,seconds,author,user_id,created,edited,comment");
+        assert_eq!(rows[1], "3,,00:00:02:05,55,2.200,Bob,user.bob,2023-11-14T22:13:23+00:00,,Jump cut");
+        assert_eq!(rows[2], "1,,00:00:10:00,250,10.000,Alice,user.alice,2023-11-14T22:13:21+00:00,,\"Too dark,\nfix \"\"grade\"\"\"");
+        assert!(rows[3].starts_with("2,1,,,,Bob,"));
+        assert!(rows[4].starts_with("4,2,,,,Carol,"));
+        assert!(rows[5].starts_with("5,,,,,Carol,"));
+
+        let json: serde_json::Value = serde_json::from_str(&render(&mf, &comments, ExportFormat::Json)).unwrap();
+        assert_eq!(json["media_file"]["fps"], 25.0);
+        assert_eq!(json["comments"][1]["timecode"], "00:00:10:00");
+        assert_eq!(json["comments"][1]["replies"][1]["author"], "Carol");
+        assert_eq!(json["comments"][2]["frame"], serde_json::Value::Null);
+        assert_eq!(json["comments"][2]["status"], "open");
+    }
+}
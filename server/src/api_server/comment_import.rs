@@ -0,0 +1,468 @@
+// Bulk import of comment threads from external review tools and NLEs.
+// Sources: CSV (with a header row), JSON (Clapshot's own export, Frame.io-style comment lists),
+// and EDL markers (CMX3600 `* LOC:` locators, DaVinci Resolve `|M:` markers).
+// Timecodes are mapped to frames with the media file's fps and start timecode (see `timecode::FrameRate`),
+// and stored in the client's timecode format, along with the frame number.
+// Authors are kept in `username_ifnull`. Comments are owned by the importing user, so they can
+// clean up, but source user IDs are not trusted. Threads are rebuilt from nested `replies`,
+// `id` / `parent_id` references, or "Author: text | Author: reply" marker text (as written by our EDL export).
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use serde_json::Value;
+
+use crate::timecode::FrameRate;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    Json,
+    Edl,
+}
+
+impl FromStr for ImportFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" | "tsv" => Ok(ImportFormat::Csv),
+            "json" | "frameio" => Ok(ImportFormat::Json),
+            "edl" | "cmx3600" | "resolve" => Ok(ImportFormat::Edl),
+            _ => anyhow::bail!("Unknown import format '{}'. Use csv, json, frameio or edl.", s),
+        }
+    }
+}
+
+impl ImportFormat {
+    /// Guess format from file extension, or content if that doesn't tell
+    pub fn detect(filename: &str, content: &str) -> Self {
+        let ext = filename.rsplit_once('.').map(|(_, e)| e).unwrap_or_default();
+        if let Ok(f) = ImportFormat::from_str(ext) {
+            return f;
+        }
+        let start = content.trim_start();
+        if start.starts_with('[') || start.starts_with('{') {
+            ImportFormat::Json
+        } else if start.starts_with("TITLE:") || content.contains("* LOC:") || content.contains("|M:") {
+            ImportFormat::Edl
+        } else {
+            ImportFormat::Csv
+        }
+    }
+}
+
+/// Comment read from an import file
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportedComment {
+    pub frame: Option<u64>,
+    pub author: Option<String>,
+    pub text: String,
+    /// Index of parent in the same list. Parents always come before replies.
+    pub parent: Option<usize>,
+}
+
+/// Result of parsing an import file
+#[derive(Debug, Default)]
+pub struct ParsedImport {
+    pub comments: Vec<ImportedComment>,
+    /// Non-fatal problems (skipped rows, unreadable timecodes etc.), for the summary message
+    pub warnings: Vec<String>,
+}
+
+/// Comment and its source ID / parent reference, before threads are resolved
+#[derive(Debug, Default)]
+struct RawComment {
+    key: Option<String>,
+    parent_key: Option<String>,
+    c: ImportedComment,
+    replies: Vec<RawComment>,
+}
+
+/// Parse an import file into a flat list of comments, parents first
+///
+/// # Arguments
+/// * `content` - File contents
+/// * `format` - File format
+/// * `rate` - Frame rate of the media file, for timecode mapping
+pub fn parse(content: &str, format: ImportFormat, rate: &FrameRate) -> anyhow::Result<ParsedImport> {
+    let mut warnings = vec![];
+    let raw = match format {
+        ImportFormat::Csv => parse_csv(content, rate, &mut warnings)?,
+        ImportFormat::Json => parse_json(content, rate, &mut warnings)?,
+        ImportFormat::Edl => parse_edl(content, rate, &mut warnings),
+    };
+    let comments = resolve_threads(raw, &mut warnings);
+    Ok(ParsedImport { comments, warnings })
+}
+
+/// Flatten nested replies and resolve `parent_key` references into parent indices.
+/// Unknown parents and reference cycles turn into top-level comments.
+fn resolve_threads(raw: Vec<RawComment>, warnings: &mut Vec<String>) -> Vec<ImportedComment> {
+    // Flatten, with parent index from nesting
+    let mut flat: Vec<(RawComment, Option<usize>)> = vec![];
+    fn push(flat: &mut Vec<(RawComment, Option<usize>)>, mut r: RawComment, nest_parent: Option<usize>) {
+        let idx = flat.len();
+        let replies = std::mem::take(&mut r.replies);
+        flat.push((r, nest_parent));
+        for reply in replies {
+            push(flat, reply, Some(idx));
+        }
+    }
+    for r in raw {
+        push(&mut flat, r, None);
+    }
+
+    let by_key: HashMap<String, usize> = flat.iter().enumerate()
+        .filter_map(|(i, (r, _))| r.key.clone().map(|k| (k, i)))
+        .collect();
+    let mut parents: Vec<Option<usize>> = flat.iter().enumerate().map(|(i, (r, np))| {
+        np.or_else(|| {
+            let pk = r.parent_key.as_ref()?;
+            match by_key.get(pk) {
+                Some(p) if *p != i => Some(*p),
+                _ => {
+                    warnings.push(format!("Reply to unknown comment '{}' imported as a new thread", pk));
+                    None
+                }
+            }
+        })
+    }).collect();
+
+    // Order parents before replies (depth first from roots). Whatever is left is in a cycle.
+    let mut children: Vec<Vec<usize>> = vec![vec![]; flat.len()];
+    for (i, p) in parents.iter().enumerate() {
+        if let Some(p) = p { children[*p].push(i); }
+    }
+    let mut order: Vec<usize> = Vec::with_capacity(flat.len());
+    let mut visited = vec![false; flat.len()];
+    let visit = |root: usize, order: &mut Vec<usize>, visited: &mut Vec<bool>| {
+        let mut stack = vec![root];
+        while let Some(i) = stack.pop() {
+            if visited[i] { continue; }
+            visited[i] = true;
+            order.push(i);
+            stack.extend(children[i].iter().rev());
+        }
+    };
+    let roots = parents.iter().enumerate().filter(|(_, p)| p.is_none()).map(|(i, _)| i).collect::<Vec<_>>();
+    for i in roots {
+        visit(i, &mut order, &mut visited);
+    }
+    while let Some(i) = visited.iter().position(|v| !v) {
+        warnings.push("Circular reply references, imported as a new thread".to_string());
+        parents[i] = None;
+        visit(i, &mut order, &mut visited);
+    }
+
+    let mut new_idx = vec![0; flat.len()];
+    for (n, i) in order.iter().enumerate() {
+        new_idx[*i] = n;
+    }
+    order.iter().map(|i| ImportedComment { parent: parents[*i].map(|p| new_idx[p]), ..flat[*i].0.c.clone() }).collect()
+}
+
+
+// CSV ---------------------------------------------------------------------------------------------
+
+/// Split CSV into records (RFC 4180 quoting, fields may contain newlines)
+fn csv_records(content: &str, delim: char) -> Vec<Vec<String>> {
+    let mut records = vec![];
+    let mut rec = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if quoted && chars.peek() == Some(&'"') => { field.push('"'); chars.next(); },
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if c == delim && !quoted => rec.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {},
+            '\n' if !quoted => {
+                rec.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut rec));
+            },
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !rec.is_empty() {
+        rec.push(field);
+        records.push(rec);
+    }
+    records.retain(|r| r.iter().any(|f| !f.trim().is_empty()));
+    records
+}
+
+fn parse_csv(content: &str, rate: &FrameRate, warnings: &mut Vec<String>) -> anyhow::Result<Vec<RawComment>> {
+    let content = content.trim_start_matches('\u{feff}');
+    let header_line = content.lines().next().unwrap_or_default();
+    let delim = [',', '\t', ';'].into_iter().max_by_key(|d| header_line.matches(*d).count()).unwrap_or(',');
+    let mut records = csv_records(content, delim).into_iter();
+    let header: Vec<String> = records.next().unwrap_or_default().iter()
+        .map(|h| h.trim().to_lowercase().replace([' ', '-'], "_"))
+        .collect();
+    let col = |names: &[&str]| names.iter().find_map(|n| header.iter().position(|h| h == n));
+
+    let text_col = col(&["comment", "text", "note", "comments", "body", "description"])
+        .ok_or_else(|| anyhow::anyhow!("CSV has no comment column (comment, text or note)"))?;
+    let author_col = col(&["author", "name", "username", "user", "commenter", "owner"]);
+    let tc_col = col(&["timecode", "tc", "record_in", "source_in"]);
+    let frame_col = col(&["frame"]);
+    let secs_col = col(&["seconds", "time"]);
+    let id_col = col(&["comment_id", "id", "#"]);
+    let parent_col = col(&["parent_id", "parent"]);
+
+    let mut res = vec![];
+    for (n, rec) in records.enumerate() {
+        let field = |c: Option<usize>| c.and_then(|c| rec.get(c)).map(|s| s.trim()).filter(|s| !s.is_empty());
+        let line = format!("Row {}", n + 2);
+        let text = match field(Some(text_col)) {
+            Some(t) => t.to_string(),
+            None => {
+                warnings.push(format!("{}: no comment text, skipped", line));
+                continue;
+            }
+        };
+        let frame = read_time(
+            field(frame_col).map(|f| Value::from(f.parse::<u64>().ok())),
+            field(tc_col),
+            field(secs_col).and_then(|s| s.parse::<f64>().ok()),
+            rate, &line, warnings);
+        res.push(RawComment {
+            key: field(id_col).map(|s| s.to_string()),
+            parent_key: field(parent_col).map(|s| s.to_string()),
+            c: ImportedComment { frame, author: field(author_col).map(|s| s.to_string()), text, parent: None },
+            replies: vec![],
+        });
+    }
+    Ok(res)
+}
+
+/// Frame number from whichever time field is present: frame number, timecode or seconds
+fn read_time(frame: Option<Value>, timecode: Option<&str>, seconds: Option<f64>, rate: &FrameRate, what: &str, warnings: &mut Vec<String>) -> Option<u64> {
+    if let Some(f) = frame.as_ref().and_then(|f| f.as_u64()) {
+        return Some(rate.clamp(f));
+    }
+    if let Some(tc) = timecode {
+        match rate.parse_nle(tc) {
+            Some(f) => return Some(f),
+            None => warnings.push(format!("{}: unreadable timecode '{}', imported without one", what, tc)),
+        }
+    }
+    seconds.and_then(|s| rate.from_seconds(s))
+}
+
+
+// JSON --------------------------------------------------------------------------------------------
+
+/// String field, or `name` of an object field (e.g. Frame.io's `"owner": {"name": ...}`)
+fn json_name(v: &Value, keys: &[&str]) -> Option<String> {
+    keys.iter().find_map(|k| match v.get(k)? {
+        Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Value::Object(o) => o.get("name").or(o.get("email")).and_then(|n| n.as_str()).map(|s| s.to_string()),
+        _ => None,
+    })
+}
+
+fn json_key(v: &Value, key: &str) -> Option<String> {
+    match v.get(key)? {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn json_comment(v: &Value, rate: &FrameRate, what: &str, warnings: &mut Vec<String>) -> Option<RawComment> {
+    let text = json_name(v, &["comment", "text", "body"]);
+    let Some(text) = text else {
+        warnings.push(format!("{}: no comment text, skipped", what));
+        return None;
+    };
+    // Frame.io's `timestamp` is a frame number
+    let frame = read_time(
+        v.get("frame").or(v.get("timestamp")).cloned(),
+        v.get("timecode").and_then(|t| t.as_str()),
+        v.get("seconds").or(v.get("time")).and_then(|s| s.as_f64()),
+        rate, what, warnings);
+    let replies = v.get("replies").and_then(|r| r.as_array()).map(|r| r.iter().enumerate()
+            .filter_map(|(i, r)| json_comment(r, rate, &format!("{}, reply {}", what, i + 1), warnings))
+            .collect())
+        .unwrap_or_default();
+    Some(RawComment {
+        key: json_key(v, "id"),
+        parent_key: json_key(v, "parent_id"),
+        c: ImportedComment { frame, author: json_name(v, &["author", "owner", "user", "commenter", "username"]), text, parent: None },
+        replies,
+    })
+}
+
+fn parse_json(content: &str, rate: &FrameRate, warnings: &mut Vec<String>) -> anyhow::Result<Vec<RawComment>> {
+    let doc: Value = serde_json::from_str(content.trim_start_matches('\u{feff}'))?;
+    let items = match &doc {
+        Value::Array(a) => a,
+        Value::Object(o) => o.get("comments").or(o.get("data")).and_then(|c| c.as_array())
+            .ok_or_else(|| anyhow::anyhow!("JSON has no 'comments' array"))?,
+        _ => anyhow::bail!("JSON must be an array of comments, or an object with a 'comments' array"),
+    };
+    Ok(items.iter().enumerate()
+        .filter_map(|(i, v)| json_comment(v, rate, &format!("Comment {}", i + 1), warnings))
+        .collect())
+}
+
+
+// EDL ---------------------------------------------------------------------------------------------
+
+/// Split marker text into thread: "Author: text | Author: reply | ..."
+fn marker_thread(text: &str, frame: Option<u64>) -> RawComment {
+    let re = regex::Regex::new(r"^([^:|]{1,64}): (.+)$").expect("valid regex");
+    let mut parts = text.split(" | ").map(|p| p.trim()).filter(|p| !p.is_empty()).map(|p| match re.captures(p) {
+        Some(cap) => (Some(cap[1].trim().to_string()), cap[2].trim().to_string()),
+        None => (None, p.to_string()),
+    });
+    let (author, text) = parts.next().unwrap_or((None, text.trim().to_string()));
+    RawComment {
+        c: ImportedComment { frame, author, text, parent: None },
+        replies: parts.map(|(author, text)| RawComment { c: ImportedComment { author, text, ..Default::default() }, ..Default::default() }).collect(),
+        ..Default::default()
+    }
+}
+
+fn parse_edl(content: &str, rate: &FrameRate, warnings: &mut Vec<String>) -> Vec<RawComment> {
+    const TC: &str = r"(\d{2}[:;]\d{2}[:;]\d{2}[:;]\d{2})";
+    let re_event = regex::Regex::new(&format!(r"^\d{{3,6}}\s+\S+\s+\S+\s+\S+(?:\s+\d+)?\s+{TC}\s+{TC}\s+{TC}\s+{TC}")).expect("valid regex");
+    let re_loc = regex::Regex::new(&format!(r"^\*\s*LOC:\s*{TC}\s+(?:(?:RED|GREEN|BLUE|CYAN|MAGENTA|YELLOW|WHITE|BLACK|PINK|PURPLE|ORANGE)\s+)?(.*)$")).expect("valid regex");
+    let re_resolve = regex::Regex::new(r"\|M:(.*?)(?:\s+\|D:\d+)?\s*$").expect("valid regex");
+
+    let mut res = vec![];
+    let mut event_in: Option<&str> = None;
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim_end();
+        if let Some(cap) = re_event.captures(line) {
+            event_in = Some(cap.get(1).expect("group").as_str());
+        } else if let Some(cap) = re_loc.captures(line) {
+            res.push((n, cap.get(1).map(|m| m.as_str()), cap[2].to_string()));
+        } else if let Some(cap) = re_resolve.captures(line) {
+            res.push((n, event_in, cap[1].to_string()));
+        }
+    }
+    res.into_iter().filter_map(|(n, tc, text)| {
+        if text.trim().is_empty() {
+            warnings.push(format!("Line {}: empty marker, skipped", n + 1));
+            return None;
+        }
+        let frame = read_time(None, tc, None, rate, &format!("Line {}", n + 1), warnings);
+        Some(marker_thread(&text, frame))
+    }).collect()
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate() -> FrameRate {
+        FrameRate::new(25.0, Some(1000))
+    }
+
+    fn summary(p: &ParsedImport) -> Vec<(Option<u64>, Option<&str>, &str, Option<usize>)> {
+        p.comments.iter().map(|c| (c.frame, c.author.as_deref(), c.text.as_str(), c.parent)).collect()
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(ImportFormat::detect("notes.CSV", "{"), ImportFormat::Csv);
+        assert_eq!(ImportFormat::detect("export.json", ""), ImportFormat::Json);
+        assert_eq!(ImportFormat::detect("markers.edl", ""), ImportFormat::Edl);
+        assert_eq!(ImportFormat::detect("x", "  [{\"text\": \"a\"}]"), ImportFormat::Json);
+        assert_eq!(ImportFormat::detect("x", "TITLE: foo\nFCM: NON-DROP FRAME\n"), ImportFormat::Edl);
+        assert_eq!(ImportFormat::detect("x", "comment,timecode\n"), ImportFormat::Csv);
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let csv = "\u{feff}comment_id,parent_id,timecode,frame,seconds,author,user_id,created,edited,comment\r\n\
+            3,,00:00:02:05,55,2.200,Bob,user.bob,,,Jump cut\r\n\
+            7,3,,,,Carol,,,,\"Fixed, \"\"really\"\"\nnow\"\r\n\
+            8,99,00:00:09:99,,,Dave,,,,Orphan\r\n\
+            9,,,,,Eve,,,,\r\n\
+            10,,,,3.5,,,,,By seconds\r\n";
+        let p = parse(csv, ImportFormat::Csv, &rate()).unwrap();
+        assert_eq!(summary(&p), vec![
+            (Some(55), Some("Bob"), "Jump cut", None),
+            (None, Some("Carol"), "Fixed, \"really\"\nnow", Some(0)),
+            (None, Some("Dave"), "Orphan", None),
+            (Some(88), None, "By seconds", None),
+        ]);
+        assert_eq!(p.warnings.len(), 3, "{:?}", p.warnings);   // Bad timecode, unknown parent, empty text
+
+        let tsv = "Name\tTimecode\tNote\nAlice\t00:01:00:00\tLate\n";
+        let p = parse(tsv, ImportFormat::Csv, &rate()).unwrap();
+        assert_eq!(summary(&p), vec![(Some(999), Some("Alice"), "Late", None)]);   // Clamped to file length
+
+        assert!(parse("a,b\n1,2\n", ImportFormat::Csv, &rate()).is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        // Clapshot export
+        let json = r#"{"media_file": {"id": "B1DE0"}, "comments": [
+            {"id": 1, "parent_id": null, "timecode": "00:00:10:00", "frame": 250, "author": "Alice", "comment": "Too dark",
+             "replies": [{"id": 2, "parent_id": 1, "author": "Bob", "comment": "Agreed"}]},
+            {"id": 5, "timecode": null, "frame": null, "author": "Carol", "comment": "General note", "replies": []}
+        ]}"#;
+        let p = parse(json, ImportFormat::Json, &rate()).unwrap();
+        assert_eq!(summary(&p), vec![
+            (Some(250), Some("Alice"), "Too dark", None),
+            (None, Some("Bob"), "Agreed", Some(0)),
+            (None, Some("Carol"), "General note", None),
+        ]);
+
+        // Frame.io style: flat list with parent_id, owner objects and frame timestamps
+        let json = r#"[
+            {"id": "b", "parent_id": "a", "text": "Reply", "owner": {"name": "Bob", "email": "bob@example.com"}},
+            {"id": "a", "parent_id": null, "text": "Cut here", "timestamp": 100, "owner": {"name": "Alice"}},
+            {"id": "c", "text": "", "owner": {"name": "Nobody"}}
+        ]"#;
+        let p = parse(json, ImportFormat::Json, &rate()).unwrap();
+        assert_eq!(summary(&p), vec![
+            (Some(100), Some("Alice"), "Cut here", None),
+            (None, Some("Bob"), "Reply", Some(0)),
+        ]);
+        assert_eq!(p.warnings.len(), 1);
+
+        assert!(parse("{\"foo\": 1}", ImportFormat::Json, &rate()).is_err());
+        assert!(parse("not json", ImportFormat::Json, &rate()).is_err());
+    }
+
+    #[test]
+    fn test_parse_edl() {
+        let edl = "TITLE: Review\nFCM: NON-DROP FRAME\n\n\
+            001  AX       V     C        00:00:00:00 00:00:40:00 00:00:00:00 00:00:40:00\n\
+            * FROM CLIP NAME: cut3.mov\n\
+            * LOC: 00:00:02:05 RED     Bob: Jump cut\n\
+            * LOC: 00:00:10:00 YELLOW  Alice: Too dark | Bob: Agreed | Carol: Done\n\
+            * LOC: 00:00:11:00 No author here\n\n\
+            002  002      V     C        00:00:20:00 00:00:20:01 00:00:20:00 00:00:20:01\n \
+            |C:ResolveColorBlue |M:Resolve marker |D:1\n";
+        let p = parse(edl, ImportFormat::Edl, &rate()).unwrap();
+        assert_eq!(summary(&p), vec![
+            (Some(55), Some("Bob"), "Jump cut", None),
+            (Some(250), Some("Alice"), "Too dark", None),
+            (None, Some("Bob"), "Agreed", Some(1)),
+            (None, Some("Carol"), "Done", Some(1)),
+            (Some(275), None, "No author here", None),
+            (Some(500), None, "Resolve marker", None),
+        ]);
+        assert!(p.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_reply_cycle() {
+        let json = r#"[{"id": "a", "parent_id": "b", "text": "A"}, {"id": "b", "parent_id": "a", "text": "B"}]"#;
+        let p = parse(json, ImportFormat::Json, &rate()).unwrap();
+        assert_eq!(p.comments.len(), 2);
+        assert_eq!(p.comments[0].parent, None);
+        assert_eq!(p.comments[1].parent, Some(0));
+    }
+}
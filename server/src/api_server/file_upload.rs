@@ -2,14 +2,19 @@ use futures_util::stream::StreamExt;
 use warp::http::HeaderMap;
 use futures::stream::TryStreamExt;
 use mpart_async::server::MultipartStream;
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::video_pipeline::IncomingFile;
-use super::parse_auth_headers;
+use crate::video_pipeline::{IncomingFile, JobPriority};
+use crate::video_pipeline::upload_limits::LimitError;
+use crate::database::{error::DBError, models, DbBasicQuery};
+use crate::send_user_error;
+use super::auth::AuthUser;
+use super::media_versions::VERSION_OF_COOKIE;
 use super::server_state::ServerState;
-use super::user_session::{org_authz_with_default, AuthzTopic, AuthzError};
+use super::user_session::{org_authz_with_default, AuthzTopic, AuthzError, OrgSessionConn, Topic};
 
 use lib_clapshot_grpc::proto;
 use proto::org::authz_user_action_request as authz_req;
@@ -21,7 +26,8 @@ use proto::org::authz_user_action_request as authz_req;
 /// * `upload_dir` - Path to the directory where the uploaded files will be stored
 /// * `upload_done` - Channel to submit the uploaded file path to further processing
 /// * `mime` - Parsed mime options from the request
-/// * `hdrs` - Authentication headers to be used for identifying the uploader
+/// * `hdrs` - HTTP headers (for Content-Length)
+/// * `user` - Authenticated uploader
 /// * `server` - Server state (for organizer connection)
 /// * `body` - The request body (stream)
 pub async fn handle_multipart_upload(
@@ -29,40 +35,28 @@ pub async fn handle_multipart_upload(
     upload_done: crossbeam_channel::Sender<IncomingFile>,
     mime: mime::Mime,
     hdrs: HeaderMap,
+    user: AuthUser,
     server: ServerState,
     body: impl warp::Stream<Item = Result<impl bytes::Buf, warp::Error>> + Unpin)
         -> Result<warp::reply::WithStatus<String>, Infallible>
 {
-    let (user_id, user_name, is_admin, cookies) = parse_auth_headers(&hdrs, &server.default_user);
-
-    // Check from organizer if user is allowed to upload.
-    // Allow by default if organizer is not configured or doesn't care.
-    if let Some(uri) = &server.organizer_uri {
-        if server.organizer_has_connected.load(std::sync::atomic::Ordering::Relaxed) {
-            let organizer = match crate::grpc::grpc_client::connect(uri.clone()).await {
-                Ok(c) => Arc::new(tokio::sync::Mutex::new(c)),
-                Err(e) => {
-                    tracing::error!("Failed to connect to organizer: {}", e);
-                    return Ok(warp::reply::with_status("Internal error: failed to connect to organizer".into(), warp::http::StatusCode::INTERNAL_SERVER_ERROR));
-                }
-            };
+    let AuthUser { user_id, user_name, is_admin, cookies } = user;
 
-            let org_session = proto::org::UserSessionData {
-                sid: "<upload--not-set>".to_string(),
-                user: Some(proto::UserInfo { id: user_id.clone(), name: user_name.clone() }),
-                is_admin,
-                cookies: cookies.clone()
-            };
+    if let Err((msg, status)) = check_upload_permission(&user_id, &user_name, is_admin, &cookies, &server).await {
+        return Ok(warp::reply::with_status(msg, status));
+    }
 
-            match org_authz_with_default(&org_session, "upload media file", true, &server, &Some(organizer),
-                true, AuthzTopic::Other(None, authz_req::other_op::Op::UploadMediaFile)).await {
-                Ok(_) => {},
-                Err(AuthzError::Denied) => {
-                    return Ok(warp::reply::with_status("Permission denied".into(), warp::http::StatusCode::FORBIDDEN));
-                },
-            }
+    // Reject early if body (file + some multipart overhead) obviously won't fit in quota.
+    // Size isn't known exactly until the end, so also cut the upload off if it exceeds remaining quota.
+    let body_len = hdrs.get(warp::http::header::CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok());
+    let est_bytes = body_len.unwrap_or(0).saturating_sub(MULTIPART_OVERHEAD);
+    let max_bytes = match check_quota(&server, &user_id, est_bytes).await {
+        Ok(remaining) => remaining,
+        Err(e) => {
+            let (msg, status) = reject_upload(&user_id, "upload", &e, &server);
+            return Ok(warp::reply::with_status(msg, status));
         }
-    }
+    };
 
     // Parse the multipart stream
     let boundary = mime.get_param("boundary").map(|v| v.to_string());
@@ -88,6 +82,10 @@ pub async fn handle_multipart_upload(
                         if path.file_name() != Some(path.as_os_str()) {
                             return Ok(warp::reply::with_status("Filename must not contain path".into(), warp::http::StatusCode::BAD_REQUEST));
                         }
+                        if let Err(e) = server.upload_limits.check_type(&filename, field.content_type().ok()) {
+                            let (msg, status) = reject_upload(&user_id, &filename, &e, &server);
+                            return Ok(warp::reply::with_status(msg, status));
+                        }
 
                         // Make a unique upload dir
                         let uuid = uuid::Uuid::new_v4();
@@ -102,9 +100,15 @@ pub async fn handle_multipart_upload(
                             return Ok(warp::reply::with_status("Internal error: failed to create upload dir".into(), warp::http::StatusCode::INTERNAL_SERVER_ERROR));
                         }
 
+                        // Count it against quota until the pipeline has ingested it (or it fails)
+                        let pending = server.upload_limits.pending.clone();
+                        let dst_path: PathBuf = dst.clone().into();
+                        pending.reserve(&dst_path, &user_id, est_bytes);
+
                         // Create the file and stream the data into it
                         match async_std::fs::File::create(&dst).await {
                             Err(e) => {
+                                pending.release(&dst_path);
                                 let msg = format!("Failed to create file '{}': {}", dst.display(), e);
                                 tracing::error!(msg);
                                 return Ok(warp::reply::with_status(msg, warp::http::StatusCode::INTERNAL_SERVER_ERROR));
@@ -119,28 +123,43 @@ pub async fn handle_multipart_upload(
                                     while let Some(chunk) = field.next().await {
                                         match chunk {
                                             Ok(data) => { buff_tx.send(data).await.unwrap(); },
-                                            Err(e) => { return Err(e.to_string()); }
+                                            Err(e) => { return Err(anyhow::anyhow!(e.to_string())); }
                                     }}; Ok(())  // buff_tx dropped
                                 };
 
                                 // Write chunks to the file
+                                let (pending_w, dst_w, user_w) = (pending.clone(), dst_path.clone(), user_id.clone());
                                 let write_all_chunks = async move {
+                                    let mut written = 0u64;
                                     while let Some(data) = buff_rx.recv().await {
-                                        futures_util::AsyncWriteExt::write_all(&mut f, &data).await
-                                            .map_err(|e| e.to_string())?;
-                                    }; Ok(())
+                                        written += data.len() as u64;
+                                        if max_bytes.is_some_and(|max| written > max) {
+                                            return Err(LimitError::QuotaExceeded("upload is larger than remaining quota".into()).into());
+                                        }
+                                        if written > est_bytes {
+                                            pending_w.reserve(&dst_w, &user_w, written);
+                                        }
+                                        futures_util::AsyncWriteExt::write_all(&mut f, &data).await?;
+                                    }
+                                    pending_w.reserve(&dst_w, &user_w, written);
+                                    Ok(())
                                 };
 
                                 // Run both tasks in parallel, cleanup on error
                                 if let Err(e) = tokio::try_join!(read_all_chunks, write_all_chunks)
                                 {
                                     tracing::error!("Upload failed: {}", e);
+                                    pending.release(&dst_path);
                                     // Remove the file & dir, since it's incomplete
                                     if let Err(e) = async_std::fs::remove_file(&dst).await {
                                         tracing::warn!("Failed to remove incomplete upload file: {}", e);
                                     } else if let Err(e) = async_std::fs::remove_dir(new_dir).await {
                                         tracing::warn!("Failed to remove incomplete upload dir: {}", e);
                                     }
+                                    if let Some(le) = e.downcast_ref::<LimitError>() {
+                                        let (msg, status) = reject_upload(&user_id, &filename, le, &server);
+                                        return Ok(warp::reply::with_status(msg, status));
+                                    }
                                     return Ok(warp::reply::with_status(format!("Upload failed: {e}"), warp::http::StatusCode::BAD_REQUEST));
                                 }
                                 tracing::info!(dst=dst.display().to_string(), "File uploaded.");
@@ -156,9 +175,107 @@ pub async fn handle_multipart_upload(
         }
     }
 
-    if let Err(e) = upload_done.send(IncomingFile{ file_path: uploaded_file, user_id: user_id, cookies }) {
+    if let Err(e) = upload_done.send(IncomingFile{ file_path: uploaded_file, user_id, cookies, priority: JobPriority::Interactive }) {
         tracing::error!("Failed to send upload ok signal: {:?}", e);
+        server.upload_limits.pending.release(&e.0.file_path);
         return Ok(warp::reply::with_status("Internal error: failed to send upload ok signal".into(), warp::http::StatusCode::INTERNAL_SERVER_ERROR));
     }
     Ok(warp::reply::with_status("Ok".into(), warp::http::StatusCode::OK))
 }
+
+
+/// Check from organizer if user is allowed to upload.
+/// Allow by default if organizer is not configured or doesn't care.
+/// If the upload is a new version of an existing media file (`version_of` cookie), also check
+/// that the media file exists and that the user may edit it (by default, owner or admin).
+/// On failure, returns the message and HTTP status to send to client.
+pub(super) async fn check_upload_permission(
+    user_id: &str,
+    user_name: &str,
+    is_admin: bool,
+    cookies: &HashMap<String, String>,
+    server: &ServerState)
+        -> Result<(), (String, warp::http::StatusCode)>
+{
+    let org_session = proto::org::UserSessionData {
+        sid: "<upload--not-set>".to_string(),
+        user: Some(proto::UserInfo { id: user_id.to_string(), name: user_name.to_string() }),
+        is_admin,
+        cookies: cookies.clone()
+    };
+    let mut organizers = vec![];
+
+    if !server.organizers.is_empty() && server.organizer_has_connected.load(std::sync::atomic::Ordering::Relaxed) {
+        for plugin in server.organizers.iter().filter(|p| p.roles.authz) {
+            match crate::grpc::grpc_client::connect(plugin.uri.clone()).await {
+                Ok(c) => organizers.push(OrgSessionConn { roles: plugin.roles, conn: Arc::new(tokio::sync::Mutex::new(c)) }),
+                Err(e) => {
+                    tracing::error!("Failed to connect to organizer: {}", e);
+                    return Err(("Internal error: failed to connect to organizer".into(), warp::http::StatusCode::INTERNAL_SERVER_ERROR));
+                }
+            };
+        }
+
+        match org_authz_with_default(&org_session, "upload media file", true, server, &organizers,
+            true, AuthzTopic::Other(None, authz_req::other_op::Op::UploadMediaFile)).await {
+            Ok(_) => {},
+            Err(AuthzError::Denied) => {
+                return Err(("Permission denied".into(), warp::http::StatusCode::FORBIDDEN));
+            },
+        }
+    }
+
+    if let Some(prev_id) = cookies.get(VERSION_OF_COOKIE).map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let prev = match server.db.conn().and_then(|mut conn| models::MediaFile::get(&mut conn, &prev_id.to_string())) {
+            Ok(mf) => mf,
+            Err(DBError::NotFound()) => return Err((format!("No such media file: '{}'", prev_id), warp::http::StatusCode::NOT_FOUND)),
+            Err(e) => {
+                tracing::error!(details=%e, "Failed to look up media file for new version.");
+                return Err(("Internal error: failed to look up media file".into(), warp::http::StatusCode::INTERNAL_SERVER_ERROR));
+            }
+        };
+        let default_perm = prev.user_id == user_id || is_admin;
+        if let Err(AuthzError::Denied) = org_authz_with_default(&org_session, "upload new version of media file", true, server, &organizers,
+            default_perm, AuthzTopic::MediaFile(&prev, authz_req::media_file_op::Op::Edit)).await {
+            return Err(("Permission denied".into(), warp::http::StatusCode::FORBIDDEN));
+        }
+    }
+    Ok(())
+}
+
+/// Rough upper bound for multipart headers and boundaries around the file data
+const MULTIPART_OVERHEAD: u64 = 16*1024;
+
+/// Check if a new file of `new_bytes` fits in user's and server's quotas.
+/// Returns how many bytes the user has left (None = unlimited).
+pub(super) async fn check_quota(server: &ServerState, user_id: &str, new_bytes: u64) -> Result<Option<u64>, LimitError>
+{
+    if !server.upload_limits.has_quotas() {
+        return Ok(None);
+    }
+    // Walks media dirs, so don't block the async runtime
+    let (server, user_id) = (server.clone(), user_id.to_string());
+    tokio::task::spawn_blocking(move || {
+        server.upload_limits.check_quota(&server.db, &server.media_files_dir, &user_id, new_bytes)?;
+        server.upload_limits.remaining_bytes(&server.db, &server.media_files_dir, &user_id)
+    }).await.map_err(|e| LimitError::Other(e.into()))?
+}
+
+/// Log and notify user about a rejected upload.
+/// Returns the message and HTTP status to send to client.
+pub(super) fn reject_upload(user_id: &str, filename: &str, e: &LimitError, server: &ServerState) -> (String, warp::http::StatusCode)
+{
+    let status = match e {
+        LimitError::TypeNotAllowed(_) => warp::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        LimitError::QuotaExceeded(_) => warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+        LimitError::Other(_) => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    tracing::info!(user=user_id, filename, details=%e, "Upload rejected.");
+    let uid = user_id.to_string();
+    let notify = || -> super::Res<()> {
+        send_user_error!(uid, server, Topic::None, "Upload rejected.", format!("'{}': {}", filename, e), true);
+        Ok(())
+    };
+    notify().unwrap_or_else(|e| tracing::warn!(details=%e, "Failed to notify user about rejected upload."));
+    (e.to_string(), status)
+}
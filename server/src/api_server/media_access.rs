@@ -0,0 +1,152 @@
+// Access control for the `/videos` static file route.
+// Media files are served only to requests that carry either a signed URL token (`?sig=`,
+// issued by `MediaFile::to_proto3()`), a share link token (see `share_links`), or user credentials
+// that pass the same authz check as opening the media file in the client.
+// A valid `?sig=` also sets a cookie scoped to `/videos/<media_file_id>/`, so files referenced
+// relatively from the signed one (HLS/DASH segments, for instance) load without their own tokens.
+
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use warp::http::HeaderMap;
+use warp::{Filter, Rejection};
+use lib_clapshot_grpc::proto;
+
+use crate::database::{error::DBError, models, DbBasicQuery};
+use super::auth::{sign_claims, verify_claims};
+use super::server_state::ServerState;
+use super::share_links::{self, ShareLinkError, SHARE_COOKIE};
+use super::user_session::{org_authz_with_default, AuthzTopic};
+
+pub const MEDIA_COOKIE: &str = "clapshot_media";
+
+/// How long signed media URLs stay valid
+pub fn media_url_ttl() -> chrono::Duration { chrono::Duration::hours(4) }
+
+#[derive(thiserror::Error, Debug)]
+pub enum MediaAccessError {
+    #[error("Access denied")]
+    Denied,
+    #[error("Invalid or expired media URL signature")]
+    BadSignature,
+}
+
+impl warp::reject::Reject for MediaAccessError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MediaUrlClaims {
+    media: String,
+    exp: i64,
+}
+
+/// Make a signed token that grants read access to `/videos/<media_file_id>/` until `expires`
+pub fn make_url_token(secret: &[u8], media_file_id: &str, expires: chrono::DateTime<chrono::Utc>) -> String {
+    sign_claims(secret, &MediaUrlClaims { media: media_file_id.into(), exp: expires.timestamp() })
+}
+
+/// Check a `make_url_token()` token. Returns its expiry time if valid for `media_file_id` at `now`.
+pub fn check_url_token(secret: &[u8], token: &str, media_file_id: &str, now: chrono::DateTime<chrono::Utc>) -> Option<chrono::DateTime<chrono::Utc>> {
+    let claims: MediaUrlClaims = verify_claims(secret, token)?;
+    let exp = chrono::DateTime::from_timestamp(claims.exp, 0)?;
+    (claims.media == media_file_id && exp > now).then_some(exp)
+}
+
+/// Check if an authenticated user may view a media file, like `msg_open_media_file()` does.
+/// Organizer authz is asked using the user's open WebSocket session. If Organizers do authz
+/// but the user has no open session, access is denied.
+pub(crate) async fn user_can_view(server: &ServerState, hdrs: &HeaderMap, media_file_id: &str) -> Result<(), Rejection>
+{
+    let user = server.auth.authenticate(hdrs, &server.default_user).map_err(warp::reject::custom)?;
+
+    let mf = match server.db.conn().and_then(|mut conn| models::MediaFile::get(&mut conn, &media_file_id.to_string())) {
+        Ok(mf) => mf,
+        Err(DBError::NotFound()) => return Err(warp::reject::not_found()),
+        Err(e) => {
+            tracing::error!(details=%e, "DB error while checking media file access.");
+            return Err(warp::reject::custom(MediaAccessError::Denied));
+        }
+    };
+
+    let (org_session, organizers) = match server.find_user_session(&user.user_id) {
+        Some(ses) => (ses.org_session, ses.organizers),
+        None => {
+            if server.organizers.iter().any(|o| o.roles.authz) {
+                tracing::info!(user=%user.user_id, media_file=%media_file_id, "No open session for Organizer authz. Denying media file access.");
+                return Err(warp::reject::custom(MediaAccessError::Denied));
+            }
+            (proto::org::UserSessionData {
+                sid: String::new(),
+                user: Some(proto::UserInfo { id: user.user_id.clone(), name: user.user_name.clone() }),
+                is_admin: user.is_admin,
+                cookies: user.cookies.clone(),
+            }, vec![])
+        }
+    };
+
+    let default_perm = true;    // anyone can view any media file, unless Organizer says otherwise
+    org_authz_with_default(&org_session, "view media file", false, server, &organizers,
+        default_perm, AuthzTopic::MediaFile(&mf, proto::org::authz_user_action_request::media_file_op::Op::View)).await
+        .map_err(|_| warp::reject::custom(MediaAccessError::Denied))
+}
+
+/// Warp filter for `/videos`: authorize the request for the media file in the first path segment.
+/// Extracts a `Set-Cookie` value to add to the response, if any.
+pub fn check_videos_access(server: ServerState) -> impl Filter<Extract=(Option<String>,), Error=Rejection> + Clone {
+    warp::path::peek()
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::cookie::optional::<String>(MEDIA_COOKIE))
+        .and(warp::cookie::optional::<String>(SHARE_COOKIE))
+        .and(warp::header::headers_cloned())
+        .and(warp::any().map(move || server.clone()))
+        .and_then(|path: warp::path::Peek, query: HashMap<String, String>, media_cookie: Option<String>, share_cookie: Option<String>, hdrs: HeaderMap, server: ServerState| async move {
+            let media_file_id = path.segments().next().unwrap_or_default().to_string();
+            if media_file_id.is_empty() {
+                return Err(warp::reject::custom(MediaAccessError::Denied));
+            }
+            let secret = &server.auth.link_secret;
+            let now = chrono::Utc::now();
+
+            // Signed URL
+            if let Some(token) = query.get("sig") {
+                let exp = check_url_token(secret, token, &media_file_id, now).ok_or(warp::reject::custom(MediaAccessError::BadSignature))?;
+                return Ok(Some(format!("{}={}; Path=/videos/{}/; HttpOnly; SameSite=Lax; Max-Age={}",
+                    MEDIA_COOKIE, token, media_file_id, (exp - now).num_seconds())));
+            }
+            if media_cookie.is_some_and(|t| check_url_token(secret, &t, &media_file_id, now).is_some()) {
+                return Ok(None);
+            }
+
+            // Share link
+            if let Some(token) = query.get("share").cloned().or(share_cookie) {
+                return match share_links::validate_token(&server.db, secret, &token, now.naive_utc()) {
+                    Ok(link) if link.media_file_id == media_file_id => Ok(None),
+                    Ok(_) => Err(warp::reject::custom(ShareLinkError::WrongMediaFile)),
+                    Err(e) => Err(warp::reject::custom(e)),
+                };
+            }
+
+            // Authenticated user
+            user_can_view(&server, &hdrs, &media_file_id).await.map(|_| None)
+        })
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_token() {
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let now = chrono::Utc::now();
+        let exp = now + media_url_ttl();
+        let token = make_url_token(secret, "B1DE0", exp);
+
+        assert_eq!(check_url_token(secret, &token, "B1DE0", now).unwrap().timestamp(), exp.timestamp());
+        assert!(check_url_token(secret, &token, "11111", now).is_none());
+        assert!(check_url_token(secret, &token, "B1DE0", exp + chrono::Duration::seconds(1)).is_none());
+        assert!(check_url_token(b"another-secret-another-secret-xx", &token, "B1DE0", now).is_none());
+        assert!(check_url_token(secret, &token.replace('.', ".x"), "B1DE0", now).is_none());
+    }
+}
@@ -0,0 +1,250 @@
+// Serving files from the media directory (`/videos` route), after `media_access` has authorized the request.
+// Supports byte ranges, ETag / Last-Modified validators and conditional GETs, so browsers can seek
+// in large originals and revalidate thumbnails cheaply. Alternatively, the actual transfer can be
+// offloaded to the reverse proxy with X-Accel-Redirect (Nginx) or X-Sendfile (Apache, Lighttpd).
+
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use futures_util::stream;
+use headers::{HeaderMapExt, AcceptRanges, ContentLength, ContentRange, ETag, IfModifiedSince, IfNoneMatch, IfRange, LastModified, Range};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use warp::http::{HeaderMap, HeaderValue, Method, Response, StatusCode};
+use warp::hyper::Body;
+use warp::Rejection;
+
+use super::server_state::ServerState;
+
+const READ_CHUNK: usize = 256 * 1024;
+
+/// How to hand file transfers over to the reverse proxy, if at all
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FileOffload {
+    #[default]
+    None,
+    /// Nginx: `X-Accel-Redirect: <prefix>/<path in media dir>`. Prefix should be an `internal` location
+    /// that aliases the media directory.
+    XAccelRedirect(String),
+    /// Apache mod_xsendfile / Lighttpd: `X-Sendfile: <absolute path>`
+    XSendfile,
+}
+
+/// Byte range to serve, from the request's `Range` header
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    Partial(u64, u64),  // Inclusive
+    Unsatisfiable,
+}
+
+/// Resolve `Range` header against file length. Multi-range requests are served in full.
+fn byte_range(range: &Range, len: u64) -> ByteRange {
+    let mut ranges = range.iter();
+    let (start, end) = match (ranges.next(), ranges.next()) {
+        (Some(r), None) => r,
+        _ => return ByteRange::Full,
+    };
+    let (start, end) = match (start, end) {
+        (Bound::Included(s), Bound::Included(e)) => (s, e.min(len.saturating_sub(1))),
+        (Bound::Included(s), Bound::Unbounded) => (s, len.saturating_sub(1)),
+        (Bound::Unbounded, Bound::Included(n)) if n > 0 => (len.saturating_sub(n), len.saturating_sub(1)),
+        _ => return ByteRange::Unsatisfiable,
+    };
+    if len == 0 || start >= len || start > end {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(start, end)
+    }
+}
+
+/// Content type by file extension. Covers what Clapshot itself writes (subtitles,
+/// thumbnails and thumb sheets, HLS/DASH), and guesses the rest.
+fn content_type(path: &Path) -> String {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    match ext.as_str() {
+        "vtt" => "text/vtt; charset=utf-8",
+        "srt" => "application/x-subrip; charset=utf-8",
+        "ass" | "ssa" => "text/x-ssa; charset=utf-8",
+        "webp" => "image/webp",
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "mpd" => "application/dash+xml",
+        "m4s" => "video/iso.segment",
+        "ts" => "video/mp2t",
+        _ => return mime_guess::from_path(path).first_or_octet_stream().to_string(),
+    }.to_string()
+}
+
+/// Resolve URL path (relative to `/videos/`) to a path inside the media directory.
+/// Returns None for anything that could escape it.
+fn resolve_path(media_dir: &Path, tail: &str) -> Option<PathBuf> {
+    let mut rel = PathBuf::new();
+    for seg in tail.split('/').filter(|s| !s.is_empty()) {
+        let seg = urlencoding::decode(seg).ok()?;
+        if seg.contains('/') || seg.contains('\\') || seg.starts_with('.') {
+            return None;
+        }
+        rel.push(seg.as_ref());
+    }
+    if rel.components().any(|c| !matches!(c, Component::Normal(_))) || rel.as_os_str().is_empty() {
+        return None;
+    }
+    Some(media_dir.join(rel))
+}
+
+/// Validators for a file: strong ETag from size and mtime, and Last-Modified
+fn validators(meta: &std::fs::Metadata) -> (ETag, LastModified, SystemTime) {
+    let mtime = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let mtime_ns = mtime.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", meta.len(), mtime_ns).parse::<ETag>().expect("valid etag");
+    (etag, LastModified::from(mtime), mtime)
+}
+
+/// Stream `len` bytes from the file's current position
+fn read_stream(file: tokio::fs::File, len: u64) -> Body {
+    Body::wrap_stream(stream::unfold((file, len), |(mut file, remaining)| async move {
+        if remaining == 0 { return None; }
+        let mut buf = vec![0u8; READ_CHUNK.min(remaining as usize)];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok::<_, std::io::Error>(bytes::Bytes::from(buf)), (file, remaining - n as u64)))
+            },
+            Err(e) => Some((Err(e), (file, 0))),
+        }
+    }))
+}
+
+/// Serve a file from the media directory. Authorization must be done before this.
+///
+/// # Arguments
+/// * `tail` - Path after `/videos/`
+/// * `method` - GET or HEAD
+/// * `hdrs` - Request headers
+/// * `set_cookie` - Optional `Set-Cookie` value from `media_access`
+/// * `server` - Server state
+pub async fn serve_media_file(
+    tail: warp::path::Tail,
+    method: Method,
+    hdrs: HeaderMap,
+    set_cookie: Option<String>,
+    server: ServerState,
+) -> Result<Response<Body>, Rejection>
+{
+    let path = resolve_path(&server.media_files_dir, tail.as_str()).ok_or_else(warp::reject::not_found)?;
+    let meta = match tokio::fs::metadata(&path).await {
+        Ok(m) if m.is_file() => m,
+        _ => return Err(warp::reject::not_found()),
+    };
+    let (etag, last_modified, mtime) = validators(&meta);
+    let len = meta.len();
+
+    let mut res = Response::new(Body::empty());
+    let rh = res.headers_mut();
+    if let Some(c) = set_cookie.and_then(|c| HeaderValue::from_str(&c).ok()) {
+        rh.insert("set-cookie", c);
+    }
+    rh.insert("cache-control", HeaderValue::from_static("private, no-cache"));
+    rh.insert("content-type", HeaderValue::from_str(&content_type(&path)).expect("valid content type"));
+
+    // Let the reverse proxy do the transfer (it handles ranges and validators itself)
+    match &server.file_offload {
+        FileOffload::None => {},
+        FileOffload::XAccelRedirect(prefix) => {
+            let rel = tail.as_str().trim_start_matches('/');
+            let target = format!("{}/{}", prefix.trim_end_matches('/'), rel);
+            rh.insert("x-accel-redirect", HeaderValue::from_str(&target).map_err(|_| warp::reject::not_found())?);
+            return Ok(res);
+        },
+        FileOffload::XSendfile => {
+            let abs = path.canonicalize().map_err(|_| warp::reject::not_found())?;
+            rh.insert("x-sendfile", HeaderValue::from_str(&abs.to_string_lossy()).map_err(|_| warp::reject::not_found())?);
+            return Ok(res);
+        },
+    }
+
+    rh.typed_insert(etag.clone());
+    rh.typed_insert(last_modified);
+    rh.typed_insert(AcceptRanges::bytes());
+
+    // Conditional GET. If-None-Match takes precedence over If-Modified-Since (RFC 9110, 13.2.2).
+    let not_modified = match hdrs.typed_get::<IfNoneMatch>() {
+        Some(inm) => !inm.precondition_passes(&etag),
+        None => hdrs.typed_get::<IfModifiedSince>().is_some_and(|ims| !ims.is_modified(mtime)),
+    };
+    if not_modified {
+        *res.status_mut() = StatusCode::NOT_MODIFIED;
+        return Ok(res);
+    }
+
+    // Range, unless If-Range says the client's copy is stale
+    let range = match hdrs.typed_get::<Range>() {
+        Some(r) if hdrs.typed_get::<IfRange>().is_none_or(|ir| !ir.is_modified(Some(&etag), Some(&last_modified))) => byte_range(&r, len),
+        _ => ByteRange::Full,
+    };
+    let (start, end) = match range {
+        ByteRange::Full => (0, len.saturating_sub(1)),
+        ByteRange::Partial(s, e) => {
+            *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+            res.headers_mut().typed_insert(ContentRange::bytes(s..=e, len).expect("valid range"));
+            (s, e)
+        },
+        ByteRange::Unsatisfiable => {
+            *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            res.headers_mut().typed_insert(ContentRange::unsatisfied_bytes(len));
+            return Ok(res);
+        },
+    };
+    let body_len = if len == 0 { 0 } else { end - start + 1 };
+    res.headers_mut().typed_insert(ContentLength(body_len));
+
+    if method == Method::HEAD || body_len == 0 {
+        return Ok(res);
+    }
+    let mut file = tokio::fs::File::open(&path).await.map_err(|e| {
+        tracing::warn!(details=%e, file=?path, "Failed to open media file.");
+        warp::reject::not_found()
+    })?;
+    if start > 0 {
+        file.seek(std::io::SeekFrom::Start(start)).await.map_err(|_| warp::reject::not_found())?;
+    }
+    *res.body_mut() = read_stream(file, body_len);
+    Ok(res)
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_range() {
+        let r = |s: &str| HeaderMap::from_iter([(warp::http::header::RANGE, s.parse().unwrap())]).typed_get::<Range>().unwrap();
+        assert_eq!(byte_range(&r("bytes=0-99"), 1000), ByteRange::Partial(0, 99));
+        assert_eq!(byte_range(&r("bytes=900-2000"), 1000), ByteRange::Partial(900, 999));
+        assert_eq!(byte_range(&r("bytes=500-"), 1000), ByteRange::Partial(500, 999));
+        assert_eq!(byte_range(&r("bytes=-100"), 1000), ByteRange::Partial(900, 999));
+        assert_eq!(byte_range(&r("bytes=-5000"), 1000), ByteRange::Partial(0, 999));
+        assert_eq!(byte_range(&r("bytes=1000-"), 1000), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(&r("bytes=0-0"), 0), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(&r("bytes=0-9,20-29"), 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn test_content_type_and_path() {
+        assert_eq!(content_type(Path::new("subs/en.vtt")), "text/vtt; charset=utf-8");
+        assert_eq!(content_type(Path::new("thumbs/sheet-10x10.webp")), "image/webp");
+        assert_eq!(content_type(Path::new("thumbs/thumb.WEBP")), "image/webp");
+        assert_eq!(content_type(Path::new("video.mp4")), "video/mp4");
+        assert_eq!(content_type(Path::new("orig/noext")), "application/octet-stream");
+
+        let base = Path::new("/data/videos");
+        assert_eq!(resolve_path(base, "B1DE0/orig/My%20clip.mov"), Some(PathBuf::from("/data/videos/B1DE0/orig/My clip.mov")));
+        assert_eq!(resolve_path(base, "B1DE0/../../etc/passwd"), None);
+        assert_eq!(resolve_path(base, "B1DE0/%2e%2e/x"), None);
+        assert_eq!(resolve_path(base, "B1DE0/a%2Fb"), None);
+        assert_eq!(resolve_path(base, ""), None);
+    }
+}
@@ -0,0 +1,74 @@
+// Version stacks: successive versions of the same work (e.g. cut v1, v2, ...), each its own media file.
+// A new version is added by uploading with the `version_of` upload cookie set to the ID of an earlier
+// version, or by an Organizer (`add_media_file_version`). The stack itself is in `media_file_versions`.
+//
+// Comment threads can be carried over from one version to another. They are copied, not moved,
+// so each version keeps its own discussion.
+
+use std::path::Path;
+use anyhow::bail;
+use lib_clapshot_grpc::proto;
+
+use crate::database::{error::DBResult, models, DbBasicQuery, PooledConnection, DB};
+use crate::grpc::datetime_to_proto3;
+
+/// Upload cookie that adds the uploaded file as a new version of the given media file ID
+pub const VERSION_OF_COOKIE: &str = "version_of";
+
+
+/// Convert a version stack to protobuf, with media file titles
+pub fn stack_to_proto(conn: &mut PooledConnection, stack: &[models::MediaFileVersion]) -> DBResult<Vec<proto::MediaFileVersion>>
+{
+    let ids = stack.iter().map(|v| v.media_file_id.clone()).collect::<Vec<_>>();
+    let media_files = models::MediaFile::get_many(conn, &ids)?;
+    Ok(stack.iter().map(|v| proto::MediaFileVersion {
+        media_file_id: v.media_file_id.clone(),
+        stack_id: v.stack_id.clone(),
+        version: v.version as u32,
+        title: media_files.iter().find(|mf| mf.id == v.media_file_id).and_then(|mf| mf.title.clone()),
+        created: Some(datetime_to_proto3(&v.created)),
+    }).collect())
+}
+
+/// Get the version stack of a media file as protobuf (oldest first, empty if not stacked)
+pub fn list_versions(db: &DB, media_file_id: &str) -> DBResult<Vec<proto::MediaFileVersion>>
+{
+    let conn = &mut db.conn()?;
+    let stack = models::MediaFileVersion::get_stack_of(conn, media_file_id)?;
+    stack_to_proto(conn, &stack)
+}
+
+/// Copy comment threads from one version to another in the same stack, along with their drawings.
+/// Threads already on the target are skipped (see `Comment::copy_threads()`).
+///
+/// # Arguments
+/// * `db` - Database
+/// * `media_files_dir` - Media file storage root (for drawing files)
+/// * `from_id` - Media file to copy from
+/// * `to_id` - Media file to copy to
+///
+/// # Returns
+/// * `Vec<models::Comment>` - Comments added to `to_id`
+pub fn carry_over_comments(db: &DB, media_files_dir: &Path, from_id: &str, to_id: &str) -> anyhow::Result<Vec<models::Comment>>
+{
+    let conn = &mut db.conn()?;
+    let stack = models::MediaFileVersion::get_stack_of(conn, from_id)?;
+    if from_id == to_id || !stack.iter().any(|v| v.media_file_id == to_id) {
+        bail!("'{}' and '{}' are not different versions of the same media", from_id, to_id);
+    }
+    let dst = models::MediaFile::get(conn, &to_id.to_string())?;
+    let added = models::Comment::copy_threads(conn, from_id, &dst)?;
+
+    let (src_dir, dst_dir) = (media_files_dir.join(from_id).join("drawings"), media_files_dir.join(to_id).join("drawings"));
+    for fname in added.iter().filter_map(|c| c.drawing.as_ref()).filter(|d| !d.is_empty() && !d.starts_with("data:")) {
+        let (src, dst) = (src_dir.join(fname), dst_dir.join(fname));
+        if !dst.exists() {
+            std::fs::create_dir_all(&dst_dir)?;
+            if let Err(e) = std::fs::copy(&src, &dst) {
+                tracing::warn!(details=%e, src=%src.display(), "Failed to copy drawing to new version.");
+            }
+        }
+    }
+    tracing::info!(from=from_id, to=to_id, n_comments=added.len(), "Carried over comments to another version.");
+    Ok(added)
+}
@@ -0,0 +1,53 @@
+// @mentions in comment text. A mention is `@user_id`, `@name` or `@"Name With Spaces"`,
+// matched against the `users` table by ID first, then by name (case-insensitive).
+// Mentioned users get a persistent message that links to the comment.
+
+use crate::database::{error::DBResult, models, PooledConnection};
+
+/// Find mentions in comment text, in order of appearance, without duplicates.
+/// E-mail addresses (`someone@example.com`) are not mentions.
+pub fn parse_mentions(text: &str) -> Vec<String> {
+    let re = regex::Regex::new(r#"(?:^|[^\w.@])@(?:"([^"\n]+)"|([\w.\-]+))"#).unwrap();
+    let mut res: Vec<String> = Vec::new();
+    for cap in re.captures_iter(text) {
+        let m = match (cap.get(1), cap.get(2)) {
+            (Some(quoted), _) => quoted.as_str().trim(),
+            (None, Some(word)) => word.as_str().trim_end_matches(['.', '-']),   // e.g. "Thanks @alice."
+            _ => continue,
+        };
+        if !m.is_empty() && !res.iter().any(|r| r == m) {
+            res.push(m.to_string());
+        }
+    }
+    res
+}
+
+/// Resolve mentions against known users.
+///
+/// # Returns
+/// * `(Vec<models::User>, Vec<String>)` - Mentioned users (deduplicated), and mentions that didn't match exactly one user
+pub fn resolve_mentions(conn: &mut PooledConnection, mentions: &[String]) -> DBResult<(Vec<models::User>, Vec<String>)> {
+    let (mut users, mut unknown): (Vec<models::User>, Vec<String>) = (vec![], vec![]);
+    for m in mentions {
+        match models::User::find_by_id_or_name(conn, m)? {
+            Some(u) => if !users.iter().any(|x| x.id == u.id) { users.push(u) },
+            None => unknown.push(m.clone()),
+        }
+    }
+    Ok((users, unknown))
+}
+
+/// Users to notify about a new or edited comment: those mentioned in `new_text`, minus
+/// the author and anyone who was already mentioned in `old_text` (so edits don't re-notify).
+///
+/// # Returns
+/// * `(Vec<models::User>, Vec<String>)` - Users to notify, and unknown mentions to report back to the author
+pub fn mentions_to_notify(conn: &mut PooledConnection, new_text: &str, old_text: Option<&str>, author_id: &str) -> DBResult<(Vec<models::User>, Vec<String>)> {
+    let (mut users, unknown) = resolve_mentions(conn, &parse_mentions(new_text))?;
+    let (old_users, _) = match old_text {
+        Some(t) => resolve_mentions(conn, &parse_mentions(t))?,
+        None => (vec![], vec![]),
+    };
+    users.retain(|u| u.id != author_id && !old_users.iter().any(|o| o.id == u.id));
+    Ok((users, unknown))
+}
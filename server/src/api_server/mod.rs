@@ -7,7 +7,7 @@ use lib_clapshot_grpc::GrpcBindAddr;
 use lib_clapshot_grpc::proto;
 use lib_clapshot_grpc::proto::org::OnStartUserSessionResponse;
 use tracing::debug;
-use warp::Filter;
+use warp::{Filter, Reply};
 use core::panic;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -38,13 +38,29 @@ pub mod test_utils;
 pub mod tests;
 mod file_upload;
 use file_upload::handle_multipart_upload;
+mod tus_upload;
+mod admin_api;
+pub mod auth;
+use auth::{with_auth, AuthUser};
+pub mod share_links;
+pub mod media_access;
+pub mod media_serving;
+pub mod comment_export;
+pub mod comment_import;
+pub mod media_versions;
+pub mod mentions;
+pub mod webhooks;
+pub mod email_notifier;
+use share_links::{ShareAccess, ShareScope};
+use admin_api::{handle_list_jobs, handle_list_webhook_deliveries};
 use crate::api_server::user_session::AuthzTopic;
-use crate::api_server::user_session::org_authz;
+use crate::api_server::user_session::{org_authz, OrgSessionConn};
 use crate::client_cmd;
 use crate::database::DbBasicQuery;
 use crate::database::models;
 use crate::grpc::db_models::proto_msg_type_to_event_name;
 use crate::grpc::grpc_client::OrganizerConnection;
+use crate::grpc::org_events::{run_org_event_relay, OrgEvent};
 use crate::grpc::grpc_client::OrganizerURI;
 use crate::grpc::{grpc_server, make_media_file_popup_actions};
 use crate::api_server::ws_handers::SessionClose;
@@ -95,6 +111,7 @@ async fn handle_ws_session(
         username: String,
         is_admin: bool,
         cookies: HashMap<String, String>,
+        share: Option<ShareScope>,
         server: ServerState)
 {
     let (msgq_tx, mut msgq_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -119,7 +136,8 @@ async fn handle_ws_session(
         cur_collab_id: None,
         media_session_guard: None,
         collab_session_guard: None,
-        organizer: None,
+        organizers: vec![],
+        share: share.clone(),
         org_session: proto::org::UserSessionData {
             sid: sid.clone(),
             user: Some(proto::UserInfo {
@@ -173,23 +191,24 @@ async fn handle_ws_session(
         return;
     }
 
-    // Tell organizer about this new user session
-    if let Some(uri) = server.organizer_uri.clone() {
-        match connect_organizer(uri, &ses.org_session).await {
+    // Share link sessions only see the shared media file -- no Organizer, no navigation
+    if let Some(scope) = &share {
+        let req = proto::client::client_to_server_cmd::OpenMediaFile { media_file_id: scope.media_file_id.clone() };
+        if let Err(e) = ws_handers::msg_open_media_file(&req, &mut ses, &server).await {
+            tracing::error!(details=%e, "Error opening shared media file. Closing session.");
+            return;
+        }
+    }
+
+    // Tell organizers about this new user session
+    for plugin in server.organizers.iter().filter(|_| share.is_none()) {
+        match connect_organizer(plugin.uri.clone(), &ses.org_session).await {
             Ok((c, _res)) => {
-                ses.organizer = Some(tokio::sync::Mutex::new(c).into());
-                let op = AuthzTopic::Other(None, proto::org::authz_user_action_request::other_op::Op::Login);
-                if org_authz(&ses.org_session, "login", true, &server, &ses.organizer, op).await == Some(false) {
-                    tracing::info!("User '{}' not authorized to login. Closing session.", ses.user_id);
-                    server.emit_cmd(
-                        client_cmd!(Error, {msg: "Login permission denied.".into()}),
-                        SendTo::MsgSender(&ses.sender)).ok();
-                    return;
-                }
+                ses.organizers.push(OrgSessionConn { roles: plugin.roles, conn: tokio::sync::Mutex::new(c).into() });
             },
             Err(e) => {
                 const MSG: &str = "Error connecting to Organizer. Closing session.";
-                tracing::error!(details=%e, MSG);
+                tracing::error!(details=%e, org=?plugin.uri, MSG);
                 server.emit_cmd(
                     client_cmd!(Error, {msg: MSG.into()}),
                     SendTo::MsgSender(&ses.sender)).ok();
@@ -197,6 +216,17 @@ async fn handle_ws_session(
             }
         }
     };
+    if let Some(mut reg) = server.get_session_write(&sid) {
+        reg.organizers = ses.organizers.clone();   // For authorizing `/videos` requests (see `media_access`)
+    }
+    let op = AuthzTopic::Other(None, proto::org::authz_user_action_request::other_op::Op::Login);
+    if share.is_none() && org_authz(&ses.org_session, "login", true, &server, &ses.organizers, op).await == Some(false) {
+        tracing::info!("User '{}' not authorized to login. Closing session.", ses.user_id);
+        server.emit_cmd(
+            client_cmd!(Error, {msg: "Login permission denied.".into()}),
+            SendTo::MsgSender(&ses.sender)).ok();
+        return;
+    }
 
     loop
     {
@@ -278,7 +308,11 @@ async fn handle_ws_session(
                                         Ok(false) => { break; }     // Session closed
                                         Err(e) => {
                                             if let Some(e) = e.downcast_ref::<SessionClose>() {
-                                                if !matches!(e, SessionClose::Logout) { tracing::debug!("[{}] Closing session: {:?}", sid, e); }
+                                                if !matches!(e, SessionClose::Logout) {
+                                                    tracing::debug!("[{}] Closing session: {:?}", sid, e);
+                                                    // Deliver pending error messages before closing
+                                                    while let Ok(m) = msgq_rx.try_recv() { ws_tx.send(m).await.ok(); }
+                                                }
                                                 break;
                                             } else if let Some(e) = e.downcast_ref::<tokio::sync::mpsc::error::SendError<Message>>() {
                                                 tracing::error!("[{}] Error sending message. Closing session. -- {}", sid, e);
@@ -335,17 +369,6 @@ async fn handle_ws_session(
 /// * Returns: (user_id: String, user_name: String, is_admin: bool, clapshot_cookies: HashMap<String, String>)
 fn parse_auth_headers(hdrs: &HeaderMap, default_user_id: &str) -> (String, String, bool, HashMap<String, String>)
 {
-    fn try_get_first_named_hdr<T>(hdrs: &HeaderMap, names: T) -> Option<String>
-        where T: IntoIterator<Item=&'static str> {
-        for n in names {
-            if let Some(val) = hdrs.get(n).or(hdrs.get(n.to_lowercase())) {
-                match val.to_str() {
-                    Ok(s) => return Some(s.into()),
-                    Err(e) => tracing::warn!(details=%e, "Error parsing header '{}'.", n),
-        }}}
-        None
-    }
-
     let user_id = match try_get_first_named_hdr(&hdrs, vec!["X-Remote-User-Id", "X_Remote_User_Id", "HTTP_X_REMOTE_USER_ID"]) {
         Some(id) => id,
         None => {
@@ -355,13 +378,31 @@ fn parse_auth_headers(hdrs: &HeaderMap, default_user_id: &str) -> (String, Strin
     let user_name = try_get_first_named_hdr(&hdrs, vec!["X-Remote-User-Name", "X_Remote_User_Name", "HTTP_X_REMOTE_USER_NAME"])
         .unwrap_or_else(|| user_id.clone());
 
-    let cookies_str = try_get_first_named_hdr(&hdrs, vec!["X-Clapshot-Cookies", "X_Clapshot_Cookies", "HTTP_X_CLAPSHOT_COOKIES"])
-        .unwrap_or_else(|| "{}".into());
-
     let is_admin: bool = try_get_first_named_hdr(&hdrs, vec!["X-Remote-User-Is-Admin", "X_Remote_User_Is_Admin", "HTTP_X_REMOTE_USER_IS_ADMIN"])
         .map(|s| s.to_lowercase() == "true" || s == "1").unwrap_or(user_id == "admin");
 
-    let app_cookies = match cookies_str.parse::<serde_json::Value>() {
+    (user_id, user_name, is_admin, parse_app_cookies(hdrs))
+}
+
+fn try_get_first_named_hdr<T>(hdrs: &HeaderMap, names: T) -> Option<String>
+    where T: IntoIterator<Item=&'static str> {
+    for n in names {
+        if let Some(val) = hdrs.get(n).or(hdrs.get(n.to_lowercase())) {
+            match val.to_str() {
+                Ok(s) => return Some(s.into()),
+                Err(e) => tracing::warn!(details=%e, "Error parsing header '{}'.", n),
+    }}}
+    None
+}
+
+/// Parse Clapshot app cookies (JSON dict in X-Clapshot-Cookies header, set by client).
+/// These are passed to Organizer, and are independent of the authentication method.
+fn parse_app_cookies(hdrs: &HeaderMap) -> HashMap<String, String>
+{
+    let cookies_str = try_get_first_named_hdr(hdrs, vec!["X-Clapshot-Cookies", "X_Clapshot_Cookies", "HTTP_X_CLAPSHOT_COOKIES"])
+        .unwrap_or_else(|| "{}".into());
+
+    match cookies_str.parse::<serde_json::Value>() {
         Ok(c) => {
             match c.as_object() {
                 Some(c) => c.iter().map(|(k, v)| (k.clone(), v.as_str().unwrap_or("<!ERROR: NON-STRING COOKIE VALUE!>").to_string())).collect(),
@@ -375,9 +416,7 @@ fn parse_auth_headers(hdrs: &HeaderMap, default_user_id: &str) -> (String, Strin
             tracing::error!("Error parsing 'clapshot_cookies' JSON: {}", e);
             HashMap::new()
         }
-    };
-
-    (user_id, user_name, is_admin, app_cookies)
+    }
 }
 
 /// Handle HTTP requests, read authentication headers and dispatch to WebSocket handler.
@@ -386,6 +425,7 @@ async fn run_api_server_async(
     cors_origins: Vec<String>,
     server_state: ServerState,
     user_msg_rx: crossbeam_channel::Receiver<UserMessage>,
+    org_event_rx: Option<crossbeam_channel::Receiver<OrgEvent>>,
     upload_results_tx: crossbeam_channel::Sender<IncomingFile>,
     grpc_server_bind: Option<GrpcBindAddr>,
     port: u16)
@@ -394,6 +434,7 @@ async fn run_api_server_async(
     let server_state_cln1 = server_state.clone();
     let server_state_cln2 = server_state.clone();
     let server_state_cln3 = server_state.clone();
+    let server_state_cln4 = server_state.clone();
 
     let url_base = server_state.url_base.clone();
 
@@ -420,14 +461,16 @@ async fn run_api_server_async(
                 wait_time = std::cmp::min(wait_time * 2, Duration::from_secs(4));
                 if server.terminate_flag.load(Relaxed) { return; }
             }
-            if let Some(org_info) = server.organizer_info.lock().await.as_ref() {
+            let org_infos = server.organizer_info.lock().await;
+            if org_infos.is_empty() {
+                panic!("Organizer connected, but no info received. This is a bug in server code.");
+            }
+            for org_info in org_infos.iter() {
                 tracing::info!(
                     org_name = &org_info.name,
                     description = &org_info.description,
                     version = org_info.version.as_ref().map(|v| format!("{}.{}.{}", v.major, v.minor, v.patch)),
                     "org->srv connected, bidirectional gRPC established.");
-            } else {
-                panic!("Organizer connected, but no info received. This is a bug in server code.");
             }
             Some(hdl)
         },
@@ -439,27 +482,82 @@ async fn run_api_server_async(
     let rt_health = warp::path("api").and(warp::path("health")).map(|| "I'm alive!");
 
     let upload_dir = server_state.upload_dir.clone();
+    let upload_results_tx_tus = upload_results_tx.clone();
     let rt_upload = warp::path("api").and(warp::path("upload"))
         .and(warp::post())
         .and(warp::any().map(move || upload_dir.clone()))
         .and(warp::any().map(move || upload_results_tx.clone()))
         .and(warp::header::<mime::Mime>("content-type"))
         .and(warp::header::headers_cloned())
+        .and(with_auth(server_state.clone()))
         .and(warp::any().map(move || server_state_cln3.clone()))
         .and(warp::body::stream())
         .and_then(handle_multipart_upload);
 
-    let rt_videos = warp::path("videos").and(
-        warp::fs::dir(server_state_cln1.media_files_dir.clone())
-            .with(warp::log("videos")));
+    // Resumable uploads (tus protocol)
+    let tus_base = warp::path("api").and(warp::path("upload")).and(warp::path("tus"));
+    let with_server = { let s = server_state.clone(); warp::any().map(move || s.clone()) };
+    let with_user = with_auth(server_state.clone());
+    let rt_tus = tus_base.and(warp::path::end()).and(warp::options())
+            .and_then(tus_upload::handle_tus_options)
+        .or(tus_base.and(warp::path::end()).and(warp::post())
+            .and(warp::header::headers_cloned()).and(with_user.clone()).and(with_server.clone())
+            .and_then(tus_upload::handle_tus_create))
+        .or(tus_base.and(warp::path::param::<String>()).and(warp::path::end()).and(warp::head())
+            .and(warp::header::headers_cloned()).and(with_user.clone()).and(with_server.clone())
+            .and_then(tus_upload::handle_tus_head))
+        .or(tus_base.and(warp::path::param::<String>()).and(warp::path::end()).and(warp::patch())
+            .and(warp::header::headers_cloned()).and(with_user.clone()).and(with_server.clone())
+            .and(warp::any().map(move || upload_results_tx_tus.clone()))
+            .and(warp::body::stream())
+            .and_then(tus_upload::handle_tus_patch))
+        .or(tus_base.and(warp::path::param::<String>()).and(warp::path::end()).and(warp::delete())
+            .and(warp::header::headers_cloned()).and(with_user.clone()).and(with_server.clone())
+            .and_then(tus_upload::handle_tus_delete));
+
+    let rt_admin_jobs = warp::path!("api" / "admin" / "jobs")
+        .and(warp::get())
+        .and(with_auth(server_state.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::any().map(move || server_state_cln4.clone()))
+        .and_then(handle_list_jobs);
+
+    let rt_admin_webhooks = warp::path!("api" / "admin" / "webhooks")
+        .and(warp::get())
+        .and(with_auth(server_state.clone()))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(with_server.clone())
+        .and_then(handle_list_webhook_deliveries);
+
+    let rt_videos = warp::path("videos")
+        .and(warp::get().or(warp::head()).unify())
+        .and(media_access::check_videos_access(server_state.clone()))
+        .and(warp::path::tail())
+        .and(warp::method())
+        .and(warp::header::headers_cloned())
+        .and(with_server.clone())
+        .and_then(|set_cookie, tail, method, hdrs, server| media_serving::serve_media_file(tail, method, hdrs, set_cookie, server))
+        .with(warp::log("videos"));
 
-    let rt_api_ws = warp::path("api").and(warp::path("ws"))
+    let rt_comment_export = warp::path!("api" / "media" / String / "comments" / "export")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
         .and(warp::header::headers_cloned())
+        .and(with_server.clone())
+        .and_then(comment_export::handle_export_comments);
+
+    // Session cookies (login/logout), for browsers that can't send bearer tokens on WebSocket connections
+    let rt_auth_session = warp::path!("api" / "auth" / "session")
+        .and(warp::post().and(with_auth(server_state.clone())).and(with_server.clone()).and_then(auth::handle_create_session)
+            .or(warp::delete().and_then(auth::handle_delete_session)));
+
+    let rt_api_ws = warp::path("api").and(warp::path("ws"))
+        .and(share_links::with_share_or_auth(server_state.clone()))
         .and(warp::ws())
-        .map (move|hdrs: HeaderMap, ws: warp::ws::Ws| {
+        .map (move|(user, share): (AuthUser, Option<ShareAccess>), ws: warp::ws::Ws| {
 
-            // Get user ID and username (from reverse proxy)
-            let (user_id, user_name, is_admin, app_cookies) = parse_auth_headers(&hdrs, &server_state.default_user);
+            // Get user ID and username (from share link, bearer token, session cookie or reverse proxy)
+            let AuthUser { user_id, user_name, is_admin, cookies: app_cookies } = user;
 
             // Increment session counter
             let sid = {
@@ -470,18 +568,29 @@ async fn run_api_server_async(
 
             let server_state = server_state.clone();
             let is_admin = is_admin.clone();
-            ws.on_upgrade(move |ws| async move {
+            let share_cookies = share.as_ref().map(|s| share_links::share_cookies(&server_state.auth.link_secret, s));
+            let share = share.map(|s| ShareScope::from(&s.link));
+            let reply = ws.on_upgrade(move |ws| async move {
                 // Diesel SQLite calls are blocking, so run a thread per user session
                 // even though we're using async/await
                 tokio::task::spawn_blocking(move || {
                     let _span = tracing::info_span!("ws_session", sid=%sid, user=%user_id).entered();
-                    block_on(handle_ws_session(ws, sid, user_id, user_name, is_admin, app_cookies, server_state));
+                    block_on(handle_ws_session(ws, sid, user_id, user_name, is_admin, app_cookies, share, server_state));
                 }).await.unwrap_or_else(|e| {
                     tracing::error!(details=%e, "Error joining handle_ws_session thread."); });
-            })
+            });
+            // Let share link users' browsers fetch the shared media file, and remember the guest
+            let mut res = reply.into_response();
+            for c in share_cookies.into_iter().flatten() {
+                if let Ok(v) = warp::http::HeaderValue::from_str(&c) {
+                    res.headers_mut().append(warp::http::header::SET_COOKIE, v);
+                }
+            }
+            res
         });
 
-    let routes = rt_health.or(rt_api_ws).or(rt_upload).or(rt_videos)
+    let routes = rt_health.or(rt_api_ws).or(rt_auth_session).or(rt_tus).or(rt_upload).or(rt_admin_jobs).or(rt_admin_webhooks).or(rt_videos).or(rt_comment_export)
+        .recover(auth::handle_rejection)
         .with(warp::log("api_server"));
 
 
@@ -491,8 +600,10 @@ async fn run_api_server_async(
         .collect();
     tracing::info!("Allowed CORS origins: {:?}", cors_origins);
 
-    let cors_methods = ["GET", "POST", "HEAD", "OPTIONS"];
-    let cors_headers = ["x-file-name", "x-clapshot-cookies", "content-type", "upgrade", "sec-websocket-protocol", "sec-websocket-version"];
+    let cors_methods = ["GET", "POST", "HEAD", "OPTIONS", "PATCH", "DELETE"];
+    let cors_headers = ["x-file-name", "x-clapshot-cookies", "content-type", "authorization", "upgrade", "sec-websocket-protocol", "sec-websocket-version",
+        "tus-resumable", "upload-length", "upload-offset", "upload-metadata"];
+    let cors_expose = ["location", "tus-resumable", "tus-version", "tus-extension", "upload-offset", "upload-length", "upload-expires"];
 
     let routes = if cors_origins.contains(&"*") {
         tracing::warn!(concat!(
@@ -501,7 +612,7 @@ async fn run_api_server_async(
             "Do NOT use '*' in production! ",
             "Instead, specify the allowed origin, such as 'https://clapshot.example.com'."
         ));
-        routes.with(warp::cors().allow_methods(cors_methods).allow_headers(cors_headers)
+        routes.with(warp::cors().allow_methods(cors_methods).allow_headers(cors_headers).expose_headers(cors_expose)
             .allow_any_origin()).boxed()
     } else {
         if cors_origins.is_empty() {
@@ -510,7 +621,7 @@ async fn run_api_server_async(
         } else {
             tracing::info!("Using CORS origins: {:?}", cors_origins);
         }
-        routes.with(warp::cors().allow_methods(cors_methods).allow_headers(cors_headers)
+        routes.with(warp::cors().allow_methods(cors_methods).allow_headers(cors_headers).expose_headers(cors_expose)
             .allow_origins(cors_origins)).boxed()
     };
 
@@ -522,6 +633,53 @@ async fn run_api_server_async(
             }
         });
 
+    // Relay event hooks to Organizers, if any want them
+    let org_event_relay = {
+        let server_state = server_state_cln2.clone();
+        async move {
+            if let Some(rx) = org_event_rx {
+                run_org_event_relay(server_state, rx).await;
+            }
+        }
+    };
+
+    // Send queued webhook deliveries, if webhooks are configured
+    let webhook_sender = {
+        let server_state = server_state_cln2.clone();
+        async move {
+            if !server_state.webhooks.is_empty() {
+                webhooks::run_webhook_sender(server_state).await;
+            }
+        }
+    };
+
+    // Mail unseen messages to users who have e-mail notifications on
+    let email_notifier = email_notifier::run_email_notifier(server_state_cln2.clone());
+
+    // Remove abandoned partial (resumable) uploads
+    let server_state = server_state_cln2.clone();
+    let upload_expirer = async move {
+        let (upload_dir, pending) = (server_state.upload_dir.clone(), server_state.upload_limits.pending.clone());
+        tokio::task::spawn_blocking(move || tus_upload::reserve_partial_uploads(&upload_dir, &pending)).await.ok();
+        let mut last_check: Option<std::time::Instant> = None;
+        while !server_state.terminate_flag.load(Relaxed) {
+            if last_check.is_none_or(|t| t.elapsed() > tus_upload::EXPIRY_CHECK_INTERVAL) {
+                let (upload_dir, pending) = (server_state.upload_dir.clone(), server_state.upload_limits.pending.clone());
+                tokio::task::spawn_blocking(move || tus_upload::expire_abandoned_uploads(&upload_dir, &pending, chrono::Utc::now())).await.ok();
+                let db = server_state.db.clone();
+                tokio::task::spawn_blocking(move || {
+                    match db.conn().and_then(|mut conn| models::ShareLink::delete_expired_before(&mut conn, chrono::Utc::now().naive_utc() - chrono::Duration::days(7))) {
+                        Ok(n) if n > 0 => tracing::info!("Deleted {} expired share links.", n),
+                        Ok(_) => {},
+                        Err(e) => tracing::error!(details=%e, "Error deleting expired share links."),
+                    }
+                }).await.ok();
+                last_check = Some(std::time::Instant::now());
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    };
+
     let server_state = server_state_cln2;
     let msg_relay = async move {
         while !server_state.terminate_flag.load(Relaxed) {
@@ -582,7 +740,7 @@ async fn run_api_server_async(
     tracing::info!("API server started Ok, waiting for clients.");
 
     // Start API server + message relay and wait for them to exit
-    tokio::join!(server, msg_relay);
+    tokio::join!(server, msg_relay, upload_expirer, org_event_relay, webhook_sender, email_notifier);
 
     // Wait for gRPC server to exit
     if let Some(g) = grpc_server {
@@ -601,6 +759,7 @@ async fn run_api_server_async(
 #[tokio::main]
 pub async fn run_forever(
     user_msg_rx: crossbeam_channel::Receiver<UserMessage>,
+    org_event_rx: Option<crossbeam_channel::Receiver<OrgEvent>>,
     grpc_server_bind: Option<GrpcBindAddr>,
     upload_res_tx: crossbeam_channel::Sender<IncomingFile>,
     bind_addr: String,
@@ -620,5 +779,5 @@ pub async fn run_forever(
     };
 
     let _span = tracing::info_span!("API").entered();
-    run_api_server_async(bind_addr, cors_origins, state, user_msg_rx, upload_res_tx, grpc_server_bind, port).await;
+    run_api_server_async(bind_addr, cors_origins, state, user_msg_rx, org_event_rx, upload_res_tx, grpc_server_bind, port).await;
 }
@@ -14,7 +14,14 @@ use super::user_session::OpaqueGuard;
 use super::{WsMsgSender, SenderList, SessionMap, SenderListMap, StringToStringMap, Res, UserSession, SendTo};
 use crate::client_cmd;
 use crate::database::{DB, models, DbBasicQuery};
-use crate::grpc::grpc_client::OrganizerURI;
+use crate::grpc::grpc_client::OrganizerPlugin;
+use crate::grpc::org_events::{self, OrgEvent};
+use crate::video_pipeline::PipelineCmd;
+use crate::video_pipeline::upload_limits::UploadLimits;
+use super::auth::AuthConfig;
+use super::media_serving::FileOffload;
+use super::webhooks::Webhooks;
+use super::email_notifier::EmailConfig;
 use lib_clapshot_grpc::proto;
 
 /// Lists of all active connections and other server state vars
@@ -25,8 +32,13 @@ pub struct ServerState {
     pub db: Arc<DB>,
     pub media_files_dir: PathBuf,
     pub upload_dir: PathBuf,
+    pub upload_expiry: chrono::Duration,    // Abandoned resumable uploads are deleted after this
+    pub upload_limits: UploadLimits,
+    pub auth: AuthConfig,
+    pub file_offload: FileOffload,          // Let reverse proxy transfer media files
     pub url_base: String,
     pub default_user: String,
+    pub pipeline_tx: crossbeam_channel::Sender<PipelineCmd>,  // Cancel / re-run media processing
 
     sid_to_session: SessionMap,
     user_id_to_senders: SenderListMap,
@@ -34,9 +46,12 @@ pub struct ServerState {
     collab_id_to_senders: SenderListMap,
     collab_id_to_media_file_id: StringToStringMap,
 
-    pub organizer_uri: Option<OrganizerURI>,
-    pub organizer_has_connected: Arc<AtomicBool>,
-    pub organizer_info: Arc<Mutex<Option<OrganizerInfo>>>
+    pub organizers: Vec<OrganizerPlugin>,
+    pub org_event_tx: Option<crossbeam_channel::Sender<OrgEvent>>,  // Some if any Organizer wants event hooks, or webhooks are configured
+    pub webhooks: Arc<Webhooks>,
+    pub email: Option<EmailConfig>,     // SMTP config, if e-mail notifications are enabled
+    pub organizer_has_connected: Arc<AtomicBool>,   // All organizers have handshaked back
+    pub organizer_info: Arc<Mutex<Vec<OrganizerInfo>>>
 }
 
 impl ServerState {
@@ -45,28 +60,44 @@ impl ServerState {
         db: Arc<DB>,
         media_files_dir: &Path,
         upload_dir: &Path,
+        upload_expiry: chrono::Duration,
+        upload_limits: UploadLimits,
+        auth: AuthConfig,
+        file_offload: FileOffload,
         url_base: &str,
-        organizer_uri: Option<OrganizerURI>,
+        organizers: Vec<OrganizerPlugin>,
+        org_event_tx: Option<crossbeam_channel::Sender<OrgEvent>>,
+        webhooks: Webhooks,
+        email: Option<EmailConfig>,
         grpc_srv_listening_flag: Arc<AtomicBool>,
         default_user: String,
-        terminate_flag: Arc<AtomicBool>) -> ServerState
+        terminate_flag: Arc<AtomicBool>,
+        pipeline_tx: crossbeam_channel::Sender<PipelineCmd>) -> ServerState
     {
         ServerState {
             db,
             media_files_dir: media_files_dir.to_path_buf(),
             upload_dir: upload_dir.to_path_buf(),
+            upload_expiry,
+            upload_limits,
+            auth,
+            file_offload,
             grpc_srv_listening_flag,
             terminate_flag,
             url_base: url_base.to_string(),
             default_user,
+            pipeline_tx,
             sid_to_session: Arc::new(RwLock::new(HashMap::<String, UserSession>::new())),
             user_id_to_senders: Arc::new(RwLock::new(HashMap::<String, SenderList>::new())),
             media_file_id_to_senders: Arc::new(RwLock::new(HashMap::<String, SenderList>::new())),
             collab_id_to_senders: Arc::new(RwLock::new(HashMap::<String, SenderList>::new())),
             collab_id_to_media_file_id: Arc::new(RwLock::new(HashMap::<String, String>::new())),
-            organizer_uri,
+            organizers,
+            org_event_tx,
+            webhooks: Arc::new(webhooks),
+            email,
             organizer_has_connected: Arc::new(AtomicBool::new(false)),
-            organizer_info: Arc::new(Mutex::new(None)),
+            organizer_info: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -88,6 +119,16 @@ impl ServerState {
             None
         }
     }
+    /// Get a copy of any open session of a user, if they have one.
+    pub fn find_user_session(&self, user_id: &str) -> Option<UserSession> {
+        self.sid_to_session.read().values().find(|s| s.user_id == user_id).cloned()
+    }
+
+    /// Make a signed token for `/videos/<media_file_id>/` URLs (see `media_access`)
+    pub fn media_url_token(&self, media_file_id: &str) -> String {
+        super::media_access::make_url_token(&self.auth.link_secret, media_file_id, chrono::Utc::now() + super::media_access::media_url_ttl())
+    }
+
     /// Register a new sender (API connection) for a user_id. One user can have multiple connections.
     /// Returns a guard that will remove the sender when dropped.
     pub fn register_user_session(&self, sid: &str, user_id: &str, ses: UserSession) -> OpaqueGuard {
@@ -107,6 +148,11 @@ impl ServerState {
         }
     }
 
+    /// Notify Organizers with the `events` role (if any) about something that happened
+    pub fn emit_org_event(&self, ev: OrgEvent) {
+        org_events::emit(&self.org_event_tx, ev);
+    }
+
     /// Send a client command to websocket of given recipient(s)
     pub fn emit_cmd(&self, cmd: proto::client::server_to_client_cmd::Cmd, send_to: SendTo) -> Res<u32>
     {
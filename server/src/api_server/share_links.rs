@@ -0,0 +1,326 @@
+// Time-limited public share links for external reviewers.
+// A share link gives access to a single media file -- view only, or view and comment -- without
+// a user account. The token is signed with the server's link key (`AuthConfig::link_secret`) and
+// refers to a row in `share_links`, which is checked on every use for revocation and expiry.
+// Share links open a restricted WebSocket session (see `share_session_allows()`) and
+// authorize `/videos/<media_file_id>/...` requests (see `media_access`), by `?share=<token>` or a path-scoped cookie.
+// Each guest gets their own pseudo-user `share.<link id>.<guest id>`. The guest ID is kept in a signed cookie,
+// so a returning guest can still edit their comments, but nobody can claim another guest's ID.
+
+use std::collections::HashMap;
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+use warp::http::HeaderMap;
+use warp::{Filter, Rejection};
+use lib_clapshot_grpc::proto;
+
+use crate::database::{error::DBError, models, DbBasicQuery, DbQueryByMediaFile, DBPaging, DB};
+use super::auth::{sign_claims, verify_claims, AuthUser};
+use super::server_state::ServerState;
+
+pub const SHARE_COOKIE: &str = "clapshot_share";
+pub const GUEST_COOKIE: &str = "clapshot_guest";
+pub const DEFAULT_VALID_HOURS: u32 = 72;
+pub const MAX_VALID_HOURS: u32 = 24 * 90;
+
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ShareLinkError {
+    #[error("Invalid share link")]
+    Invalid,
+    #[error("Share link has expired")]
+    Expired,
+    #[error("Share link has been revoked")]
+    Revoked,
+    #[error("Share link is for another media file")]
+    WrongMediaFile,
+}
+
+impl warp::reject::Reject for ShareLinkError {}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ShareClaims {
+    id: String,
+    mf: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GuestClaims {
+    link: String,
+    guest: String,
+}
+
+/// What a share link session is allowed to access
+#[derive(Debug, Clone)]
+pub struct ShareScope {
+    pub link_id: String,
+    pub media_file_id: String,
+    pub can_comment: bool,
+}
+
+impl From<&models::ShareLink> for ShareScope {
+    fn from(link: &models::ShareLink) -> Self {
+        ShareScope {
+            link_id: link.id.clone(),
+            media_file_id: link.media_file_id.clone(),
+            can_comment: link.permission == models::ShareLink::COMMENT,
+        }
+    }
+}
+
+/// Validated share link from a request, the token it came with, and the guest's ID
+#[derive(Debug, Clone)]
+pub struct ShareAccess {
+    pub link: models::ShareLink,
+    pub token: String,
+    pub guest_id: String,
+}
+
+
+/// Make signed access token for a share link
+pub fn make_token(secret: &[u8], link: &models::ShareLink) -> String {
+    sign_claims(secret, &ShareClaims { id: link.id.clone(), mf: link.media_file_id.clone() })
+}
+
+/// Convert share link to protobuf, with a freshly signed token and URL
+pub fn link_to_proto(server: &ServerState, link: &models::ShareLink) -> proto::ShareLink {
+    link.to_proto3(&server.url_base, make_token(&server.auth.link_secret, link))
+}
+
+/// Check token signature, and that the link it refers to exists and is active.
+///
+/// # Arguments
+/// * `db` - Database
+/// * `secret` - Link signing key
+/// * `token` - Token from the client
+/// * `now` - Current UTC time
+pub fn validate_token(db: &DB, secret: &[u8], token: &str, now: chrono::NaiveDateTime) -> Result<models::ShareLink, ShareLinkError>
+{
+    let claims: ShareClaims = verify_claims(secret, token).ok_or(ShareLinkError::Invalid)?;
+    let link = db.conn()
+        .and_then(|mut conn| models::ShareLink::get(&mut conn, &claims.id))
+        .map_err(|e| {
+            if !matches!(e, DBError::NotFound()) { tracing::error!(details=%e, "DB error while checking share link."); }
+            ShareLinkError::Invalid
+        })?;
+    if link.media_file_id != claims.mf {
+        return Err(ShareLinkError::Invalid);
+    }
+    if link.revoked.is_some() {
+        return Err(ShareLinkError::Revoked);
+    }
+    if !link.is_active(now) {
+        return Err(ShareLinkError::Expired);
+    }
+    Ok(link)
+}
+
+impl ShareScope {
+
+    /// Check that the link hasn't been revoked or expired since the session started
+    pub fn check(&self, db: &DB) -> Result<(), ShareLinkError> {
+        let link = db.conn()
+            .and_then(|mut conn| models::ShareLink::get(&mut conn, &self.link_id))
+            .map_err(|_| ShareLinkError::Invalid)?;
+        if link.revoked.is_some() { return Err(ShareLinkError::Revoked); }
+        if !link.is_active(chrono::Utc::now().naive_utc()) { return Err(ShareLinkError::Expired); }
+        Ok(())
+    }
+}
+
+/// Can a share link session run this command?
+/// Sessions are limited to watching (and optionally commenting) the shared media file.
+pub fn share_session_allows(cmd: &proto::client::client_to_server_cmd::Cmd, scope: &ShareScope, db: &DB) -> bool {
+    use proto::client::client_to_server_cmd::Cmd;
+    let on_shared_file = |comment_id: &str| comment_id.parse::<i32>().ok()
+        .and_then(|id| db.conn().and_then(|mut conn| models::Comment::get(&mut conn, &id)).ok())
+        .is_some_and(|c| c.media_file_id == scope.media_file_id);
+    match cmd {
+        Cmd::OpenMediaFile(d) => d.media_file_id == scope.media_file_id,
+        Cmd::JoinCollab(d) => d.media_file_id == scope.media_file_id,
+        Cmd::AddComment(d) => scope.can_comment && d.media_file_id == scope.media_file_id,
+        // Own comments only, by default permissions
+        Cmd::EditComment(d) => scope.can_comment && on_shared_file(&d.comment_id),
+        Cmd::DelComment(d) => scope.can_comment && on_shared_file(&d.comment_id),
+        Cmd::ListMyMessages(_) | Cmd::LeaveCollab(_) | Cmd::CollabReport(_) | Cmd::Logout(_) => true,
+        _ => false,
+    }
+}
+
+/// Create a new share link. Authorization is up to the caller.
+///
+/// # Arguments
+/// * `server` - Server state
+/// * `media_file_id` - Media file to share
+/// * `perm` - What the link allows
+/// * `valid_hours` - Validity period, or None for `DEFAULT_VALID_HOURS`
+/// * `created_by` - User ID of the creator
+pub fn create_share_link(server: &ServerState, media_file_id: &str, perm: proto::ShareLinkPermission, valid_hours: Option<u32>, created_by: &str) -> anyhow::Result<models::ShareLink>
+{
+    let hours = valid_hours.unwrap_or(DEFAULT_VALID_HOURS);
+    if hours == 0 || hours > MAX_VALID_HOURS {
+        bail!("Share link validity must be 1..{} hours", MAX_VALID_HOURS);
+    }
+    let conn = &mut server.db.conn()?;
+    models::MediaFile::get(conn, &media_file_id.to_string()).map_err(|_| anyhow!("No such media file"))?;
+    let link = models::ShareLink::insert(conn, &models::ShareLinkInsert {
+        id: uuid::Uuid::new_v4().simple().to_string(),
+        media_file_id: media_file_id.into(),
+        created_by: created_by.into(),
+        permission: match perm {
+            proto::ShareLinkPermission::View => models::ShareLink::VIEW,
+            proto::ShareLinkPermission::ViewAndComment => models::ShareLink::COMMENT,
+        }.into(),
+        expires: chrono::Utc::now().naive_utc() + chrono::Duration::hours(hours as i64),
+    })?;
+    tracing::info!(link=%link.id, media_file=%media_file_id, user=%created_by, perm=%link.permission, hours, "Share link created.");
+    Ok(link)
+}
+
+/// Get share links of a media file that haven't expired yet (including revoked ones)
+pub fn list_share_links(server: &ServerState, media_file_id: &str) -> anyhow::Result<Vec<proto::ShareLink>> {
+    let now = chrono::Utc::now().naive_utc();
+    Ok(models::ShareLink::get_by_media_file(&mut server.db.conn()?, media_file_id, DBPaging::default())?
+        .iter().filter(|l| l.expires > now).map(|l| link_to_proto(server, l)).collect())
+}
+
+
+/// Get guest ID from a signed guest cookie, if it's for this link, or make a new one
+fn guest_id(secret: &[u8], link: &models::ShareLink, cookie: Option<&str>) -> String {
+    cookie.and_then(|c| verify_claims::<GuestClaims>(secret, c))
+        .filter(|c| c.link == link.id)
+        .map(|c| c.guest)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()[..16].to_string())
+}
+
+/// Warp filter for the WebSocket route: authenticate with a share token (`?share=<token>`), if given,
+/// or normally (see `auth::with_auth`) otherwise. Share sessions get a pseudo-user per guest (see `GUEST_COOKIE`),
+/// with display name from `?name=`. The name isn't verified, so it's tagged with the start of the guest ID.
+pub fn with_share_or_auth(server: ServerState) -> impl Filter<Extract=((AuthUser, Option<ShareAccess>),), Error=Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .and(warp::header::headers_cloned())
+        .and(warp::any().map(move || server.clone()))
+        .and_then(|query: HashMap<String, String>, hdrs: HeaderMap, server: ServerState| async move {
+            match query.get("share") {
+                Some(token) => {
+                    let link = validate_token(&server.db, &server.auth.link_secret, token, chrono::Utc::now().naive_utc()).map_err(|e| {
+                        tracing::info!(details=%e, "Share link rejected.");
+                        warp::reject::custom(e)
+                    })?;
+                    let name: String = query.get("name").map(|n| n.trim()).filter(|n| !n.is_empty())
+                        .unwrap_or("Guest").chars().take(64).collect();
+                    let guest_id = guest_id(&server.auth.link_secret, &link, super::auth::cookie_value(&hdrs, GUEST_COOKIE).as_deref());
+                    let user = AuthUser {
+                        user_id: format!("share.{}.{}", link.id, guest_id),
+                        user_name: format!("{} (guest {})", name, &guest_id[..4]),
+                        is_admin: false,
+                        cookies: super::parse_app_cookies(&hdrs),
+                    };
+                    Ok((user, Some(ShareAccess { link, token: token.clone(), guest_id })))
+                },
+                None => server.auth.authenticate(&hdrs, &server.default_user)
+                    .map(|u| (u, None))
+                    .map_err(|e| {
+                        tracing::info!(details=%e, "Authentication failed.");
+                        warp::reject::custom(e)
+                    }),
+            }
+        })
+}
+
+/// `Set-Cookie` values for a share link session: one that lets the browser fetch the shared
+/// media file's `/videos` files, and one that keeps the guest ID for reconnects
+pub fn share_cookies(secret: &[u8], access: &ShareAccess) -> [String; 2] {
+    let max_age = (access.link.expires - chrono::Utc::now().naive_utc()).num_seconds().max(0);
+    let guest = sign_claims(secret, &GuestClaims { link: access.link.id.clone(), guest: access.guest_id.clone() });
+    [
+        format!("{}={}; Path=/videos/{}/; HttpOnly; SameSite=Lax; Max-Age={}", SHARE_COOKIE, access.token, access.link.media_file_id, max_age),
+        format!("{}={}; Path=/api/; HttpOnly; SameSite=Lax; Max-Age={}", GUEST_COOKIE, guest, max_age),
+    ]
+}
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::make_test_db;
+
+    #[test]
+    fn test_validate_token() {
+        let (db, _data_dir, media_files, _comments) = make_test_db();
+        let conn = &mut db.conn().unwrap();
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let now = chrono::Utc::now().naive_utc();
+
+        let mut mk = |id: &str, mf: &str, expires| models::ShareLink::insert(conn, &models::ShareLinkInsert {
+            id: id.into(), media_file_id: mf.into(), created_by: "user.num1".into(), permission: models::ShareLink::VIEW.into(), expires }).unwrap();
+        let ok = mk("ok", &media_files[0].id, now + chrono::Duration::hours(1));
+        let old = mk("old", &media_files[0].id, now - chrono::Duration::seconds(1));
+        let rev = mk("rev", &media_files[1].id, now + chrono::Duration::hours(1));
+        models::ShareLink::revoke(conn, &rev.id).unwrap();
+
+        assert_eq!(validate_token(&db, secret, &make_token(secret, &ok), now).unwrap().id, "ok");
+        assert_eq!(validate_token(&db, secret, &make_token(secret, &old), now).unwrap_err(), ShareLinkError::Expired);
+        assert_eq!(validate_token(&db, secret, &make_token(secret, &rev), now).unwrap_err(), ShareLinkError::Revoked);
+
+        // Forged or tampered tokens
+        assert_eq!(validate_token(&db, b"another-secret-another-secret-xx", &make_token(secret, &ok), now).unwrap_err(), ShareLinkError::Invalid);
+        let other_mf = models::ShareLink { media_file_id: media_files[1].id.clone(), ..ok.clone() };
+        assert_eq!(validate_token(&db, secret, &make_token(secret, &other_mf), now).unwrap_err(), ShareLinkError::Invalid);
+        assert_eq!(validate_token(&db, secret, "garbage", now).unwrap_err(), ShareLinkError::Invalid);
+
+        // Deleting the media file deletes its links
+        models::MediaFile::delete(conn, &media_files[0].id).unwrap();
+        assert_eq!(validate_token(&db, secret, &make_token(secret, &ok), now).unwrap_err(), ShareLinkError::Invalid);
+        assert!(matches!(models::ShareLink::revoke(conn, "ok"), Err(DBError::NotFound())));
+    }
+
+    #[test]
+    fn test_share_session_allows() {
+        use proto::client::client_to_server_cmd::{self as c, Cmd};
+        let (db, _data_dir, media_files, comments) = make_test_db();
+        let view = ShareScope { link_id: "x".into(), media_file_id: media_files[0].id.clone(), can_comment: false };
+        let comment = ShareScope { can_comment: true, ..view.clone() };
+
+        let open = |id: &str| Cmd::OpenMediaFile(c::OpenMediaFile { media_file_id: id.into() });
+        let add = |id: &str| Cmd::AddComment(c::AddComment { media_file_id: id.into(), ..Default::default() });
+
+        assert!(share_session_allows(&open("B1DE0"), &view, &db));
+        assert!(!share_session_allows(&open("11111"), &view, &db));
+        assert!(!share_session_allows(&add("B1DE0"), &view, &db));
+        assert!(share_session_allows(&add("B1DE0"), &comment, &db));
+        assert!(!share_session_allows(&add("11111"), &comment, &db));
+        assert!(!share_session_allows(&Cmd::OpenNavigationPage(Default::default()), &comment, &db));
+        assert!(!share_session_allows(&Cmd::DelMediaFile(c::DelMediaFile { media_file_id: "B1DE0".into() }), &comment, &db));
+        assert!(!share_session_allows(&Cmd::CreateShareLink(c::CreateShareLink { media_file_id: "B1DE0".into(), ..Default::default() }), &comment, &db));
+
+        // Editing and deleting only on the shared file (ownership is checked by the handlers)
+        let own = comments.iter().find(|c| c.media_file_id == media_files[0].id).unwrap().id.to_string();
+        let other = comments.iter().find(|c| c.media_file_id != media_files[0].id).unwrap().id.to_string();
+        let edit = |id: &str| Cmd::EditComment(c::EditComment { comment_id: id.into(), new_comment: "x".into() });
+        let del = |id: &str| Cmd::DelComment(c::DelComment { comment_id: id.into() });
+        assert!(share_session_allows(&edit(&own), &comment, &db));
+        assert!(share_session_allows(&del(&own), &comment, &db));
+        assert!(!share_session_allows(&edit(&own), &view, &db));
+        assert!(!share_session_allows(&edit(&other), &comment, &db));
+        assert!(!share_session_allows(&del(&other), &comment, &db));
+        assert!(!share_session_allows(&del("nonexistent"), &comment, &db));
+    }
+
+    #[test]
+    fn test_guest_id() {
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let link = models::ShareLink { id: "link1".into(), media_file_id: "B1DE0".into(), created_by: "user.num1".into(),
+            permission: models::ShareLink::COMMENT.into(), created: Default::default(), expires: Default::default(), revoked: None };
+        let access = ShareAccess { link: link.clone(), token: "t".into(), guest_id: guest_id(secret, &link, None) };
+        assert_ne!(access.guest_id, guest_id(secret, &link, None));
+
+        // Returning guest keeps their ID, but the cookie can't be forged or reused for another link
+        let cookie = share_cookies(secret, &access)[1].split_once('=').unwrap().1.split(';').next().unwrap().to_string();
+        assert_eq!(guest_id(secret, &link, Some(&cookie)), access.guest_id);
+        assert_ne!(guest_id(b"another-secret-another-secret-xx", &link, Some(&cookie)), access.guest_id);
+        let other_link = models::ShareLink { id: "link2".into(), ..link.clone() };
+        assert_ne!(guest_id(secret, &other_link, Some(&cookie)), access.guest_id);
+    }
+}
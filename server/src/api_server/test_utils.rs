@@ -11,8 +11,9 @@ use lib_clapshot_grpc::proto;
 use std::path::PathBuf;
 use tokio_tungstenite::tungstenite::Message;
 
-use crate::video_pipeline::IncomingFile;
+use crate::video_pipeline::{IncomingFile, PipelineCmd};
 use crate::api_server::UserMessage;
+use crate::grpc::org_events::OrgEvent;
 use crate::database::{DB, models};
 
 
@@ -32,6 +33,8 @@ pub(crate) struct ApiTestState {
     pub(crate) db: Arc<DB>,
     pub(crate) user_msg_tx: crossbeam_channel::Sender<UserMessage>,
     pub(crate) upload_res_rx: crossbeam_channel::Receiver<IncomingFile>,
+    pub(crate) pipeline_rx: crossbeam_channel::Receiver<PipelineCmd>,
+    pub(crate) org_event_rx: crossbeam_channel::Receiver<OrgEvent>,
     pub(crate) media_files_dir: PathBuf,
     pub(crate) upload_dir: PathBuf,
     pub(crate) terminate_flag: Arc<AtomicBool>,
@@ -176,14 +179,47 @@ pub(crate) async fn connect_client_ws(ws_url: &str, user_id: &str) -> WsClient {
     ws
 }
 
+/// Connect to the WebSocket API with a share link token, as an unauthenticated guest
+///
+/// # Returns
+/// * WebSocket client, and the `Set-Cookie` header from the server (if any)
+pub(crate) async fn connect_share_ws(ws_url: &str, token: &str, guest_name: &str) -> (WsClient, Vec<String>) {
+    use tokio_tungstenite::tungstenite::http;
+    use tokio_tungstenite::connect_async;
+
+    let request = http::Request::builder()
+        .uri(format!("{}?share={}&name={}", ws_url, token, guest_name))
+        .header("Host", "127.0.0.1")
+        .header("Connection", "Upgrade")
+        .header("Upgrade", "websocket")
+        .header("Sec-WebSocket-Version", "13")
+        .header("Sec-WebSocket-Key", "1234567890")
+        .body(()).unwrap();
+
+    let (mut ws, resp) = connect_async(request).await.expect("Share link connection failed");
+    let cookies = resp.headers().get_all("set-cookie").iter().map(|v| v.to_str().unwrap().to_string()).collect();
+
+    expect_client_cmd!(&mut ws, Welcome);
+    expect_client_cmd!(&mut ws, DefineActions);
+    (ws, cookies)
+}
+
 macro_rules! api_test {
     ([$ws:ident, $state:ident] $($body:tt)*) => {
+        api_test!{[$ws, $state, Default::default()] $($body)*}
+    };
+    ([$ws:ident, $state:ident, $upload_limits:expr] $($body:tt)*) => {
+        api_test!{[$ws, $state, $upload_limits, Default::default()] $($body)*}
+    };
+    ([$ws:ident, $state:ident, $upload_limits:expr, $file_offload:expr] $($body:tt)*) => {
         {
             let (db, data_dir, media_files, comments) = make_test_db();
 
             let port = portpicker::pick_unused_port().expect("No TCP ports free");
             let (user_msg_tx, user_msg_rx) = crossbeam_channel::unbounded();
             let (upload_res_tx, upload_res_rx) = crossbeam_channel::unbounded();
+            let (pipeline_tx, pipeline_rx) = crossbeam_channel::unbounded();
+            let (org_event_tx, org_event_rx) = crossbeam_channel::unbounded();
             let grpc_srv_listening_flag = Arc::new(AtomicBool::new(false));
             let terminate_flag = Arc::new(AtomicBool::new(false));
             let url_base = format!("http://127.0.0.1:{port}");
@@ -194,15 +230,23 @@ macro_rules! api_test {
             let server_state = ServerState::new( db.clone(),
                 &media_files_dir.clone(),
                 &upload_dir.clone(),
+                chrono::Duration::hours(24),
+                $upload_limits,
+                Default::default(),
+                $file_offload,
                 &url_base.clone(),
+                vec![],
+                Some(org_event_tx),
+                Default::default(),
                 None,
                 grpc_srv_listening_flag.clone(),
                 "anonymous".to_string(),
-                terminate_flag.clone());
+                terminate_flag.clone(),
+                pipeline_tx);
 
             let bind_addr: std::net::IpAddr = "127.0.0.1".parse().unwrap();
-            let $state = ApiTestState { db, user_msg_tx, upload_res_rx, media_files_dir, upload_dir, terminate_flag, media_files, comments, url_base, port, ws_url };
-            let api = async move { run_api_server_async(bind_addr, vec![], server_state, user_msg_rx, upload_res_tx, None, port).await; Ok(()) };
+            let $state = ApiTestState { db, user_msg_tx, upload_res_rx, pipeline_rx, org_event_rx, media_files_dir, upload_dir, terminate_flag, media_files, comments, url_base, port, ws_url };
+            let api = async move { run_api_server_async(bind_addr, vec![], server_state, user_msg_rx, None, upload_res_tx, None, port).await; Ok(()) };
 
             let tst = tokio::spawn(async move {
                 tracing::info!("TEST: Client connecting to {}", $state.ws_url);
@@ -16,10 +16,12 @@ use crate::api_server::server_state::ServerState;
 use crate::database::models::{self};
 use crate::database::tests::make_test_db;
 
-use crate::api_server::test_utils::{ApiTestState, expect_msg, expect_no_msg, write, open_media_file, connect_client_ws};
+use crate::api_server::test_utils::{ApiTestState, expect_msg, expect_no_msg, write, open_media_file, connect_client_ws, connect_share_ws};
 use crate::grpc::db_models::proto_msg_type_to_event_name;
+use crate::grpc::org_events::OrgEvent;
 
-use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddComment, DelComment, DelMediaFile, EditComment, ListMyMessages, OpenNavigationPage, OpenMediaFile, RenameMediaFile};
+use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddComment, CancelMediaFileProcessing, DelComment, DelMediaFile, EditComment, ListMyMessages, OpenNavigationPage, OpenMediaFile, RenameMediaFile, RerunMediaFileProcessing};
+use crate::video_pipeline::{PipelineCmd, ProcessingStep};
 use std::convert::TryFrom;
 
 // ---------------------------------------------------------------------------------------------
@@ -196,6 +198,123 @@ async fn test_api_rename_media_file()
 
 
 
+/// Wait for a command from API server to the processing pipeline
+/// (without blocking the runtime, as server runs on the same thread)
+async fn recv_pipeline_cmd(ts: &ApiTestState) -> PipelineCmd {
+    for _ in 0..50 {
+        if let Ok(cmd) = ts.pipeline_rx.try_recv() { return cmd; }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("No command sent to pipeline");
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_cancel_and_rerun_processing()
+{
+    api_test! {[ws, ts]
+        let media_file = &ts.media_files[0];
+
+        send_server_cmd!(ws, CancelMediaFileProcessing, CancelMediaFileProcessing{media_file_id: media_file.id.clone()});
+        match recv_pipeline_cmd(&ts).await {
+            PipelineCmd::Cancel { media_file_id, user_id } => {
+                assert_eq!(media_file_id, media_file.id);
+                assert_eq!(user_id, "user.num1");
+            },
+            cmd => panic!("Unexpected pipeline command: {:?}", cmd),
+        }
+
+        send_server_cmd!(ws, RerunMediaFileProcessing, RerunMediaFileProcessing{
+            media_file_id: media_file.id.clone(),
+            step: proto::MediaProcessingStep::Transcode.into(),
+            video_bitrate: Some(500_000)});
+        match recv_pipeline_cmd(&ts).await {
+            PipelineCmd::Rerun { media_file_id, step, video_bitrate, .. } => {
+                assert_eq!(media_file_id, media_file.id);
+                assert_eq!(step, ProcessingStep::Transcode);
+                assert_eq!(video_bitrate, Some(500_000));
+            },
+            cmd => panic!("Unexpected pipeline command: {:?}", cmd),
+        }
+
+        // Bad bitrate
+        send_server_cmd!(ws, RerunMediaFileProcessing, RerunMediaFileProcessing{media_file_id: media_file.id.clone(), step: proto::MediaProcessingStep::Transcode.into(), video_bitrate: Some(0)});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // Someone else's media file
+        send_server_cmd!(ws, RerunMediaFileProcessing, RerunMediaFileProcessing{media_file_id: ts.media_files[1].id.clone(), step: proto::MediaProcessingStep::Thumbnails.into(), video_bitrate: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, CancelMediaFileProcessing, CancelMediaFileProcessing{media_file_id: ts.media_files[1].id.clone()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(ts.pipeline_rx.is_empty());
+    }
+}
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_generate_review_report()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::GenerateReviewReport;
+    use crate::video_pipeline::review_report::ReportFormat;
+
+    api_test! {[ws, ts]
+        // Anyone who can view the media file may generate a report
+        let media_file = &ts.media_files[1];
+        send_server_cmd!(ws, GenerateReviewReport, GenerateReviewReport{media_file_id: media_file.id.clone(), format: Some("PDF".into())});
+        match recv_pipeline_cmd(&ts).await {
+            PipelineCmd::Report { media_file_id, user_id, format } => {
+                assert_eq!(media_file_id, media_file.id);
+                assert_eq!(user_id, "user.num1");
+                assert_eq!(format, ReportFormat::Pdf);
+            },
+            cmd => panic!("Unexpected pipeline command: {:?}", cmd),
+        }
+        send_server_cmd!(ws, GenerateReviewReport, GenerateReviewReport{media_file_id: media_file.id.clone(), format: None});
+        assert!(matches!(recv_pipeline_cmd(&ts).await, PipelineCmd::Report { format: ReportFormat::Html, .. }));
+
+        // Bad format or media file
+        send_server_cmd!(ws, GenerateReviewReport, GenerateReviewReport{media_file_id: media_file.id.clone(), format: Some("docx".into())});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, GenerateReviewReport, GenerateReviewReport{media_file_id: "non-existent".into(), format: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(ts.pipeline_rx.is_empty());
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_search()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::Search;
+    use lib_clapshot_grpc::proto::search_hit::Kind;
+
+    api_test! {[ws, ts]
+        // Only hits from the user's own media files are returned
+        send_server_cmd!(ws, Search, Search{query: "test".into(), media_file_id: None});
+        let res = expect_client_cmd!(&mut ws, SearchResults);
+        assert_eq!(res.query, "test");
+        let mut ids = res.hits.iter().map(|h| h.media_file_id.clone()).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec!["22222", "B1DE0", "B1DE4"]);
+        assert!(res.hits.iter().all(|h| h.kind() == Kind::MediaFile && h.snippet.contains("**test")));
+
+        // Comments, limited to one media file
+        send_server_cmd!(ws, Search, Search{query: "Comment".into(), media_file_id: Some(ts.media_files[0].id.clone())});
+        let res = expect_client_cmd!(&mut ws, SearchResults);
+        assert_eq!(res.hits.len(), ts.comments.iter().filter(|c| c.media_file_id == ts.media_files[0].id).count());
+        assert!(res.hits.iter().all(|h| h.kind() == Kind::Comment && h.comment_id.is_some()));
+
+        // Someone else's media file
+        send_server_cmd!(ws, Search, Search{query: "Comment".into(), media_file_id: Some(ts.media_files[1].id.clone())});
+        assert!(expect_client_cmd!(&mut ws, SearchResults).hits.is_empty());
+
+        // Nothing searchable
+        send_server_cmd!(ws, Search, Search{query: " * ".into(), media_file_id: None});
+        assert!(expect_client_cmd!(&mut ws, SearchResults).hits.is_empty());
+    }
+}
+
 #[tokio::test]
 #[traced_test]
 async fn test_api_add_plain_comment()
@@ -221,6 +340,11 @@ async fn test_api_add_plain_comment()
         assert!(!models::Comment::get(&mut ts.db.conn().unwrap(), &cid).unwrap().drawing.unwrap().contains("data:image"));
         assert!(c.comments[0].clone().drawing.unwrap().starts_with("data:image/webp"));
 
+        // Organizer event hooks got both comments
+        let evs: Vec<_> = ts.org_event_rx.try_iter().collect();
+        assert_eq!(evs.len(), 2);
+        assert!(matches!(&evs[1], OrgEvent::CommentAdded { comment_id, ses: Some(ses) } if *comment_id == cid && ses.user.as_ref().unwrap().id == media.user_id));
+
         // Add a comment to a nonexisting media file
         send_server_cmd!(ws, AddComment, AddComment{media_file_id: "bad_id".into(), comment: "Test comment 3".into(), ..Default::default()});
         expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
@@ -275,6 +399,7 @@ async fn test_api_edit_comment()
         assert!(m.comments[0].clone().drawing.unwrap().starts_with("data:image/webp"));
         let drw_data = String::from_utf8( data_url::DataUrl::process(m.comments[0].clone().drawing.unwrap().as_str()).unwrap().decode_to_vec().unwrap().0 ).unwrap();
         assert_eq!(drw_data, "IMAGE_DATA");
+        assert!(matches!(ts.org_event_rx.try_recv(), Ok(OrgEvent::CommentEdited { comment_id, .. }) if comment_id == com.id));
 
         // Edit nonexisting comment
         send_server_cmd!(ws, EditComment, EditComment{comment_id: "1234566999".into(), new_comment: "Edited comment 2".into(), ..Default::default()});
@@ -283,6 +408,7 @@ async fn test_api_edit_comment()
         // Try to edit someone else's comment
         send_server_cmd!(ws, EditComment, EditComment{comment_id: ts.comments[1].id.to_string(), new_comment: "Edited comment 3".into(), ..Default::default()});
         expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(ts.org_event_rx.try_recv().is_err());
 
         // Break the database
         ts.db.break_db();
@@ -292,6 +418,209 @@ async fn test_api_edit_comment()
 }
 
 
+#[tokio::test]
+#[traced_test]
+async fn test_api_comment_status_and_assignee()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AssignComment, SetCommentStatus};
+    api_test! {[ws, ts]
+        let media = &ts.media_files[0];     // Owned by user.num1
+        let com = &ts.comments[3];          // Posted on it by user.num2
+        open_media_file(&mut ws, &media.id).await;
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        open_media_file(&mut ws2, &media.id).await;
+
+        // Media file owner resolves someone else's thread. Everyone viewing the file gets the update.
+        send_server_cmd!(ws, SetCommentStatus, SetCommentStatus{comment_id: com.id.to_string(), status: proto::CommentStatus::Resolved.into()});
+        for w in [&mut ws, &mut ws2] {
+            assert_eq!(expect_client_cmd!(w, DelComment).comment_id, com.id.to_string());
+            let c = expect_client_cmd!(w, AddComments).comments.pop().unwrap();
+            assert_eq!(c.status(), proto::CommentStatus::Resolved);
+            assert_eq!(c.resolved_by.as_deref(), Some("user.num1"));
+            assert!(c.resolved_at.is_some());
+        }
+        assert!(matches!(ts.org_event_rx.try_recv(), Ok(OrgEvent::CommentEdited { comment_id, .. }) if comment_id == com.id));
+
+        // Assign (and the assignee may then reopen)
+        send_server_cmd!(ws, AssignComment, AssignComment{comment_id: com.id.to_string(), assignee_id: Some("user.num2".into())});
+        expect_client_cmd!(&mut ws, DelComment);
+        assert_eq!(expect_client_cmd!(&mut ws, AddComments).comments[0].assignee_id.as_deref(), Some("user.num2"));
+        send_server_cmd!(ws, AssignComment, AssignComment{comment_id: com.id.to_string(), assignee_id: Some("no.such.user".into())});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        let mut ws3 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        send_server_cmd!(ws3, SetCommentStatus, SetCommentStatus{comment_id: com.id.to_string(), status: proto::CommentStatus::Open.into()});
+        expect_client_cmd!(&mut ws, DelComment);
+        let c = expect_client_cmd!(&mut ws, AddComments).comments.pop().unwrap();
+        assert_eq!((c.status(), c.resolved_by), (proto::CommentStatus::Open, None));
+        assert_eq!(models::Comment::get(&mut ts.db.conn().unwrap(), &com.id).unwrap().status, models::Comment::OPEN);
+
+        // Replies have no status, and others' threads can't be resolved by default
+        send_server_cmd!(ws, SetCommentStatus, SetCommentStatus{comment_id: ts.comments[5].id.to_string(), status: proto::CommentStatus::WontFix.into()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws3, SetCommentStatus, SetCommentStatus{comment_id: ts.comments[0].id.to_string(), status: proto::CommentStatus::WontFix.into()});
+        expect_user_msg(&mut ws3, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws3, AssignComment, AssignComment{comment_id: ts.comments[0].id.to_string(), assignee_id: None});
+        expect_user_msg(&mut ws3, proto::user_message::Type::Error).await;
+        expect_no_msg(&mut ws).await;
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_approvals()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::SetApproval;
+    use proto::approval_summary::Status;
+    api_test! {[ws, ts]
+        let media = &ts.media_files[0];     // Owned by user.num1
+        open_media_file(&mut ws, &media.id).await;
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        let open = open_media_file(&mut ws2, &media.id).await;
+        assert_eq!(open.media_file.unwrap().approval.unwrap().status(), Status::NoReviews);
+
+        // Reviewer requests changes. Viewers get the new summary, uploader gets notified with the note.
+        send_server_cmd!(ws2, SetApproval, SetApproval{media_file_id: media.id.clone(), decision: proto::ApprovalDecision::ChangesRequested.into(), note: Some(" Too dark ".into())});
+        for w in [&mut ws, &mut ws2] {
+            let sa = expect_client_cmd!(w, ShowApprovals).approval.unwrap();
+            assert_eq!(sa.status(), Status::ChangesRequested);
+            assert_eq!(sa.approvals[0].user_id, "user.num2");
+        }
+        let msg = expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        assert!(msg.message.contains("requested changes"));
+        assert_eq!(msg.details.as_deref(), Some("Too dark"));
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+
+        // Uploader's own decision isn't notified back to them. Reviewer then approves too.
+        send_server_cmd!(ws, SetApproval, SetApproval{media_file_id: media.id.clone(), decision: proto::ApprovalDecision::Approved.into(), note: None});
+        assert_eq!(expect_client_cmd!(&mut ws, ShowApprovals).approval.unwrap().status(), Status::ChangesRequested);
+        expect_client_cmd!(&mut ws2, ShowApprovals);
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        expect_no_msg(&mut ws2).await;
+
+        send_server_cmd!(ws2, SetApproval, SetApproval{media_file_id: media.id.clone(), decision: proto::ApprovalDecision::Approved.into(), note: None});
+        let sa = expect_client_cmd!(&mut ws, ShowApprovals).approval.unwrap();
+        assert_eq!((sa.status(), sa.approvals.len()), (Status::Approved, 2));
+        assert!(expect_user_msg(&mut ws, proto::user_message::Type::Ok).await.message.contains("approved"));
+        expect_client_cmd!(&mut ws2, ShowApprovals);
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+
+        // Aggregate status is part of the media file
+        let open = open_media_file(&mut ws, &media.id).await;
+        assert_eq!(open.media_file.unwrap().approval.unwrap().status(), Status::Approved);
+
+        send_server_cmd!(ws2, SetApproval, SetApproval{media_file_id: "non-existent".into(), decision: proto::ApprovalDecision::Approved.into(), note: None});
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+    }
+}
+
+#[test]
+fn test_parse_mentions() {
+    use crate::api_server::mentions::parse_mentions;
+    assert_eq!(parse_mentions("@alice: see 00:01, cc @bob.smith and @alice."), vec!["alice", "bob.smith"]);
+    assert_eq!(parse_mentions("(@\"Jane Doe\") mail me at me@example.com"), vec!["Jane Doe"]);
+    assert!(parse_mentions("no mentions @ all").is_empty());
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_comment_mentions()
+{
+    use crate::database::DbQueryByUser;
+    api_test! {[ws, ts]
+        let media = &ts.media_files[0];
+        open_media_file(&mut ws, &media.id).await;
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;     // Online, but not viewing the file
+
+        // Mentioned user gets a persistent message, unknown mention is reported back, self-mention ignored
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: media.id.clone(), comment: "@user.num2 check this, @ghost and @\"username for user.num1\" too".into(), ..Default::default()});
+        let err = expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        assert!(err.details.unwrap().contains("@ghost"));
+        let com = expect_client_cmd!(&mut ws, AddComments).comments.pop().unwrap();
+
+        let msg = expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+        assert!(msg.message.contains("mentioned you"));
+        let refs = msg.refs.unwrap();
+        assert_eq!((refs.media_file_id.as_deref(), refs.comment_id.as_deref()), (Some(media.id.as_str()), Some(com.id.as_str())));
+        let conn = &mut ts.db.conn().unwrap();
+        let stored = models::Message::get_by_user(conn, "user.num2", crate::database::DBPaging::default()).unwrap();
+        assert_eq!(stored[0].comment_id.map(|id| id.to_string()), Some(com.id.clone()));
+        assert!(models::Message::get_by_user(conn, "user.num1", crate::database::DBPaging::default()).unwrap().iter().all(|m| !m.message.contains("mentioned")));
+
+        // Editing doesn't notify again about users who were already mentioned
+        send_server_cmd!(ws, EditComment, EditComment{comment_id: com.id.clone(), new_comment: "Fixed, thanks @user.num2".into()});
+        expect_client_cmd!(&mut ws, DelComment);
+        expect_client_cmd!(&mut ws, AddComments);
+        expect_no_msg(&mut ws2).await;
+
+        // Mentions by name work too (case-insensitive)
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: media.id.clone(), comment: "Ping @\"USERNAME FOR USER.NUM2\"".into(), ..Default::default()});
+        expect_client_cmd!(&mut ws, AddComments);
+        expect_user_msg(&mut ws2, proto::user_message::Type::Ok).await;
+        expect_no_msg(&mut ws).await;
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_comment_reply_notification()
+{
+    use crate::database::DbQueryByUser;
+    api_test! {[ws, ts]
+        let media = &ts.media_files[0];
+        let parent = &ts.comments[0];     // By user.num1
+        open_media_file(&mut ws, &media.id).await;
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+
+        // Author of the parent comment is notified about replies by others
+        send_server_cmd!(ws2, AddComment, AddComment{media_file_id: media.id.clone(), comment: "Fixed it".into(), parent_id: Some(parent.id.to_string()), ..Default::default()});
+        let msg = expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        assert!(msg.message.contains("replied to your comment"));
+        assert_eq!(msg.details.as_deref(), Some("Fixed it"));
+        let reply = expect_client_cmd!(&mut ws, AddComments).comments.pop().unwrap();
+        assert_eq!(msg.refs.unwrap().comment_id, Some(reply.id.clone()));
+        let stored = models::Message::get_by_user(&mut ts.db.conn().unwrap(), "user.num1", crate::database::DBPaging::default()).unwrap();
+        assert_eq!(stored[0].event_name, crate::grpc::db_models::COMMENT_REPLY_EVENT);
+
+        // ...but not about their own replies, and only once if also mentioned
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: media.id.clone(), comment: "Thanks".into(), parent_id: Some(parent.id.to_string()), ..Default::default()});
+        expect_client_cmd!(&mut ws, AddComments);
+        send_server_cmd!(ws2, AddComment, AddComment{media_file_id: media.id.clone(), comment: "@user.num1 again".into(), parent_id: Some(parent.id.to_string()), ..Default::default()});
+        assert!(expect_user_msg(&mut ws, proto::user_message::Type::Ok).await.message.contains("mentioned you"));
+        expect_client_cmd!(&mut ws, AddComments);
+        expect_no_msg(&mut ws).await;
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_api_email_settings()
+{
+    use proto::client::{email_settings::Frequency, EmailSettings};
+    use proto::client::client_to_server_cmd::{GetEmailSettings, SetEmailSettings};
+    api_test! {[ws, ts]
+        send_server_cmd!(ws, GetEmailSettings, GetEmailSettings{});
+        let cmd = expect_client_cmd!(&mut ws, ShowEmailSettings);
+        assert!(!cmd.available);    // No SMTP server configured
+        let settings = cmd.settings.unwrap();
+        assert_eq!((settings.email.as_deref(), settings.frequency()), (None, Frequency::Off));
+
+        // Bad address, or missing address with notifications on
+        send_server_cmd!(ws, SetEmailSettings, SetEmailSettings{settings: Some(EmailSettings{email: Some("not an address".into()), frequency: Frequency::Daily.into()})});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+        send_server_cmd!(ws, SetEmailSettings, SetEmailSettings{settings: Some(EmailSettings{email: None, frequency: Frequency::Daily.into()})});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        send_server_cmd!(ws, SetEmailSettings, SetEmailSettings{settings: Some(EmailSettings{email: Some(" one@example.com ".into()), frequency: Frequency::Daily.into()})});
+        let settings = expect_client_cmd!(&mut ws, ShowEmailSettings).settings.unwrap();
+        assert_eq!((settings.email.as_deref(), settings.frequency()), (Some("one@example.com"), Frequency::Daily));
+        expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+
+        let u = models::User::get(&mut ts.db.conn().unwrap(), &"user.num1".to_string()).unwrap();
+        assert_eq!((u.email.as_deref(), u.email_frequency.as_str()), (Some("one@example.com"), models::User::EMAIL_DAILY));
+    }
+}
+
 #[tokio::test]
 #[traced_test]
 async fn test_api_del_comment()
@@ -403,3 +732,541 @@ async fn test_multipart_upload()
         assert_eq!(contents, file_body);
     }
 }
+
+
+#[tokio::test]
+#[traced_test]
+async fn test_tus_resumable_upload()
+{
+    api_test! {[_ws, ts]
+        use base64::{Engine as _, engine::general_purpose as Base64GP};
+        let client = Client::new();
+        let file_body = "0123456789abcdef";
+        let meta = format!("filename {}", Base64GP::STANDARD.encode("resumed.mov"));
+
+        // Without Tus-Resumable header
+        let res = client.post(format!("{}/api/upload/tus", ts.url_base)).header("Upload-Length", "16").send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::PRECONDITION_FAILED);
+
+        // Create
+        let res = client.post(format!("{}/api/upload/tus", ts.url_base))
+            .header("Tus-Resumable", "1.0.0")
+            .header("Upload-Length", file_body.len().to_string())
+            .header("Upload-Metadata", meta)
+            .header("X-Remote-User-Id", "user.num1")
+            .header("X-Clapshot-Cookies", r#"{"folder": "123"}"#)
+            .send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+        let location = res.headers().get("Location").unwrap().to_str().unwrap().to_string();
+        assert!(location.starts_with(&format!("{}/api/upload/tus/", ts.url_base)));
+
+        let patch = |offset: usize, data: &'static str, user: &'static str| client.patch(&location)
+            .header("Tus-Resumable", "1.0.0")
+            .header("Content-Type", "application/offset+octet-stream")
+            .header("Upload-Offset", offset.to_string())
+            .header("X-Remote-User-Id", user)
+            .body(data).send();
+        let head = |user: &'static str| client.head(&location)
+            .header("Tus-Resumable", "1.0.0")
+            .header("X-Remote-User-Id", user).send();
+
+        // First part, then "reconnect" and ask where to continue
+        let res = patch(0, &file_body[..6], "user.num1").await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(res.headers().get("Upload-Offset").unwrap(), "6");
+
+        let res = head("user.num1").await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::OK);
+        assert_eq!(res.headers().get("Upload-Offset").unwrap(), "6");
+        assert_eq!(res.headers().get("Upload-Length").unwrap(), "16");
+
+        // Other users can't see or touch it
+        assert_eq!(head("user.num2").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(patch(6, &file_body[6..], "user.num2").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+
+        // Wrong offset
+        let res = patch(3, &file_body[3..], "user.num1").await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::CONFLICT);
+        assert!(ts.upload_res_rx.is_empty());
+
+        // Rest of the file completes the upload
+        let res = patch(6, &file_body[6..], "user.num1").await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(res.headers().get("Upload-Offset").unwrap(), "16");
+
+        let up_res = ts.upload_res_rx.try_recv().unwrap();
+        assert_eq!(up_res.file_path.file_name().unwrap(), "resumed.mov");
+        assert_eq!(up_res.user_id, "user.num1");
+        assert_eq!(up_res.cookies.get("folder").unwrap(), "123");
+        assert_eq!(std::fs::read_to_string(&up_res.file_path).unwrap(), file_body);
+        assert_eq!(head("user.num1").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+
+        // Create and terminate
+        let res = client.post(format!("{}/api/upload/tus", ts.url_base))
+            .header("Tus-Resumable", "1.0.0")
+            .header("Upload-Length", "100")
+            .header("Upload-Metadata", format!("filename {}", Base64GP::STANDARD.encode("aborted.mp4")))
+            .send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+        let location = res.headers().get("Location").unwrap().to_str().unwrap().to_string();
+        let res = client.delete(&location).header("Tus-Resumable", "1.0.0").send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(std::fs::read_dir(ts.upload_dir.join("tus")).unwrap().count(), 0);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_upload_limits()
+{
+    let limits = crate::video_pipeline::upload_limits::UploadLimits {
+        user_max_bytes: Some(100),
+        allowed_mime_types: vec!["video/*".into()],
+        allowed_extensions: vec!["mp4".into()],
+        ..Default::default()
+    };
+    api_test! {[ws, ts, limits]
+        use base64::{Engine as _, engine::general_purpose as Base64GP};
+        let url = format!("http://127.0.0.1:{}/api/upload", ts.port);
+        let upload = |name: &'static str, mime: &'static str, body: String| {
+            let part = multipart::Part::stream(body).file_name(name).mime_str(mime).unwrap();
+            Client::new().post(&url).header("X-Remote-User-Id", "user.num1").multipart(multipart::Form::new().part("fileupload", part)).send()
+        };
+
+        // Wrong extension or MIME type
+        assert_eq!(upload("evil.exe", "video/mp4", "x".into()).await.unwrap().status(), reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let msg = expect_client_cmd!(&mut ws, ShowMessages);
+        assert_eq!(msg.msgs[0].r#type, proto::user_message::Type::Error as i32);
+        assert!(msg.msgs[0].details.as_ref().unwrap().contains("evil.exe"));
+        assert_eq!(upload("clip.mp4", "application/pdf", "x".into()).await.unwrap().status(), reqwest::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        expect_client_cmd!(&mut ws, ShowMessages);
+
+        // Over quota while streaming
+        assert_eq!(upload("big.mp4", "video/mp4", "x".repeat(101)).await.unwrap().status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        expect_client_cmd!(&mut ws, ShowMessages);
+        assert!(std::fs::read_dir(&ts.upload_dir).unwrap().flatten().all(|d| std::fs::read_dir(d.path()).unwrap().count() == 0));
+
+        // Fits
+        assert_eq!(upload("ok.mp4", "video/mp4", "x".repeat(10)).await.unwrap().status(), reqwest::StatusCode::OK);
+        assert!(ts.upload_res_rx.try_recv().is_ok());
+        assert!(ts.upload_res_rx.is_empty());
+
+        // Uploads waiting for ingestion and partial resumable uploads count against quota (test DB already uses 50 bytes)
+        assert_eq!(upload("more.mp4", "video/mp4", "x".repeat(45)).await.unwrap().status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        expect_client_cmd!(&mut ws, ShowMessages);
+        let res = Client::new().post(format!("{}/api/upload/tus", ts.url_base))
+            .header("Tus-Resumable", "1.0.0")
+            .header("Upload-Length", "30")
+            .header("Upload-Metadata", format!("filename {}", Base64GP::STANDARD.encode("partial.mp4")))
+            .header("X-Remote-User-Id", "user.num1")
+            .send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::CREATED);
+        let tus_url = res.headers().get("Location").unwrap().to_str().unwrap().to_string();
+        assert_eq!(upload("small.mp4", "video/mp4", "x".repeat(15)).await.unwrap().status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        expect_client_cmd!(&mut ws, ShowMessages);
+        let res = Client::new().delete(&tus_url).header("Tus-Resumable", "1.0.0").header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::NO_CONTENT);
+        assert_eq!(upload("small.mp4", "video/mp4", "x".repeat(15)).await.unwrap().status(), reqwest::StatusCode::OK);
+        assert!(ts.upload_res_rx.try_recv().is_ok());
+
+        // Resumable upload is checked at creation time, before any data is sent
+        let res = Client::new().post(format!("{}/api/upload/tus", ts.url_base))
+            .header("Tus-Resumable", "1.0.0")
+            .header("Upload-Length", "1000000")
+            .header("Upload-Metadata", format!("filename {}", Base64GP::STANDARD.encode("huge.mp4")))
+            .header("X-Remote-User-Id", "user.num1")
+            .send().await.unwrap();
+        assert_eq!(res.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        expect_client_cmd!(&mut ws, ShowMessages);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_admin_list_jobs()
+{
+    api_test! {[_ws, ts]
+        models::Job::insert(&mut ts.db.conn().unwrap(), &models::JobInsert {
+            media_file_id: ts.media_files[0].id.clone(),
+            user_id: ts.media_files[0].user_id.clone(),
+            job_type: "transcode".into(),
+            state: models::Job::QUEUED.into(),
+            payload: "{}".into(),
+        }).unwrap();
+        let url = format!("http://127.0.0.1:{}/api/admin/jobs", ts.port);
+
+        // Non-admin gets denied
+        let response = Client::new().get(&url).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+
+        // Admin sees the queue, optionally filtered by state
+        let response = Client::new().get(&url).header("X-Remote-User-Id", "admin").send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        let jobs: Vec<models::Job> = response.json().await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].state, models::Job::QUEUED);
+
+        let response = Client::new().get(format!("{url}?state=failed")).header("X-Remote-User-Id", "admin").send().await.unwrap();
+        let jobs: Vec<models::Job> = response.json().await.unwrap();
+        assert!(jobs.is_empty());
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_share_links()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::{CreateShareLink, RevokeShareLink};
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];    // user.num1's
+        let other_mf = &ts.media_files[1];
+
+        // Only owner can share by default
+        send_server_cmd!(ws, CreateShareLink, CreateShareLink{media_file_id: other_mf.id.clone(), permission: proto::ShareLinkPermission::View.into(), valid_hours: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        send_server_cmd!(ws, CreateShareLink, CreateShareLink{media_file_id: mf.id.clone(), permission: proto::ShareLinkPermission::View.into(), valid_hours: Some(2)});
+        let links = expect_client_cmd!(&mut ws, ShowShareLinks);
+        assert_eq!(links.links.len(), 1);
+        let link = links.links[0].clone();
+        assert!(link.url.ends_with(&format!("/?share={}", link.token)));
+
+        // Guest gets the shared media file opened automatically, and a cookie for its /videos files
+        let (mut guest, cookies) = connect_share_ws(&ts.ws_url, &link.token, "Alice").await;
+        assert!(cookies.iter().any(|c| c.contains(&format!("Path=/videos/{}/", mf.id))));
+        let opened = expect_client_cmd!(&mut guest, OpenMediaFile);
+        assert_eq!(opened.media_file.unwrap().id, mf.id);
+        while crate::api_server::test_utils::read(&mut guest).await.is_some() {}
+
+        // ...but nothing else, and no commenting on a view-only link
+        send_server_cmd!(guest, OpenMediaFile, OpenMediaFile{media_file_id: other_mf.id.clone()});
+        expect_user_msg(&mut guest, proto::user_message::Type::Error).await;
+        send_server_cmd!(guest, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "hi".into(), ..Default::default()});
+        expect_user_msg(&mut guest, proto::user_message::Type::Error).await;
+        send_server_cmd!(guest, CreateShareLink, CreateShareLink{media_file_id: mf.id.clone(), permission: 0, valid_hours: None});
+        expect_user_msg(&mut guest, proto::user_message::Type::Error).await;
+
+        // Media file access with the token is limited to the shared file
+        let videos_url = format!("{}/videos", ts.url_base);
+        let resp = Client::new().get(format!("{videos_url}/{}/nonexistent.mp4?share={}", other_mf.id, link.token)).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+        let resp = Client::new().get(format!("{videos_url}/{}/nonexistent.mp4?share={}", mf.id, link.token)).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+        let resp = Client::new().get(format!("{videos_url}/{}/nonexistent.mp4?share=bad.token", mf.id)).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+
+        // Revoking ends the guest session and rejects new ones
+        send_server_cmd!(ws, RevokeShareLink, RevokeShareLink{id: link.id.clone()});
+        let links = expect_client_cmd!(&mut ws, ShowShareLinks);
+        assert!(links.links[0].revoked.is_some());
+
+        send_server_cmd!(guest, ListMyMessages, ListMyMessages{});
+        let err = expect_client_cmd!(&mut guest, Error);
+        assert!(err.msg.contains("revoked"));
+
+        let resp = Client::new().get(format!("{}/api/ws?share={}", ts.url_base, link.token)).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_share_link_guests()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::CreateShareLink;
+    use crate::api_server::test_utils::read;
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];
+        send_server_cmd!(ws, CreateShareLink, CreateShareLink{media_file_id: mf.id.clone(), permission: proto::ShareLinkPermission::ViewAndComment.into(), valid_hours: None});
+        let link = expect_client_cmd!(&mut ws, ShowShareLinks).links[0].clone();
+
+        // Guests on the same link are different users
+        let (mut alice, _) = connect_share_ws(&ts.ws_url, &link.token, "Alice").await;
+        let (mut bob, _) = connect_share_ws(&ts.ws_url, &link.token, "Alice").await;
+        while read(&mut alice).await.is_some() {}
+        while read(&mut bob).await.is_some() {}
+
+        send_server_cmd!(alice, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Alice's note".into(), ..Default::default()});
+        let c = expect_client_cmd!(&mut alice, AddComments).comments.pop().unwrap();
+        assert!(c.user_id.as_deref().unwrap().starts_with(&format!("share.{}.", link.id)));
+        assert!(c.username_ifnull.starts_with("Alice (guest "));
+        while read(&mut bob).await.is_some() {}
+
+        send_server_cmd!(bob, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Bob's note".into(), ..Default::default()});
+        let c2 = expect_client_cmd!(&mut bob, AddComments).comments.pop().unwrap();
+        assert_ne!(c.user_id, c2.user_id);
+        assert_ne!(c.username_ifnull, c2.username_ifnull);    // Same name, but different guest tag
+        while read(&mut alice).await.is_some() {}
+
+        // ...and can't edit or delete each other's comments
+        send_server_cmd!(bob, EditComment, EditComment{comment_id: c.id.clone(), new_comment: "Hijacked".into()});
+        expect_user_msg(&mut bob, proto::user_message::Type::Error).await;
+        send_server_cmd!(bob, DelComment, DelComment{comment_id: c.id.clone()});
+        expect_user_msg(&mut bob, proto::user_message::Type::Error).await;
+        let stored = models::Comment::get(&mut ts.db.conn().unwrap(), &c.id.parse().unwrap()).unwrap();
+        assert_eq!(stored.comment, "Alice's note");
+
+        // ...but can edit their own
+        send_server_cmd!(alice, EditComment, EditComment{comment_id: c.id.clone(), new_comment: "Alice's edit".into()});
+        expect_client_cmd!(&mut alice, DelComment);
+        expect_client_cmd!(&mut alice, AddComments);
+        let stored = models::Comment::get(&mut ts.db.conn().unwrap(), &c.id.parse().unwrap()).unwrap();
+        assert_eq!(stored.comment, "Alice's edit");
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_videos_access()
+{
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];
+        let mf_dir = ts.media_files_dir.join(&mf.id);
+        std::fs::create_dir_all(mf_dir.join("adaptive")).unwrap();
+        std::fs::write(mf_dir.join("video.mp4"), b"fake video").unwrap();
+        std::fs::write(mf_dir.join("adaptive").join("seg0.ts"), b"fake segment").unwrap();
+
+        // Media file URLs are signed
+        let opened = open_media_file(&mut ws, &mf.id).await.media_file.unwrap();
+        let playback_url = opened.playback_url.unwrap();
+        assert!(playback_url.starts_with(&format!("{}/videos/{}/video.mp4?sig=", ts.url_base, mf.id)));
+        assert!(opened.orig_url.unwrap().contains("?sig="));
+        let sig = playback_url.split("?sig=").nth(1).unwrap().to_string();
+
+        // Signed URL works without credentials, and sets a cookie for the rest of the directory
+        let resp = Client::new().get(&playback_url).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let cookie = resp.headers().get("set-cookie").unwrap().to_str().unwrap().to_string();
+        assert!(cookie.starts_with(&format!("clapshot_media={}", sig)));
+        assert!(cookie.contains(&format!("Path=/videos/{}/", mf.id)));
+        assert_eq!(resp.text().await.unwrap(), "fake video");
+
+        let seg_url = format!("{}/videos/{}/adaptive/seg0.ts", ts.url_base, mf.id);
+        let resp = Client::new().get(&seg_url).header("Cookie", format!("clapshot_media={}", sig)).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        // Signature is only good for its own media file, and can't be tampered with
+        let other_url = format!("{}/videos/{}/video.mp4?sig={}", ts.url_base, ts.media_files[1].id, sig);
+        assert_eq!(Client::new().get(&other_url).send().await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+        let bad_url = playback_url.replace("?sig=", "?sig=x");
+        assert_eq!(Client::new().get(&bad_url).send().await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+
+        // Authenticated users go through the same authz as opening the file
+        let resp = Client::new().get(&seg_url).header("X-Remote-User-Id", "user.num2").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let resp = Client::new().get(format!("{}/videos/NOSUCH/video.mp4", ts.url_base)).header("X-Remote-User-Id", "user.num2").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_videos_byte_serving()
+{
+    api_test! {[_ws, ts]
+        let mf = &ts.media_files[0];
+        let mf_dir = ts.media_files_dir.join(&mf.id);
+        std::fs::create_dir_all(mf_dir.join("subs")).unwrap();
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(mf_dir.join("video.mp4"), &data).unwrap();
+        std::fs::write(mf_dir.join("subs").join("en.vtt"), "WEBVTT\n").unwrap();
+
+        let url = format!("{}/videos/{}/video.mp4", ts.url_base, mf.id);
+        let get = || Client::new().get(&url).header("X-Remote-User-Id", "user.num1");
+
+        // Full file, with validators
+        let resp = get().send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let hdr = |r: &reqwest::Response, h: &str| r.headers().get(h).map(|v| v.to_str().unwrap().to_string());
+        assert_eq!(hdr(&resp, "content-type").unwrap(), "video/mp4");
+        assert_eq!(hdr(&resp, "accept-ranges").unwrap(), "bytes");
+        let etag = hdr(&resp, "etag").unwrap();
+        let last_modified = hdr(&resp, "last-modified").unwrap();
+        assert_eq!(resp.bytes().await.unwrap().as_ref(), &data[..]);
+
+        // Ranges
+        let resp = get().header("Range", "bytes=10-19").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(hdr(&resp, "content-range").unwrap(), "bytes 10-19/1000");
+        assert_eq!(resp.bytes().await.unwrap().as_ref(), &data[10..20]);
+
+        let resp = get().header("Range", "bytes=-5").send().await.unwrap();
+        assert_eq!(resp.bytes().await.unwrap().as_ref(), &data[995..]);
+
+        let resp = get().header("Range", "bytes=2000-").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(hdr(&resp, "content-range").unwrap(), "bytes */1000");
+
+        // Stale If-Range gets the whole file
+        let resp = get().header("Range", "bytes=10-19").header("If-Range", "\"stale\"").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let resp = get().header("Range", "bytes=10-19").header("If-Range", &etag).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+
+        // Conditional GET
+        let resp = get().header("If-None-Match", &etag).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_MODIFIED);
+        let resp = get().header("If-Modified-Since", &last_modified).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_MODIFIED);
+        let resp = get().header("If-None-Match", "\"other\"").header("If-Modified-Since", &last_modified).send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+
+        // HEAD
+        let resp = Client::new().head(&url).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(hdr(&resp, "content-length").unwrap(), "1000");
+
+        let resp = Client::new().get(format!("{}/videos/{}/subs/en.vtt", ts.url_base, mf.id)).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(hdr(&resp, "content-type").unwrap(), "text/vtt; charset=utf-8");
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_videos_x_accel_redirect()
+{
+    use crate::api_server::media_serving::FileOffload;
+    api_test! {[_ws, ts, Default::default(), FileOffload::XAccelRedirect("/protected-videos/".into())]
+        let mf = &ts.media_files[0];
+        std::fs::create_dir_all(ts.media_files_dir.join(&mf.id).join("orig")).unwrap();
+        std::fs::write(ts.media_files_dir.join(&mf.id).join("orig").join("My clip.mov"), b"data").unwrap();
+
+        let url = format!("{}/videos/{}/orig/My%20clip.mov", ts.url_base, mf.id);
+        let resp = Client::new().get(&url).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert_eq!(resp.headers().get("x-accel-redirect").unwrap(), &format!("/protected-videos/{}/orig/My%20clip.mov", mf.id));
+        assert_eq!(resp.headers().get("content-type").unwrap(), "video/quicktime");
+        assert!(resp.bytes().await.unwrap().is_empty());
+
+        // Missing files are still 404 from us, not the proxy
+        let resp = Client::new().get(format!("{}/videos/{}/nope.mp4", ts.url_base, mf.id)).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_comment_export()
+{
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];
+        open_media_file(&mut ws, &mf.id).await;
+        send_server_cmd!(ws, AddComment, AddComment{media_file_id: mf.id.clone(), comment: "Marker here".into(), timecode: Some("00:00:01:05".into()), ..Default::default()});
+        assert_eq!(expect_client_cmd!(&mut ws, AddComments).comments[0].frame, Some(30));    // Normalized frame number
+
+        let url = |fmt: &str| format!("{}/api/media/{}/comments/export?format={}", ts.url_base, mf.id, fmt);
+        let resp = Client::new().get(url("csv")).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        assert!(resp.headers()["content-type"].to_str().unwrap().starts_with("text/csv"));
+        assert!(resp.headers()["content-disposition"].to_str().unwrap().starts_with("attachment; filename=\"test0 comments.csv\""));
+        let csv = resp.text().await.unwrap();
+        assert!(csv.starts_with("comment_id,parent_id,timecode,"));
+        assert!(csv.contains(",00:00:01:05,30,1.200,Username for user.num1,user.num1,"));   // No fps in test data => 25 fps
+        assert_eq!(csv.lines().count(), 1 + ts.comments.iter().filter(|c| c.media_file_id == mf.id).count() + 1);
+
+        let resp = Client::new().get(url("edl")).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert!(resp.text().await.unwrap().contains("* LOC: 00:00:01:05 RED     Username for user.num1: Marker here\n"));
+
+        let resp = Client::new().get(url("nosuch")).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+        let resp = Client::new().get(format!("{}/api/media/NOSUCH/comments/export", ts.url_base)).header("X-Remote-User-Id", "user.num1").send().await.unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_comment_import()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::ImportComments;
+    api_test! {[ws, ts]
+        let mf = &ts.media_files[0];    // Owned by user.num1
+        open_media_file(&mut ws, &mf.id).await;
+
+        let csv = "id,parent_id,timecode,author,comment\n1,,00:00:01:05,Alice,Fix color\n2,1,,Bob,On it\n3,,00:00:02:00,,Mine\n";
+        send_server_cmd!(ws, ImportComments, ImportComments{media_file_id: mf.id.clone(), filename: "notes.csv".into(), content: csv.into(), format: None});
+        let mut added = vec![];
+        for _ in 0..3 {
+            added.extend(expect_client_cmd!(&mut ws, AddComments).comments);
+        }
+        let msg = expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        assert_eq!(msg.message, "Imported 3 comments (2 threads) from 'notes.csv'.");
+
+        assert_eq!((added[0].username_ifnull.as_str(), added[0].timecode.as_deref()), ("Alice", Some("00:00:01:05")));
+        assert_eq!(added[0].frame, Some(30));
+        assert_eq!(added[1].parent_id, Some(added[0].id.clone()));
+        assert_eq!(added[1].username_ifnull, "Bob");
+        assert_eq!(added[2].username_ifnull, "Username for user.num1");
+        assert!(added.iter().all(|c| c.user_id.as_deref() == Some("user.num1")));
+
+        // Unreadable file doesn't add anything
+        send_server_cmd!(ws, ImportComments, ImportComments{media_file_id: mf.id.clone(), filename: "bad.json".into(), content: "{oops".into(), format: None});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // Only the owner can import by default
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        send_server_cmd!(ws2, ImportComments, ImportComments{media_file_id: mf.id.clone(), filename: "x.csv".into(), content: csv.into(), format: None});
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+        expect_no_msg(&mut ws).await;
+    }
+}
+
+#[tokio::test]
+#[traced_test]
+async fn test_media_file_versions()
+{
+    use lib_clapshot_grpc::proto::client::client_to_server_cmd::CarryOverComments;
+    api_test! {[ws, ts]
+        let (v1, v2) = (&ts.media_files[0], &ts.media_files[2]);    // Both owned by user.num1
+        models::MediaFileVersion::add_version(&mut ts.db.conn().unwrap(), &v1.id, &v2.id).unwrap();
+
+        // Opening any version lists the whole stack
+        let versions = open_media_file(&mut ws, &v2.id).await.versions;
+        assert_eq!(versions.iter().map(|v| (v.media_file_id.as_str(), v.version)).collect::<Vec<_>>(), [(v1.id.as_str(), 1), (v2.id.as_str(), 2)]);
+        assert!(versions.iter().all(|v| v.stack_id == v1.id));
+        assert_eq!(versions[0].title, v1.title);
+        assert!(open_media_file(&mut ws, &ts.media_files[4].id).await.versions.is_empty());
+
+        // Carry over v1's three threads (five comments) to v2
+        open_media_file(&mut ws, &v2.id).await;
+        send_server_cmd!(ws, CarryOverComments, CarryOverComments{from_media_file_id: v1.id.clone(), to_media_file_id: v2.id.clone()});
+        let mut added = vec![];
+        for _ in 0..5 {
+            added.extend(expect_client_cmd!(&mut ws, AddComments).comments);
+        }
+        let msg = expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        assert_eq!(msg.message, format!("Carried over 5 comments (3 threads) from '{}'.", v1.title.clone().unwrap()));
+        assert!(added.iter().all(|c| c.media_file_id == v2.id));
+        assert!(ts.media_files_dir.join(&v2.id).join("drawings").join("drawing_0.webp").is_file());
+
+        // Repeating doesn't duplicate
+        send_server_cmd!(ws, CarryOverComments, CarryOverComments{from_media_file_id: v1.id.clone(), to_media_file_id: v2.id.clone()});
+        let msg = expect_user_msg(&mut ws, proto::user_message::Type::Ok).await;
+        assert!(msg.message.starts_with("Carried over 0 comments"));
+
+        // Not in the same stack
+        send_server_cmd!(ws, CarryOverComments, CarryOverComments{from_media_file_id: v1.id.clone(), to_media_file_id: ts.media_files[4].id.clone()});
+        expect_user_msg(&mut ws, proto::user_message::Type::Error).await;
+
+        // Only the target's owner can carry over by default
+        let mut ws2 = connect_client_ws(&ts.ws_url, "user.num2").await;
+        send_server_cmd!(ws2, CarryOverComments, CarryOverComments{from_media_file_id: v1.id.clone(), to_media_file_id: v2.id.clone()});
+        expect_user_msg(&mut ws2, proto::user_message::Type::Error).await;
+        expect_no_msg(&mut ws).await;
+
+        // Upload as a new version: target must exist and be editable by the uploader
+        let upload = |version_of: &str| {
+            let part = multipart::Part::stream("Testfile 1234").file_name("cut_v3.mp4").mime_str("video/mp4").unwrap();
+            Client::new().post(format!("http://127.0.0.1:{}/api/upload", ts.port))
+                .header("X-Remote-User-Id", "user.num1")
+                .header("X-Clapshot-Cookies", serde_json::json!({"version_of": version_of}).to_string())
+                .multipart(multipart::Form::new().part("fileupload", part)).send()
+        };
+        assert_eq!(upload("no_such_file").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+        assert_eq!(upload(&ts.media_files[1].id).await.unwrap().status(), reqwest::StatusCode::FORBIDDEN);
+        assert_eq!(upload(&v2.id).await.unwrap().status(), reqwest::StatusCode::OK);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        let up_res = ts.upload_res_rx.recv().unwrap();
+        assert_eq!(up_res.cookies.get("version_of"), Some(&v2.id));
+    }
+}
@@ -0,0 +1,463 @@
+// Resumable uploads, following the tus protocol v1.0.0 (https://tus.io/protocols/resumable-upload)
+// with the `creation`, `expiration` and `termination` extensions.
+//
+// Partial uploads are kept in `upload_dir/tus/<upload id>/`, as `data` (bytes received so far,
+// so its size is the current offset) and `info.json` (owner, filename, total length, cookies, expiry).
+// Both survive server restarts. When the last byte arrives, the data is moved to a regular upload dir
+// and submitted to the processing pipeline just like a multipart upload.
+// The full `Upload-Length` is reserved against the owner's quota from creation until ingestion.
+
+
+use futures_util::stream::StreamExt;
+use warp::http::{HeaderMap, Response, StatusCode};
+use std::collections::{BTreeSet, HashMap};
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use base64::{Engine as _, engine::general_purpose as Base64GP};
+
+use crate::video_pipeline::{IncomingFile, JobPriority};
+use crate::video_pipeline::upload_limits::PendingUploads;
+use super::auth::AuthUser;
+use super::server_state::ServerState;
+use super::file_upload::{check_upload_permission, check_quota, reject_upload};
+
+pub const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// How often to look for abandoned partial uploads
+pub const EXPIRY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15*60);
+
+/// IDs of uploads currently receiving data. Concurrent PATCHes to the same upload are refused.
+static ACTIVE_UPLOADS: parking_lot::Mutex<BTreeSet<String>> = parking_lot::Mutex::new(BTreeSet::new());
+
+
+/// Persistent state of a partial upload (`info.json`)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct TusUploadInfo {
+    user_id: String,
+    filename: String,
+    length: u64,
+    cookies: HashMap<String, String>,
+    expires: chrono::DateTime<chrono::Utc>,
+}
+
+fn tus_root(upload_dir: &Path) -> PathBuf {
+    upload_dir.join("tus")
+}
+
+fn upload_path(upload_dir: &Path, id: &str) -> Option<PathBuf> {
+    // IDs are uuids we made ourselves. Reject anything else, to stay inside upload dir.
+    uuid::Uuid::parse_str(id).ok()?;
+    Some(tus_root(upload_dir).join(id))
+}
+
+fn read_info(dir: &Path) -> anyhow::Result<TusUploadInfo> {
+    Ok(serde_json::from_slice(&std::fs::read(dir.join("info.json"))?)?)
+}
+
+fn write_info(dir: &Path, info: &TusUploadInfo) -> anyhow::Result<()> {
+    let tmp = dir.join("info.json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec(info)?)?;
+    std::fs::rename(tmp, dir.join("info.json"))?;
+    Ok(())
+}
+
+fn current_offset(dir: &Path) -> std::io::Result<u64> {
+    Ok(std::fs::metadata(dir.join("data"))?.len())
+}
+
+fn http_date(t: &chrono::DateTime<chrono::Utc>) -> String {
+    t.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn reply(status: StatusCode) -> warp::http::response::Builder {
+    Response::builder().status(status)
+        .header("Tus-Resumable", TUS_VERSION)
+        .header("Cache-Control", "no-store")
+}
+
+fn reply_text(status: StatusCode, msg: &str) -> Response<String> {
+    reply(status).body(msg.to_string()).unwrap()
+}
+
+/// Check Tus-Resumable header. Returns an error reply if the client speaks some other version.
+fn version_mismatch(hdrs: &HeaderMap) -> Option<Response<String>> {
+    match hdrs.get("Tus-Resumable").and_then(|v| v.to_str().ok()) {
+        Some(TUS_VERSION) => None,
+        _ => Some(reply(StatusCode::PRECONDITION_FAILED)
+            .header("Tus-Version", TUS_VERSION)
+            .body("Unsupported or missing Tus-Resumable version".into()).unwrap()),
+    }
+}
+
+/// Parse tus Upload-Metadata header (comma separated "key base64value" pairs)
+fn parse_upload_metadata(hdr: &str) -> HashMap<String, String> {
+    hdr.split(',').filter_map(|kv| {
+        let mut parts = kv.trim().splitn(2, ' ');
+        let key = parts.next().filter(|k| !k.is_empty())?;
+        let val = match parts.next() {
+            Some(v) => String::from_utf8(Base64GP::STANDARD.decode(v.trim()).ok()?).ok()?,
+            None => String::new(),
+        };
+        Some((key.to_string(), val))
+    }).collect()
+}
+
+/// Load upload state, if it exists, belongs to given user and hasn't expired.
+fn load_owned_upload(upload_dir: &Path, id: &str, user_id: &str) -> Option<(PathBuf, TusUploadInfo)> {
+    let dir = upload_path(upload_dir, id)?;
+    let info = read_info(&dir).ok()?;
+    if info.user_id != user_id || info.expires < chrono::Utc::now() {
+        return None;
+    }
+    Some((dir, info))
+}
+
+
+/// OPTIONS: advertise server capabilities
+pub async fn handle_tus_options() -> Result<Response<String>, Infallible>
+{
+    Ok(reply(StatusCode::NO_CONTENT)
+        .header("Tus-Version", TUS_VERSION)
+        .header("Tus-Extension", TUS_EXTENSIONS)
+        .body(String::new()).unwrap())
+}
+
+/// POST: create a new upload. Requires `Upload-Length` and `filename` (or `name`) in `Upload-Metadata`.
+///
+/// # Arguments
+/// * `hdrs` - Request headers (tus + authentication headers)
+/// * `server` - Server state
+pub async fn handle_tus_create(hdrs: HeaderMap, user: AuthUser, server: ServerState) -> Result<Response<String>, Infallible>
+{
+    if let Some(r) = version_mismatch(&hdrs) { return Ok(r); }
+    let AuthUser { user_id, user_name, is_admin, cookies } = user;
+
+    if let Err((msg, status)) = check_upload_permission(&user_id, &user_name, is_admin, &cookies, &server).await {
+        return Ok(reply_text(status, &msg));
+    }
+
+    let length = match hdrs.get("Upload-Length").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok()) {
+        Some(l) => l,
+        None => return Ok(reply_text(StatusCode::BAD_REQUEST, "Missing or bad Upload-Length (deferred length is not supported)")),
+    };
+    let meta = parse_upload_metadata(hdrs.get("Upload-Metadata").and_then(|v| v.to_str().ok()).unwrap_or(""));
+    let filename = match meta.get("filename").or(meta.get("name")) {
+        Some(f) => f.clone(),
+        None => return Ok(reply_text(StatusCode::BAD_REQUEST, "Missing filename in Upload-Metadata")),
+    };
+    let path = Path::new(&filename);
+    if filename.is_empty() || path.file_name() != Some(path.as_os_str()) {
+        return Ok(reply_text(StatusCode::BAD_REQUEST, "Filename must not contain path"));
+    }
+    let limits_res = match server.upload_limits.check_type(&filename, meta.get("filetype").map(|s| s.as_str())) {
+        Ok(_) => check_quota(&server, &user_id, length).await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = limits_res {
+        let (msg, status) = reject_upload(&user_id, &filename, &e, &server);
+        return Ok(reply_text(status, &msg));
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let _guard = claim_upload(&id);     // Keep expiry away from the half-created dir
+    let dir = tus_root(&server.upload_dir).join(&id);
+    let info = TusUploadInfo {
+        user_id: user_id.clone(),
+        filename,
+        length,
+        cookies,
+        expires: chrono::Utc::now() + server.upload_expiry,
+    };
+    let res = (|| -> anyhow::Result<()> {
+        std::fs::create_dir_all(&dir)?;
+        write_info(&dir, &info)?;
+        std::fs::File::create(dir.join("data"))?;
+        Ok(())
+    })();
+    if let Err(e) = res {
+        tracing::error!(details=%e, "Failed to create resumable upload dir");
+        return Ok(reply_text(StatusCode::INTERNAL_SERVER_ERROR, "Internal error: failed to create upload"));
+    }
+    server.upload_limits.pending.reserve(&dir, &user_id, length);
+
+    tracing::info!(upload_id=%id, user=%user_id, filename=%info.filename, length, "Resumable upload created.");
+    Ok(reply(StatusCode::CREATED)
+        .header("Location", format!("{}/api/upload/tus/{}", server.url_base, id))
+        .header("Upload-Expires", http_date(&info.expires))
+        .body(String::new()).unwrap())
+}
+
+/// HEAD: report current offset of an upload, so the client knows where to resume
+pub async fn handle_tus_head(id: String, hdrs: HeaderMap, user: AuthUser, server: ServerState) -> Result<Response<String>, Infallible>
+{
+    if let Some(r) = version_mismatch(&hdrs) { return Ok(r); }
+    let (dir, info) = match load_owned_upload(&server.upload_dir, &id, &user.user_id) {
+        Some(u) => u,
+        None => return Ok(reply_text(StatusCode::NOT_FOUND, "")),
+    };
+    match current_offset(&dir) {
+        Ok(offset) => Ok(reply(StatusCode::OK)
+            .header("Upload-Offset", offset)
+            .header("Upload-Length", info.length)
+            .header("Upload-Expires", http_date(&info.expires))
+            .body(String::new()).unwrap()),
+        Err(_) => Ok(reply_text(StatusCode::NOT_FOUND, "")),
+    }
+}
+
+/// Removes upload ID from ACTIVE_UPLOADS when dropped
+struct ActiveGuard(String);
+impl Drop for ActiveGuard {
+    fn drop(&mut self) { ACTIVE_UPLOADS.lock().remove(&self.0); }
+}
+
+/// Mark upload as busy (being created, receiving data or being removed), so that
+/// nothing else touches its dir until the returned guard is dropped.
+/// Returns None if it's already busy.
+fn claim_upload(id: &str) -> Option<ActiveGuard> {
+    ACTIVE_UPLOADS.lock().insert(id.to_string()).then(|| ActiveGuard(id.to_string()))
+}
+
+/// PATCH: append data to an upload at `Upload-Offset`.
+/// When the upload completes, it's submitted for processing.
+///
+/// # Arguments
+/// * `id` - Upload ID (from URL)
+/// * `hdrs` - Request headers (tus + authentication headers)
+/// * `server` - Server state
+/// * `upload_done` - Channel to submit the completed file to further processing
+/// * `body` - The request body (stream)
+pub async fn handle_tus_patch(
+    id: String,
+    hdrs: HeaderMap,
+    user: AuthUser,
+    server: ServerState,
+    upload_done: crossbeam_channel::Sender<IncomingFile>,
+    mut body: impl warp::Stream<Item = Result<impl bytes::Buf, warp::Error>> + Unpin)
+        -> Result<Response<String>, Infallible>
+{
+    if let Some(r) = version_mismatch(&hdrs) { return Ok(r); }
+    if hdrs.get("Content-Type").and_then(|v| v.to_str().ok()) != Some("application/offset+octet-stream") {
+        return Ok(reply_text(StatusCode::UNSUPPORTED_MEDIA_TYPE, "Content-Type must be application/offset+octet-stream"));
+    }
+    let (dir, mut info) = match load_owned_upload(&server.upload_dir, &id, &user.user_id) {
+        Some(u) => u,
+        None => return Ok(reply_text(StatusCode::NOT_FOUND, "")),
+    };
+    let client_offset = match hdrs.get("Upload-Offset").and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<u64>().ok()) {
+        Some(o) => o,
+        None => return Ok(reply_text(StatusCode::BAD_REQUEST, "Missing or bad Upload-Offset")),
+    };
+
+    let _guard = match claim_upload(&id) {
+        Some(g) => g,
+        None => return Ok(reply_text(StatusCode::CONFLICT, "Upload is already receiving data in another request")),
+    };
+
+    let mut offset = match current_offset(&dir) {
+        Ok(o) => o,
+        Err(_) => return Ok(reply_text(StatusCode::NOT_FOUND, "")),
+    };
+    if client_offset != offset {
+        return Ok(reply(StatusCode::CONFLICT).header("Upload-Offset", offset)
+            .body(format!("Upload-Offset mismatch, server has {} bytes", offset)).unwrap());
+    }
+
+    // Append body to data file. Whatever was received is kept, even if the connection drops.
+    let mut f = match async_std::fs::OpenOptions::new().append(true).open(dir.join("data")).await {
+        Ok(f) => f,
+        Err(e) => {
+            tracing::error!(upload_id=%id, details=%e, "Failed to open resumable upload data file");
+            return Ok(reply_text(StatusCode::INTERNAL_SERVER_ERROR, "Internal error: failed to open upload"));
+        }
+    };
+    let mut err: Option<(StatusCode, String)> = None;
+    while let Some(chunk) = body.next().await {
+        let mut chunk = match chunk {
+            Ok(c) => c,
+            Err(e) => { err = Some((StatusCode::BAD_REQUEST, format!("Upload interrupted: {}", e))); break; }
+        };
+        let data = chunk.copy_to_bytes(chunk.remaining());
+        if offset + data.len() as u64 > info.length {
+            err = Some((StatusCode::BAD_REQUEST, "Data exceeds Upload-Length".into()));
+            break;
+        }
+        if let Err(e) = futures_util::AsyncWriteExt::write_all(&mut f, &data).await {
+            tracing::error!(upload_id=%id, details=%e, "Failed to write resumable upload data");
+            err = Some((StatusCode::INTERNAL_SERVER_ERROR, "Internal error: failed to write upload".into()));
+            break;
+        }
+        offset += data.len() as u64;
+    }
+    if let Err(e) = futures_util::AsyncWriteExt::flush(&mut f).await {
+        tracing::warn!(upload_id=%id, details=%e, "Failed to flush resumable upload data");
+    }
+    drop(f);
+
+    // Activity extends the expiry time
+    info.expires = chrono::Utc::now() + server.upload_expiry;
+    if let Err(e) = write_info(&dir, &info) {
+        tracing::warn!(upload_id=%id, details=%e, "Failed to update resumable upload info");
+    }
+    if let Some((status, msg)) = err {
+        tracing::info!(upload_id=%id, offset, "Resumable upload PATCH ended early: {}", msg);
+        return Ok(reply(status).header("Upload-Offset", offset).body(msg).unwrap());
+    }
+
+    if offset == info.length {
+        if let Err(msg) = finish_upload(&id, &dir, &info, &server.upload_dir, &server.upload_limits.pending, &upload_done) {
+            return Ok(reply_text(StatusCode::INTERNAL_SERVER_ERROR, msg));
+        }
+    }
+
+    Ok(reply(StatusCode::NO_CONTENT)
+        .header("Upload-Offset", offset)
+        .header("Upload-Expires", http_date(&info.expires))
+        .body(String::new()).unwrap())
+}
+
+/// Move completed upload into a regular upload dir and submit it for processing.
+/// Its quota reservation moves along, and is released by the pipeline.
+fn finish_upload(id: &str, dir: &Path, info: &TusUploadInfo, upload_dir: &Path, pending: &PendingUploads, upload_done: &crossbeam_channel::Sender<IncomingFile>)
+    -> Result<(), &'static str>
+{
+    let new_dir = upload_dir.join(uuid::Uuid::new_v4().to_string());
+    let dst = new_dir.join(&info.filename);
+    let res = std::fs::create_dir_all(&new_dir)
+        .and_then(|_| std::fs::rename(dir.join("data"), &dst))
+        .and_then(|_| std::fs::remove_dir_all(dir));
+    if let Err(e) = res {
+        tracing::error!(upload_id=%id, details=%e, "Failed to move completed resumable upload");
+        return Err("Internal error: failed to finish upload");
+    }
+    tracing::info!(upload_id=%id, dst=dst.display().to_string(), "Resumable upload complete.");
+    pending.rename(dir, &dst);
+
+    if let Err(e) = upload_done.send(IncomingFile{ file_path: dst, user_id: info.user_id.clone(), cookies: info.cookies.clone(), priority: JobPriority::Interactive }) {
+        tracing::error!("Failed to send upload ok signal: {:?}", e);
+        pending.release(&e.0.file_path);
+        return Err("Internal error: failed to send upload ok signal");
+    }
+    Ok(())
+}
+
+/// DELETE: abort an upload and remove received data (termination extension)
+pub async fn handle_tus_delete(id: String, hdrs: HeaderMap, user: AuthUser, server: ServerState) -> Result<Response<String>, Infallible>
+{
+    if let Some(r) = version_mismatch(&hdrs) { return Ok(r); }
+    let dir = match load_owned_upload(&server.upload_dir, &id, &user.user_id) {
+        Some((dir, _)) => dir,
+        None => return Ok(reply_text(StatusCode::NOT_FOUND, "")),
+    };
+    let _guard = match claim_upload(&id) {
+        Some(g) => g,
+        None => return Ok(reply_text(StatusCode::CONFLICT, "Upload is receiving data")),
+    };
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        tracing::error!(upload_id=%id, details=%e, "Failed to delete resumable upload");
+        return Ok(reply_text(StatusCode::INTERNAL_SERVER_ERROR, "Internal error: failed to delete upload"));
+    }
+    server.upload_limits.pending.release(&dir);
+    tracing::info!(upload_id=%id, "Resumable upload terminated by client.");
+    Ok(reply(StatusCode::NO_CONTENT).body(String::new()).unwrap())
+}
+
+
+/// Reserve quota for partial uploads left over from before a restart
+pub fn reserve_partial_uploads(upload_dir: &Path, pending: &PendingUploads)
+{
+    let entries = match std::fs::read_dir(tus_root(upload_dir)) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        if let Ok(info) = read_info(&entry.path()) {
+            pending.reserve(&entry.path(), &info.user_id, info.length);
+        }
+    }
+}
+
+/// Delete partial uploads that have expired (or whose state is unreadable), and release their quota reservations.
+/// Uploads that are busy (see `claim_upload()`) are skipped, and stay claimed while they're checked and removed.
+/// Returns number of uploads removed.
+pub fn expire_abandoned_uploads(upload_dir: &Path, pending: &PendingUploads, now: chrono::DateTime<chrono::Utc>) -> usize
+{
+    let entries = match std::fs::read_dir(tus_root(upload_dir)) {
+        Ok(e) => e,
+        Err(_) => return 0,     // No resumable uploads yet
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let id = entry.file_name().to_string_lossy().to_string();
+        let _guard = match claim_upload(&id) {
+            Some(g) => g,
+            None => continue,
+        };
+        let expired = match read_info(&entry.path()) {
+            Ok(info) => info.expires < now,
+            Err(_) => true,
+        };
+        if expired {
+            match std::fs::remove_dir_all(entry.path()) {
+                Ok(_) => {
+                    pending.release(&entry.path());
+                    tracing::info!(upload_id=%id, "Removed expired partial upload.");
+                    removed += 1;
+                },
+                Err(e) => tracing::warn!(upload_id=%id, details=%e, "Failed to remove expired partial upload."),
+            }
+        }
+    }
+    removed
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_upload_metadata() {
+        let m = parse_upload_metadata("filename dGVzdC5tcDQ=,is_confidential, filetype dmlkZW8vbXA0");
+        assert_eq!(m.get("filename").unwrap(), "test.mp4");
+        assert_eq!(m.get("filetype").unwrap(), "video/mp4");
+        assert_eq!(m.get("is_confidential").unwrap(), "");
+        assert!(parse_upload_metadata("filename !!notbase64").is_empty());
+    }
+
+    #[test]
+    fn test_expire_abandoned_uploads() {
+        let tmp = tempfile::tempdir().unwrap();
+        let now = chrono::Utc::now();
+        let mk = |expires| {
+            let dir = tus_root(tmp.path()).join(uuid::Uuid::new_v4().to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            write_info(&dir, &TusUploadInfo { user_id: "u".into(), filename: "f.mp4".into(), length: 10, cookies: HashMap::new(), expires }).unwrap();
+            dir
+        };
+        let old = mk(now - chrono::Duration::hours(1));
+        let fresh = mk(now + chrono::Duration::hours(1));
+        let pending = PendingUploads::default();
+        reserve_partial_uploads(tmp.path(), &pending);
+        assert_eq!(pending.usage(Some("u")).bytes, 20);
+        let broken = tus_root(tmp.path()).join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&broken).unwrap();
+
+        // Busy uploads are left alone, even if expired
+        let busy = mk(now - chrono::Duration::hours(1));
+        let busy_guard = claim_upload(&busy.file_name().unwrap().to_string_lossy()).unwrap();
+
+        assert_eq!(expire_abandoned_uploads(tmp.path(), &pending, now), 2);
+        assert!(!old.exists() && !broken.exists());
+        assert!(fresh.exists() && busy.exists());
+        assert_eq!(pending.usage(Some("u")).bytes, 10);
+
+        drop(busy_guard);
+        assert_eq!(expire_abandoned_uploads(tmp.path(), &pending, now), 1);
+        assert!(!busy.exists());
+    }
+}
@@ -1,7 +1,8 @@
 use std::sync::Arc;
-use crate::{database::models::{self, MediaFile, Comment}, grpc::grpc_client::OrganizerConnection, client_cmd};
+use crate::{database::models::{self, MediaFile, Comment}, grpc::grpc_client::{OrganizerConnection, OrganizerRoles}, client_cmd};
 
 use super::{WsMsgSender, server_state::ServerState, SendTo};
+use super::share_links::ShareScope;
 use lib_clapshot_grpc::proto;
 use tracing::{debug, error};
 
@@ -71,6 +72,18 @@ pub enum AuthzError {
 
 pub type OpaqueGuard = Arc<tokio::sync::Mutex<dyn Send>>;
 
+/// Session's connection to one Organizer plugin
+#[derive (Clone)]
+pub struct OrgSessionConn {
+    pub roles: OrganizerRoles,
+    pub conn: Arc<tokio::sync::Mutex<OrganizerConnection>>,
+}
+
+/// Iterate over session's Organizer connections that have the given role, in configuration order.
+pub fn orgs_with_role(orgs: &[OrgSessionConn], role: fn(&OrganizerRoles) -> bool) -> impl Iterator<Item = &Arc<tokio::sync::Mutex<OrganizerConnection>>> {
+    orgs.iter().filter(move |o| role(&o.roles)).map(|o| &o.conn)
+}
+
 #[derive (Clone)]
 pub struct UserSession {
     pub sid: String,
@@ -84,8 +97,10 @@ pub struct UserSession {
     pub media_session_guard: Option<OpaqueGuard>,
     pub collab_session_guard: Option<OpaqueGuard>,
 
-    pub organizer: Option<Arc<tokio::sync::Mutex<OrganizerConnection>>>,
+    pub organizers: Vec<OrgSessionConn>,
     pub org_session: proto::org::UserSessionData,
+
+    pub share: Option<ShareScope>,      // Set for share link (guest) sessions
 }
 
 impl UserSession {
@@ -114,13 +129,28 @@ fn try_send_error<'a>(user_id: &str, server: &ServerState, msg: String, details:
 
 
 
-/// Check from Organizer if the user is allowed to perform given action.
+/// Combine authz decisions of two Organizers (deny-overrides).
+///
+/// Any denial wins, otherwise any approval wins, otherwise
+/// the decision is left to server defaults (None).
+pub fn combine_authz(a: Option<bool>, b: Option<bool>) -> Option<bool> {
+    match (a, b) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (None, None) => None,
+    }
+}
+
+/// Check from Organizers if the user is allowed to perform given action.
 ///
 /// Some(true) = allowed
 /// Some(false) = denied
 /// None = default, as determined by the server - no Organizer or it doesn't support authz
 ///
-/// If Organizer is not connected, returns None.
+/// All Organizers with the `authz` role are asked in order, and their answers
+/// combined with `combine_authz`. Querying stops at the first denial.
+///
+/// If no Organizer is connected, returns None.
 /// If check fails and Organizer is connected, logs an error and denies the action.
 /// If the user is not allowed, an error message is sent to the user if `msg_on_deny` is true.
 pub async fn org_authz<'a>(
@@ -128,7 +158,25 @@ pub async fn org_authz<'a>(
     desc: &str,
     msg_on_deny: bool,
     server: &ServerState,
-    organizer: &Option<Arc<tokio::sync::Mutex<OrganizerConnection>>>,
+    organizers: &[OrgSessionConn],
+    op: AuthzTopic<'a>,
+) -> Option<bool>
+{
+    let mut res = None;
+    for org in orgs_with_role(organizers, |r| r.authz) {
+        res = combine_authz(res, org_authz_single(session, desc, msg_on_deny, server, org, op.clone()).await);
+        if res == Some(false) { break; }
+    }
+    res
+}
+
+/// Ask a single Organizer for authz. See `org_authz`.
+async fn org_authz_single<'a>(
+    session: &proto::org::UserSessionData,
+    desc: &str,
+    msg_on_deny: bool,
+    server: &ServerState,
+    org: &Arc<tokio::sync::Mutex<OrganizerConnection>>,
     op: AuthzTopic<'a>,
 ) -> Option<bool>
 {
@@ -140,10 +188,6 @@ pub async fn org_authz<'a>(
         }
     };
 
-    let org = match &organizer {
-        Some(org) => org,
-        None => { return None; }
-    };
     tracing::debug!(op=?op, user=user_id, desc, "Checking authz from Organizer");
 
     use proto::org::authz_user_action_request as authz_op;
@@ -151,7 +195,7 @@ pub async fn org_authz<'a>(
         AuthzTopic::MediaFile(v, op) => authz_op::Op::MediaFileOp(
             authz_op::MediaFileOp {
                 op: op.into(),
-                media_file: Some(v.to_proto3(&server.url_base, vec![])) }), // omit subtitles for authz check
+                media_file: Some(v.to_proto3(&server.url_base, vec![], vec![], Some(&server.media_url_token(&v.id)))) }), // omit subtitles and approvals for authz check
         AuthzTopic::Comment(c, op) => authz_op::Op::CommentOp(
             authz_op::CommentOp {
                 op: op.into(),
@@ -204,11 +248,11 @@ pub async fn org_authz_with_default<'a>(
     desc: &str,
     msg_on_deny: bool,
     server: &ServerState,
-    organizer: &Option<Arc<tokio::sync::Mutex<OrganizerConnection>>>,
+    organizers: &[OrgSessionConn],
     default: bool,
     op: AuthzTopic<'a>,
 ) -> Result<(), AuthzError> {
-    if let Some(res) = org_authz(session, desc, msg_on_deny, server, organizer, op.clone()).await {
+    if let Some(res) = org_authz(session, desc, msg_on_deny, server, organizers, op.clone()).await {
         if res { Ok(()) } else { Err(AuthzError::Denied) }
     } else {
         if default { Ok(()) } else {
@@ -223,3 +267,23 @@ pub async fn org_authz_with_default<'a>(
         }
     }
 }
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combine_authz() {
+        let all = [None, Some(true), Some(false)];
+        for a in all {
+            assert_eq!(combine_authz(a, Some(false)), Some(false));
+            assert_eq!(combine_authz(Some(false), a), Some(false));
+            assert_eq!(combine_authz(a, None), a);
+            assert_eq!(combine_authz(None, a), a);
+        }
+        assert_eq!(combine_authz(Some(true), Some(true)), Some(true));
+    }
+}
@@ -0,0 +1,412 @@
+// Outgoing webhooks: POST a signed JSON payload to admin-configured endpoints when something happens
+// on the server, so external tools (chat bots, issue tracker sync) can react without an Organizer.
+//
+// Events come from the same `OrgEvent` relay as Organizer event hooks. Each one is turned into a payload
+// right away and stored in `webhook_deliveries`, once per interested endpoint. `run_webhook_sender()`
+// then sends them, retrying failures with exponential backoff. The table doubles as a delivery log
+// (`GET /api/admin/webhooks`).
+
+use std::path::Path;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+use anyhow::{bail, Context};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use lib_clapshot_grpc::proto;
+
+use crate::database::{models, DbBasicQuery, DB};
+use crate::grpc::org_events::{OrgEvent, OrgEventRequest};
+use super::server_state::ServerState;
+
+/// HMAC-SHA256 of the request body with the endpoint's secret, as `sha256=<hex>`
+pub const SIGNATURE_HEADER: &str = "X-Clapshot-Signature";
+pub const EVENT_HEADER: &str = "X-Clapshot-Event";
+pub const DELIVERY_HEADER: &str = "X-Clapshot-Delivery";
+
+/// Names of events that can be subscribed to
+pub const EVENT_NAMES: [&str; 8] = [
+    "media_file_added", "media_file_processed",
+    "comment_added", "comment_edited", "comment_deleted",
+    "subtitle_added", "subtitle_edited", "subtitle_deleted"];
+
+/// How many times to try a delivery before giving up on it
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// How often to check for deliveries whose retry backoff has passed
+pub const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Delay before the first retry. Doubled for every subsequent one.
+const RETRY_BASE_DELAY_SECS: i64 = 10;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Successful deliveries are pruned from the log after this many days
+const KEEP_DONE_DAYS: i64 = 7;
+
+
+/// Where to send events, and the shared secret to sign them with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookEndpoint {
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub events: Vec<String>,    // Event names to send (see EVENT_NAMES). Empty = all.
+}
+
+impl WebhookEndpoint {
+    pub fn wants(&self, event_name: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event_name)
+    }
+}
+
+/// Configured webhook endpoints (`--webhooks` file)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Webhooks {
+    pub endpoints: Vec<WebhookEndpoint>,
+}
+
+impl Webhooks {
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read webhooks from {:?}", path))?;
+        Self::from_json(&json).with_context(|| format!("Bad webhooks file {:?}", path))
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let cfg: Webhooks = serde_json::from_str(json)?;
+        for ep in &cfg.endpoints {
+            if !(ep.url.starts_with("http://") || ep.url.starts_with("https://")) {
+                bail!("Webhook URL must be http(s): '{}'", ep.url);
+            }
+            if ep.secret.len() < 16 {
+                bail!("Webhook secret for '{}' is too short (min. 16 characters)", ep.url);
+            }
+            if let Some(e) = ep.events.iter().find(|e| !EVENT_NAMES.contains(&e.as_str())) {
+                bail!("Unknown webhook event '{}' for '{}'. Valid events: {}", e, ep.url, EVENT_NAMES.join(", "));
+            }
+        }
+        Ok(cfg)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.endpoints.is_empty()
+    }
+}
+
+
+/// Webhook event name for a server event
+pub fn event_name(ev: &OrgEvent) -> &'static str {
+    match ev {
+        OrgEvent::MediaFileAdded { .. } => "media_file_added",
+        OrgEvent::MediaFileProcessed { .. } => "media_file_processed",
+        OrgEvent::CommentAdded { .. } => "comment_added",
+        OrgEvent::CommentEdited { .. } => "comment_edited",
+        OrgEvent::CommentDeleted { .. } => "comment_deleted",
+        OrgEvent::SubtitleAdded { .. } => "subtitle_added",
+        OrgEvent::SubtitleEdited { .. } => "subtitle_edited",
+        OrgEvent::SubtitleDeleted { .. } => "subtitle_deleted",
+    }
+}
+
+/// Build the JSON payload for an event, from the same protobuf objects Organizers get.
+/// Session details (session ID, cookies) are left out; only the acting user is included.
+///
+/// # Arguments
+/// * `ev` - Event
+/// * `db` - Database, for looking up objects the event refers to
+/// * `url_base` - Server URL base, for media file URLs
+pub fn event_payload(ev: &OrgEvent, db: &DB, url_base: &str) -> anyhow::Result<serde_json::Value>
+{
+    let mut payload = serde_json::json!({
+        "event": event_name(ev),
+        "time": chrono::Utc::now().to_rfc3339(),
+    });
+    let user = |ses: &Option<proto::org::UserSessionData>| ses.as_ref().and_then(|s| s.user.clone());
+    let fields = match ev {
+        OrgEvent::CommentDeleted { comment, ses } => serde_json::json!({ "comment": comment, "user": user(ses) }),
+        OrgEvent::SubtitleDeleted { subtitle, ses } => serde_json::json!({ "subtitle": subtitle, "user": user(ses) }),
+        OrgEvent::SubtitleEdited { subtitle_id, ses } => {
+            let sub = models::Subtitle::get(&mut db.conn()?, subtitle_id).context("Subtitle not found")?;
+            serde_json::json!({ "subtitle": sub.to_proto3(url_base, None), "user": user(ses) })
+        },
+        _ => match ev.to_request(db, url_base)? {
+            OrgEventRequest::MediaFileAdded(r) => serde_json::json!({ "media_file": r.media_file }),
+            OrgEventRequest::MediaFileProcessed(r) => serde_json::json!({
+                "media_file": r.media_file, "step": r.step().as_str_name(), "success": r.success, "details": r.details }),
+            OrgEventRequest::CommentAdded(r) => serde_json::json!({ "comment": r.comment, "user": user(&r.ses) }),
+            OrgEventRequest::CommentEdited(r) => serde_json::json!({ "comment": r.comment, "user": user(&r.ses) }),
+            OrgEventRequest::SubtitleAdded(r) => serde_json::json!({ "subtitle": r.subtitle, "user": user(&r.ses) }),
+        },
+    };
+    if let (Some(p), serde_json::Value::Object(f)) = (payload.as_object_mut(), fields) {
+        p.extend(f.into_iter().filter(|(_, v)| !v.is_null()));
+    }
+    Ok(payload)
+}
+
+/// Queue an event for delivery to all endpoints that want it.
+///
+/// # Returns
+/// * `usize` - Number of deliveries queued
+pub fn enqueue(db: &DB, hooks: &Webhooks, ev: &OrgEvent, url_base: &str) -> anyhow::Result<usize>
+{
+    let name = event_name(ev);
+    let targets: Vec<_> = hooks.endpoints.iter().filter(|ep| ep.wants(name)).collect();
+    if targets.is_empty() {
+        return Ok(0);
+    }
+    let payload = event_payload(ev, db, url_base)?.to_string();
+    let conn = &mut db.conn()?;
+    for ep in &targets {
+        models::WebhookDelivery::insert(conn, &models::WebhookDeliveryInsert {
+            endpoint_url: ep.url.clone(),
+            event_name: name.into(),
+            payload: payload.clone(),
+            state: models::WebhookDelivery::QUEUED.into(),
+        })?;
+    }
+    Ok(targets.len())
+}
+
+/// Sign a request body for `SIGNATURE_HEADER`
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+fn retry_delay_secs(attempts: i32) -> i64 {
+    RETRY_BASE_DELAY_SECS * 2i64.pow((attempts.max(1) - 1) as u32)
+}
+
+/// Send one delivery. Returns HTTP status (if any) and error (if not accepted with a 2xx).
+async fn send(client: &reqwest::Client, ep: &WebhookEndpoint, d: &models::WebhookDelivery) -> (Option<i32>, Option<String>)
+{
+    let res = client.post(&ep.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, sign(&ep.secret, &d.payload))
+        .header(EVENT_HEADER, &d.event_name)
+        .header(DELIVERY_HEADER, d.id.to_string())
+        .body(d.payload.clone())
+        .send().await;
+    match res {
+        Ok(r) if r.status().is_success() => (Some(r.status().as_u16() as i32), None),
+        Ok(r) => (Some(r.status().as_u16() as i32), Some(format!("Endpoint replied {}", r.status()))),
+        Err(e) => (None, Some(e.to_string())),
+    }
+}
+
+/// Send all queued deliveries whose retry backoff (if any) has passed, and record the results.
+///
+/// # Returns
+/// * `usize` - Number of deliveries attempted
+pub async fn deliver_due(db: &DB, hooks: &Webhooks, client: &reqwest::Client) -> anyhow::Result<usize>
+{
+    let due = models::WebhookDelivery::get_due(&mut db.conn()?, chrono::Utc::now().naive_utc())?;
+    for d in &due {
+        let (status, err) = match hooks.endpoints.iter().find(|ep| ep.url == d.endpoint_url) {
+            Some(ep) => send(client, ep, d).await,
+            None => {
+                // Removed from config after the event was queued
+                models::WebhookDelivery::set_attempt_result(&mut db.conn()?, d.id, None, Some("Endpoint no longer configured"), None)?;
+                continue;
+            }
+        };
+        let attempt = d.attempts + 1;
+        let retry_at = match &err {
+            Some(e) if attempt < MAX_ATTEMPTS => {
+                let delay = retry_delay_secs(attempt);
+                tracing::info!(delivery=d.id, url=%d.endpoint_url, attempt, delay_secs=delay, details=%e, "Webhook delivery failed. Will retry.");
+                Some(chrono::Utc::now().naive_utc() + chrono::Duration::seconds(delay))
+            },
+            Some(e) => {
+                tracing::warn!(delivery=d.id, url=%d.endpoint_url, attempts=attempt, details=%e, "Webhook delivery failed. Giving up.");
+                None
+            },
+            None => {
+                tracing::debug!(delivery=d.id, url=%d.endpoint_url, event=%d.event_name, "Webhook delivered.");
+                None
+            },
+        };
+        models::WebhookDelivery::set_attempt_result(&mut db.conn()?, d.id, status, err.as_deref(), retry_at)?;
+    }
+    Ok(due.len())
+}
+
+/// Send queued webhook deliveries until terminate flag is set.
+/// Deliveries left in the queue by a previous run are resumed.
+pub async fn run_webhook_sender(server: ServerState)
+{
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!(details=%e, "Failed to create HTTP client. Webhooks disabled.");
+            return;
+        }
+    };
+    let keep_done = chrono::Duration::days(KEEP_DONE_DAYS);
+    if let Err(e) = server.db.conn().and_then(|mut conn| models::WebhookDelivery::delete_done_before(&mut conn, chrono::Utc::now().naive_utc() - keep_done)) {
+        tracing::error!(details=%e, "Failed to prune old webhook deliveries.");
+    }
+    let mut last_check: Option<std::time::Instant> = None;
+    while !server.terminate_flag.load(Relaxed) {
+        if last_check.is_none_or(|t| t.elapsed() > POLL_INTERVAL) {
+            if let Err(e) = deliver_due(&server.db, &server.webhooks, &client).await {
+                tracing::error!(details=%e, "Error sending webhooks.");
+            }
+            last_check = Some(std::time::Instant::now());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::AtomicU16;
+    use warp::Filter;
+    use crate::database::tests::make_test_db;
+
+    type Received = Arc<Mutex<Vec<(warp::http::HeaderMap, String)>>>;
+
+    /// Start a local HTTP listener that records requests and replies with `status`
+    async fn start_listener(status: Arc<AtomicU16>) -> (String, Received) {
+        let received: Received = Arc::new(Mutex::new(vec![]));
+        let rec = received.clone();
+        let route = warp::post()
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |headers, body: bytes::Bytes| {
+                rec.lock().unwrap().push((headers, String::from_utf8_lossy(&body).to_string()));
+                warp::reply::with_status("", warp::http::StatusCode::from_u16(status.load(Relaxed)).unwrap())
+            });
+        let port = portpicker::pick_unused_port().expect("No free ports");
+        tokio::spawn(warp::serve(route).run(([127, 0, 0, 1], port)));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        (format!("http://127.0.0.1:{}/hook", port), received)
+    }
+
+    fn hooks_for(url: &str, events: &[&str]) -> Webhooks {
+        Webhooks { endpoints: vec![WebhookEndpoint {
+            url: url.into(),
+            secret: "0123456789abcdef".into(),
+            events: events.iter().map(|e| e.to_string()).collect() }] }
+    }
+
+    #[test]
+    fn test_webhooks_config() {
+        let cfg = Webhooks::from_json(r#"{"endpoints": [
+            {"url": "https://example.com/a", "secret": "0123456789abcdef"},
+            {"url": "http://example.com/b", "secret": "0123456789abcdef", "events": ["comment_added"]}]}"#).unwrap();
+        assert_eq!(cfg.endpoints.len(), 2);
+        assert!(cfg.endpoints[0].wants("subtitle_deleted"));
+        assert!(cfg.endpoints[1].wants("comment_added"));
+        assert!(!cfg.endpoints[1].wants("comment_edited"));
+
+        assert!(Webhooks::from_json(r#"{"endpoints": [{"url": "ftp://example.com", "secret": "0123456789abcdef"}]}"#).is_err());
+        assert!(Webhooks::from_json(r#"{"endpoints": [{"url": "https://example.com", "secret": "short"}]}"#).is_err());
+        assert!(Webhooks::from_json(r#"{"endpoints": [{"url": "https://example.com", "secret": "0123456789abcdef", "events": ["nope"]}]}"#).is_err());
+        assert!(Webhooks::from_json(r#"{"endpoints": [], "extra": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_webhook_enqueue_and_payload() {
+        let (db, _data_dir, media_files, comments) = make_test_db();
+        let hooks = hooks_for("http://localhost:1/hook", &["comment_deleted", "media_file_processed"]);
+
+        // Not subscribed
+        let ev = OrgEvent::CommentEdited { comment_id: comments[0].id, ses: None };
+        assert_eq!(enqueue(&db, &hooks, &ev, "http://localhost").unwrap(), 0);
+
+        // Deleted objects are sent as they were
+        let ev = OrgEvent::CommentDeleted { comment: comments[0].to_proto3(), ses: None };
+        assert_eq!(enqueue(&db, &hooks, &ev, "http://localhost").unwrap(), 1);
+
+        let ev = OrgEvent::MediaFileProcessed {
+            media_file_id: media_files[1].id.clone(),
+            step: proto::MediaProcessingStep::Transcode,
+            success: false,
+            details: Some("boom".into()) };
+        assert_eq!(enqueue(&db, &hooks, &ev, "http://localhost").unwrap(), 1);
+
+        let queued = models::WebhookDelivery::get_by_state(&mut db.conn().unwrap(), models::WebhookDelivery::QUEUED, Default::default()).unwrap();
+        assert_eq!(queued.len(), 2);
+        let del = queued.iter().find(|d| d.event_name == "comment_deleted").unwrap();
+        let p: serde_json::Value = serde_json::from_str(&del.payload).unwrap();
+        assert_eq!(p["event"], "comment_deleted");
+        assert_eq!(p["comment"]["id"], comments[0].id.to_string());
+        assert!(p.get("user").is_none());
+
+        let proc = queued.iter().find(|d| d.event_name == "media_file_processed").unwrap();
+        let p: serde_json::Value = serde_json::from_str(&proc.payload).unwrap();
+        assert_eq!(p["media_file"]["id"], media_files[1].id);
+        assert_eq!(p["step"], "TRANSCODE");
+        assert_eq!(p["success"], false);
+        assert_eq!(p["details"], "boom");
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivery_and_retry() {
+        let (db, _data_dir, _media_files, comments) = make_test_db();
+        let status = Arc::new(AtomicU16::new(200));
+        let (url, received) = start_listener(status.clone()).await;
+        let hooks = hooks_for(&url, &[]);
+        let client = reqwest::Client::new();
+
+        // Successful delivery, signed with the shared secret
+        let ev = OrgEvent::CommentAdded { comment_id: comments[1].id, ses: None };
+        assert_eq!(enqueue(&db, &hooks, &ev, "http://localhost").unwrap(), 1);
+        assert_eq!(deliver_due(&db, &hooks, &client).await.unwrap(), 1);
+        {
+            let rec = received.lock().unwrap();
+            assert_eq!(rec.len(), 1);
+            let (headers, body) = &rec[0];
+            assert_eq!(headers[SIGNATURE_HEADER].to_str().unwrap(), sign("0123456789abcdef", body));
+            assert_eq!(headers[EVENT_HEADER].to_str().unwrap(), "comment_added");
+            let p: serde_json::Value = serde_json::from_str(body).unwrap();
+            assert_eq!(p["comment"]["id"], comments[1].id.to_string());
+        }
+        let done = models::WebhookDelivery::get_by_state(&mut db.conn().unwrap(), models::WebhookDelivery::DONE, Default::default()).unwrap();
+        assert_eq!(done.len(), 1);
+        assert_eq!((done[0].attempts, done[0].response_status), (1, Some(200)));
+
+        // Failed delivery is rescheduled with backoff, not retried right away
+        status.store(500, Relaxed);
+        assert_eq!(enqueue(&db, &hooks, &ev, "http://localhost").unwrap(), 1);
+        assert_eq!(deliver_due(&db, &hooks, &client).await.unwrap(), 1);
+        assert_eq!(deliver_due(&db, &hooks, &client).await.unwrap(), 0);
+        let queued = models::WebhookDelivery::get_by_state(&mut db.conn().unwrap(), models::WebhookDelivery::QUEUED, Default::default()).unwrap();
+        assert_eq!(queued.len(), 1);
+        let d = &queued[0];
+        assert_eq!((d.attempts, d.response_status), (1, Some(500)));
+        assert!(d.error.as_ref().unwrap().contains("500"));
+        let delay = (d.next_attempt.unwrap() - d.last_attempt.unwrap()).num_seconds();
+        assert!((RETRY_BASE_DELAY_SECS-1..=RETRY_BASE_DELAY_SECS).contains(&delay));
+        assert_eq!(retry_delay_secs(3), RETRY_BASE_DELAY_SECS * 4);
+
+        // Give up after MAX_ATTEMPTS
+        for _ in 1..MAX_ATTEMPTS {
+            let mut d = models::WebhookDelivery::get(&mut db.conn().unwrap(), &d.id).unwrap();
+            d.next_attempt = None;
+            diesel::RunQueryDsl::execute(diesel::update(&d).set(&d), &mut db.conn().unwrap()).unwrap();
+            assert_eq!(deliver_due(&db, &hooks, &client).await.unwrap(), 1);
+        }
+        let failed = models::WebhookDelivery::get_by_state(&mut db.conn().unwrap(), models::WebhookDelivery::FAILED, Default::default()).unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].attempts, MAX_ATTEMPTS);
+        assert_eq!(received.lock().unwrap().len(), 1 + MAX_ATTEMPTS as usize);
+
+        // Endpoint removed from config
+        assert_eq!(enqueue(&db, &hooks, &ev, "http://localhost").unwrap(), 1);
+        assert_eq!(deliver_due(&db, &Webhooks::default(), &client).await.unwrap(), 1);
+        let failed = models::WebhookDelivery::get_by_state(&mut db.conn().unwrap(), models::WebhookDelivery::FAILED, Default::default()).unwrap();
+        assert_eq!(failed.len(), 2);
+        assert!(failed.iter().any(|d| d.error.as_deref() == Some("Endpoint no longer configured")));
+    }
+}
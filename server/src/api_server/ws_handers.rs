@@ -7,7 +7,7 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::str::FromStr;
 use lib_clapshot_grpc::proto::client::ClientToServerCmd;
-use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddSubtitle, CollabReport, DelComment, DelMediaFile, DelSubtitle, EditComment, EditSubtitleInfo, JoinCollab, LeaveCollab, OpenMediaFile, OpenNavigationPage, RenameMediaFile, ReorderItems};
+use lib_clapshot_grpc::proto::client::client_to_server_cmd::{AddSubtitle, CancelMediaFileProcessing, CollabReport, DelComment, DelMediaFile, DelSubtitle, EditComment, EditSubtitleInfo, JoinCollab, LeaveCollab, OpenMediaFile, OpenNavigationPage, RenameMediaFile, ReorderItems, RerunMediaFileProcessing};
 use parking_lot::RwLock;
 type WsMsg = warp::ws::Message;
 
@@ -32,6 +32,9 @@ use crate::api_server::server_state::ServerState;
 use crate::api_server::user_session::Topic;
 use crate::database::error::DBError;
 use crate::database::{models, DBPaging, DbBasicQuery, DbQueryByMediaFile, DbQueryByUser, DbUpdate, DB};
+use crate::video_pipeline::{PipelineCmd, ProcessingStep};
+use crate::grpc::org_events::OrgEvent;
+use crate::grpc::db_models::comment_status_from_proto3;
 use crate::{client_cmd, optional_str_to_i32_or_tonic_error, send_user_error, send_user_ok, str_to_i32_or_tonic_error};
 
 use lib_clapshot_grpc::proto;
@@ -63,10 +66,10 @@ async fn get_media_file_or_send_error(media_file_id: Option<&str>, ses: &Option<
 /// Send user a navigation page to browse the files / folders they have (and/or something else, if Organizer handles it).
 pub async fn msg_open_navigation_page(data: &OpenNavigationPage , ses: &mut UserSession, server: &ServerState) -> Res<()> {
     org_authz_with_default(&ses.org_session, "list media files", true, server,
-        &ses.organizer, true, AuthzTopic::Other(None, authz_req::other_op::Op::ViewHome)).await?;
+        &ses.organizers, true, AuthzTopic::Other(None, authz_req::other_op::Op::ViewHome)).await?;
 
-    // Try to delegate request to Organizer.
-    if let Some(org) = &ses.organizer {
+    // Try to delegate request to Organizers, first one to implement it wins.
+    for org in user_session::orgs_with_role(&ses.organizers, |r| r.navigation) {
         let req = proto::org::NavigatePageRequest {
             ses: Some(ses.org_session.clone()),
             page_id: data.page_id.clone(),
@@ -101,7 +104,8 @@ pub async fn msg_open_navigation_page(data: &OpenNavigationPage , ses: &mut User
     let mut media_files: Vec<proto::MediaFile> = Vec::new();
     for m in models::MediaFile::get_by_user(&mut server.db.conn()?, &ses.user_id, DBPaging::default())? {
         let subs = models::Subtitle::get_by_media_file(&mut server.db.conn()?, &m.id, DBPaging::default())?;
-        media_files.push(m.to_proto3(&server.url_base, subs));
+        let approvals = m.get_approvals(&mut server.db.conn()?)?;
+        media_files.push(m.to_proto3(&server.url_base, subs, approvals, Some(&server.media_url_token(&m.id))));
     }
 
     let h_txt = if media_files.is_empty() { "<h2>You have no media yet.</h2>" } else { "<h2>All your media files</h2>" };
@@ -122,7 +126,7 @@ pub async fn msg_open_navigation_page(data: &OpenNavigationPage , ses: &mut User
 pub async fn msg_open_media_file(data: &OpenMediaFile, ses: &mut UserSession, server: &ServerState) -> Res<()> {
     if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
         org_authz_with_default(&ses.org_session,
-            "open media file", true, server, &ses.organizer,
+            "open media file", true, server, &ses.organizers,
             true, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
         send_open_media_file_cmd(server, &ses.sid, &v.id).await?;
         ses.cur_media_file_id = Some(v.id);
@@ -136,12 +140,14 @@ pub async fn send_open_media_file_cmd(server: &ServerState, session_id: &str, me
     let conn = &mut server.db.conn()?;
     let v_db = models::MediaFile::get(conn, &media_file_id.into())?;
     let subs = models::Subtitle::get_by_media_file(conn, media_file_id, DBPaging::default())?;
-    let v = v_db.to_proto3(&server.url_base, subs);
+    let v = v_db.to_proto3(&server.url_base, subs, v_db.get_approvals(conn)?, Some(&server.media_url_token(&v_db.id)));
     if v.playback_url.is_none() {
         return Err(anyhow!("No playback file"));
     }
+    let stack = models::MediaFileVersion::get_stack_of(conn, media_file_id)?;
+    let versions = super::media_versions::stack_to_proto(conn, &stack)?;
     server.emit_cmd(
-        client_cmd!(OpenMediaFile, {media_file: Some(v)}),
+        client_cmd!(OpenMediaFile, {media_file: Some(v), versions: versions}),
         super::SendTo::UserSession(session_id))?;
     let mut cmts = vec![];
     for mut c in models::Comment::get_by_media_file(conn, media_file_id, DBPaging::default())? {
@@ -163,7 +169,7 @@ pub async fn del_media_file_and_cleanup(media_file_id: &str, ses: Option<&mut Us
         // Check authorization against user session, if provided
         if let Some(ses) = &ses {
             let default_perm = ses.user_id == (&v).user_id || ses.is_admin;
-            org_authz_with_default(&ses.org_session, "delete media file", true, server, &ses.organizer,
+            org_authz_with_default(&ses.org_session, "delete media file", true, server, &ses.organizers,
                 default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Delete)).await?;
         }
 
@@ -223,10 +229,60 @@ pub async fn msg_del_media_file(data: &DelMediaFile, ses: &mut UserSession, serv
 }
 
 
+/// Ask media processing pipeline to cancel or re-run processing of a media file.
+/// Pipeline reports the result to the requesting user (or media file owner, if called by Organizer).
+///
+/// # Arguments
+/// * `media_file_id` - Media file to reprocess
+/// * `rerun` - Step to re-run (and optional new video bitrate), or None to cancel processing
+/// * `ses` - User session to authorize against, or None if called by Organizer
+pub async fn reprocess_media_file(media_file_id: &str, rerun: Option<(proto::MediaProcessingStep, Option<u32>)>, ses: Option<&mut UserSession>, server: &ServerState) -> Res<()> {
+    tracing::info!(media_file_id=media_file_id, rerun=?rerun, user_id=ses.as_ref().map(|u|u.user_id.clone()), "Reprocessing media file.");
+
+    if let Some(v) = get_media_file_or_send_error(Some(media_file_id), &ses, server).await? {
+        if let Some(ses) = &ses {
+            let default_perm = ses.user_id == v.user_id || ses.is_admin;
+            org_authz_with_default(&ses.org_session, if rerun.is_some() { "re-run media processing" } else { "cancel media processing" }, true,
+                server, &ses.organizers, default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Reprocess)).await?;
+        }
+        let user_id = ses.map(|s| s.user_id.clone()).unwrap_or(v.user_id.clone());
+        let cmd = match rerun {
+            None => PipelineCmd::Cancel { media_file_id: v.id, user_id },
+            Some((step, video_bitrate)) => PipelineCmd::Rerun {
+                media_file_id: v.id,
+                user_id,
+                step: match step {
+                    proto::MediaProcessingStep::Transcode => ProcessingStep::Transcode,
+                    proto::MediaProcessingStep::Thumbnails => ProcessingStep::Thumbnails,
+                    proto::MediaProcessingStep::Adaptive => ProcessingStep::Adaptive,
+                },
+                video_bitrate,
+            },
+        };
+        server.pipeline_tx.send(cmd).context("Media processing pipeline is not running")?;
+    }
+    Ok(())
+}
+
+
+pub async fn msg_cancel_media_file_processing(data: &CancelMediaFileProcessing, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    reprocess_media_file(&data.media_file_id, None, Some(ses), server).await
+}
+
+
+pub async fn msg_rerun_media_file_processing(data: &RerunMediaFileProcessing, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if data.video_bitrate == Some(0) {
+        send_user_error!(&ses.user_id, server, Topic::MediaFile(&data.media_file_id), "Invalid video bitrate");
+        return Ok(());
+    }
+    reprocess_media_file(&data.media_file_id, Some((data.step(), data.video_bitrate)), Some(ses), server).await
+}
+
+
 pub async fn msg_rename_media_file(data: &RenameMediaFile, ses: &mut UserSession, server: &ServerState) -> Res<()> {
     if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
         let default_perm = ses.user_id == (&v).user_id || ses.is_admin;
-        org_authz_with_default(&ses.org_session, "rename media file", true, server, &ses.organizer,
+        org_authz_with_default(&ses.org_session, "rename media file", true, server, &ses.organizers,
             default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Rename)).await?;
 
         let new_name = data.new_name.trim();
@@ -249,15 +305,16 @@ pub async fn msg_rename_media_file(data: &RenameMediaFile, ses: &mut UserSession
 
 pub async fn msg_add_comment(data: &proto::client::client_to_server_cmd::AddComment, ses: &mut UserSession, server: &ServerState) -> Res<()> {
 
-    let media_file_id = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+    let media_file = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
         Some(v) => {
             let default_perm = true;    // anyone can comment on any media file
-            org_authz_with_default(&ses.org_session, "comment media file", true, server, &ses.organizer,
+            org_authz_with_default(&ses.org_session, "comment media file", true, server, &ses.organizers,
                 default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Comment)).await?;
-            v.id
+            v
         },
         None => return Ok(()),
     };
+    let media_file_id = media_file.id.clone();
 
     // Parse drawing data if present and write to file
     let mut drwn = data.drawing.clone();
@@ -303,10 +360,15 @@ pub async fn msg_add_comment(data: &proto::client::client_to_server_cmd::AddComm
         timecode: data.timecode.clone(),
         drawing: drwn.clone(),
         subtitle_id: optional_str_to_i32_or_tonic_error!(data.subtitle_id)?,
-        subtitle_filename_ifnull: None
+        subtitle_filename_ifnull: None,
+        frame: crate::timecode::comment_frame(&media_file, data.timecode.as_deref()),
+        assignee_id: None,
     };
     let c = models::Comment::insert(&mut server.db.conn()?, &c)
         .map_err(|e| anyhow!("Failed to add comment: {:?}", e))?;
+    server.emit_org_event(OrgEvent::CommentAdded { comment_id: c.id, ses: Some(ses.org_session.clone()) });
+    let mentioned = notify_mentions(&c, None, &media_file, ses, server)?;
+    notify_reply(&c, &mentioned, &media_file, ses, server)?;
     // Send to all clients watching this media file
     ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&media_file_id)).await?;
     Ok(())
@@ -320,18 +382,13 @@ pub async fn msg_edit_comment(data: &EditComment, ses: &mut UserSession, server:
     match models::Comment::get(conn, &id) {
         Ok(old) => {
             let default_perm = Some(&ses.user_id) == old.user_id.as_ref() || ses.is_admin;
-            org_authz_with_default(&ses.org_session, "edit comment", true, server, &ses.organizer,
+            org_authz_with_default(&ses.org_session, "edit comment", true, server, &ses.organizers,
                 default_perm, AuthzTopic::Comment(&old, authz_req::comment_op::Op::Edit)).await?;
 
-            let vid = &old.media_file_id;
             models::Comment::edit(conn, id, &data.new_comment)?;
-
-            server.emit_cmd(
-                client_cmd!(DelComment, {comment_id: id.to_string()}),
-                super::SendTo::MediaFileId(&vid))?;
-
-            let c = models::Comment::get(conn, &id)?;
-            ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&vid)).await?;
+            let edited = models::Comment::get(conn, &id)?;
+            notify_mentions(&edited, Some(&old.comment), &models::MediaFile::get(conn, &old.media_file_id)?, ses, server)?;
+            emit_comment_changed(id, ses, server).await?;
         }
         Err(DBError::NotFound()) => {
             send_user_error!(&ses.user_id, server, Topic::None, "Failed to edit comment.", "No such comment. Cannot edit.", true);
@@ -341,6 +398,119 @@ pub async fn msg_edit_comment(data: &EditComment, ses: &mut UserSession, server:
     Ok(())
 }
 
+/// Send a persistent message to users @mentioned in a new or edited comment, and
+/// tell the author about mentions that didn't match any user.
+/// On edit, `old_text` is the previous comment text; users already mentioned there aren't notified again.
+/// Returns IDs of the notified users.
+fn notify_mentions(c: &models::Comment, old_text: Option<&str>, media_file: &models::MediaFile, ses: &UserSession, server: &ServerState) -> Res<Vec<String>> {
+    let (users, unknown) = super::mentions::mentions_to_notify(&mut server.db.conn()?, &c.comment, old_text, &ses.user_id)?;
+    let title = media_file.title.clone().unwrap_or(media_file.id.clone());
+    for u in &users {
+        server.push_notify_message(&models::MessageInsert {
+            event_name: crate::grpc::db_models::MENTION_EVENT.to_string(),
+            user_id: u.id.clone(),
+            seen: false,
+            media_file_id: Some(c.media_file_id.clone()),
+            comment_id: Some(c.id),
+            subtitle_id: None,
+            message: format!("'{}' mentioned you in a comment on '{}'.", ses.user_name, title),
+            details: c.comment.clone(),
+        }, super::SendTo::UserId(&u.id), true)?;
+    }
+    if !unknown.is_empty() {
+        let names = unknown.iter().map(|m| format!("@{}", m)).collect::<Vec<_>>().join(", ");
+        send_user_error!(&ses.user_id, server, Topic::Comment(c.id), "Some mentioned users were not found.", format!("Unknown: {}", names), false);
+    }
+    Ok(users.into_iter().map(|u| u.id).collect())
+}
+
+/// Send a persistent message to the author of the comment that `c` replies to,
+/// unless they wrote the reply themselves or were already notified of it (`skip_ids`, e.g. by a mention).
+fn notify_reply(c: &models::Comment, skip_ids: &[String], media_file: &models::MediaFile, ses: &UserSession, server: &ServerState) -> Res<()> {
+    let Some(parent_id) = c.parent_id else { return Ok(()) };
+    let parent = models::Comment::get(&mut server.db.conn()?, &parent_id)?;
+    let Some(uid) = parent.user_id.filter(|uid| uid != &ses.user_id && !skip_ids.contains(uid)) else { return Ok(()) };
+    server.push_notify_message(&models::MessageInsert {
+        event_name: crate::grpc::db_models::COMMENT_REPLY_EVENT.to_string(),
+        user_id: uid.clone(),
+        seen: false,
+        media_file_id: Some(c.media_file_id.clone()),
+        comment_id: Some(c.id),
+        subtitle_id: None,
+        message: format!("'{}' replied to your comment on '{}'.", ses.user_name, media_file.title.clone().unwrap_or(media_file.id.clone())),
+        details: c.comment.clone(),
+    }, super::SendTo::UserId(&uid), true)
+}
+
+/// Notify Organizers about a changed comment, and replace it for everyone viewing the media file.
+async fn emit_comment_changed(comment_id: i32, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    server.emit_org_event(OrgEvent::CommentEdited { comment_id, ses: Some(ses.org_session.clone()) });
+    let c = models::Comment::get(&mut server.db.conn()?, &comment_id)?;
+    let vid = c.media_file_id.clone();
+    server.emit_cmd(
+        client_cmd!(DelComment, {comment_id: comment_id.to_string()}),
+        super::SendTo::MediaFileId(&vid))?;
+    ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&vid)).await?;
+    Ok(())
+}
+
+/// Get a top-level comment (thread starter) and its media file, or send an error to the user.
+/// Only these have a review status and assignee.
+async fn get_thread_root_or_send_error(comment_id: &str, what: &str, ses: &mut UserSession, server: &ServerState) -> Res<Option<(models::Comment, models::MediaFile)>> {
+    let id = i32::from_str(comment_id)?;
+    let conn = &mut server.db.conn()?;
+    match models::Comment::get(conn, &id) {
+        Ok(c) if c.parent_id.is_some() => {
+            send_user_error!(&ses.user_id, server, Topic::MediaFile(&c.media_file_id), format!("Failed to {}.", what), "Only top-level comments have a status and assignee.", true);
+            Ok(None)
+        }
+        Ok(c) => {
+            let mf = models::MediaFile::get(conn, &c.media_file_id)?;
+            Ok(Some((c, mf)))
+        }
+        Err(DBError::NotFound()) => {
+            send_user_error!(&ses.user_id, server, Topic::None, format!("Failed to {}.", what), "No such comment.", true);
+            Ok(None)
+        }
+        Err(e) => { bail!(e); }
+    }
+}
+
+/// Resolve, won't-fix or reopen a comment thread.
+/// Allowed by default for the comment's author and assignee, the media file's owner, and admins.
+pub async fn msg_set_comment_status(data: &proto::client::client_to_server_cmd::SetCommentStatus, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let Some((cmt, mf)) = get_thread_root_or_send_error(&data.comment_id, "change comment status", ses, server).await? else { return Ok(()) };
+    let default_perm = ses.is_admin || [cmt.user_id.as_ref(), cmt.assignee_id.as_ref(), Some(&mf.user_id)].contains(&Some(&ses.user_id));
+    org_authz_with_default(&ses.org_session, "change comment status", true, server, &ses.organizers,
+        default_perm, AuthzTopic::Comment(&cmt, authz_req::comment_op::Op::Resolve)).await?;
+
+    let new_status = comment_status_from_proto3(data.status());
+    models::Comment::set_status(&mut server.db.conn()?, cmt.id, new_status, &ses.user_id)?;
+    tracing::info!(comment_id=cmt.id, user=%ses.user_id, status=new_status, "Comment status changed.");
+    emit_comment_changed(cmt.id, ses, server).await
+}
+
+/// Set or clear the assignee of a comment thread. The assignee must be a known user.
+/// Allowed by default for the comment's author, the media file's owner, and admins.
+pub async fn msg_assign_comment(data: &proto::client::client_to_server_cmd::AssignComment, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let Some((cmt, mf)) = get_thread_root_or_send_error(&data.comment_id, "assign comment", ses, server).await? else { return Ok(()) };
+    let default_perm = ses.is_admin || [cmt.user_id.as_ref(), Some(&mf.user_id)].contains(&Some(&ses.user_id));
+    org_authz_with_default(&ses.org_session, "assign comment", true, server, &ses.organizers,
+        default_perm, AuthzTopic::Comment(&cmt, authz_req::comment_op::Op::Assign)).await?;
+
+    let conn = &mut server.db.conn()?;
+    let assignee = data.assignee_id.as_deref().map(str::trim).filter(|s| !s.is_empty());
+    if let Some(a) = assignee {
+        if let Err(DBError::NotFound()) = models::User::get(conn, &a.to_string()) {
+            send_user_error!(&ses.user_id, server, Topic::MediaFile(&mf.id), "Failed to assign comment.", format!("No such user: '{}'", a), true);
+            return Ok(());
+        }
+    }
+    models::Comment::set_assignee(conn, cmt.id, assignee)?;
+    tracing::info!(comment_id=cmt.id, user=%ses.user_id, assignee=?assignee, "Comment assigned.");
+    emit_comment_changed(cmt.id, ses, server).await
+}
+
 
 pub async fn msg_del_comment(data: &DelComment, ses: &mut UserSession, server: &ServerState) -> Res<()> {
     let id = i32::from_str(&data.comment_id)?;
@@ -348,10 +518,10 @@ pub async fn msg_del_comment(data: &DelComment, ses: &mut UserSession, server: &
     match models::Comment::get(conn, &id) {
         Ok(cmt) => {
             let default_perm = Some(&ses.user_id) == cmt.user_id.as_ref() || ses.is_admin;
-            org_authz_with_default(&ses.org_session, "delete comment", true, server, &ses.organizer,
+            org_authz_with_default(&ses.org_session, "delete comment", true, server, &ses.organizers,
                 default_perm, AuthzTopic::Comment(&cmt, authz_req::comment_op::Op::Delete)).await?;
 
-            let vid = cmt.media_file_id;
+            let vid = cmt.media_file_id.clone();
             if Some(&ses.user_id) != cmt.user_id.as_ref() && !ses.is_admin {
                 send_user_error!(&ses.user_id, server, Topic::MediaFile(&vid), "Failed to delete comment.", "You can only delete your own comments", true);
                 return Ok(());
@@ -361,7 +531,9 @@ pub async fn msg_del_comment(data: &DelComment, ses: &mut UserSession, server: &
                 send_user_error!(&ses.user_id, server, Topic::MediaFile(&vid), "Failed to delete comment.", "Comment has replies. Cannot delete.", true);
                 return Ok(());
             }
+            let deleted = cmt.to_proto3();
             models::Comment::delete(conn, &id)?;
+            server.emit_org_event(OrgEvent::CommentDeleted { comment: deleted, ses: Some(ses.org_session.clone()) });
             server.emit_cmd(
                 client_cmd!(DelComment, {comment_id: id.to_string()}),
                 super::SendTo::MediaFileId(&vid))?;
@@ -379,7 +551,7 @@ pub async fn msg_add_subtitle(data: &AddSubtitle, ses: &mut UserSession, server:
     let mf = match get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
         Some(v) => {
             let default_perm = ses.user_id == (&v).user_id || ses.is_admin;
-            org_authz_with_default(&ses.org_session, "add subtitle", true, server, &ses.organizer,
+            org_authz_with_default(&ses.org_session, "add subtitle", true, server, &ses.organizers,
                 default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Edit)).await?;
             v
         },
@@ -471,6 +643,8 @@ pub async fn msg_add_subtitle(data: &AddSubtitle, ses: &mut UserSession, server:
         filename: playback_filename,
         time_offset: 0.0,
     }) .map_err(|e| anyhow!("Failed to add subtitle: {:?}", e))?;
+    crate::database::search::index_subtitle_file(conn, &server.media_files_dir, &new_sub);
+    server.emit_org_event(OrgEvent::SubtitleAdded { subtitle_id: new_sub.id, ses: Some(ses.org_session.clone()) });
 
     let all_subs = models::Subtitle::get_by_media_file(conn, &mf.id, DBPaging::default())?;
     if all_subs.len() == 1 {
@@ -491,7 +665,7 @@ pub async fn msg_edit_subtitle_info(data: &EditSubtitleInfo, ses: &mut UserSessi
     let mf = models::MediaFile::get(conn, &sub.media_file_id).map_err(|e| anyhow!("Failed to get media file: {:?}", e))?;
 
     let default_perm = ses.user_id == mf.user_id || ses.is_admin;
-    org_authz_with_default(&ses.org_session, "edit subtitle", true, server, &ses.organizer,
+    org_authz_with_default(&ses.org_session, "edit subtitle", true, server, &ses.organizers,
         default_perm, AuthzTopic::MediaFile(&mf, authz_req::media_file_op::Op::Edit)).await?;
 
     // Update subtitle in DB
@@ -508,6 +682,7 @@ pub async fn msg_edit_subtitle_info(data: &EditSubtitleInfo, ses: &mut UserSessi
                 .map_err(|e| anyhow!("Failed to set default subtitle: {:?}", e))?;
         }
     }
+    server.emit_org_event(OrgEvent::SubtitleEdited { subtitle_id: id, ses: Some(ses.org_session.clone()) });
 
     send_open_media_file_cmd(server, &ses.sid, &mf.id).await?;
     Ok(())
@@ -521,9 +696,10 @@ pub async fn msg_del_subtitle(data: &DelSubtitle, ses: &mut UserSession, server:
     let mf = models::MediaFile::get(conn, &sub.media_file_id).map_err(|e| anyhow!("Failed to get media file: {:?}", e))?;
 
     let default_perm = ses.user_id == mf.user_id || ses.is_admin;
-    org_authz_with_default(&ses.org_session, "delete subtitle", true, server, &ses.organizer,
+    org_authz_with_default(&ses.org_session, "delete subtitle", true, server, &ses.organizers,
         default_perm, AuthzTopic::MediaFile(&mf, authz_req::media_file_op::Op::Edit)).await?;
 
+    let deleted = sub.to_proto3(&server.url_base, None);
     let subs_dir = server.media_files_dir.join(&mf.id).join("subs");
     tracing::debug!(orig_file=?sub.orig_filename, vtt_file=?sub.filename, "Deleting subtitle files");
 
@@ -536,6 +712,7 @@ pub async fn msg_del_subtitle(data: &DelSubtitle, ses: &mut UserSession, server:
     }
 
     models::Subtitle::delete(conn, &id).map_err(|e| anyhow!("Failed to delete subtitle: {:?}", e))?;
+    server.emit_org_event(OrgEvent::SubtitleDeleted { subtitle: deleted, ses: Some(ses.org_session.clone()) });
     send_open_media_file_cmd(server, &ses.sid, &mf.id).await?;
     Ok(())
 }
@@ -565,7 +742,7 @@ pub async fn msg_join_collab(data: &JoinCollab, ses: &mut UserSession, server: &
     ses.cur_collab_id = None;
 
     if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
-        org_authz_with_default(&ses.org_session, "join collab", true, server, &ses.organizer,
+        org_authz_with_default(&ses.org_session, "join collab", true, server, &ses.organizers,
             true, AuthzTopic::Other(Some(&data.collab_id), authz_req::other_op::Op::JoinCollabSession)).await?;
 
         match server.link_session_to_collab(&data.collab_id, &v.id, ses.sender.clone()) {
@@ -630,51 +807,68 @@ pub async fn msg_collab_report(data: &CollabReport, ses: &mut UserSession, serve
 
 
 pub async fn msg_move_to_folder(data: &proto::client::client_to_server_cmd::MoveToFolder, ses: &mut UserSession, server: &ServerState) -> Res<()> {
-    if let Some(org) = &ses.organizer {
+    if user_session::orgs_with_role(&ses.organizers, |r| r.commands).next().is_none() {
+        send_user_error!(&ses.user_id, server, Topic::None, "No organizer session.");
+        return Ok(());
+    }
+    for org in user_session::orgs_with_role(&ses.organizers, |r| r.commands) {
         let req = proto::org::MoveToFolderRequest {
             ses: Some(ses.org_session.clone()),
             dst_folder_id: data.dst_folder_id.clone(),
             ids: data.ids.clone(),
             listing_data: data.listing_data.clone(),
         };
-        if let Err(e) = org.lock().await.move_to_folder(req).await {
-            if e.code() == tonic::Code::Unimplemented {
-                tracing::debug!("Organizer doesn't implement move_to_folder(). Ignoring.");
-            } else if e.code() == tonic::Code::Aborted {
+        match org.lock().await.move_to_folder(req).await {
+            Err(e) if e.code() == tonic::Code::Unimplemented => {
+                tracing::debug!("Organizer doesn't implement move_to_folder(). Trying next.");
+            },
+            Err(e) if e.code() == tonic::Code::Aborted => {
                 tracing::debug!("Ignoring org.move_to_folder() result because it GrpcStatus.ABORTED.");
-            } else {
+                break;
+            },
+            Err(e) => {
                 tracing::error!(err=?e, "Error in organizer move_to_folder() call");
                 anyhow::bail!("Organizer error: {:?}", e);
-            }
+            },
+            Ok(_) => { break; }
         }
-    } else { send_user_error!(&ses.user_id, server, Topic::None, "No organizer session."); }
+    }
     Ok(())
 }
 
 pub async fn msg_reorder_items(data: &ReorderItems, ses: &mut UserSession, server: &ServerState) -> Res<()> {
-    if let Some(org) = &ses.organizer {
+    if user_session::orgs_with_role(&ses.organizers, |r| r.commands).next().is_none() {
+        send_user_error!(&ses.user_id, server, Topic::None, "No organizer session.");
+        return Ok(());
+    }
+    for org in user_session::orgs_with_role(&ses.organizers, |r| r.commands) {
         let req = proto::org::ReorderItemsRequest {
             ses: Some(ses.org_session.clone()),
             ids: data.ids.clone(),
             listing_data: data.listing_data.clone(),
         };
-        if let Err(e) = org.lock().await.reorder_items(req).await {
-            if e.code() == tonic::Code::Unimplemented {
-                tracing::debug!("Organizer doesn't implement reorder_items(). Ignoring.");
-            } else if e.code() == tonic::Code::Aborted {
+        match org.lock().await.reorder_items(req).await {
+            Err(e) if e.code() == tonic::Code::Unimplemented => {
+                tracing::debug!("Organizer doesn't implement reorder_items(). Trying next.");
+            },
+            Err(e) if e.code() == tonic::Code::Aborted => {
                 tracing::debug!("Ignoring org.reorder_items() result because it GrpcStatus.ABORTED.");
-            } else {
+                break;
+            },
+            Err(e) => {
                 tracing::error!(err=?e, "Error in organizer reorder_items() call");
                 anyhow::bail!("Organizer error: {:?}", e);
-            }
+            },
+            Ok(_) => { break; }
         }
-    } else { send_user_error!(&ses.user_id, server, Topic::None, "No organizer session."); }
+    }
     Ok(())
 }
 
 
 pub async fn msg_organizer_cmd(data: &proto::client::client_to_server_cmd::OrganizerCmd, ses: &mut UserSession, server: &ServerState) -> Res<()> {
-    if let Some(org) = &ses.organizer {
+    // Commands are namespaced by plugins themselves, so pass to each until one implements it
+    for org in user_session::orgs_with_role(&ses.organizers, |r| r.commands) {
         let req = proto::org::CmdFromClientRequest {
             ses: Some(ses.org_session.clone()),
             cmd: data.cmd.clone(),
@@ -682,8 +876,11 @@ pub async fn msg_organizer_cmd(data: &proto::client::client_to_server_cmd::Organ
         };
         match org.lock().await.cmd_from_client(req).await {
             Err(e) => {
-                if e.code() == tonic::Code::Aborted {
+                if e.code() == tonic::Code::Unimplemented {
+                    tracing::debug!("Organizer doesn't implement cmd_from_client(). Trying next.");
+                } else if e.code() == tonic::Code::Aborted {
                     tracing::debug!("Ignoring org.cmd_from_client() result because it GrpcStatus.ABORTED.");
+                    break;
                 } else {
                     tracing::error!(err=?e, "Error in organizer cmd_from_client() call");
                     anyhow::bail!("Organizer error: {:?}", e);
@@ -696,17 +893,336 @@ pub async fn msg_organizer_cmd(data: &proto::client::client_to_server_cmd::Organ
 }
 
 
+/// Send user the list of (unexpired) share links for a media file
+async fn send_share_links(media_file_id: &str, ses: &UserSession, server: &ServerState) -> Res<()> {
+    let links = super::share_links::list_share_links(server, media_file_id)?;
+    server.emit_cmd(
+        client_cmd!(ShowShareLinks, { media_file_id: media_file_id.into(), links: links }),
+        super::SendTo::UserSession(&ses.sid))?;
+    Ok(())
+}
+
+/// Create a time-limited share link for an external reviewer.
+/// By default, only the owner (or admin) can share a media file.
+pub async fn msg_create_share_link(data: &proto::client::client_to_server_cmd::CreateShareLink, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        let default_perm = ses.user_id == v.user_id || ses.is_admin;
+        org_authz_with_default(&ses.org_session, "share media file", true, server, &ses.organizers,
+            default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Share)).await?;
+
+        let perm = proto::ShareLinkPermission::try_from(data.permission).unwrap_or(proto::ShareLinkPermission::View);
+        if let Err(e) = super::share_links::create_share_link(server, &v.id, perm, data.valid_hours, &ses.user_id) {
+            send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), format!("Failed to create share link: {}", e));
+            return Ok(());
+        }
+        send_share_links(&v.id, ses, server).await?;
+    }
+    Ok(())
+}
+
+/// Revoke a share link. Sessions using it are closed on their next command.
+pub async fn msg_revoke_share_link(data: &proto::client::client_to_server_cmd::RevokeShareLink, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let link = match models::ShareLink::get(&mut server.db.conn()?, &data.id) {
+        Ok(l) => l,
+        Err(DBError::NotFound()) => {
+            send_user_error!(&ses.user_id, server, Topic::None, "No such share link.");
+            return Ok(());
+        }
+        Err(e) => bail!(e),
+    };
+    if let Some(v) = get_media_file_or_send_error(Some(&link.media_file_id), &Some(ses), server).await? {
+        let default_perm = ses.user_id == v.user_id || ses.user_id == link.created_by || ses.is_admin;
+        org_authz_with_default(&ses.org_session, "revoke share link", true, server, &ses.organizers,
+            default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Share)).await?;
+
+        models::ShareLink::revoke(&mut server.db.conn()?, &link.id)?;
+        tracing::info!(link=%link.id, user=%ses.user_id, "Share link revoked.");
+        send_share_links(&v.id, ses, server).await?;
+    }
+    Ok(())
+}
+
+pub async fn msg_list_share_links(data: &proto::client::client_to_server_cmd::ListShareLinks, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        let default_perm = ses.user_id == v.user_id || ses.is_admin;
+        org_authz_with_default(&ses.org_session, "list share links", true, server, &ses.organizers,
+            default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Share)).await?;
+        send_share_links(&v.id, ses, server).await?;
+    }
+    Ok(())
+}
+
+/// Full-text search over media file titles, comments and subtitle cues.
+/// Only hits from media files the user may view are returned: by default their own
+/// (or all, for admins), unless Organizer authz says otherwise.
+pub async fn msg_search(data: &proto::client::client_to_server_cmd::Search, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use crate::database::search;
+    const MAX_HITS: usize = 50;
+    const SCAN_PAGE_SIZE: u32 = 200;
+    const MAX_SCAN_PAGES: u32 = 5;   // Give up on finding more viewable hits after this many
+
+    let scope = data.media_file_id.as_ref().map(|id| vec![id.clone()]);
+    let mut viewable: HashMap<String, bool> = HashMap::new();
+    let mut hits = vec![];
+    for page_num in 0..MAX_SCAN_PAGES {
+        let pg = DBPaging { page_num, page_size: std::num::NonZeroU32::new(SCAN_PAGE_SIZE).unwrap() };
+        let page = search::search(&mut server.db.conn()?, &data.query, scope.as_deref(), None, pg)?;
+        let page_len = page.len();
+        for h in page {
+            if !viewable.contains_key(&h.media_file_id) {
+                let ok = match models::MediaFile::get(&mut server.db.conn()?, &h.media_file_id) {
+                    Ok(v) => {
+                        let default_perm = ses.user_id == v.user_id || ses.is_admin;
+                        org_authz_with_default(&ses.org_session, "search media file", false, server, &ses.organizers,
+                            default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await.is_ok()
+                    },
+                    Err(_) => false,
+                };
+                viewable.insert(h.media_file_id.clone(), ok);
+            }
+            if viewable[&h.media_file_id] && hits.len() < MAX_HITS {
+                hits.push(h.to_proto3());
+            }
+        }
+        if hits.len() >= MAX_HITS || page_len < SCAN_PAGE_SIZE as usize { break; }
+    }
+
+    server.emit_cmd(
+        client_cmd!(SearchResults, { query: data.query.clone(), hits: hits }),
+        super::SendTo::UserSession(&ses.sid))?;
+    Ok(())
+}
+
+/// Ask the pipeline to render a printable review report (comment threads with still frames and drawings).
+/// Anyone who can view the media file can generate one. The pipeline tells the user when it's ready.
+pub async fn msg_generate_review_report(data: &proto::client::client_to_server_cmd::GenerateReviewReport, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use crate::video_pipeline::review_report::ReportFormat;
+
+    if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        org_authz_with_default(&ses.org_session, "generate review report", true, server, &ses.organizers,
+            true, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::View)).await?;
+
+        let format = match data.format.as_deref().map(ReportFormat::from_str) {
+            Some(Ok(f)) => f,
+            Some(Err(e)) => {
+                send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), "Review report failed.", e.to_string(), false);
+                return Ok(());
+            },
+            None => ReportFormat::Html,
+        };
+        server.pipeline_tx.send(PipelineCmd::Report { media_file_id: v.id, user_id: ses.user_id.clone(), format })
+            .context("Media processing pipeline is not running")?;
+    }
+    Ok(())
+}
+
+/// Bulk import comment threads from a CSV, JSON or EDL file, in one transaction.
+/// By default, only the owner (or admin) can import. Result is reported as a user message.
+pub async fn msg_import_comments(data: &proto::client::client_to_server_cmd::ImportComments, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use super::comment_import::{self, ImportFormat};
+    const MAX_WARNINGS_SHOWN: usize = 20;
+
+    if let Some(v) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? {
+        let default_perm = ses.user_id == v.user_id || ses.is_admin;
+        org_authz_with_default(&ses.org_session, "import comments", true, server, &ses.organizers,
+            default_perm, AuthzTopic::MediaFile(&v, authz_req::media_file_op::Op::Edit)).await?;
+
+        let format = match data.format.as_deref().map(ImportFormat::from_str) {
+            Some(Ok(f)) => f,
+            Some(Err(e)) => {
+                send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), "Comment import failed.", e.to_string(), true);
+                return Ok(());
+            },
+            None => ImportFormat::detect(&data.filename, &data.content),
+        };
+        let rate = crate::timecode::FrameRate::of(&v);
+        let parsed = match comment_import::parse(&data.content, format, &rate) {
+            Ok(p) if !p.comments.is_empty() => p,
+            Ok(_) => {
+                send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), format!("No comments found in '{}'.", data.filename), true);
+                return Ok(());
+            },
+            Err(e) => {
+                send_user_error!(&ses.user_id, server, Topic::MediaFile(&v.id), format!("Failed to read '{}'.", data.filename), e.to_string(), true);
+                return Ok(());
+            },
+        };
+
+        let items = parsed.comments.iter().map(|c| (models::CommentInsert {
+            media_file_id: v.id.clone(),
+            parent_id: None,
+            user_id: Some(ses.user_id.clone()),
+            username_ifnull: c.author.clone().unwrap_or_else(|| ses.user_name.clone()),
+            comment: c.text.clone(),
+            timecode: c.frame.map(|f| rate.client_timecode(f)),
+            drawing: None,
+            subtitle_id: None,
+            subtitle_filename_ifnull: None,
+            frame: c.frame.and_then(|f| i32::try_from(f).ok()),
+            assignee_id: None,
+        }, c.parent)).collect::<Vec<_>>();
+        let inserted = models::Comment::insert_threads(&mut server.db.conn()?, &items)
+            .map_err(|e| anyhow!("Failed to import comments: {:?}", e))?;
+        tracing::info!(media_file=%v.id, user=%ses.user_id, count=inserted.len(), format=?format, "Comments imported.");
+
+        let threads = parsed.comments.iter().filter(|c| c.parent.is_none()).count();
+        for c in inserted {
+            server.emit_org_event(OrgEvent::CommentAdded { comment_id: c.id, ses: Some(ses.org_session.clone()) });
+            ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&v.id)).await?;
+        }
+
+        let mut details = parsed.warnings.iter().take(MAX_WARNINGS_SHOWN).cloned().collect::<Vec<_>>();
+        if parsed.warnings.len() > MAX_WARNINGS_SHOWN {
+            details.push(format!("...and {} more", parsed.warnings.len() - MAX_WARNINGS_SHOWN));
+        }
+        send_user_ok!(&ses.user_id, server, Topic::MediaFile(&v.id),
+            format!("Imported {} comments ({} threads) from '{}'.", items.len(), threads, data.filename), details.join("\n"), true);
+    }
+    Ok(())
+}
+
+/// Copy comment threads from one version of a media file to another (in the same version stack).
+/// Requires view permission on the source and edit permission on the target.
+pub async fn msg_carry_over_comments(data: &proto::client::client_to_server_cmd::CarryOverComments, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let Some(from) = get_media_file_or_send_error(Some(&data.from_media_file_id), &Some(ses), server).await? else { return Ok(()) };
+    let Some(to) = get_media_file_or_send_error(Some(&data.to_media_file_id), &Some(ses), server).await? else { return Ok(()) };
+
+    org_authz_with_default(&ses.org_session, "view media file", true, server, &ses.organizers,
+        true, AuthzTopic::MediaFile(&from, authz_req::media_file_op::Op::View)).await?;
+    let default_perm = ses.user_id == to.user_id || ses.is_admin;
+    org_authz_with_default(&ses.org_session, "carry over comments", true, server, &ses.organizers,
+        default_perm, AuthzTopic::MediaFile(&to, authz_req::media_file_op::Op::Edit)).await?;
+
+    let added = match super::media_versions::carry_over_comments(&server.db, &server.media_files_dir, &from.id, &to.id) {
+        Ok(a) => a,
+        Err(e) => {
+            send_user_error!(&ses.user_id, server, Topic::MediaFile(&to.id), "Failed to carry over comments.", e.to_string(), true);
+            return Ok(());
+        }
+    };
+    let threads = added.iter().filter(|c| c.parent_id.is_none()).count();
+    let n_added = added.len();
+    for c in added {
+        server.emit_org_event(OrgEvent::CommentAdded { comment_id: c.id, ses: Some(ses.org_session.clone()) });
+        ses.emit_new_comment(server, c, super::SendTo::MediaFileId(&to.id)).await?;
+    }
+    send_user_ok!(&ses.user_id, server, Topic::MediaFile(&to.id),
+        format!("Carried over {} comments ({} threads) from '{}'.", n_added, threads, from.title.unwrap_or(from.id)), true);
+    Ok(())
+}
+
+
+/// Record the user's approval / sign-off decision on a media file.
+/// Viewers get the updated summary, and the uploader is notified (unless they reviewed it themselves).
+pub async fn msg_set_approval(data: &proto::client::client_to_server_cmd::SetApproval, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    let Some(mf) = get_media_file_or_send_error(Some(&data.media_file_id), &Some(ses), server).await? else { return Ok(()) };
+    let default_perm = true;    // anyone who can view a media file can review it
+    org_authz_with_default(&ses.org_session, "approve media file", true, server, &ses.organizers,
+        default_perm, AuthzTopic::MediaFile(&mf, authz_req::media_file_op::Op::Approve)).await?;
+
+    let note = data.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if note.map(|n| n.len() > 4000).unwrap_or(false) {
+        send_user_error!(&ses.user_id, server, Topic::MediaFile(&mf.id), "Approval note too long (max 4000)");
+        return Ok(());
+    }
+    let decision = crate::grpc::db_models::approval_decision_from_proto3(data.decision());
+    let conn = &mut server.db.conn()?;
+    models::Approval::set(conn, &mf.id, &ses.user_id, &ses.user_name, decision, note)?;
+    let approvals = mf.get_approvals(conn)?;
+
+    server.emit_cmd(
+        client_cmd!(ShowApprovals, {media_file_id: mf.id.clone(), approval: Some(crate::grpc::db_models::approval_summary_to_proto3(&approvals))}),
+        super::SendTo::MediaFileId(&mf.id))?;
+
+    let title = mf.title.clone().unwrap_or(mf.id.clone());
+    let msg = match decision {
+        models::Approval::APPROVED => format!("'{}' approved '{}'.", ses.user_name, title),
+        models::Approval::CHANGES_REQUESTED => format!("'{}' requested changes to '{}'.", ses.user_name, title),
+        _ => format!("'{}' marked review of '{}' as pending.", ses.user_name, title),
+    };
+    if mf.user_id != ses.user_id {
+        send_user_ok!(&mf.user_id, server, Topic::MediaFile(&mf.id), msg, note.unwrap_or_default().to_string(), true);
+    }
+    send_user_ok!(&ses.user_id, server, Topic::MediaFile(&mf.id), "Review decision recorded.");
+    Ok(())
+}
+
+
+/// Send user's e-mail notification settings to the session
+fn send_email_settings(ses: &UserSession, server: &ServerState) -> Res<()> {
+    use proto::client::email_settings::Frequency;
+    let u = models::User::get(&mut server.db.conn()?, &ses.user_id)?;
+    let frequency = match u.email_frequency.as_str() {
+        models::User::EMAIL_IMMEDIATE => Frequency::Immediate,
+        models::User::EMAIL_HOURLY => Frequency::Hourly,
+        models::User::EMAIL_DAILY => Frequency::Daily,
+        _ => Frequency::Off,
+    };
+    server.emit_cmd(
+        client_cmd!(ShowEmailSettings, {
+            settings: Some(proto::client::EmailSettings { email: u.email, frequency: frequency.into() }),
+            available: server.email.is_some() }),
+        super::SendTo::UserSession(&ses.sid))?;
+    Ok(())
+}
+
+pub async fn msg_get_email_settings(ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    send_email_settings(ses, server)
+}
+
+/// Set user's e-mail address and how often to get unseen messages by e-mail.
+pub async fn msg_set_email_settings(data: &proto::client::client_to_server_cmd::SetEmailSettings, ses: &mut UserSession, server: &ServerState) -> Res<()> {
+    use proto::client::email_settings::Frequency;
+    let settings = data.settings.clone().unwrap_or_default();
+    let email = settings.email.as_deref().map(str::trim).filter(|e| !e.is_empty());
+    if let Some(Err(e)) = email.map(|e| e.parse::<lettre::Address>()) {
+        send_user_error!(&ses.user_id, server, Topic::None, "Invalid e-mail address.", e.to_string(), false);
+        return Ok(());
+    }
+    let frequency = match settings.frequency() {
+        Frequency::Off => models::User::EMAIL_OFF,
+        Frequency::Immediate => models::User::EMAIL_IMMEDIATE,
+        Frequency::Hourly => models::User::EMAIL_HOURLY,
+        Frequency::Daily => models::User::EMAIL_DAILY,
+    };
+    if email.is_none() && frequency != models::User::EMAIL_OFF {
+        send_user_error!(&ses.user_id, server, Topic::None, "E-mail address is required for notifications.");
+        return Ok(());
+    }
+    models::User::set_email_settings(&mut server.db.conn()?, &ses.user_id, email, frequency)?;
+    send_email_settings(ses, server)?;
+    send_user_ok!(&ses.user_id, server, Topic::None, "E-mail settings saved.");
+    Ok(())
+}
+
 
 #[derive(thiserror::Error, Debug)]
 pub enum SessionClose {
     #[error("User logout")]
     Logout,
+    #[error("Share link no longer valid: {0}")]
+    ShareLinkInvalid(super::share_links::ShareLinkError),
 }
 
 /// Dispatch a message from client to appropriate handler.
 /// Return true if the session should be kept open, or false if it should be closed.
 pub async fn msg_dispatch(req: &ClientToServerCmd, ses: &mut UserSession, server: &ServerState) -> Res<bool> {
     use proto::client::client_to_server_cmd::Cmd;
+
+    // Share link sessions are limited to the shared media file, and end when the link is revoked or expires
+    if let Some(scope) = &ses.share {
+        if let Err(e) = scope.check(&server.db) {
+            server.emit_cmd(client_cmd!(Error, {msg: e.to_string()}), super::SendTo::MsgSender(&ses.sender)).ok();
+            return Err(SessionClose::ShareLinkInvalid(e).into());
+        }
+        if let Some(cmd) = &req.cmd {
+            if !super::share_links::share_session_allows(cmd, scope, &server.db) {
+                send_user_error!(&ses.user_id, server, Topic::None, "Not permitted with a share link.");
+                return Ok(true);
+            }
+        }
+    }
+
     let res = match req.cmd.as_ref() {
         None => {
             send_user_error!(&ses.user_id, server, Topic::None, format!("Missing command from client: {:?}", req));
@@ -717,9 +1233,13 @@ pub async fn msg_dispatch(req: &ClientToServerCmd, ses: &mut UserSession, server
             Cmd::OpenMediaFile(data) => msg_open_media_file(&data, ses, server).await,
             Cmd::DelMediaFile(data) => msg_del_media_file(&data, ses, server).await,
             Cmd::RenameMediaFile(data) => msg_rename_media_file(&data, ses, server).await,
+            Cmd::CancelMediaFileProcessing(data) => msg_cancel_media_file_processing(data, ses, server).await,
+            Cmd::RerunMediaFileProcessing(data) => msg_rerun_media_file_processing(data, ses, server).await,
             Cmd::AddComment(data) => msg_add_comment(&data, ses, server).await,
             Cmd::EditComment(data) => msg_edit_comment(&data, ses, server).await,
             Cmd::DelComment(data) => msg_del_comment(&data, ses, server).await,
+            Cmd::SetCommentStatus(data) => msg_set_comment_status(data, ses, server).await,
+            Cmd::AssignComment(data) => msg_assign_comment(data, ses, server).await,
             Cmd::AddSubtitle(data) => msg_add_subtitle(&data, ses, server).await,
             Cmd::EditSubtitleInfo(data) => msg_edit_subtitle_info(&data, ses, server).await,
             Cmd::DelSubtitle(data) => msg_del_subtitle(&data, ses, server).await,
@@ -730,6 +1250,16 @@ pub async fn msg_dispatch(req: &ClientToServerCmd, ses: &mut UserSession, server
             Cmd::OrganizerCmd(data) => msg_organizer_cmd(&data, ses, server).await,
             Cmd::MoveToFolder(data) => msg_move_to_folder(&data, ses, server).await,
             Cmd::ReorderItems(data) => msg_reorder_items(&data, ses, server).await,
+            Cmd::CreateShareLink(data) => msg_create_share_link(data, ses, server).await,
+            Cmd::RevokeShareLink(data) => msg_revoke_share_link(data, ses, server).await,
+            Cmd::ListShareLinks(data) => msg_list_share_links(data, ses, server).await,
+            Cmd::ImportComments(data) => msg_import_comments(data, ses, server).await,
+            Cmd::GenerateReviewReport(data) => msg_generate_review_report(data, ses, server).await,
+            Cmd::Search(data) => msg_search(data, ses, server).await,
+            Cmd::CarryOverComments(data) => msg_carry_over_comments(data, ses, server).await,
+            Cmd::SetApproval(data) => msg_set_approval(data, ses, server).await,
+            Cmd::GetEmailSettings(_) => msg_get_email_settings(ses, server).await,
+            Cmd::SetEmailSettings(data) => msg_set_email_settings(data, ses, server).await,
             Cmd::Logout(_) => {
                 tracing::info!("logout from client: user={}", ses.user_id);
                 return Err(SessionClose::Logout.into());
@@ -1,7 +1,7 @@
 use anyhow::Context;
 use diesel::prelude::*;
 use chrono::offset::Local;
-use crate::{database::{models, schema, to_db_res, DBResult, EmptyDBResult}, retry_if_db_locked};
+use crate::{database::{models, schema, to_db_res, CommentFilter, DBPaging, DBResult, EmptyDBResult}, retry_if_db_locked};
 
 use super::{error::DBError, DbBasicQuery, PooledConnection};
 
@@ -46,6 +46,77 @@ impl models::User {
             Err(e) => { Err(e) }
         }
     }
+
+    /// Find a user by exact ID, or failing that, by name (case-insensitive).
+    ///
+    /// # Returns
+    /// * `DBResult<Option<models::User>>` - The user, or None if there's no match or the name is ambiguous
+    pub fn find_by_id_or_name(conn: &mut PooledConnection, id_or_name: &str) -> DBResult<Option<models::User>>
+    {
+        match models::User::get(conn, &id_or_name.to_string()) {
+            Ok(u) => return Ok(Some(u)),
+            Err(DBError::NotFound()) => {},
+            Err(e) => return Err(e),
+        };
+        use schema::users::dsl::*;
+        let pattern = id_or_name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        let mut found = users.filter(name.like(pattern).escape('\\')).limit(2).load::<models::User>(conn)?;
+        Ok(if found.len() == 1 { found.pop() } else { None })
+    }
+
+    pub const EMAIL_OFF: &'static str = "off";
+    pub const EMAIL_IMMEDIATE: &'static str = "immediate";
+    pub const EMAIL_HOURLY: &'static str = "hourly";
+    pub const EMAIL_DAILY: &'static str = "daily";
+
+    /// Set user's e-mail address and notification frequency.
+    /// When notifications get turned on, messages received before that are skipped (not mailed).
+    ///
+    /// # Arguments
+    /// * `conn` - Database connection
+    /// * `uid` - ID of the user
+    /// * `new_email` - E-mail address, or None to remove it
+    /// * `frequency` - One of the `EMAIL_*` constants
+    pub fn set_email_settings(conn: &mut PooledConnection, uid: &str, new_email: Option<&str>, frequency: &str) -> EmptyDBResult
+    {
+        conn.transaction::<_, DBError, _>(|conn| {
+            let old = models::User::get(conn, &uid.to_string())?;
+            let was_on = old.email.is_some() && old.email_frequency != Self::EMAIL_OFF;
+            let last_msg_id = if was_on { old.email_last_msg_id } else {
+                use schema::messages::dsl::*;
+                messages.filter(user_id.eq(uid)).select(diesel::dsl::max(id)).first::<Option<i32>>(conn)?.unwrap_or(0)
+            };
+            use schema::users::dsl::*;
+            diesel::update(users.filter(id.eq(uid)))
+                .set((email.eq(new_email), email_frequency.eq(frequency), email_last_msg_id.eq(last_msg_id)))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Get users who have an e-mail address and notifications turned on.
+    pub fn get_email_recipients(conn: &mut PooledConnection) -> DBResult<Vec<models::User>>
+    {
+        use schema::users::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            users.filter(email.is_not_null())
+                .filter(email_frequency.ne(Self::EMAIL_OFF))
+                .order(id.asc())
+                .load::<models::User>(conn)
+        }))
+    }
+
+    /// Record that messages up to `last_msg_id` have been mailed to the user.
+    pub fn set_email_sent(conn: &mut PooledConnection, uid: &str, last_msg_id: i32, sent_at: chrono::NaiveDateTime) -> EmptyDBResult
+    {
+        use schema::users::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(users.filter(id.eq(uid)))
+                .set((email_last_msg_id.eq(last_msg_id), email_last_sent.eq(sent_at)))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
 }
 
 
@@ -56,12 +127,30 @@ impl models::MediaFile {
     /// # Arguments
     /// * `db` - Database
     /// * `vid` - Id of the media file
-    pub fn set_recompressed(conn: &mut PooledConnection, vid: &str) -> EmptyDBResult
+    /// * `bitrate` - Video bitrate of the transcode (None if unknown)
+    pub fn set_recompressed(conn: &mut PooledConnection, vid: &str, bitrate: Option<i32>) -> EmptyDBResult
+    {
+        use schema::media_files::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(media_files.filter(id.eq(vid)))
+                .set((recompression_done.eq(Local::now().naive_local()), transcode_bitrate.eq(bitrate)))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Mark the adaptive streaming ladder (HLS, optionally DASH) as done for a media file.
+    ///
+    /// # Arguments
+    /// * `db` - Database
+    /// * `vid` - Id of the media file
+    /// * `has_dash` - True if a DASH manifest was also generated
+    pub fn set_adaptive_done(conn: &mut PooledConnection, vid: &str, has_dash: bool) -> EmptyDBResult
     {
         use schema::media_files::dsl::*;
         retry_if_db_locked!({
             diesel::update(media_files.filter(id.eq(vid)))
-                .set(recompression_done.eq(Local::now().naive_local()))
+                .set((adaptive_done.eq(Local::now().naive_local()), adaptive_has_dash.eq(has_dash)))
                 .execute(conn)
         })?;
         Ok(())
@@ -169,11 +258,48 @@ impl models::MediaFile {
         use schema::media_files::dsl::*;
         to_db_res(retry_if_db_locked!({ media_files.filter(thumbs_done.is_null()).order_by(added_time.desc()).load::<MediaFile>(conn) }))
     }
+
+    /// Get all media files with identical contents (same SHA-256 of the original file), oldest first.
+    ///
+    /// # Arguments
+    /// * `hash` - Content hash (hex)
+    ///
+    /// # Returns
+    /// * `Vec<models::MediaFile>` - List of MediaFile objects
+    pub fn get_by_content_hash(conn: &mut PooledConnection, hash: &str) -> DBResult<Vec<models::MediaFile>>
+    {
+        use models::*;
+        use schema::media_files::dsl::*;
+        to_db_res(retry_if_db_locked!({ media_files.filter(content_hash.eq(hash)).order_by(added_time.asc()).then_order_by(id.asc()).load::<MediaFile>(conn) }))
+    }
+
+    /// Get IDs of all media files, or only those of a given user.
+    ///
+    /// # Arguments
+    /// * `owner` - User ID to filter by, or None for all media files
+    ///
+    /// # Returns
+    /// * `Vec<String>` - List of media file IDs
+    pub fn get_ids(conn: &mut PooledConnection, owner: Option<&str>) -> DBResult<Vec<String>>
+    {
+        use schema::media_files::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            let mut q = media_files.select(id).into_boxed();
+            if let Some(uid) = owner {
+                q = q.filter(user_id.eq(uid));
+            }
+            q.load::<String>(conn)
+        }))
+    }
 }
 
 
 impl models::Comment {
 
+    pub const OPEN: &'static str = "open";
+    pub const RESOLVED: &'static str = "resolved";
+    pub const WONTFIX: &'static str = "wontfix";
+
     /// Edit a comment (change text).
     ///
     /// # Arguments
@@ -190,6 +316,180 @@ impl models::Comment {
                 .set((comment.eq(new_comment), edited.eq(diesel::dsl::now))).execute(conn).map(|x| x > 0)
         }))
     }
+
+    /// Insert comment threads in a single transaction. Either all are inserted, or none.
+    ///
+    /// # Arguments
+    /// * `items` - Comments to insert, each with an optional index of its parent in `items`.
+    ///   Parents must come before their replies. `parent_id` of the inserts is overwritten.
+    ///
+    /// # Returns
+    /// * `DBResult<Vec<models::Comment>>` - Inserted comments, in the same order
+    pub fn insert_threads(conn: &mut PooledConnection, items: &[(models::CommentInsert, Option<usize>)]) -> DBResult<Vec<models::Comment>>
+    {
+        conn.transaction::<_, DBError, _>(|conn| {
+            let mut inserted: Vec<models::Comment> = Vec::with_capacity(items.len());
+            for (i, (item, parent)) in items.iter().enumerate() {
+                let parent_id = match parent {
+                    Some(p) if *p < i => Some(inserted[*p].id),
+                    Some(p) => return Err(DBError::Other(anyhow::anyhow!("Comment #{} refers to later parent #{}", i, p))),
+                    None => None,
+                };
+                let c = models::CommentInsert { parent_id, ..item.clone() };
+                inserted.push(models::Comment::insert(conn, &c)?);
+            }
+            Ok(inserted)
+        })
+    }
+
+    /// Set resolution status of a comment. Resolving (or won't-fixing) records who did it and when;
+    /// reopening clears them.
+    ///
+    /// # Arguments
+    /// * `comment_id` - ID of the comment
+    /// * `new_status` - `Comment::OPEN`, `RESOLVED` or `WONTFIX`
+    /// * `by_user` - ID of the user making the change
+    ///
+    /// # Returns
+    /// * `DBResult<bool>` - True if comment was updated, false if it was not found
+    pub fn set_status(conn: &mut PooledConnection, comment_id: i32, new_status: &str, by_user: &str) -> DBResult<bool>
+    {
+        use schema::comments::dsl::*;
+        let (by, at) = match new_status {
+            Self::OPEN => (None, None),
+            Self::RESOLVED | Self::WONTFIX => (Some(by_user), Some(chrono::Utc::now().naive_utc())),
+            _ => return Err(DBError::Other(anyhow::anyhow!("Invalid comment status '{}'", new_status))),
+        };
+        to_db_res(retry_if_db_locked!({
+            diesel::update(comments.filter(id.eq(comment_id)))
+                .set((status.eq(new_status), resolved_by.eq(by), resolved_at.eq(at))).execute(conn).map(|x| x > 0)
+        }))
+    }
+
+    /// Assign a comment to a user, or clear the assignee.
+    ///
+    /// # Returns
+    /// * `DBResult<bool>` - True if comment was updated, false if it was not found
+    pub fn set_assignee(conn: &mut PooledConnection, comment_id: i32, new_assignee: Option<&str>) -> DBResult<bool>
+    {
+        use schema::comments::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            diesel::update(comments.filter(id.eq(comment_id)))
+                .set(assignee_id.eq(new_assignee)).execute(conn).map(|x| x > 0)
+        }))
+    }
+
+    /// Get comments that match all conditions in `filter`, newest first.
+    pub fn get_filtered(conn: &mut PooledConnection, filter: &CommentFilter, pg: DBPaging) -> DBResult<Vec<models::Comment>>
+    {
+        use schema::comments::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            let mut q = comments.into_boxed();
+            if let Some(ids) = &filter.ids { q = q.filter(id.eq_any(ids)); }
+            if let Some(uid) = &filter.user_id { q = q.filter(user_id.eq(uid)); }
+            if let Some(mf) = &filter.media_file_id { q = q.filter(media_file_id.eq(mf)); }
+            if let Some(st) = &filter.status { q = q.filter(status.eq(st)); }
+            if let Some(a) = &filter.assignee_id { q = q.filter(assignee_id.eq(a)); }
+            q.order(created.desc())
+                .then_order_by(id.asc())
+                .offset(pg.offset())
+                .limit(pg.limit())
+                .load::<models::Comment>(conn)
+        }))
+    }
+
+    /// Set `frame` of a comment from its timecode and the media file's frame rate (see `timecode::comment_frame()`).
+    ///
+    /// # Returns
+    /// * `DBResult<Option<i32>>` - New frame number, None if the comment has no valid timecode
+    pub fn update_frame(conn: &mut PooledConnection, c: &models::Comment, mf: &models::MediaFile) -> DBResult<Option<i32>>
+    {
+        use schema::comments::dsl::*;
+        let new_frame = crate::timecode::comment_frame(mf, c.timecode.as_deref());
+        retry_if_db_locked!({
+            diesel::update(comments.filter(id.eq(c.id))).set(frame.eq(new_frame)).execute(conn)
+        })?;
+        Ok(new_frame)
+    }
+
+    /// Fill in `frame` for comments that have a timecode but no frame number
+    /// (e.g. added before frame numbers were stored).
+    ///
+    /// # Returns
+    /// * `DBResult<usize>` - Number of comments updated
+    pub fn fill_missing_frames(conn: &mut PooledConnection) -> DBResult<usize>
+    {
+        use schema::comments::dsl::*;
+        let todo: Vec<models::Comment> = retry_if_db_locked!({
+            comments.filter(frame.is_null().and(timecode.is_not_null())).load(conn)
+        })?;
+
+        let mut media_files: std::collections::HashMap<String, Option<models::MediaFile>> = std::collections::HashMap::new();
+        let mut n_updated = 0;
+        for c in todo {
+            let mf = media_files.entry(c.media_file_id.clone())
+                .or_insert_with(|| models::MediaFile::get(conn, &c.media_file_id).ok());
+            if let Some(mf) = mf {
+                if models::Comment::update_frame(conn, &c, mf)?.is_some() {
+                    n_updated += 1;
+                }
+            }
+        }
+        Ok(n_updated)
+    }
+
+    /// Copy open (unresolved) comment threads from one media file to another, e.g. from an earlier
+    /// version to a new one. Replies and assignees are copied along with their threads. Threads that the
+    /// target already has (same author, text and timecode, e.g. from an earlier carry-over) are skipped,
+    /// so this can be repeated safely. Frame numbers are recomputed for the target, and links to the
+    /// source's subtitles are dropped. Drawing filenames are kept as-is; copying the files is up to the caller.
+    ///
+    /// # Arguments
+    /// * `src_id` - ID of the media file to copy from
+    /// * `dst` - Media file to copy to
+    ///
+    /// # Returns
+    /// * `DBResult<Vec<models::Comment>>` - New comments on `dst`, parents before replies
+    pub fn copy_threads(conn: &mut PooledConnection, src_id: &str, dst: &models::MediaFile) -> DBResult<Vec<models::Comment>>
+    {
+        use super::DbQueryByMediaFile;
+        let mut src = models::Comment::get_by_media_file(conn, src_id, DBPaging::default())?;
+        src.sort_by_key(|c| c.id);
+
+        let thread_key = |c: &models::Comment| (c.user_id.clone(), c.username_ifnull.clone(), c.comment.clone(), c.timecode.clone());
+        let existing = models::Comment::get_by_media_file(conn, &dst.id, DBPaging::default())?.iter()
+            .filter(|c| c.parent_id.is_none())
+            .map(thread_key)
+            .collect::<std::collections::HashSet<_>>();
+
+        let mut index_of: std::collections::HashMap<i32, usize> = std::collections::HashMap::new();
+        let mut items: Vec<(models::CommentInsert, Option<usize>)> = Vec::new();
+        for c in src {
+            let parent = match c.parent_id {
+                None if c.status != Self::OPEN || existing.contains(&thread_key(&c)) => continue,
+                None => None,
+                Some(pid) => match index_of.get(&pid) {
+                    Some(i) => Some(*i),
+                    None => continue,   // Parent thread was skipped
+                },
+            };
+            index_of.insert(c.id, items.len());
+            items.push((models::CommentInsert {
+                media_file_id: dst.id.clone(),
+                parent_id: None,
+                user_id: c.user_id,
+                username_ifnull: c.username_ifnull,
+                comment: c.comment,
+                frame: crate::timecode::comment_frame(dst, c.timecode.as_deref()),
+                timecode: c.timecode,
+                drawing: c.drawing,
+                subtitle_id: None,
+                subtitle_filename_ifnull: None,
+                assignee_id: c.assignee_id,
+            }, parent));
+        }
+        models::Comment::insert_threads(conn, &items)
+    }
 }
 
 
@@ -228,4 +528,462 @@ impl models::Message {
             messages.filter(comment_id.eq(cid)).order(created.desc()).load::<models::Message>(conn)
         }))
     }
+
+    /// Get user's unseen messages with ID greater than `after_id`, oldest first.
+    pub fn get_unseen_after(conn: &mut PooledConnection, uid: &str, after_id: i32) -> DBResult<Vec<models::Message>>
+    {
+        use schema::messages::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            messages.filter(user_id.eq(uid))
+                .filter(seen.eq(false))
+                .filter(id.gt(after_id))
+                .order(id.asc())
+                .load::<models::Message>(conn)
+        }))
+    }
+}
+
+
+impl models::Job {
+
+    pub const QUEUED: &'static str = "queued";
+    pub const RUNNING: &'static str = "running";
+    pub const DONE: &'static str = "done";
+    pub const FAILED: &'static str = "failed";
+    pub const CANCELLED: &'static str = "cancelled";
+
+    /// Mark a queued job as handed to the worker pool. It stays queued until a worker
+    /// actually starts it, but is no longer returned by `get_due()`.
+    ///
+    /// # Arguments
+    /// * `job_id` - ID of the job
+    pub fn set_dispatched(conn: &mut PooledConnection, job_id: i32) -> EmptyDBResult
+    {
+        use schema::jobs::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(jobs.filter(id.eq(job_id)).filter(state.eq(Self::QUEUED)))
+                .set(next_attempt.eq(None::<chrono::NaiveDateTime>))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Mark a queued job as started by a worker, and count the attempt.
+    ///
+    /// # Arguments
+    /// * `job_id` - ID of the job
+    ///
+    /// # Returns
+    /// * `bool` - False if the job was no longer queued (e.g. it was cancelled)
+    pub fn set_running(conn: &mut PooledConnection, job_id: i32) -> DBResult<bool>
+    {
+        use schema::jobs::dsl::*;
+        let n = retry_if_db_locked!({
+            diesel::update(jobs.filter(id.eq(job_id)).filter(state.eq(Self::QUEUED)))
+                .set((state.eq(Self::RUNNING), attempts.eq(attempts + 1), started.eq(Local::now().naive_local()), next_attempt.eq(None::<chrono::NaiveDateTime>)))
+                .execute(conn)
+        })?;
+        Ok(n > 0)
+    }
+
+    /// Mark a job as successfully finished.
+    ///
+    /// # Arguments
+    /// * `job_id` - ID of the job
+    pub fn set_done(conn: &mut PooledConnection, job_id: i32) -> EmptyDBResult
+    {
+        use schema::jobs::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(jobs.filter(id.eq(job_id)))
+                .set((state.eq(Self::DONE), error.eq(None::<String>), finished.eq(Local::now().naive_local())))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Record a failed attempt. The job is either queued for a retry, or marked as permanently failed.
+    ///
+    /// # Arguments
+    /// * `job_id` - ID of the job
+    /// * `err_text` - Error message from the attempt
+    /// * `retry_at` - When to try again, or None to give up
+    pub fn set_failed(conn: &mut PooledConnection, job_id: i32, err_text: &str, retry_at: Option<chrono::NaiveDateTime>) -> EmptyDBResult
+    {
+        use schema::jobs::dsl::*;
+        retry_if_db_locked!({
+            match retry_at {
+                Some(t) => diesel::update(jobs.filter(id.eq(job_id)))
+                    .set((state.eq(Self::QUEUED), error.eq(err_text), next_attempt.eq(t)))
+                    .execute(conn),
+                None => diesel::update(jobs.filter(id.eq(job_id)))
+                    .set((state.eq(Self::FAILED), error.eq(err_text), finished.eq(Local::now().naive_local())))
+                    .execute(conn),
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Mark a job as cancelled by user or Organizer. Cancelled jobs are not retried.
+    ///
+    /// # Arguments
+    /// * `job_id` - ID of the job
+    pub fn set_cancelled(conn: &mut PooledConnection, job_id: i32) -> EmptyDBResult
+    {
+        use schema::jobs::dsl::*;
+        retry_if_db_locked!({
+            diesel::update(jobs.filter(id.eq(job_id)))
+                .set((state.eq(Self::CANCELLED), next_attempt.eq(None::<chrono::NaiveDateTime>), finished.eq(Local::now().naive_local())))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Put jobs that were running when the server stopped back in the queue, and make
+    /// them (and jobs that were still waiting in the worker pool) due right away.
+    ///
+    /// # Returns
+    /// * `usize` - Number of jobs re-queued
+    pub fn requeue_interrupted(conn: &mut PooledConnection) -> DBResult<usize>
+    {
+        use schema::jobs::dsl::*;
+        let now = Local::now().naive_local();
+        conn.transaction::<_, DBError, _>(|conn| {
+            let n_running = diesel::update(jobs.filter(state.eq(Self::RUNNING)))
+                .set((state.eq(Self::QUEUED), error.eq("Interrupted by server shutdown"), next_attempt.eq(now)))
+                .execute(conn)?;
+            let n_waiting = diesel::update(jobs.filter(state.eq(Self::QUEUED)).filter(next_attempt.is_null()))
+                .set(next_attempt.eq(now))
+                .execute(conn)?;
+            Ok(n_running + n_waiting)
+        })
+    }
+
+    /// Get queued jobs whose (re)start time has come, oldest first.
+    /// Jobs already handed to the worker pool (no `next_attempt`) are not included.
+    ///
+    /// # Arguments
+    /// * `now` - Current time
+    pub fn get_due(conn: &mut PooledConnection, now: chrono::NaiveDateTime) -> DBResult<Vec<models::Job>>
+    {
+        use schema::jobs::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            jobs.filter(state.eq(Self::QUEUED))
+                .filter(next_attempt.le(now))
+                .order(id.asc())
+                .load::<models::Job>(conn)
+        }))
+    }
+
+    /// Get jobs in given state, newest first.
+    ///
+    /// # Arguments
+    /// * `st` - State to filter by (e.g. `Job::QUEUED`)
+    /// * `pg` - Paging
+    pub fn get_by_state(conn: &mut PooledConnection, st: &str, pg: DBPaging) -> DBResult<Vec<models::Job>>
+    {
+        use schema::jobs::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            jobs.filter(state.eq(st))
+                .order(created.desc())
+                .then_order_by(id.desc())
+                .offset(pg.offset())
+                .limit(pg.limit())
+                .load::<models::Job>(conn)
+        }))
+    }
+
+    /// Get unfinished (queued or running) jobs of a media file, oldest first.
+    ///
+    /// # Arguments
+    /// * `vid` - ID of the media file
+    /// * `typ` - Only jobs of this type (e.g. "thumbs"), or None for all
+    pub fn get_pending(conn: &mut PooledConnection, vid: &str, typ: Option<&str>) -> DBResult<Vec<models::Job>>
+    {
+        use schema::jobs::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            let mut q = jobs.filter(media_file_id.eq(vid))
+                .filter(state.eq_any([Self::QUEUED, Self::RUNNING]))
+                .into_boxed();
+            if let Some(t) = typ {
+                q = q.filter(job_type.eq(t));
+            }
+            q.order(id.asc()).load::<models::Job>(conn)
+        }))
+    }
+
+    /// Check if a media file has unfinished (queued or running) jobs of given type.
+    ///
+    /// # Arguments
+    /// * `vid` - ID of the media file
+    /// * `typ` - Job type (e.g. "thumbs")
+    pub fn has_pending(conn: &mut PooledConnection, vid: &str, typ: &str) -> DBResult<bool>
+    {
+        use schema::jobs::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            jobs.filter(media_file_id.eq(vid))
+                .filter(job_type.eq(typ))
+                .filter(state.eq_any([Self::QUEUED, Self::RUNNING]))
+                .count()
+                .get_result::<i64>(conn)
+                .map(|n| n > 0)
+        }))
+    }
+
+    /// Delete finished (done) jobs older than given time, to keep the table from growing forever.
+    /// Failed jobs are kept for inspection.
+    ///
+    /// # Returns
+    /// * `usize` - Number of jobs deleted
+    pub fn delete_done_before(conn: &mut PooledConnection, before: chrono::NaiveDateTime) -> DBResult<usize>
+    {
+        use schema::jobs::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            diesel::delete(jobs.filter(state.eq(Self::DONE)).filter(finished.lt(before))).execute(conn)
+        }))
+    }
+}
+
+
+impl models::MediaFileVersion {
+
+    /// Get the version stack that a media file belongs to, oldest version first.
+    ///
+    /// # Returns
+    /// * `DBResult<Vec<models::MediaFileVersion>>` - All versions in the stack, or empty if the media file isn't stacked
+    pub fn get_stack_of(conn: &mut PooledConnection, mf_id: &str) -> DBResult<Vec<models::MediaFileVersion>>
+    {
+        use schema::media_file_versions::dsl::*;
+        let this: Option<models::MediaFileVersion> = retry_if_db_locked!({
+            media_file_versions.filter(media_file_id.eq(mf_id)).first(conn).optional()
+        })?;
+        match this {
+            None => Ok(vec![]),
+            Some(this) => to_db_res(retry_if_db_locked!({
+                media_file_versions.filter(stack_id.eq(&this.stack_id))
+                    .order(version.asc())
+                    .load::<models::MediaFileVersion>(conn)
+            })),
+        }
+    }
+
+    /// Add a media file as the newest version in another media file's stack.
+    /// If `prev_id` isn't stacked yet, a new stack is started with it as version 1.
+    ///
+    /// # Arguments
+    /// * `prev_id` - ID of any media file in the stack (typically the previous version)
+    /// * `new_id` - ID of the media file to add. Must not be in a stack already.
+    ///
+    /// # Returns
+    /// * `DBResult<models::MediaFileVersion>` - Version entry of the new media file
+    pub fn add_version(conn: &mut PooledConnection, prev_id: &str, new_id: &str) -> DBResult<models::MediaFileVersion>
+    {
+        conn.transaction::<_, DBError, _>(|conn| {
+            if prev_id == new_id {
+                return Err(DBError::Other(anyhow::anyhow!("Media file can't be a version of itself")));
+            }
+            for id in [prev_id, new_id] {
+                models::MediaFile::get(conn, &id.to_string())?;
+            }
+            if let Some(v) = Self::get_stack_of(conn, new_id)?.into_iter().find(|v| v.media_file_id == new_id) {
+                return Err(DBError::Other(anyhow::anyhow!("Media file '{}' is already version {} of stack '{}'", new_id, v.version, v.stack_id)));
+            }
+            let stack = match Self::get_stack_of(conn, prev_id)? {
+                s if s.is_empty() => vec![models::MediaFileVersion::insert(conn, &models::MediaFileVersionInsert {
+                    media_file_id: prev_id.to_string(),
+                    stack_id: prev_id.to_string(),
+                    version: 1,
+                })?],
+                s => s,
+            };
+            let last = stack.last().expect("stack is never empty here");
+            models::MediaFileVersion::insert(conn, &models::MediaFileVersionInsert {
+                media_file_id: new_id.to_string(),
+                stack_id: last.stack_id.clone(),
+                version: last.version + 1,
+            })
+        })
+    }
+}
+
+
+impl models::ShareLink {
+
+    pub const VIEW: &'static str = "view";
+    pub const COMMENT: &'static str = "comment";
+
+    /// Revoke a share link. Revoking an already revoked link keeps the original revocation time.
+    ///
+    /// # Arguments
+    /// * `link_id` - ID of the share link
+    pub fn revoke(conn: &mut PooledConnection, link_id: &str) -> EmptyDBResult
+    {
+        use schema::share_links::dsl::*;
+        let n = retry_if_db_locked!({
+            diesel::update(share_links.filter(id.eq(link_id)).filter(revoked.is_null()))
+                .set(revoked.eq(chrono::Utc::now().naive_utc()))
+                .execute(conn)
+        })?;
+        if n == 0 && models::ShareLink::get(conn, &link_id.to_string()).is_err() {
+            return Err(DBError::NotFound());
+        }
+        Ok(())
+    }
+
+    /// Is the link currently usable (not revoked or expired)?
+    ///
+    /// # Arguments
+    /// * `now` - Current UTC time
+    pub fn is_active(&self, now: chrono::NaiveDateTime) -> bool {
+        self.revoked.is_none() && self.expires > now
+    }
+
+    /// Delete links that expired before given time. Revoked links are deleted on the same schedule.
+    ///
+    /// # Returns
+    /// * `usize` - Number of links deleted
+    pub fn delete_expired_before(conn: &mut PooledConnection, before: chrono::NaiveDateTime) -> DBResult<usize>
+    {
+        use schema::share_links::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            diesel::delete(share_links.filter(expires.lt(before))).execute(conn)
+        }))
+    }
+}
+
+
+impl models::Approval {
+
+    pub const APPROVED: &'static str = "approved";
+    pub const CHANGES_REQUESTED: &'static str = "changes_requested";
+    pub const PENDING: &'static str = "pending";
+
+    /// Record a reviewer's decision on a media file, replacing their earlier one (if any).
+    ///
+    /// # Arguments
+    /// * `mf_id` - Media file being reviewed
+    /// * `reviewer_id` - User ID of the reviewer
+    /// * `reviewer_name` - Reviewer's display name
+    /// * `new_decision` - One of `APPROVED`, `CHANGES_REQUESTED` or `PENDING`
+    /// * `new_note` - Optional free-form note (e.g. what needs to change)
+    ///
+    /// # Returns
+    /// * `DBResult<models::Approval>` - The stored decision
+    pub fn set(conn: &mut PooledConnection, mf_id: &str, reviewer_id: &str, reviewer_name: &str, new_decision: &str, new_note: Option<&str>) -> DBResult<models::Approval>
+    {
+        if ![Self::APPROVED, Self::CHANGES_REQUESTED, Self::PENDING].contains(&new_decision) {
+            return Err(DBError::Other(anyhow::anyhow!("Invalid approval decision '{}'", new_decision)));
+        }
+        conn.transaction::<_, DBError, _>(|conn| {
+            use schema::approvals::dsl::*;
+            let n = retry_if_db_locked!({
+                diesel::update(approvals.filter(media_file_id.eq(mf_id)).filter(user_id.eq(reviewer_id)))
+                    .set((username.eq(reviewer_name), decision.eq(new_decision), note.eq(new_note), updated.eq(chrono::Utc::now().naive_utc())))
+                    .execute(conn)
+            })?;
+            if n == 0 {
+                models::Approval::insert(conn, &models::ApprovalInsert {
+                    media_file_id: mf_id.to_string(),
+                    user_id: reviewer_id.to_string(),
+                    username: reviewer_name.to_string(),
+                    decision: new_decision.to_string(),
+                    note: new_note.map(|s| s.to_string()),
+                })?;
+            }
+            Ok(approvals.filter(media_file_id.eq(mf_id)).filter(user_id.eq(reviewer_id)).first::<models::Approval>(conn)?)
+        })
+    }
+
+    /// Combine reviewers' decisions into an overall status for the media file:
+    /// any `CHANGES_REQUESTED` wins, then any `PENDING`, and it's `APPROVED` only if everyone approved.
+    ///
+    /// # Returns
+    /// * `Option<&str>` - Overall decision, or None if there are no reviews
+    pub fn aggregate(approvals: &[models::Approval]) -> Option<&'static str> {
+        if approvals.is_empty() {
+            None
+        } else if approvals.iter().any(|a| a.decision == Self::CHANGES_REQUESTED) {
+            Some(Self::CHANGES_REQUESTED)
+        } else if approvals.iter().all(|a| a.decision == Self::APPROVED) {
+            Some(Self::APPROVED)
+        } else {
+            Some(Self::PENDING)
+        }
+    }
+}
+
+
+impl models::WebhookDelivery {
+
+    pub const QUEUED: &'static str = "queued";
+    pub const DONE: &'static str = "done";
+    pub const FAILED: &'static str = "failed";
+
+    /// Record the outcome of a delivery attempt, and count it.
+    ///
+    /// # Arguments
+    /// * `delivery_id` - ID of the delivery
+    /// * `status` - HTTP status of the response, if there was one
+    /// * `err_text` - Error message, or None if the endpoint accepted the delivery
+    /// * `retry_at` - When to try again after an error, or None to give up
+    pub fn set_attempt_result(conn: &mut PooledConnection, delivery_id: i32, status: Option<i32>, err_text: Option<&str>, retry_at: Option<chrono::NaiveDateTime>) -> EmptyDBResult
+    {
+        use schema::webhook_deliveries::dsl::*;
+        let new_state = match (err_text, retry_at) {
+            (None, _) => Self::DONE,
+            (Some(_), Some(_)) => Self::QUEUED,
+            (Some(_), None) => Self::FAILED,
+        };
+        retry_if_db_locked!({
+            diesel::update(webhook_deliveries.filter(id.eq(delivery_id)))
+                .set((state.eq(new_state), attempts.eq(attempts + 1), response_status.eq(status), error.eq(err_text),
+                    last_attempt.eq(chrono::Utc::now().naive_utc()), next_attempt.eq(retry_at)))
+                .execute(conn)
+        })?;
+        Ok(())
+    }
+
+    /// Get queued deliveries that are ready to be sent (i.e. not waiting for retry backoff), oldest first.
+    ///
+    /// # Arguments
+    /// * `now` - Current UTC time
+    pub fn get_due(conn: &mut PooledConnection, now: chrono::NaiveDateTime) -> DBResult<Vec<models::WebhookDelivery>>
+    {
+        use schema::webhook_deliveries::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            webhook_deliveries.filter(state.eq(Self::QUEUED))
+                .filter(next_attempt.is_null().or(next_attempt.le(now)))
+                .order(id.asc())
+                .load::<models::WebhookDelivery>(conn)
+        }))
+    }
+
+    /// Get deliveries in given state, newest first.
+    ///
+    /// # Arguments
+    /// * `st` - State to filter by (e.g. `WebhookDelivery::FAILED`)
+    /// * `pg` - Paging
+    pub fn get_by_state(conn: &mut PooledConnection, st: &str, pg: DBPaging) -> DBResult<Vec<models::WebhookDelivery>>
+    {
+        use schema::webhook_deliveries::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            webhook_deliveries.filter(state.eq(st))
+                .order(created.desc())
+                .then_order_by(id.desc())
+                .offset(pg.offset())
+                .limit(pg.limit())
+                .load::<models::WebhookDelivery>(conn)
+        }))
+    }
+
+    /// Delete successful deliveries older than given time. Failed ones are kept for inspection.
+    ///
+    /// # Returns
+    /// * `usize` - Number of deliveries deleted
+    pub fn delete_done_before(conn: &mut PooledConnection, before: chrono::NaiveDateTime) -> DBResult<usize>
+    {
+        use schema::webhook_deliveries::dsl::*;
+        to_db_res(retry_if_db_locked!({
+            diesel::delete(webhook_deliveries.filter(state.eq(Self::DONE)).filter(created.lt(before))).execute(conn)
+        }))
+    }
 }
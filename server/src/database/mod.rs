@@ -14,6 +14,7 @@ pub mod models;
 pub mod error;
 pub mod migration_solver;
 pub mod db_backup;
+pub mod search;
 
 #[cfg(test)]
 pub mod tests;
@@ -253,6 +254,18 @@ impl Default for DBPaging {
     }
 }
 
+// ---------------- Filters ----------------
+
+/// Conditions for `Comment::get_filtered()`. Comments must match all that are set.
+#[derive(Debug, Default, Clone)]
+pub struct CommentFilter {
+    pub ids: Option<Vec<i32>>,
+    pub user_id: Option<String>,
+    pub media_file_id: Option<String>,
+    pub status: Option<String>,         // Comment::OPEN, RESOLVED or WONTFIX
+    pub assignee_id: Option<String>,
+}
+
 
 pub trait DbBasicQuery<P, I>: Sized
     where P: std::str::FromStr + Send + Sync + Clone,
@@ -295,6 +308,11 @@ crate::implement_basic_query_traits!(models::MediaFile, models::MediaFileInsert,
 crate::implement_basic_query_traits!(models::Comment, models::CommentInsert, comments, i32, created.desc());
 crate::implement_basic_query_traits!(models::Message, models::MessageInsert, messages, i32, created.desc());
 crate::implement_basic_query_traits!(models::Subtitle, models::SubtitleInsert, subtitles, i32, added_time.desc());
+crate::implement_basic_query_traits!(models::Job, models::JobInsert, jobs, i32, created.desc());
+crate::implement_basic_query_traits!(models::ShareLink, models::ShareLinkInsert, share_links, String, created.desc());
+crate::implement_basic_query_traits!(models::MediaFileVersion, models::MediaFileVersionInsert, media_file_versions, i32, created.desc());
+crate::implement_basic_query_traits!(models::Approval, models::ApprovalInsert, approvals, i32, updated.desc());
+crate::implement_basic_query_traits!(models::WebhookDelivery, models::WebhookDeliveryInsert, webhook_deliveries, i32, created.desc());
 
 crate::implement_update_traits!(models::User, users, String);
 crate::implement_update_traits!(models::MediaFile, media_files, String);
@@ -322,3 +340,6 @@ pub trait DbQueryByMediaFile: Sized {
 crate::implement_query_by_media_file_traits!(models::Comment, comments, media_file_id, created.desc());
 crate::implement_query_by_media_file_traits!(models::Message, messages, media_file_id, created.desc());
 crate::implement_query_by_media_file_traits!(models::Subtitle, subtitles, media_file_id, added_time.desc());
+crate::implement_query_by_media_file_traits!(models::Job, jobs, media_file_id, created.desc());
+crate::implement_query_by_media_file_traits!(models::ShareLink, share_links, media_file_id, created.desc());
+crate::implement_query_by_media_file_traits!(models::Approval, approvals, media_file_id, updated.desc());
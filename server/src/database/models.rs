@@ -14,6 +14,13 @@ pub struct User {
 
     #[serde(with = "ts_seconds")]
     pub created: chrono::NaiveDateTime,
+
+    pub email: Option<String>,
+    pub email_frequency: String,
+    pub email_last_msg_id: i32,
+
+    #[serde(with = "ts_seconds_option")]
+    pub email_last_sent: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Insertable)]
@@ -56,6 +63,12 @@ pub struct MediaFile {
     pub fps: Option<String>,
     pub raw_metadata_all: Option<String>,
     pub default_subtitle_id: Option<i32>,
+    pub adaptive_done: Option<chrono::NaiveDateTime>,
+    pub adaptive_has_dash: Option<bool>,
+    pub content_hash: Option<String>,
+    pub start_timecode: Option<String>,     // Embedded source timecode of the first frame, if any
+    pub transcode_profile: Option<String>,  // Transcoding profile picked at upload, None = default
+    pub transcode_bitrate: Option<i32>,     // Video bitrate of the finished transcode, if any
 }
 
 #[derive(Serialize, Deserialize, Debug, Insertable)]
@@ -77,6 +90,12 @@ pub struct MediaFileInsert {
     pub fps: Option<String>,
     pub raw_metadata_all: Option<String>,
     pub default_subtitle_id: Option<i32>,
+    pub adaptive_done: Option<chrono::NaiveDateTime>,
+    pub adaptive_has_dash: Option<bool>,
+    pub content_hash: Option<String>,
+    pub start_timecode: Option<String>,     // Embedded source timecode of the first frame, if any
+    pub transcode_profile: Option<String>,  // Transcoding profile picked at upload, None = default
+    pub transcode_bitrate: Option<i32>,     // Video bitrate of the finished transcode, if any
 }
 
 // -------------------------------------------------------
@@ -135,9 +154,16 @@ pub struct Comment {
     pub drawing: Option<String>,
     pub subtitle_id: Option<i32>,
     pub subtitle_filename_ifnull: Option<String>,
+    pub frame: Option<i32>,     // Frame number from start of media file, normalized from `timecode`
+    pub status: String,         // Comment::OPEN, RESOLVED or WONTFIX
+    pub assignee_id: Option<String>,
+    pub resolved_by: Option<String>,
+
+    #[serde(with = "ts_seconds_option")]
+    pub resolved_at: Option<chrono::NaiveDateTime>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[derive(Serialize, Deserialize, Debug, Insertable, Clone)]
 #[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
 #[diesel(belongs_to(User, foreign_key = user_id))]
 #[diesel(table_name = comments)]
@@ -151,7 +177,8 @@ pub struct CommentInsert {
     pub drawing: Option<String>,
     pub subtitle_id: Option<i32>,
     pub subtitle_filename_ifnull: Option<String>,
-
+    pub frame: Option<i32>,
+    pub assignee_id: Option<String>,
 }
 
 // -------------------------------------------------------
@@ -193,6 +220,161 @@ pub struct MessageInsert {
     pub details: String,
 }
 
+// -------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, AsChangeset, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(treat_none_as_null = true)]
+pub struct Job {
+    pub id: i32,
+    pub media_file_id: String,
+    pub user_id: String,
+    pub job_type: String,
+    pub state: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds_option")]
+    pub started: Option<chrono::NaiveDateTime>,
+
+    #[serde(with = "ts_seconds_option")]
+    pub finished: Option<chrono::NaiveDateTime>,
+
+    #[serde(with = "ts_seconds_option")]
+    pub next_attempt: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = jobs)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+pub struct JobInsert {
+    pub media_file_id: String,
+    pub user_id: String,
+    pub job_type: String,
+    pub state: String,
+    pub payload: String,
+}
+
+// -------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, AsChangeset, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(treat_none_as_null = true)]
+pub struct ShareLink {
+    pub id: String,
+    pub media_file_id: String,
+    pub created_by: String,
+    pub permission: String,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds")]
+    pub expires: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds_option")]
+    pub revoked: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = share_links)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+pub struct ShareLinkInsert {
+    pub id: String,
+    pub media_file_id: String,
+    pub created_by: String,
+    pub permission: String,
+    pub expires: chrono::NaiveDateTime,
+}
+
+// -------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, AsChangeset, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(treat_none_as_null = true)]
+pub struct MediaFileVersion {
+    pub id: i32,
+    pub media_file_id: String,
+    pub stack_id: String,       // ID of the first version's media file
+    pub version: i32,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = media_file_versions)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+pub struct MediaFileVersionInsert {
+    pub media_file_id: String,
+    pub stack_id: String,
+    pub version: i32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, Associations, AsChangeset, Clone)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+#[diesel(treat_none_as_null = true)]
+pub struct Approval {
+    pub id: i32,
+    pub media_file_id: String,
+    pub user_id: String,        // Reviewer
+    pub username: String,
+    pub decision: String,       // See `Approval::APPROVED` etc.
+    pub note: Option<String>,
+
+    #[serde(with = "ts_seconds")]
+    pub updated: chrono::NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = approvals)]
+#[diesel(belongs_to(MediaFile, foreign_key = media_file_id))]
+pub struct ApprovalInsert {
+    pub media_file_id: String,
+    pub user_id: String,
+    pub username: String,
+    pub decision: String,
+    pub note: Option<String>,
+}
+
+// -------------------------------------------------------
+
+#[derive(Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable, AsChangeset, Clone)]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(treat_none_as_null = true)]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub endpoint_url: String,
+    pub event_name: String,
+    pub payload: String,
+    pub state: String,
+    pub attempts: i32,
+    pub response_status: Option<i32>,
+    pub error: Option<String>,
+
+    #[serde(with = "ts_seconds")]
+    pub created: chrono::NaiveDateTime,
+
+    #[serde(with = "ts_seconds_option")]
+    pub last_attempt: Option<chrono::NaiveDateTime>,
+
+    #[serde(with = "ts_seconds_option")]
+    pub next_attempt: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDeliveryInsert {
+    pub endpoint_url: String,
+    pub event_name: String,
+    pub payload: String,
+    pub state: String,
+}
+
 // -------------------------------------------------------
 // Serialization helpers
 // -------------------------------------------------------
@@ -5,6 +5,10 @@ diesel::table! {
         id -> Text,
         name -> Text,
         created -> Timestamp,
+        email -> Nullable<Text>,
+        email_frequency -> Text,
+        email_last_msg_id -> Integer,
+        email_last_sent -> Nullable<Timestamp>,
     }
 }
 
@@ -32,6 +36,12 @@ diesel::table! {
         fps -> Nullable<Text>,
         raw_metadata_all -> Nullable<Text>,
         default_subtitle_id -> Nullable<Integer>,
+        adaptive_done -> Nullable<Timestamp>,
+        adaptive_has_dash -> Nullable<Bool>,
+        content_hash -> Nullable<Text>,
+        start_timecode -> Nullable<Text>,
+        transcode_profile -> Nullable<Text>,
+        transcode_bitrate -> Nullable<Integer>,
     }
 }
 
@@ -49,6 +59,11 @@ diesel::table! {
         drawing -> Nullable<Text>,
         subtitle_id -> Nullable<Integer>,
         subtitle_filename_ifnull -> Nullable<Text>,
+        frame -> Nullable<Integer>,
+        status -> Text,
+        assignee_id -> Nullable<Text>,
+        resolved_by -> Nullable<Text>,
+        resolved_at -> Nullable<Timestamp>,
     }
 }
 
@@ -84,6 +99,77 @@ diesel::table! {
 }
 diesel::joinable!(comments -> subtitles (subtitle_id));
 
+diesel::table! {
+    jobs (id) {
+        id -> Integer,
+        media_file_id -> Text,
+        user_id -> Text,
+        job_type -> Text,
+        state -> Text,
+        payload -> Text,
+        attempts -> Integer,
+        error -> Nullable<Text>,
+        created -> Timestamp,
+        started -> Nullable<Timestamp>,
+        finished -> Nullable<Timestamp>,
+        next_attempt -> Nullable<Timestamp>,
+    }
+}
+diesel::joinable!(jobs -> media_files (media_file_id));
+
+diesel::table! {
+    share_links (id) {
+        id -> Text,
+        media_file_id -> Text,
+        created_by -> Text,
+        permission -> Text,
+        created -> Timestamp,
+        expires -> Timestamp,
+        revoked -> Nullable<Timestamp>,
+    }
+}
+diesel::joinable!(share_links -> media_files (media_file_id));
+
+diesel::table! {
+    media_file_versions (id) {
+        id -> Integer,
+        media_file_id -> Text,
+        stack_id -> Text,
+        version -> Integer,
+        created -> Timestamp,
+    }
+}
+diesel::joinable!(media_file_versions -> media_files (media_file_id));
+
+diesel::table! {
+    approvals (id) {
+        id -> Integer,
+        media_file_id -> Text,
+        user_id -> Text,
+        username -> Text,
+        decision -> Text,
+        note -> Nullable<Text>,
+        updated -> Timestamp,
+    }
+}
+diesel::joinable!(approvals -> media_files (media_file_id));
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Integer,
+        endpoint_url -> Text,
+        event_name -> Text,
+        payload -> Text,
+        state -> Text,
+        attempts -> Integer,
+        response_status -> Nullable<Integer>,
+        error -> Nullable<Text>,
+        created -> Timestamp,
+        last_attempt -> Nullable<Timestamp>,
+        next_attempt -> Nullable<Timestamp>,
+    }
+}
+
 
 diesel::allow_tables_to_appear_in_same_query!(
     users,
@@ -92,4 +178,9 @@ diesel::allow_tables_to_appear_in_same_query!(
     media_files,
     media_types,
     subtitles,
+    jobs,
+    share_links,
+    media_file_versions,
+    approvals,
+    webhook_deliveries,
 );
@@ -0,0 +1,227 @@
+// Full-text search (SQLite FTS5) over media file titles and original filenames, comment text and subtitle cues.
+// The `search_index` table is kept up to date by triggers for media files and comments (see migrations).
+// Subtitle cues are parsed from the stored WebVTT files, so they are indexed here, by `index_subtitle()`.
+
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{anyhow, Context};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Double, Integer, Nullable, Text};
+
+use crate::database::{models, DBPaging, DBResult, EmptyDBResult, DbBasicQuery, DB};
+use crate::retry_if_db_locked;
+use super::PooledConnection;
+
+
+/// What a search hit refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchKind {
+    MediaFile,
+    Comment,
+    Subtitle,
+}
+
+impl SearchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchKind::MediaFile => "media_file",
+            SearchKind::Comment => "comment",
+            SearchKind::Subtitle => "subtitle",
+        }
+    }
+}
+
+impl FromStr for SearchKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "media_file" => Ok(SearchKind::MediaFile),
+            "comment" => Ok(SearchKind::Comment),
+            "subtitle" => Ok(SearchKind::Subtitle),
+            _ => Err(anyhow!("Unknown search index kind '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub kind: SearchKind,
+    pub media_file_id: String,
+    pub comment_id: Option<i32>,
+    pub subtitle_id: Option<i32>,
+    pub start_time_sec: Option<f64>,    // Subtitle cue start, with the subtitle's time offset applied
+    pub snippet: String,                // Matching text, matched terms wrapped in `**`
+    pub score: f64,                     // Relevance (higher is better)
+}
+
+#[derive(QueryableByName, Debug)]
+struct SearchRow {
+    #[diesel(sql_type = Text)]
+    kind: String,
+    #[diesel(sql_type = Text)]
+    ref_id: String,
+    #[diesel(sql_type = Text)]
+    media_file_id: String,
+    #[diesel(sql_type = Nullable<Double>)]
+    start_sec: Option<f64>,
+    #[diesel(sql_type = Text)]
+    snippet: String,
+    #[diesel(sql_type = Double)]
+    rank: f64,
+}
+
+
+/// Turn user input into an FTS5 query: every word must match, as a prefix.
+/// Quoting each word keeps FTS5 operators and punctuation in the input from being interpreted.
+/// Returns None if there's nothing to search for.
+pub fn fts_query(user_query: &str) -> Option<String> {
+    let terms = user_query.split_whitespace()
+        .map(|w| w.replace('"', ""))
+        .filter(|w| w.chars().any(|c| c.is_alphanumeric()))
+        .map(|w| format!("\"{}\"*", w))
+        .collect::<Vec<_>>();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Search the full-text index, best matches first.
+///
+/// # Arguments
+/// * `conn` - Database connection
+/// * `query` - Words to search for (see `fts_query()`)
+/// * `media_file_ids` - Only search within these media files, or None for all
+/// * `kinds` - Only return these kinds of hits, or None for all
+/// * `pg` - Paging
+///
+/// # Returns
+/// * Matches. Empty if the query has no searchable words.
+pub fn search(conn: &mut PooledConnection, query: &str, media_file_ids: Option<&[String]>, kinds: Option<&[SearchKind]>, pg: DBPaging) -> DBResult<Vec<SearchHit>>
+{
+    let Some(fts) = fts_query(query) else { return Ok(vec![]) };
+    let ids_json = media_file_ids.map(|ids| serde_json::json!(ids).to_string());
+    let kinds_json = kinds.map(|k| serde_json::json!(k.iter().map(|k| k.as_str()).collect::<Vec<_>>()).to_string());
+
+    let rows: Vec<SearchRow> = retry_if_db_locked!({
+        diesel::sql_query(r#"
+            SELECT search_index.kind AS kind,
+                CAST(search_index.ref_id AS TEXT) AS ref_id,
+                search_index.media_file_id AS media_file_id,
+                (search_index.start_ms / 1000.0) + coalesce(subtitles.time_offset, 0) AS start_sec,
+                snippet(search_index, 4, '**', '**', '...', 16) AS snippet,
+                bm25(search_index) AS rank
+            FROM search_index
+            LEFT JOIN subtitles ON search_index.kind = 'subtitle' AND subtitles.id = search_index.ref_id
+            WHERE search_index MATCH ?
+                AND (? IS NULL OR search_index.media_file_id IN (SELECT value FROM json_each(?)))
+                AND (? IS NULL OR search_index.kind IN (SELECT value FROM json_each(?)))
+            ORDER BY rank, search_index.rowid
+            LIMIT ? OFFSET ?"#)
+            .bind::<Text, _>(&fts)
+            .bind::<Nullable<Text>, _>(&ids_json)
+            .bind::<Nullable<Text>, _>(&ids_json)
+            .bind::<Nullable<Text>, _>(&kinds_json)
+            .bind::<Nullable<Text>, _>(&kinds_json)
+            .bind::<BigInt, _>(pg.limit())
+            .bind::<BigInt, _>(pg.offset())
+            .load(conn)
+    })?;
+
+    rows.into_iter().map(|r| {
+        let kind = SearchKind::from_str(&r.kind)?;
+        let int_ref = || r.ref_id.parse::<i32>().map_err(|_| anyhow!("Bad {} ID in search index: '{}'", r.kind, r.ref_id));
+        Ok(SearchHit {
+            comment_id: if kind == SearchKind::Comment { Some(int_ref()?) } else { None },
+            subtitle_id: if kind == SearchKind::Subtitle { Some(int_ref()?) } else { None },
+            start_time_sec: r.start_sec.filter(|_| kind == SearchKind::Subtitle),
+            kind,
+            media_file_id: r.media_file_id,
+            snippet: r.snippet,
+            score: -r.rank,     // bm25() is smaller for better matches
+        })
+    }).collect()
+}
+
+
+/// Replace the indexed cues of a subtitle.
+///
+/// # Arguments
+/// * `conn` - Database connection
+/// * `sub` - Subtitle the cues belong to
+/// * `cues` - (start time in milliseconds, text) of each cue
+pub fn index_subtitle(conn: &mut PooledConnection, sub: &models::Subtitle, cues: &[(i64, String)]) -> EmptyDBResult
+{
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::sql_query("DELETE FROM search_index WHERE kind = 'subtitle' AND ref_id = ?")
+            .bind::<Integer, _>(sub.id)
+            .execute(conn)?;
+        for (start_ms, text) in cues.iter().filter(|(_, t)| !t.trim().is_empty()) {
+            diesel::sql_query("INSERT INTO search_index (kind, ref_id, media_file_id, start_ms, body) VALUES ('subtitle', ?, ?, ?, ?)")
+                .bind::<Integer, _>(sub.id)
+                .bind::<Text, _>(&sub.media_file_id)
+                .bind::<BigInt, _>(start_ms)
+                .bind::<Text, _>(text)
+                .execute(conn)?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Get subtitles that have nothing in the search index (e.g. added before the index existed)
+fn unindexed_subtitles(conn: &mut PooledConnection) -> DBResult<Vec<models::Subtitle>>
+{
+    #[derive(QueryableByName, Debug)]
+    struct SubId {
+        #[diesel(sql_type = Integer)]
+        id: i32,
+    }
+    let ids: Vec<SubId> = diesel::sql_query(
+        "SELECT id FROM subtitles WHERE id NOT IN (SELECT ref_id FROM search_index WHERE kind = 'subtitle')")
+        .load(conn)?;
+    models::Subtitle::get_many(conn, &ids.iter().map(|s| s.id).collect::<Vec<_>>())
+}
+
+/// Read cues (start time in ms, plain text) of a subtitle from its WebVTT file.
+/// Playback file is either the converted .vtt in `subs/`, or the original (if it was WebVTT already).
+pub fn read_subtitle_cues(media_files_dir: &Path, sub: &models::Subtitle) -> anyhow::Result<Vec<(i64, String)>>
+{
+    use aspasia::{Subtitle, TextEvent, WebVttSubtitle};
+
+    let subs_dir = media_files_dir.join(&sub.media_file_id).join("subs");
+    let path = match &sub.filename {
+        Some(f) => subs_dir.join(f),
+        None => subs_dir.join("orig").join(&sub.orig_filename),
+    };
+    let vtt = WebVttSubtitle::from_path(&path).with_context(|| format!("Failed to read WebVTT file {:?}", path))?;
+    Ok(vtt.events().iter()
+        .map(|cue| (i64::from(cue.start), cue.as_plaintext().split_whitespace().collect::<Vec<_>>().join(" ")))
+        .filter(|(_, text)| !text.is_empty())
+        .collect())
+}
+
+/// Parse a subtitle's WebVTT file and (re)index its cues.
+/// Errors are logged, not returned, as search is secondary to adding the subtitle.
+pub fn index_subtitle_file(conn: &mut PooledConnection, media_files_dir: &Path, sub: &models::Subtitle)
+{
+    match read_subtitle_cues(media_files_dir, sub) {
+        Ok(cues) => {
+            if let Err(e) = index_subtitle(conn, sub, &cues) {
+                tracing::error!(subtitle=sub.id, details=%e, "Failed to index subtitle for search.");
+            }
+        },
+        Err(e) => tracing::warn!(subtitle=sub.id, details=?e, "Couldn't read subtitle cues for search index."),
+    }
+}
+
+/// Index cues of all subtitles missing from the search index. Call on startup.
+///
+/// # Returns
+/// * Number of subtitles checked
+pub fn index_missing_subtitles(db: &DB, media_files_dir: &Path) -> DBResult<usize>
+{
+    let conn = &mut db.conn()?;
+    let subs = unindexed_subtitles(conn)?;
+    for sub in &subs {
+        index_subtitle_file(conn, media_files_dir, sub);
+    }
+    Ok(subs.len())
+}
@@ -1,7 +1,7 @@
 use tracing_test::traced_test;
 use crate::database::*;
 
-use models::{User, MediaType, MediaFile, MediaFileInsert, Message, MessageInsert, Comment, CommentInsert};
+use models::{User, MediaType, MediaFile, MediaFileInsert, Message, MessageInsert, Comment, CommentInsert, Job, JobInsert, MediaFileVersion, Approval};
 
 
 fn _dump_db(conn: &mut PooledConnection) {
@@ -29,6 +29,61 @@ fn _dump_db(conn: &mut PooledConnection) {
     println!("=========================================");
 }
 
+/// Make an in-memory (not stored) video media file for unit tests.
+/// Everything optional is None; override fields with struct update syntax as needed.
+pub fn make_test_media_file() -> MediaFile
+{
+    MediaFile {
+        id: "B1DE0".into(),
+        user_id: "user.num1".into(),
+        media_type: Some("video".into()),
+        added_time: chrono::NaiveDateTime::default(),
+        recompression_done: None,
+        thumbs_done: None,
+        has_thumbnail: None,
+        thumb_sheet_cols: None,
+        thumb_sheet_rows: None,
+        orig_filename: None,
+        title: None,
+        total_frames: None,
+        duration: None,
+        fps: None,
+        raw_metadata_all: None,
+        default_subtitle_id: None,
+        adaptive_done: None,
+        adaptive_has_dash: None,
+        content_hash: None,
+        start_timecode: None,
+        transcode_profile: None,
+        transcode_bitrate: None,
+    }
+}
+
+/// Make an in-memory (not stored) open comment on `make_test_media_file()` for unit tests.
+/// Created time is derived from `id`, so comments sort by ID; override fields with struct update syntax as needed.
+pub fn make_test_comment(id: i32) -> Comment
+{
+    Comment {
+        id,
+        media_file_id: "B1DE0".into(),
+        parent_id: None,
+        created: chrono::DateTime::from_timestamp(1_700_000_000 + id as i64, 0).unwrap().naive_utc(),
+        edited: None,
+        user_id: Some("user.num1".into()),
+        username_ifnull: "User Number1".into(),
+        comment: format!("Comment {}", id),
+        timecode: None,
+        drawing: None,
+        subtitle_id: None,
+        subtitle_filename_ifnull: None,
+        frame: None,
+        status: Comment::OPEN.into(),
+        assignee_id: None,
+        resolved_by: None,
+        resolved_at: None,
+    }
+}
+
 /// Create a temporary database and populate it for testing.
 ///
 /// Contents are roughly as follows:
@@ -86,6 +141,12 @@ pub fn make_test_db() -> (std::sync::Arc<DB>, assert_fs::TempDir, Vec<MediaFile>
             fps: Some(format!("{}", i * i)),
             raw_metadata_all: Some(format!("{{all: {{video: {}}}}}", i)),
             default_subtitle_id: None,
+            adaptive_done: None,
+            adaptive_has_dash: None,
+            content_hash: None,
+            start_timecode: None,
+            transcode_profile: None,
+            transcode_bitrate: None,
         };
         MediaFile::insert(conn, &v).expect("Failed to insert video");
         MediaFile::get(conn, &v.id.into()).expect("Failed to get video")
@@ -104,6 +165,8 @@ pub fn make_test_db() -> (std::sync::Arc<DB>, assert_fs::TempDir, Vec<MediaFile>
             drawing: Some(format!("drawing_{}.webp", i)),
             subtitle_id: None,
             subtitle_filename_ifnull: None,
+            frame: None,
+            assignee_id: None,
         };
         let c = Comment::insert(conn, &c).expect("Failed to insert comment");
         let dp = data_dir.join("videos").join(vid).join("drawings");
@@ -130,6 +193,8 @@ pub fn make_test_db() -> (std::sync::Arc<DB>, assert_fs::TempDir, Vec<MediaFile>
         drawing: Some("".into()),
         subtitle_id: None,
         subtitle_filename_ifnull: None,
+        frame: None,
+        assignee_id: None,
     };
     let cmt = models::Comment::insert(conn, &c).expect("Failed to insert comment");
     comments.push(cmt);
@@ -255,6 +320,8 @@ fn test_comment_delete() -> anyhow::Result<()> {
         drawing: None,
         subtitle_id: None,
         subtitle_filename_ifnull: None,
+        frame: None,
+        assignee_id: None,
     };
     let new_id = models::Comment::insert(conn, &c)?.id;
     assert_ne!(new_id, com[6].id, "Comment ID was re-used after deletion. This would mix up comment threads in the UI.");
@@ -282,6 +349,96 @@ fn test_rename_video() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+#[traced_test]
+fn test_adaptive_playback_urls() -> anyhow::Result<()> {
+    let (db, _data_dir, _vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    // No ladder yet
+    let v = MediaFile::get(conn, &"11111".into())?.to_proto3("https://example.com", vec![], vec![], None);
+    assert!(v.adaptive_playback_url.is_none());
+    assert!(v.dash_manifest_url.is_none());
+
+    // HLS only
+    MediaFile::set_adaptive_done(conn, "11111", false)?;
+    let v = MediaFile::get(conn, &"11111".into())?.to_proto3("https://example.com", vec![], vec![], None);
+    assert_eq!(v.adaptive_playback_url, Some("https://example.com/videos/11111/adaptive/master.m3u8".into()));
+    assert!(v.dash_manifest_url.is_none());
+    assert!(v.processing_metadata.unwrap().adaptive_done.is_some());
+
+    // HLS + DASH, and roundtrip through proto
+    MediaFile::set_adaptive_done(conn, "22222", true)?;
+    let v = MediaFile::get(conn, &"22222".into())?;
+    let p = v.to_proto3("https://example.com", vec![], vec![], None);
+    assert_eq!(p.dash_manifest_url, Some("https://example.com/videos/22222/adaptive/manifest.mpd".into()));
+    assert_eq!(MediaFile::from_proto3(&p)?.adaptive_has_dash, Some(true));
+
+    Ok(())
+}
+
+
+#[test]
+#[traced_test]
+fn test_job_queue_states() -> anyhow::Result<()> {
+    let (db, _data_dir, _vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+    let now = chrono::Local::now().naive_local();
+
+    let j = Job::insert(conn, &JobInsert {
+        media_file_id: "11111".into(),
+        user_id: "user.num2".into(),
+        job_type: "thumbs".into(),
+        state: Job::QUEUED.into(),
+        payload: "{}".into(),
+    })?;
+    assert_eq!(j.attempts, 0);
+    assert!(Job::has_pending(conn, "11111", "thumbs")?);
+    assert!(!Job::has_pending(conn, "11111", "transcode")?);
+    assert!(Job::get_due(conn, now)?.is_empty());    // Already handed to the worker pool
+
+    // Start, fail and schedule a retry
+    assert!(Job::set_running(conn, j.id)?);
+    assert!(!Job::set_running(conn, j.id)?);
+    let retry_at = now + chrono::Duration::seconds(60);
+    Job::set_failed(conn, j.id, "ffmpeg exploded", Some(retry_at))?;
+    let j = Job::get(conn, &j.id)?;
+    assert_eq!((j.state.as_str(), j.attempts), (Job::QUEUED, 1));
+    assert_eq!(j.error.as_deref(), Some("ffmpeg exploded"));
+    assert!(Job::get_due(conn, now)?.is_empty());
+    assert_eq!(Job::get_due(conn, retry_at)?.len(), 1);
+    Job::set_dispatched(conn, j.id)?;
+    assert!(Job::get_due(conn, retry_at)?.is_empty());
+    assert_eq!(Job::get(conn, &j.id)?.state, Job::QUEUED);
+
+    // Interrupted by a restart
+    assert!(Job::set_running(conn, j.id)?);
+    assert_eq!(Job::get_by_state(conn, Job::RUNNING, DBPaging::default())?.len(), 1);
+    assert_eq!(Job::requeue_interrupted(conn)?, 1);
+    assert_eq!(Job::get(conn, &j.id)?.state, Job::QUEUED);
+    assert_eq!(Job::get_due(conn, chrono::Local::now().naive_local())?.len(), 1);
+
+    // Finish
+    assert!(Job::set_running(conn, j.id)?);
+    Job::set_done(conn, j.id)?;
+    let j = Job::get(conn, &j.id)?;
+    assert_eq!((j.state.as_str(), j.attempts), (Job::DONE, 3));
+    assert!(j.error.is_none() && j.finished.is_some());
+    assert!(!Job::has_pending(conn, "11111", "thumbs")?);
+
+    // Give up on a second one
+    let j2 = Job::insert(conn, &JobInsert { media_file_id: "22222".into(), user_id: "user.num1".into(), job_type: "transcode".into(), state: Job::QUEUED.into(), payload: "{}".into() })?;
+    Job::set_failed(conn, j2.id, "no", None)?;
+    assert_eq!(Job::get_by_state(conn, Job::FAILED, DBPaging::default())?.len(), 1);
+
+    // Prune finished ones, and cascade delete with media file
+    assert_eq!(Job::delete_done_before(conn, now + chrono::Duration::days(1))?, 1);
+    MediaFile::delete(conn, &"22222".into())?;
+    assert!(Job::get_all(conn, DBPaging::default())?.is_empty());
+
+    Ok(())
+}
+
 
 #[test]
 #[traced_test]
@@ -424,6 +581,8 @@ fn test_subtitle_add_update_delete() -> anyhow::Result<()> {
         drawing: None,
         subtitle_id: Some(s.id),
         subtitle_filename_ifnull: None,
+        frame: None,
+        assignee_id: None,
     };
     let c = models::Comment::insert(conn, &c)?;
     assert_eq!(models::Comment::get(conn, &c.id)?.subtitle_id, Some(s.id));
@@ -442,6 +601,265 @@ fn test_subtitle_add_update_delete() -> anyhow::Result<()> {
 }
 
 
+#[test]
+#[traced_test]
+fn test_comment_frames() -> anyhow::Result<()> {
+    let (db, _data_dir, vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    // Fixture comments have no timecodes
+    assert_eq!(Comment::fill_missing_frames(conn)?, 0);
+
+    let mut add = |media_file_id: &str, timecode: Option<&str>| Comment::insert(conn, &CommentInsert {
+        media_file_id: media_file_id.to_string(),
+        parent_id: None,
+        user_id: Some("user.num1".to_string()),
+        username_ifnull: "User Number1".to_string(),
+        comment: "Timed comment".to_string(),
+        timecode: timecode.map(|s| s.to_string()),
+        drawing: None,
+        subtitle_id: None,
+        subtitle_filename_ifnull: None,
+        frame: None,
+        assignee_id: None,
+    }).unwrap();
+    let a = add(&vid[0].id, Some("00:00:02:05"));   // No valid fps, so default 25
+    let b = add(&vid[3].id, Some("00:00:02:05"));   // 9 fps
+    let c = add(&vid[3].id, Some("00:00:02:10"));   // Invalid at 9 fps
+    let d = add(&vid[3].id, None);
+
+    assert_eq!(Comment::fill_missing_frames(conn)?, 2);
+    let frame_of = |conn: &mut PooledConnection, c: &Comment| Comment::get(conn, &c.id).unwrap().frame;
+    assert_eq!(frame_of(conn, &a), Some(55));
+    assert_eq!(frame_of(conn, &b), Some(23));
+    assert_eq!(frame_of(conn, &c), None);
+    assert_eq!(frame_of(conn, &d), None);
+
+    // Recompute after timecode change
+    let mut b = Comment::get(conn, &b.id)?;
+    b.timecode = Some("00:00:01:00".into());
+    Comment::update_many(conn, &[b.clone()])?;
+    assert_eq!(Comment::update_frame(conn, &b, &vid[3])?, Some(9));
+    assert_eq!(frame_of(conn, &b), Some(9));
+
+    Ok(())
+}
+
+#[test]
+#[traced_test]
+fn test_media_file_versions() -> anyhow::Result<()> {
+    let (db, _data_dir, vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+    let ids = |stack: Vec<MediaFileVersion>| stack.into_iter().map(|v| (v.media_file_id, v.version)).collect::<Vec<_>>();
+
+    assert!(MediaFileVersion::get_stack_of(conn, &vid[0].id)?.is_empty());
+
+    // First new version starts a stack, with the previous file as version 1
+    let v = MediaFileVersion::add_version(conn, &vid[0].id, &vid[3].id)?;
+    assert_eq!((v.stack_id.as_str(), v.version), (vid[0].id.as_str(), 2));
+
+    // Adding after any member appends to the end
+    let v = MediaFileVersion::add_version(conn, &vid[3].id, &vid[4].id)?;
+    assert_eq!((v.stack_id.as_str(), v.version), (vid[0].id.as_str(), 3));
+    assert_eq!(ids(MediaFileVersion::get_stack_of(conn, &vid[3].id)?),
+        vec![(vid[0].id.clone(), 1), (vid[3].id.clone(), 2), (vid[4].id.clone(), 3)]);
+
+    // Already stacked, itself, or nonexistent
+    assert!(MediaFileVersion::add_version(conn, &vid[1].id, &vid[4].id).is_err());
+    assert!(MediaFileVersion::add_version(conn, &vid[1].id, &vid[1].id).is_err());
+    assert!(matches!(MediaFileVersion::add_version(conn, &vid[1].id, "no_such_file"), Err(DBError::NotFound())));
+    assert!(MediaFileVersion::get_stack_of(conn, &vid[1].id)?.is_empty());
+
+    // Deleting a media file removes it from the stack, but keeps the stack ID
+    MediaFile::delete(conn, &vid[0].id)?;
+    assert_eq!(ids(MediaFileVersion::get_stack_of(conn, &vid[4].id)?),
+        vec![(vid[3].id.clone(), 2), (vid[4].id.clone(), 3)]);
+    let v = MediaFileVersion::add_version(conn, &vid[4].id, &vid[1].id)?;
+    assert_eq!((v.stack_id.as_str(), v.version), (vid[0].id.as_str(), 4));
+
+    Ok(())
+}
+
+#[test]
+#[traced_test]
+fn test_copy_comment_threads() -> anyhow::Result<()> {
+    let (db, _data_dir, vid, com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    // Give one thread a timecode, to check frame recomputation (vid[0] has no valid fps, vid[3] has 9)
+    let mut c = com[0].clone();
+    c.timecode = Some("00:00:01:05".into());
+    Comment::update_many(conn, &[c])?;
+
+    // vid[0] has three threads: comments 1 (with replies 6 and 7), 4 and 8. Resolved ones aren't copied.
+    Comment::set_status(conn, com[3].id, Comment::RESOLVED, "user.num1")?;
+    Comment::set_assignee(conn, com[0].id, Some("user.num2"))?;
+    let copied = Comment::copy_threads(conn, &vid[0].id, &vid[3])?;
+    assert_eq!(copied.len(), 4);
+    assert!(copied.iter().all(|c| c.media_file_id == vid[3].id && c.status == Comment::OPEN));
+    assert!(!copied.iter().any(|c| c.comment == "Comment 3"));
+    let root = copied.iter().find(|c| c.comment == "Comment 0").unwrap();
+    assert_eq!(root.frame, Some(14));
+    assert_eq!(root.drawing.as_deref(), Some("drawing_0.webp"));
+    assert_eq!(root.assignee_id.as_deref(), Some("user.num2"));
+    assert_eq!(Comment::get(conn, &root.id)?.assignee_id.as_deref(), Some("user.num2"));
+    for reply in ["Comment 5", "Comment 6"] {
+        let r = copied.iter().find(|c| c.comment == reply).unwrap();
+        assert_eq!(r.parent_id, Some(root.id));
+        assert_eq!(r.user_id, com.iter().find(|c| c.comment == reply).unwrap().user_id);
+    }
+
+    // Source is unchanged, and a second copy doesn't duplicate threads
+    assert_eq!(Comment::get_by_media_file(conn, &vid[0].id, DBPaging::default())?.len(), 5);
+    assert!(Comment::copy_threads(conn, &vid[0].id, &vid[3])?.is_empty());
+    assert_eq!(Comment::get_by_media_file(conn, &vid[3].id, DBPaging::default())?.len(), 4);
+
+    Ok(())
+}
+
+#[test]
+#[traced_test]
+fn test_comment_status() -> anyhow::Result<()> {
+    let (db, _data_dir, vid, com) = make_test_db();
+    let conn = &mut db.conn()?;
+    assert!(com.iter().all(|c| c.status == Comment::OPEN && c.assignee_id.is_none()));
+
+    // Resolving records who and when, reopening clears them
+    assert!(Comment::set_status(conn, com[0].id, Comment::RESOLVED, "user.num2")?);
+    let c = Comment::get(conn, &com[0].id)?;
+    assert_eq!((c.status.as_str(), c.resolved_by.as_deref()), (Comment::RESOLVED, Some("user.num2")));
+    assert!(c.resolved_at.is_some());
+    Comment::set_status(conn, com[0].id, Comment::OPEN, "user.num1")?;
+    let c = Comment::get(conn, &com[0].id)?;
+    assert_eq!((c.status.as_str(), c.resolved_by, c.resolved_at), (Comment::OPEN, None, None));
+
+    assert!(Comment::set_status(conn, com[0].id, "maybe", "user.num1").is_err());
+    assert!(!Comment::set_status(conn, 9999, Comment::WONTFIX, "user.num1")?);
+
+    // Filter by status and assignee, combined with other conditions
+    Comment::set_status(conn, com[3].id, Comment::WONTFIX, "user.num1")?;
+    Comment::set_status(conn, com[1].id, Comment::WONTFIX, "user.num1")?;
+    Comment::set_assignee(conn, com[0].id, Some("user.num2"))?;
+    Comment::set_assignee(conn, com[1].id, Some("user.num2"))?;
+    let mut ids = |f: CommentFilter| Comment::get_filtered(conn, &f, DBPaging::default()).unwrap().iter().map(|c| c.id).collect::<std::collections::BTreeSet<_>>();
+
+    assert_eq!(ids(CommentFilter { status: Some(Comment::WONTFIX.into()), ..Default::default() }), [com[1].id, com[3].id].into());
+    assert_eq!(ids(CommentFilter { status: Some(Comment::WONTFIX.into()), media_file_id: Some(vid[0].id.clone()), ..Default::default() }), [com[3].id].into());
+    assert_eq!(ids(CommentFilter { assignee_id: Some("user.num2".into()), ..Default::default() }), [com[0].id, com[1].id].into());
+    assert_eq!(ids(CommentFilter { assignee_id: Some("user.num2".into()), status: Some(Comment::OPEN.into()), ..Default::default() }), [com[0].id].into());
+    assert_eq!(ids(CommentFilter { ids: Some(vec![com[0].id, com[2].id]), ..Default::default() }), [com[0].id, com[2].id].into());
+    assert_eq!(ids(CommentFilter::default()).len(), com.len());
+
+    Comment::set_assignee(conn, com[0].id, None)?;
+    assert_eq!(Comment::get(conn, &com[0].id)?.assignee_id, None);
+
+    Ok(())
+}
+
+#[test]
+#[traced_test]
+fn test_approvals() -> anyhow::Result<()> {
+    let (db, _data_dir, vid, _com) = make_test_db();
+    let conn = &mut db.conn()?;
+    assert_eq!(Approval::aggregate(&vid[0].get_approvals(conn)?), None);
+
+    // One decision per reviewer; a new one replaces the old
+    Approval::set(conn, &vid[0].id, "user.num2", "User 2", Approval::CHANGES_REQUESTED, Some("Fix the audio"))?;
+    Approval::set(conn, &vid[0].id, "user.num1", "User 1", Approval::APPROVED, None)?;
+    assert_eq!(Approval::aggregate(&vid[0].get_approvals(conn)?), Some(Approval::CHANGES_REQUESTED));
+
+    let a = Approval::set(conn, &vid[0].id, "user.num2", "User 2", Approval::PENDING, None)?;
+    assert_eq!((a.decision.as_str(), a.note), (Approval::PENDING, None));
+    assert_eq!(vid[0].get_approvals(conn)?.len(), 2);
+    assert_eq!(Approval::aggregate(&vid[0].get_approvals(conn)?), Some(Approval::PENDING));
+
+    Approval::set(conn, &vid[0].id, "user.num2", "User 2", Approval::APPROVED, Some("LGTM"))?;
+    assert_eq!(Approval::aggregate(&vid[0].get_approvals(conn)?), Some(Approval::APPROVED));
+    assert_eq!(vid[1].get_approvals(conn)?.len(), 0);
+
+    assert!(Approval::set(conn, &vid[0].id, "user.num2", "User 2", "maybe", None).is_err());
+    assert!(Approval::set(conn, "no-such-file", "user.num2", "User 2", Approval::APPROVED, None).is_err());
+
+    // Deleted with the media file
+    MediaFile::delete(conn, &vid[0].id)?;
+    assert!(Approval::get_all(conn, DBPaging::default())?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_search_fts_query() {
+    use crate::database::search::fts_query;
+    assert_eq!(fts_query("hello world"), Some("\"hello\"* \"world\"*".into()));
+    assert_eq!(fts_query("  say \"NEAR(a b)\" OR - "), Some("\"say\"* \"NEAR(a\"* \"b)\"* \"OR\"*".into()));
+    assert_eq!(fts_query(" - * \" "), None);
+}
+
+#[test]
+#[traced_test]
+fn test_search_index() -> anyhow::Result<()> {
+    use crate::database::search::{search, index_subtitle_file, SearchKind};
+    let (db, data_dir, vid, com) = make_test_db();
+    let conn = &mut db.conn()?;
+
+    // Media file titles and comments are indexed by triggers, including fixture data
+    let hits = search(conn, "test3", None, None, DBPaging::default())?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].kind, hits[0].media_file_id.as_str()), (SearchKind::MediaFile, "B1DE3"));
+    assert!(hits[0].snippet.contains("**test3"));
+
+    let hits = search(conn, "comm", None, Some(&[SearchKind::Comment]), DBPaging::default())?;
+    assert_eq!(hits.len(), com.len());
+    let hits = search(conn, "comment", Some(&["11111".to_string()]), None, DBPaging::default())?;
+    assert_eq!(hits.iter().map(|h| h.comment_id.unwrap()).collect::<std::collections::HashSet<_>>(), [com[1].id, com[4].id].into());
+
+    // Updates and deletes are reflected
+    MediaFile::rename(conn, "11111", "Holiday footage")?;
+    let hits = search(conn, "holi test1", None, None, DBPaging::default())?;   // New title + original filename
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].media_file_id, "11111");
+
+    models::Comment::update_many(conn, &[models::Comment { comment: "Zebra crossing".into(), ..com[0].clone() }])?;
+    let hits = search(conn, "zebra", None, None, DBPaging::default())?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].comment_id, Some(com[0].id));
+
+    models::Comment::delete(conn, &com[0].id)?;
+    assert!(search(conn, "zebra", None, None, DBPaging::default())?.is_empty());
+
+    // Subtitle cues are indexed from the .vtt file, with time offset applied
+    let subs_dir = data_dir.join("videos").join(&vid[2].id).join("subs");
+    std::fs::create_dir_all(&subs_dir)?;
+    std::fs::write(subs_dir.join("sub1.vtt"), "WEBVTT\n\n00:00:01.000 --> 00:00:02.000\nFirst line\n\n00:01:05.500 --> 00:01:07.000\n<i>Kangaroo</i> jumps\n")?;
+    let sub = models::Subtitle::insert(conn, &models::SubtitleInsert {
+        media_file_id: vid[2].id.clone(),
+        title: "Subtitle 1".to_string(),
+        language_code: "en".to_string(),
+        filename: Some("sub1.vtt".to_string()),
+        orig_filename: "sub1.srt".to_string(),
+        time_offset: 2.0,
+    })?;
+    index_subtitle_file(conn, &data_dir.join("videos"), &sub);
+
+    let hits = search(conn, "kangaroo", None, None, DBPaging::default())?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].kind, hits[0].subtitle_id, hits[0].start_time_sec), (SearchKind::Subtitle, Some(sub.id), Some(67.5)));
+    assert_eq!(hits[0].snippet, "**Kangaroo** jumps");
+
+    // Deleting the subtitle, or its media file, removes its cues
+    models::Subtitle::delete(conn, &sub.id)?;
+    assert!(search(conn, "kangaroo", None, None, DBPaging::default())?.is_empty());
+
+    MediaFile::delete(conn, &vid[2].id)?;
+    assert!(search(conn, "test2", None, None, DBPaging::default())?.is_empty());
+
+    // Paging
+    let all = search(conn, "comment", None, None, DBPaging::default())?;
+    let pg = DBPaging { page_num: 1, page_size: std::num::NonZeroU32::new(2).unwrap() };
+    assert_eq!(search(conn, "comment", None, None, pg)?, all[2..4].to_vec());
+
+    Ok(())
+}
+
 #[test]
 #[traced_test]
 fn test_migrate_existing_v056_db() -> anyhow::Result<()> {
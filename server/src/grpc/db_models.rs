@@ -1,10 +1,16 @@
 use lib_clapshot_grpc::proto;
 use crate::database::{error::{DBError, DBResult}, DBPaging, DbQueryByMediaFile, PooledConnection};
 use crate::database::models;
+use crate::database::search::{SearchHit, SearchKind};
 
 use super::{datetime_to_proto3, proto3_to_datetime};
 
 
+/// Event names for persistent user messages that are shown as `Ok` messages, but
+/// need to be told apart later (e.g. for picking an e-mail template).
+pub const MENTION_EVENT: &str = "mention";
+pub const COMMENT_REPLY_EVENT: &str = "comment_reply";
+
 pub fn proto_msg_type_to_event_name(t: proto::user_message::Type) -> &'static str {
     match t {
         proto::user_message::Type::Ok => "ok",
@@ -22,11 +28,20 @@ pub fn msg_event_name_to_proto_msg_type(t: &str) -> proto::user_message::Type {
         "progress" => proto::user_message::Type::Progress,
         "media_file_updated" => proto::user_message::Type::MediaFileUpdated,
         "media_file_added" => proto::user_message::Type::MediaFileAdded,
+        MENTION_EVENT | COMMENT_REPLY_EVENT => proto::user_message::Type::Ok,
         _ => proto::user_message::Type::Ok,
     }
 }
 
 
+/// URL of a file under `/videos/<media_file_id>/`, with signed access token if given
+fn videos_url(url_base: &str, media_file_id: &str, uri: &str, url_token: Option<&str>) -> String {
+    match url_token {
+        Some(t) => format!("{}/videos/{}/{}?sig={}", url_base, media_file_id, uri, t),
+        None => format!("{}/videos/{}/{}", url_base, media_file_id, uri),
+    }
+}
+
 // ============================ MediaFile ============================
 
 impl models::MediaFile
@@ -50,16 +65,31 @@ impl models::MediaFile
             fps: v.duration.as_ref().map(|d| d.fps.clone()),
             raw_metadata_all: v.processing_metadata.as_ref().map(|m| m.ffprobe_metadata_all.clone()).flatten(),
             default_subtitle_id: v.default_subtitle_id.as_ref().map(|id| id.parse().map_err(|_| DBError::Other(anyhow::anyhow!("Invalid default_subtitle_id")))).transpose()?,
+            adaptive_done: v.processing_metadata.as_ref().and_then(|m| m.adaptive_done.as_ref()).and_then(proto3_to_datetime),
+            adaptive_has_dash: v.adaptive_playback_url.as_ref().map(|_| v.dash_manifest_url.is_some()),
+            content_hash: v.processing_metadata.as_ref().and_then(|m| m.content_hash.clone()),
+            start_timecode: v.duration.as_ref().and_then(|d| d.start_timecode.clone()),
+            transcode_profile: None,
+            transcode_bitrate: None,
         })
     }
 
-    pub fn to_proto3(&self, url_base: &str, subtitles: Vec<models::Subtitle>) -> proto::MediaFile
+    /// Convert to protobuf.
+    ///
+    /// # Arguments
+    /// * `url_base` - Server URL base, for media file URLs
+    /// * `subtitles` - Subtitles to include
+    /// * `url_token` - Signed access token (see `ServerState::media_url_token()`) to append to `/videos` URLs, if any
+    pub fn to_proto3(&self, url_base: &str, subtitles: Vec<models::Subtitle>, approvals: Vec<models::Approval>, url_token: Option<&str>) -> proto::MediaFile
     {
+        let file_url = |uri: &str| videos_url(url_base, &self.id, uri, url_token);
+
         let duration = match (self.duration, self.total_frames, &self.fps) {
             (Some(dur), Some(total_frames), Some(fps)) => Some(proto::MediaFileDuration {
                 duration: dur as f64,
                 total_frames: total_frames as i64,
                 fps: fps.clone(),
+                start_timecode: self.start_timecode.clone(),
             }),
             _ => None,
         };
@@ -68,6 +98,8 @@ impl models::MediaFile
                 orig_filename: orig_filename.clone(),
                 recompression_done: recompression_done.map(|t| datetime_to_proto3(&t)),
                 thumbs_done: self.thumbs_done.map(|t| datetime_to_proto3(&t)),
+                adaptive_done: self.adaptive_done.map(|t| datetime_to_proto3(&t)),
+                content_hash: self.content_hash.clone(),
                 ffprobe_metadata_all: ffprobe_metadata_all.clone(),
             }),
             _ => None,
@@ -75,12 +107,12 @@ impl models::MediaFile
 
         // Make preview data (thumb sheet and/or thumb url)
         let thumb_url = if matches!(self.has_thumbnail, Some(true)) {
-            Some(format!("{}/videos/{}/thumbs/thumb.webp", &url_base, &self.id))
+            Some(file_url("thumbs/thumb.webp"))
         } else { None };
 
         let thumb_sheet = match (self.thumb_sheet_cols, self.thumb_sheet_rows) {
             (Some(cols), Some(rows)) => Some(proto::media_file_preview_data::ThumbSheet {
-                url: format!("{}/videos/{}/thumbs/sheet-{}x{}.webp", &url_base, &self.id, cols, rows),
+                url: file_url(&format!("thumbs/sheet-{}x{}.webp", cols, rows)),
                 rows: rows as u32,
                 cols: cols as u32,
             }),
@@ -101,6 +133,14 @@ impl models::MediaFile
             None => orig_uri.clone()
         };
 
+        // Adaptive bitrate ladder (HLS master playlist + optional DASH manifest), if transcoded
+        let (adaptive_uri, dash_uri) = match self.adaptive_done {
+            Some(_) => (
+                Some("adaptive/master.m3u8".to_string()),
+                if matches!(self.adaptive_has_dash, Some(true)) { Some("adaptive/manifest.mpd".to_string()) } else { None }),
+            None => (None, None)
+        };
+
         proto::MediaFile {
             id: self.id.clone(),
             title: self.title.clone(),
@@ -110,16 +150,23 @@ impl models::MediaFile
             added_time: Some(datetime_to_proto3(&self.added_time)),
             preview_data,
             processing_metadata,
-            subtitles: subtitles.into_iter().map(|s| s.to_proto3(url_base)).collect(),
+            approval: Some(approval_summary_to_proto3(&approvals)),
+            subtitles: subtitles.into_iter().map(|s| s.to_proto3(url_base, url_token)).collect(),
             default_subtitle_id: self.default_subtitle_id.map(|id| id.to_string()),
-            playback_url: playback_uri.map(|uri| format!("{}/videos/{}/{}", url_base, &self.id, uri)),
-            orig_url: orig_uri.map(|uri| format!("{}/videos/{}/{}", url_base, &self.id, uri))
+            playback_url: playback_uri.map(|uri| file_url(&uri)),
+            orig_url: orig_uri.map(|uri| file_url(&uri)),
+            adaptive_playback_url: adaptive_uri.map(|uri| file_url(&uri)),
+            dash_manifest_url: dash_uri.map(|uri| file_url(&uri)),
         }
     }
 
     pub fn get_subtitles(&self, conn: &mut PooledConnection) -> DBResult<Vec<models::Subtitle>> {
         models::Subtitle::get_by_media_file(conn, &self.id, DBPaging::default())
     }
+
+    pub fn get_approvals(&self, conn: &mut PooledConnection) -> DBResult<Vec<models::Approval>> {
+        models::Approval::get_by_media_file(conn, &self.id, DBPaging::default())
+    }
 }
 
 impl models::MediaFileInsert
@@ -142,6 +189,12 @@ impl models::MediaFileInsert
             fps: v.duration.as_ref().map(|d| d.fps.clone()),
             raw_metadata_all: v.processing_metadata.as_ref().map(|m| m.ffprobe_metadata_all.clone()).flatten(),
             default_subtitle_id: v.default_subtitle_id.as_ref().map(|id| id.parse().map_err(|_| DBError::Other(anyhow::anyhow!("Invalid default_subtitle_id")))).transpose()?,
+            adaptive_done: v.processing_metadata.as_ref().and_then(|m| m.adaptive_done.as_ref()).and_then(proto3_to_datetime),
+            adaptive_has_dash: v.adaptive_playback_url.as_ref().map(|_| v.dash_manifest_url.is_some()),
+            content_hash: v.processing_metadata.as_ref().and_then(|m| m.content_hash.clone()),
+            start_timecode: v.duration.as_ref().and_then(|d| d.start_timecode.clone()),
+            transcode_profile: None,
+            transcode_bitrate: None,
         })
     }
 }
@@ -158,18 +211,18 @@ impl models::Subtitle
             media_file_id: v.media_file_id.clone(),
             title: v.title.clone(),
             language_code: v.language_code.clone(),
-            filename: v.playback_url.split('/').last().map(|s| s.to_string()),
+            filename: v.playback_url.split('?').next().and_then(|u| u.rsplit('/').next()).map(|s| s.to_string()),
             orig_filename: v.orig_filename.clone(),
             added_time: proto3_to_datetime(added_time).ok_or(anyhow::anyhow!("Invalid 'added_time' timestamp"))?,
             time_offset: v.time_offset,
         })
     }
 
-    pub fn to_proto3(&self, url_base: &str) -> proto::Subtitle
+    pub fn to_proto3(&self, url_base: &str, url_token: Option<&str>) -> proto::Subtitle
     {
-        let orig_url = format!("{}/videos/{}/subs/orig/{}", url_base, &self.media_file_id, &self.orig_filename);
+        let orig_url = videos_url(url_base, &self.media_file_id, &format!("subs/orig/{}", &self.orig_filename), url_token);
         let playback_url = match &self.filename {
-            Some(f) => format!("{}/videos/{}/subs/{}", url_base, &self.media_file_id, f),
+            Some(f) => videos_url(url_base, &self.media_file_id, &format!("subs/{}", f), url_token),
             None => orig_url.clone()
         };
         proto::Subtitle {
@@ -198,7 +251,7 @@ impl models::SubtitleInsert
             media_file_id: s.media_file_id.clone(),
             title: s.title.clone(),
             language_code: s.language_code.clone(),
-            filename: s.playback_url.split('/').last().map(|s| s.to_string()),
+            filename: s.playback_url.split('?').next().and_then(|u| u.rsplit('/').next()).map(|s| s.to_string()),
             orig_filename: s.orig_filename.clone(),
             time_offset: s.time_offset,
         })
@@ -227,6 +280,11 @@ impl models::Comment
             drawing: c.drawing.clone(),
             subtitle_id: c.subtitle_id.as_ref().map(|id| id.parse()).transpose().map_err(|_| DBError::Other(anyhow::anyhow!("Invalid subtitle ID")))?,
             subtitle_filename_ifnull: c.subtitle_filename_ifnull.clone(),
+            frame: None,    // Derived from timecode by the server, see `Comment::fill_missing_frames()`
+            status: comment_status_from_proto3(c.status()).to_string(),
+            assignee_id: c.assignee_id.clone(),
+            resolved_by: c.resolved_by.clone(),
+            resolved_at: c.resolved_at.as_ref().and_then(proto3_to_datetime),
         })
     }
 
@@ -248,10 +306,31 @@ impl models::Comment
             drawing: self.drawing.clone(),
             subtitle_id: self.subtitle_id.map(|id| id.to_string()),
             subtitle_filename_ifnull: self.subtitle_filename_ifnull.clone(),
+            frame: self.frame,
+            status: comment_status_to_proto3(&self.status).into(),
+            assignee_id: self.assignee_id.clone(),
+            resolved_by: self.resolved_by.clone(),
+            resolved_at: self.resolved_at.map(|t| datetime_to_proto3(&t)),
         }
     }
 }
 
+pub fn comment_status_to_proto3(status: &str) -> proto::CommentStatus {
+    match status {
+        models::Comment::RESOLVED => proto::CommentStatus::Resolved,
+        models::Comment::WONTFIX => proto::CommentStatus::WontFix,
+        _ => proto::CommentStatus::Open,
+    }
+}
+
+pub fn comment_status_from_proto3(status: proto::CommentStatus) -> &'static str {
+    match status {
+        proto::CommentStatus::Open => models::Comment::OPEN,
+        proto::CommentStatus::Resolved => models::Comment::RESOLVED,
+        proto::CommentStatus::WontFix => models::Comment::WONTFIX,
+    }
+}
+
 impl models::CommentInsert
 {
     pub fn from_proto3(c: &proto::Comment) -> DBResult<Self>
@@ -269,6 +348,8 @@ impl models::CommentInsert
             drawing: c.drawing.clone(),
             subtitle_id: c.subtitle_id.as_ref().map(|id| id.parse()).transpose().map_err(|_| DBError::Other(anyhow::anyhow!("Invalid subtitle ID")))?,
             subtitle_filename_ifnull: c.subtitle_filename_ifnull.clone(),
+            frame: None,
+            assignee_id: c.assignee_id.clone(),
         })
     }
 }
@@ -356,3 +437,114 @@ impl models::MessageInsert
         }
     }
 }
+
+// ============================ ShareLink ============================
+
+impl models::ShareLink
+{
+    /// Convert to protobuf. Token is signed by the server, so it's passed in separately.
+    pub fn to_proto3(&self, url_base: &str, token: String) -> proto::ShareLink
+    {
+        proto::ShareLink {
+            id: self.id.clone(),
+            media_file_id: self.media_file_id.clone(),
+            permission: match self.permission.as_str() {
+                models::ShareLink::COMMENT => proto::ShareLinkPermission::ViewAndComment,
+                _ => proto::ShareLinkPermission::View,
+            }.into(),
+            created_by: self.created_by.clone(),
+            created: Some(datetime_to_proto3(&self.created)),
+            expires: Some(datetime_to_proto3(&self.expires)),
+            revoked: self.revoked.map(|t| datetime_to_proto3(&t)),
+            url: format!("{}/?share={}", url_base, token),
+            token,
+        }
+    }
+}
+
+// ============================ Approval ============================
+
+impl models::Approval
+{
+    pub fn to_proto3(&self) -> proto::Approval
+    {
+        proto::Approval {
+            media_file_id: self.media_file_id.clone(),
+            user_id: self.user_id.clone(),
+            user_name: self.username.clone(),
+            decision: approval_decision_to_proto3(&self.decision).into(),
+            note: self.note.clone(),
+            updated: Some(datetime_to_proto3(&self.updated)),
+        }
+    }
+}
+
+pub fn approval_decision_to_proto3(decision: &str) -> proto::ApprovalDecision {
+    match decision {
+        models::Approval::APPROVED => proto::ApprovalDecision::Approved,
+        models::Approval::CHANGES_REQUESTED => proto::ApprovalDecision::ChangesRequested,
+        _ => proto::ApprovalDecision::Pending,
+    }
+}
+
+pub fn approval_decision_from_proto3(decision: proto::ApprovalDecision) -> &'static str {
+    match decision {
+        proto::ApprovalDecision::Pending => models::Approval::PENDING,
+        proto::ApprovalDecision::Approved => models::Approval::APPROVED,
+        proto::ApprovalDecision::ChangesRequested => models::Approval::CHANGES_REQUESTED,
+    }
+}
+
+/// Aggregate status and individual decisions of a media file's reviewers
+pub fn approval_summary_to_proto3(approvals: &[models::Approval]) -> proto::ApprovalSummary
+{
+    use proto::approval_summary::Status;
+    let status = match models::Approval::aggregate(approvals) {
+        None => Status::NoReviews,
+        Some(models::Approval::APPROVED) => Status::Approved,
+        Some(models::Approval::CHANGES_REQUESTED) => Status::ChangesRequested,
+        Some(_) => Status::Pending,
+    };
+    proto::ApprovalSummary {
+        status: status.into(),
+        approvals: approvals.iter().map(|a| a.to_proto3()).collect(),
+    }
+}
+
+// ============================ SearchHit ============================
+
+impl From<SearchKind> for proto::search_hit::Kind {
+    fn from(k: SearchKind) -> Self {
+        match k {
+            SearchKind::MediaFile => proto::search_hit::Kind::MediaFile,
+            SearchKind::Comment => proto::search_hit::Kind::Comment,
+            SearchKind::Subtitle => proto::search_hit::Kind::Subtitle,
+        }
+    }
+}
+
+impl From<proto::search_hit::Kind> for SearchKind {
+    fn from(k: proto::search_hit::Kind) -> Self {
+        match k {
+            proto::search_hit::Kind::MediaFile => SearchKind::MediaFile,
+            proto::search_hit::Kind::Comment => SearchKind::Comment,
+            proto::search_hit::Kind::Subtitle => SearchKind::Subtitle,
+        }
+    }
+}
+
+impl SearchHit
+{
+    pub fn to_proto3(&self) -> proto::SearchHit
+    {
+        proto::SearchHit {
+            kind: proto::search_hit::Kind::from(self.kind).into(),
+            media_file_id: self.media_file_id.clone(),
+            comment_id: self.comment_id.map(|id| id.to_string()),
+            subtitle_id: self.subtitle_id.map(|id| id.to_string()),
+            start_time_sec: self.start_time_sec,
+            snippet: self.snippet.clone(),
+            score: self.score,
+        }
+    }
+}
@@ -42,40 +42,96 @@ pub async fn connect(uri: OrganizerURI) -> anyhow::Result<OrganizerConnection>
     Ok(OrganizerInboundClient::new(channel))
 }
 
-/// Parse Organizer plugin arguments and spawn it if necessary
-pub fn prepare_organizer(
-        org_uri: &Option<String>,
-        cmd: &Option<String>,
+/// What an Organizer plugin is used for.
+/// All plugins get session start calls, but only those with a role get the corresponding requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrganizerRoles {
+    pub navigation: bool,   // navigate_page
+    pub authz: bool,        // authz_user_action
+    pub commands: bool,     // cmd_from_client, move_to_folder, reorder_items
+    pub events: bool,       // event hooks
+}
+
+impl OrganizerRoles {
+    pub const ALL: OrganizerRoles = OrganizerRoles { navigation: true, authz: true, commands: true, events: true };
+
+    /// Parse a comma separated list, e.g. "navigation,authz". "all" = every role.
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let mut res = OrganizerRoles { navigation: false, authz: false, commands: false, events: false };
+        for r in s.split(',').map(|r| r.trim().to_lowercase()).filter(|r| !r.is_empty()) {
+            match r.as_str() {
+                "all" => res = Self::ALL,
+                "navigation" | "nav" => res.navigation = true,
+                "authz" => res.authz = true,
+                "commands" | "cmd" => res.commands = true,
+                "events" => res.events = true,
+                _ => bail!("Unknown organizer role '{}' (expected navigation, authz, commands, events or all)", r),
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl Default for OrganizerRoles {
+    fn default() -> Self { Self::ALL }
+}
+
+/// Organizer plugin endpoint and its declared responsibilities
+#[derive(Debug, Clone)]
+pub struct OrganizerPlugin {
+    pub uri: OrganizerURI,
+    pub roles: OrganizerRoles,
+}
+
+fn parse_organizer_uri(s: &str) -> anyhow::Result<OrganizerURI> {
+    Ok(match s.split_once("://") {
+        Some(("http", _)) | Some(("https", _)) => OrganizerURI::Http(s.to_string()),
+        Some(("unix", p)) | Some(("file", p)) => OrganizerURI::UnixSocket(p.into()),
+        None => OrganizerURI::UnixSocket(s.into()),
+        Some((pcol, _)) => bail!("Unsupported gRPC protocol: {}", pcol),
+    })
+}
+
+/// Parse Organizer plugin arguments and spawn them if necessary.
+///
+/// Plugin N is made of the Nth `org_uris`, `cmds` and `roles` items (any of which may be missing).
+/// Plugins with a command but no URI get a temp Unix socket in data dir.
+/// Missing roles default to all roles.
+pub fn prepare_organizers(
+        org_uris: &[String],
+        cmds: &[String],
+        roles: &[String],
         level: tracing::Level,
         json: bool,
         data_dir: &Path)
-    -> anyhow::Result<(Option<OrganizerURI>, Option<ProcHandle>)>
+    -> anyhow::Result<(Vec<OrganizerPlugin>, Vec<ProcHandle>)>
 {
     assert!(tracing::Level::TRACE > tracing::Level::DEBUG);
     let debug = level >= tracing::Level::DEBUG;
 
-    let mut org_uri = match org_uri {
-        None => None,
-        Some(s) => Some(match s.split_once("://") {
-            Some(("http", _)) | Some(("https", _)) => OrganizerURI::Http(s.clone()),
-            Some(("unix", p)) | Some(("file", p)) => OrganizerURI::UnixSocket(p.into()),
-            None => OrganizerURI::UnixSocket(s.into()),
-            Some((pcol, _)) => bail!("Unsupported gRPC protocol: {}", pcol),
-        }),
-    };
-    let org_hdl =
-        if let Some(cmd) = cmd {
-            // Use a temp sock if none was given
-            if org_uri.is_none() {
-                let unix_sock = data_dir
-                    .canonicalize().context("Expanding data dir")?
-                    .join("grpc-srv-to-org.sock");
-                org_uri = Some(OrganizerURI::UnixSocket(unix_sock));
-            };
-            Some(spawn_organizer(&cmd.as_str(), org_uri.clone().unwrap(), debug, json)?)
-        } else { None };
-
-    Ok((org_uri, org_hdl))
+    let n_plugins = org_uris.len().max(cmds.len());
+    if roles.len() > n_plugins {
+        bail!("Got {} organizer role lists but only {} organizer plugins", roles.len(), n_plugins);
+    }
+
+    let mut plugins = vec![];
+    let mut handles = vec![];
+    for i in 0..n_plugins {
+        let uri = match (org_uris.get(i), cmds.get(i)) {
+            (Some(u), _) => parse_organizer_uri(u)?,
+            (None, _) => {
+                // Use a temp sock if none was given
+                let sock_name = if i == 0 { "grpc-srv-to-org.sock".to_string() } else { format!("grpc-srv-to-org-{}.sock", i+1) };
+                OrganizerURI::UnixSocket(data_dir.canonicalize().context("Expanding data dir")?.join(sock_name))
+            },
+        };
+        if let Some(cmd) = cmds.get(i) {
+            handles.push(spawn_organizer(cmd.as_str(), uri.clone(), debug, json)?);
+        }
+        let roles = roles.get(i).map(|r| OrganizerRoles::parse(r)).transpose()?.unwrap_or_default();
+        plugins.push(OrganizerPlugin { uri, roles });
+    }
+    Ok((plugins, handles))
 }
 
 /// Spawn organizer gRPC server as a subprocess.
@@ -99,3 +155,36 @@ fn spawn_organizer(cmd: &str, uri: OrganizerURI, debug: bool, json: bool)
     if json { cmd += " --json"; }
     spawn_shell(&cmd, "organizer", info_span!("ORG"))
 }
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_organizer_roles_parse() {
+        assert_eq!(OrganizerRoles::parse("all").unwrap(), OrganizerRoles::ALL);
+        let r = OrganizerRoles::parse("nav, Authz").unwrap();
+        assert_eq!(r, OrganizerRoles { navigation: true, authz: true, commands: false, events: false });
+        let r = OrganizerRoles::parse("cmd,events").unwrap();
+        assert_eq!(r, OrganizerRoles { navigation: false, authz: false, commands: true, events: true });
+        assert!(OrganizerRoles::parse("navigation,bogus").is_err());
+    }
+
+    #[test]
+    fn test_prepare_organizers() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let uris = ["http://[::1]:50051".to_string(), "/tmp/b.sock".to_string()];
+        let (plugins, hdls) = prepare_organizers(&uris, &[], &["authz".into()], tracing::Level::INFO, false, data_dir.path()).unwrap();
+        assert!(hdls.is_empty());
+        assert_eq!(plugins.len(), 2);
+        assert!(matches!(&plugins[0].uri, OrganizerURI::Http(u) if u == "http://[::1]:50051"));
+        assert!(matches!(&plugins[1].uri, OrganizerURI::UnixSocket(p) if p == Path::new("/tmp/b.sock")));
+        assert_eq!(plugins[0].roles, OrganizerRoles::parse("authz").unwrap());
+        assert_eq!(plugins[1].roles, OrganizerRoles::ALL);
+
+        assert!(prepare_organizers(&uris[..1], &[], &["all".into(), "all".into()], tracing::Level::INFO, false, data_dir.path()).is_err());
+    }
+}
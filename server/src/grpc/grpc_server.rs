@@ -1,9 +1,9 @@
 use std::{path::Path, sync::atomic::Ordering::Relaxed};
 use anyhow::Context;
 use tonic::{Request, Response, Status};
-use crate::{api_server::{server_state::ServerState, ws_handers::del_media_file_and_cleanup, SendTo}, client_cmd, database::{DbBasicQuery, DbQueryByMediaFile, DbQueryByUser, DbUpdate}, grpc::grpc_impl_helpers::{paged_vec, rpc_expect_field}, optional_str_to_i32_or_tonic_error, str_to_i32_or_tonic_error};
-use crate::grpc::db_models::proto_msg_type_to_event_name;
-use crate::database::models;
+use crate::{api_server::{server_state::ServerState, ws_handers::{del_media_file_and_cleanup, reprocess_media_file}, SendTo}, client_cmd, database::{CommentFilter, DbBasicQuery, DbQueryByMediaFile, DbQueryByUser, DbUpdate}, grpc::grpc_impl_helpers::{paged_vec, rpc_expect_field}, optional_str_to_i32_or_tonic_error, str_to_i32_or_tonic_error};
+use crate::grpc::db_models::{comment_status_from_proto3, proto_msg_type_to_event_name};
+use crate::database::{models, error::DBError};
 
 use lib_clapshot_grpc::{proto::{self}, run_grpc_server, GrpcBindAddr, RpcResult};
 use lib_clapshot_grpc::proto::org;
@@ -20,8 +20,11 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
     async fn handshake(&self, req: tonic::Request<org::OrganizerInfo>) -> RpcResult<proto::Empty>
     {
         tracing::debug!("org->srv handshake received");
-        self.server.organizer_info.lock().await.replace(req.into_inner());
-        self.server.organizer_has_connected.store(true, Relaxed);
+        let mut infos = self.server.organizer_info.lock().await;
+        infos.push(req.into_inner());
+        if infos.len() >= self.server.organizers.len() {
+            self.server.organizer_has_connected.store(true, Relaxed);
+        }
         Ok(Response::new(proto::Empty {}))
     }
 
@@ -110,6 +113,70 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
         to_rpc_empty(del_media_file_and_cleanup(req.id.as_str(), None, &self.server).await)
     }
 
+    async fn cancel_media_file_processing(&self, req: Request<org::CancelMediaFileProcessingRequest>) -> RpcResult<proto::Empty>
+    {
+        let req = req.into_inner();
+        to_rpc_empty(reprocess_media_file(req.id.as_str(), None, None, &self.server).await)
+    }
+
+    async fn rerun_media_file_processing(&self, req: Request<org::RerunMediaFileProcessingRequest>) -> RpcResult<proto::Empty>
+    {
+        let req = req.into_inner();
+        if req.video_bitrate == Some(0) {
+            return Err(Status::invalid_argument("video_bitrate must be positive"));
+        }
+        to_rpc_empty(reprocess_media_file(req.id.as_str(), Some((req.step(), req.video_bitrate)), None, &self.server).await)
+    }
+
+    async fn create_share_link(&self, req: Request<org::CreateShareLinkRequest>) -> RpcResult<proto::ShareLink>
+    {
+        let req = req.into_inner();
+        if req.created_by.is_empty() {
+            return Err(Status::invalid_argument("created_by is required"));
+        }
+        let link = crate::api_server::share_links::create_share_link(&self.server, &req.media_file_id, req.permission(), req.valid_hours, &req.created_by)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Response::new(crate::api_server::share_links::link_to_proto(&self.server, &link)))
+    }
+
+    async fn revoke_share_link(&self, req: Request<org::RevokeShareLinkRequest>) -> RpcResult<proto::Empty>
+    {
+        let req = req.into_inner();
+        models::ShareLink::revoke(&mut self.server.db.conn()?, &req.id)?;
+        Ok(Response::new(proto::Empty {}))
+    }
+
+    async fn export_comments(&self, req: Request<org::ExportCommentsRequest>) -> RpcResult<org::ExportCommentsResponse>
+    {
+        let req = req.into_inner();
+        let exp = crate::api_server::comment_export::export_comments(&self.server.db, &req.media_file_id, req.format().into())?;
+        Ok(Response::new(org::ExportCommentsResponse { content: exp.content, mime_type: exp.mime_type, filename: exp.filename }))
+    }
+
+    async fn list_media_file_versions(&self, req: Request<org::ListMediaFileVersionsRequest>) -> RpcResult<org::MediaFileVersionList>
+    {
+        let req = req.into_inner();
+        models::MediaFile::get(&mut self.server.db.conn()?, &req.media_file_id)?;
+        let items = crate::api_server::media_versions::list_versions(&self.server.db, &req.media_file_id)?;
+        Ok(Response::new(org::MediaFileVersionList { items }))
+    }
+
+    async fn add_media_file_version(&self, req: Request<org::AddMediaFileVersionRequest>) -> RpcResult<org::MediaFileVersionList>
+    {
+        let req = req.into_inner();
+        models::MediaFileVersion::add_version(&mut self.server.db.conn()?, &req.media_file_id, &req.new_version_id)?;
+        let items = crate::api_server::media_versions::list_versions(&self.server.db, &req.new_version_id)?;
+        Ok(Response::new(org::MediaFileVersionList { items }))
+    }
+
+    async fn carry_over_comments(&self, req: Request<org::CarryOverCommentsRequest>) -> RpcResult<org::CarryOverCommentsResponse>
+    {
+        let req = req.into_inner();
+        let added = crate::api_server::media_versions::carry_over_comments(&self.server.db, &self.server.media_files_dir, &req.from_media_file_id, &req.to_media_file_id)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Response::new(org::CarryOverCommentsResponse { comments: added.iter().map(|c| c.to_proto3()).collect() }))
+    }
+
     // ========================================================================
     // Database functions
     // ========================================================================
@@ -131,7 +198,7 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
         };
 
         let mut proto_items = Vec::with_capacity(items.len());
-        for mf in items { proto_items.push(mf.to_proto3(&self.server.url_base, mf.get_subtitles(conn)?)); }
+        for mf in items { proto_items.push(mf.to_proto3(&self.server.url_base, mf.get_subtitles(conn)?, mf.get_approvals(conn)?, Some(&self.server.media_url_token(&mf.id)))); }
 
         Ok(Response::new(org::DbMediaFileList {
             items: proto_items,
@@ -148,15 +215,21 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
         let pg = req.paging.as_ref().try_into()?;
         let conn = &mut db.conn()?;
 
-        let items = match rpc_expect_field(&req.filter, "filter")? {
-            Filter::All(_) => { models::Comment::get_all(conn, pg)? },
+        let mut filter = CommentFilter {
+            status: req.status.map(|_| comment_status_from_proto3(req.status()).to_string()),
+            assignee_id: req.assignee_id.clone(),
+            ..Default::default()
+        };
+        match rpc_expect_field(&req.filter, "filter")? {
+            Filter::All(_) => {},
             Filter::Ids(ids) => {
-                let ids = ids.ids.iter().map(|comment_id| str_to_i32_or_tonic_error!(comment_id)).collect::<Result<Vec<_>, _>>()?;
-                paged_vec(models::Comment::get_many(conn, &ids)?, pg)
+                filter.ids = Some(ids.ids.iter().map(|comment_id| comment_id.parse::<i32>()).collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| Status::invalid_argument(format!("Could not parse comment ID as int: {}", e)))?);
             },
-            Filter::UserId(user_id) => { models::Comment::get_by_user(conn, user_id, pg)? },
-            Filter::MediaFileId(media_file_id) => { models::Comment::get_by_media_file(conn, media_file_id, pg)? },
+            Filter::UserId(user_id) => { filter.user_id = Some(user_id.clone()); },
+            Filter::MediaFileId(media_file_id) => { filter.media_file_id = Some(media_file_id.clone()); },
         };
+        let items = models::Comment::get_filtered(conn, &filter, pg)?;
         Ok(Response::new(org::DbCommentList {
             items: items.into_iter().map(|c| c.to_proto3()).collect(),
             paging: req.paging,
@@ -188,6 +261,23 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
     }
 
 
+    async fn db_search(&self, req: Request<org::DbSearchRequest>) -> RpcResult<org::DbSearchResults>
+    {
+        use crate::database::search::{self, SearchKind};
+        let req = req.into_inner();
+        let pg = req.paging.as_ref().try_into()?;
+        let kinds = req.kinds().map(SearchKind::from).collect::<Vec<_>>();
+        let media_file_ids = (!req.media_file_ids.is_empty()).then_some(req.media_file_ids.as_slice());
+
+        let hits = search::search(&mut self.server.db.conn()?, &req.query, media_file_ids,
+            (!kinds.is_empty()).then_some(kinds.as_slice()), pg)?;
+        Ok(Response::new(org::DbSearchResults {
+            items: hits.iter().map(|h| h.to_proto3()).collect(),
+            paging: req.paging,
+        }))
+    }
+
+
     async fn db_upsert(&self, req: Request<org::DbUpsertRequest>) -> RpcResult<org::DbUpsertResponse>
     {
         let req = req.into_inner();
@@ -221,8 +311,8 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
                         }
                     }).collect::<Vec<_>>();
 
-                    // Convert back to proto3
-                    res_comb_orig_order.iter().map(|it| $to_proto(it)).collect::<Result<Vec<_>, tonic::Status>>()
+                    // Convert back to proto3 (DB errors are mapped to a Status by the caller's `?`)
+                    res_comb_orig_order.iter().map(|it| $to_proto(it)).collect::<Result<Vec<_>, DBError>>()
                 }
             }
         }
@@ -231,11 +321,18 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
             media_files: upsert_type!([
                 conn, req.media_files, models::MediaFile, models::MediaFileInsert,
                 |it: &proto::MediaFile| it.id.is_empty(),
-                |it: &models::MediaFile| Ok(it.to_proto3(self.server.url_base.as_str(), it.get_subtitles(conn)?))])?,
+                |it: &models::MediaFile| Ok(it.to_proto3(self.server.url_base.as_str(), it.get_subtitles(conn)?, it.get_approvals(conn)?, Some(&self.server.media_url_token(&it.id))))])?,
             comments: upsert_type!([
                 conn, req.comments, models::Comment, models::CommentInsert,
                 |it: &proto::Comment| it.id.is_empty(),
-                |it: &models::Comment| Ok(it.to_proto3())])?,
+                |it: &models::Comment| {
+                    // Frame number is derived from the timecode, not taken from the Organizer
+                    let mut it = it.clone();
+                    if let Ok(mf) = models::MediaFile::get(conn, &it.media_file_id) {
+                        it.frame = models::Comment::update_frame(conn, &it, &mf)?;
+                    }
+                    Ok(it.to_proto3())
+                }])?,
             user_messages: upsert_type!([
                 conn, req.user_messages, models::Message, models::MessageInsert,
                 |it: &proto::UserMessage| it.id.is_none(),
@@ -243,7 +340,10 @@ impl org::organizer_outbound_server::OrganizerOutbound for OrganizerOutboundImpl
             subtitles: upsert_type!([
                 conn, req.subtitles, models::Subtitle, models::SubtitleInsert,
                 |it: &proto::Subtitle| it.id.is_empty(),
-                |it: &models::Subtitle| Ok(it.to_proto3(self.server.url_base.as_str()))])?,
+                |it: &models::Subtitle| {
+                    crate::database::search::index_subtitle_file(conn, &self.server.media_files_dir, it);
+                    Ok(it.to_proto3(self.server.url_base.as_str(), Some(&self.server.media_url_token(&it.media_file_id))))
+                }])?,
         }))
     }
 
@@ -3,6 +3,7 @@ pub mod caller;
 pub mod grpc_server;
 pub mod grpc_impl_helpers;
 pub mod db_models;
+pub mod org_events;
 
 use std::collections::HashMap;
 use lib_clapshot_grpc::proto;
@@ -131,6 +132,17 @@ if (confirm(msg)) {
 
 
 
+/// Folder item color for an aggregate approval status (None = default color)
+fn approval_color(status: proto::approval_summary::Status) -> Option<proto::Color> {
+    use proto::approval_summary::Status;
+    match status {
+        Status::NoReviews => None,
+        Status::Pending => Some(proto::Color { r: 128, g: 128, b: 64 }),
+        Status::Approved => Some(proto::Color { r: 48, g: 128, b: 48 }),
+        Status::ChangesRequested => Some(proto::Color { r: 160, g: 64, b: 48 }),
+    }
+}
+
 /// Convert a list of database MediaFiles to a protobuf3 PageItem (FolderListing)
 pub (crate) fn folder_listing_for_media_files(media_files: &[proto::MediaFile]) -> proto::PageItem {
     let media_files: Vec<proto::page_item::folder_listing::Item> = media_files.iter().map(|v| {
@@ -141,10 +153,10 @@ pub (crate) fn folder_listing_for_media_files(media_files: &[proto::MediaFile])
                     code: format!("clapshot.openMediaFile(\"{}\")", v.id).into()
                 }),
                 popup_actions: vec!["popup_builtin_rename".into(), "popup_builtin_trash".into()],
-                vis: if v.preview_data.as_ref().and_then(|pv| pv.thumb_url.as_ref()).is_some() { None } else {
+                vis: {
                     // If no thumbnail, show an icon based on media type instead
-                    Some(proto::page_item::folder_listing::item::Visualization {
-                        icon: Some(proto::Icon {
+                    let icon = if v.preview_data.as_ref().and_then(|pv| pv.thumb_url.as_ref()).is_some() { None } else {
+                        Some(proto::Icon {
                             src: Some(proto::icon::Src::FaClass(proto::icon::FaClass {
                                 classes: match v.media_type.as_str() {
                                     "audio" => "fas fa-volume-high",
@@ -153,9 +165,12 @@ pub (crate) fn folder_listing_for_media_files(media_files: &[proto::MediaFile])
                                     _ => "fa fa-circle-question",
                                 }.into(), color: None, })),
                             ..Default::default()
-                        }),
-                        ..Default::default()
-                    })
+                        })
+                    };
+                    let base_color = v.approval.as_ref().and_then(|a| approval_color(a.status()));
+                    if icon.is_none() && base_color.is_none() { None } else {
+                        Some(proto::page_item::folder_listing::item::Visualization { icon, base_color })
+                    }
                 },
             }
         }).collect();
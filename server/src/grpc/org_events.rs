@@ -0,0 +1,227 @@
+// Event hooks for Organizer plugins (and outgoing webhooks).
+// Server modules (media pipeline, WebSocket handlers) send `OrgEvent`s to a channel, and
+// `run_org_event_relay()` delivers them to all Organizers with the `events` role, in configuration order.
+// It also queues them for webhook endpoints, if any are configured (see `api_server::webhooks`).
+// Events are notifications only: delivery failures are logged, not reported back to the emitter.
+
+use std::{collections::{HashMap, VecDeque}, sync::atomic::Ordering::Relaxed, time::Duration};
+use anyhow::Context;
+use crossbeam_channel::{Receiver, Sender};
+use lib_clapshot_grpc::proto::{self, org};
+
+use crate::api_server::{server_state::ServerState, webhooks};
+use crate::database::{models, DbBasicQuery, DbQueryByMediaFile, DBPaging, DB};
+use crate::grpc::grpc_client::{connect, OrganizerConnection};
+
+
+/// Something happened that Organizers may want to know about.
+/// Objects are referred to by ID, and looked up from DB at delivery time.
+#[derive(Debug, Clone)]
+pub enum OrgEvent {
+    MediaFileAdded { media_file_id: String, upload_cookies: HashMap<String, String> },
+    MediaFileProcessed { media_file_id: String, step: proto::MediaProcessingStep, success: bool, details: Option<String> },
+    CommentAdded { comment_id: i32, ses: Option<org::UserSessionData> },
+    CommentEdited { comment_id: i32, ses: Option<org::UserSessionData> },
+    CommentDeleted { comment: proto::Comment, ses: Option<org::UserSessionData> },     // Already gone from DB, so passed as is
+    SubtitleAdded { subtitle_id: i32, ses: Option<org::UserSessionData> },
+    SubtitleEdited { subtitle_id: i32, ses: Option<org::UserSessionData> },
+    SubtitleDeleted { subtitle: proto::Subtitle, ses: Option<org::UserSessionData> },
+}
+
+/// Event resolved into a gRPC request
+#[derive(Debug, Clone)]
+pub enum OrgEventRequest {
+    MediaFileAdded(org::OnMediaFileAddedRequest),
+    MediaFileProcessed(org::OnMediaFileProcessedRequest),
+    CommentAdded(org::OnCommentAddedRequest),
+    CommentEdited(org::OnCommentEditedRequest),
+    SubtitleAdded(org::OnSubtitleAddedRequest),
+}
+
+/// Send event to the relay, if event hooks are enabled (`tx` is Some).
+/// Best effort: a closed channel is only logged.
+pub fn emit(tx: &Option<Sender<OrgEvent>>, ev: OrgEvent) {
+    if let Some(tx) = tx {
+        if let Err(e) = tx.send(ev) {
+            tracing::warn!(details=%e, "Organizer event relay is gone. Event dropped.");
+        }
+    }
+}
+
+fn media_file_proto(db: &DB, url_base: &str, id: &str) -> anyhow::Result<proto::MediaFile> {
+    let conn = &mut db.conn()?;
+    let mf = models::MediaFile::get(conn, &id.to_string()).context("Media file not found")?;
+    let subs = models::Subtitle::get_by_media_file(conn, id, DBPaging::default())?;
+    let approvals = mf.get_approvals(conn)?;
+    Ok(mf.to_proto3(url_base, subs, approvals, None))
+}
+
+impl OrgEvent {
+
+    /// Does the Organizer API have a hook for this event? (Others are only sent to webhooks.)
+    pub fn has_org_hook(&self) -> bool {
+        !matches!(self, OrgEvent::CommentDeleted { .. } | OrgEvent::SubtitleEdited { .. } | OrgEvent::SubtitleDeleted { .. })
+    }
+
+    /// Look up the objects the event refers to, and build the gRPC request.
+    /// Fails for events without an Organizer hook.
+    ///
+    /// # Arguments
+    /// * `db` - Database
+    /// * `url_base` - Server URL base, for media file URLs
+    pub fn to_request(&self, db: &DB, url_base: &str) -> anyhow::Result<OrgEventRequest>
+    {
+        Ok(match self {
+            OrgEvent::MediaFileAdded { media_file_id, upload_cookies } =>
+                OrgEventRequest::MediaFileAdded(org::OnMediaFileAddedRequest {
+                    media_file: Some(media_file_proto(db, url_base, media_file_id)?),
+                    upload_cookies: upload_cookies.clone(),
+                }),
+            OrgEvent::MediaFileProcessed { media_file_id, step, success, details } =>
+                OrgEventRequest::MediaFileProcessed(org::OnMediaFileProcessedRequest {
+                    media_file: Some(media_file_proto(db, url_base, media_file_id)?),
+                    step: (*step).into(),
+                    success: *success,
+                    details: details.clone(),
+                }),
+            OrgEvent::CommentAdded { comment_id, ses } =>
+                OrgEventRequest::CommentAdded(org::OnCommentAddedRequest {
+                    comment: Some(models::Comment::get(&mut db.conn()?, comment_id).context("Comment not found")?.to_proto3()),
+                    ses: ses.clone(),
+                }),
+            OrgEvent::CommentEdited { comment_id, ses } =>
+                OrgEventRequest::CommentEdited(org::OnCommentEditedRequest {
+                    comment: Some(models::Comment::get(&mut db.conn()?, comment_id).context("Comment not found")?.to_proto3()),
+                    ses: ses.clone(),
+                }),
+            OrgEvent::SubtitleAdded { subtitle_id, ses } =>
+                OrgEventRequest::SubtitleAdded(org::OnSubtitleAddedRequest {
+                    subtitle: Some(models::Subtitle::get(&mut db.conn()?, subtitle_id).context("Subtitle not found")?.to_proto3(url_base, None)),
+                    ses: ses.clone(),
+                }),
+            OrgEvent::CommentDeleted { .. } | OrgEvent::SubtitleEdited { .. } | OrgEvent::SubtitleDeleted { .. } =>
+                anyhow::bail!("No Organizer hook for {:?}", self),
+        })
+    }
+}
+
+async fn send_request(org: &mut OrganizerConnection, req: OrgEventRequest) -> Result<(), tonic::Status> {
+    match req {
+        OrgEventRequest::MediaFileAdded(r) => org.on_media_file_added(r).await.map(|_| ()),
+        OrgEventRequest::MediaFileProcessed(r) => org.on_media_file_processed(r).await.map(|_| ()),
+        OrgEventRequest::CommentAdded(r) => org.on_comment_added(r).await.map(|_| ()),
+        OrgEventRequest::CommentEdited(r) => org.on_comment_edited(r).await.map(|_| ()),
+        OrgEventRequest::SubtitleAdded(r) => org.on_subtitle_added(r).await.map(|_| ()),
+    }
+}
+
+/// Deliver events from `event_rx` to Organizers with the `events` role until terminate flag is set.
+/// Events are queued for webhooks right away, but buffered for Organizers until all of them have handshaked back.
+pub async fn run_org_event_relay(server: ServerState, event_rx: Receiver<OrgEvent>)
+{
+    let plugins: Vec<_> = server.organizers.iter().filter(|p| p.roles.events).cloned().collect();
+    let mut conns: Vec<Option<OrganizerConnection>> = plugins.iter().map(|_| None).collect();
+    let mut pending: VecDeque<OrgEvent> = VecDeque::new();
+
+    while !server.terminate_flag.load(Relaxed) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        while let Ok(ev) = event_rx.try_recv() {
+            if !server.webhooks.is_empty() {
+                if let Err(e) = webhooks::enqueue(&server.db, &server.webhooks, &ev, &server.url_base) {
+                    tracing::warn!(event=?ev, details=%e, "Failed to queue webhook deliveries. Skipping.");
+                }
+            }
+            if !plugins.is_empty() && ev.has_org_hook() {
+                pending.push_back(ev);
+            }
+        }
+        if !server.organizer_has_connected.load(Relaxed) { continue; }
+
+        while let Some(ev) = pending.pop_front() {
+            let req = match ev.to_request(&server.db, &server.url_base) {
+                Ok(req) => req,
+                Err(e) => {
+                    tracing::warn!(event=?ev, details=%e, "Failed to prepare Organizer event. Skipping.");
+                    continue;
+                }
+            };
+            for (plugin, conn) in plugins.iter().zip(conns.iter_mut()) {
+                if conn.is_none() {
+                    match connect(plugin.uri.clone()).await {
+                        Ok(c) => { *conn = Some(c); },
+                        Err(e) => {
+                            tracing::error!(org=?plugin.uri, details=%e, "Failed to connect to Organizer. Event dropped.");
+                            continue;
+                        }
+                    }
+                }
+                if let Some(c) = conn.as_mut() {
+                    if let Err(e) = send_request(c, req.clone()).await {
+                        match e.code() {
+                            tonic::Code::Unimplemented => tracing::debug!(org=?plugin.uri, "Organizer doesn't implement event hook {:?}. Ignoring.", ev),
+                            tonic::Code::Aborted => tracing::debug!(org=?plugin.uri, "Organizer ABORTED event hook. Ignoring."),
+                            _ => {
+                                tracing::error!(org=?plugin.uri, err=?e, "Error delivering event to Organizer. Will reconnect.");
+                                *conn = None;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::make_test_db;
+
+    #[test]
+    fn test_event_to_request() {
+        let (db, _data_dir, media_files, comments) = make_test_db();
+
+        let ev = OrgEvent::MediaFileProcessed {
+            media_file_id: media_files[0].id.clone(),
+            step: proto::MediaProcessingStep::Thumbnails,
+            success: false,
+            details: Some("boom".into()) };
+        match ev.to_request(&db, "http://localhost").unwrap() {
+            OrgEventRequest::MediaFileProcessed(r) => {
+                assert_eq!(r.media_file.unwrap().id, media_files[0].id);
+                assert_eq!(r.step, proto::MediaProcessingStep::Thumbnails as i32);
+                assert!(!r.success);
+                assert_eq!(r.details.as_deref(), Some("boom"));
+            },
+            r => panic!("Unexpected request {:?}", r),
+        }
+
+        let ev = OrgEvent::CommentEdited { comment_id: comments[2].id, ses: None };
+        match ev.to_request(&db, "http://localhost").unwrap() {
+            OrgEventRequest::CommentEdited(r) => assert_eq!(r.comment.unwrap().id, comments[2].id.to_string()),
+            r => panic!("Unexpected request {:?}", r),
+        }
+
+        // Deleted objects can't be delivered
+        assert!(OrgEvent::CommentAdded { comment_id: 999999, ses: None }.to_request(&db, "").is_err());
+        assert!(OrgEvent::MediaFileAdded { media_file_id: "nonexistent".into(), upload_cookies: HashMap::new() }.to_request(&db, "").is_err());
+
+        // Webhook-only events
+        let ev = OrgEvent::CommentDeleted { comment: comments[0].to_proto3(), ses: None };
+        assert!(!ev.has_org_hook());
+        assert!(ev.to_request(&db, "").is_err());
+        assert!(OrgEvent::CommentAdded { comment_id: comments[0].id, ses: None }.has_org_hook());
+    }
+
+    #[test]
+    fn test_emit() {
+        emit(&None, OrgEvent::CommentAdded { comment_id: 1, ses: None });   // no-op
+        let (tx, rx) = crossbeam_channel::unbounded();
+        emit(&Some(tx), OrgEvent::CommentAdded { comment_id: 1, ses: None });
+        assert!(matches!(rx.try_recv(), Ok(OrgEvent::CommentAdded { comment_id: 1, .. })));
+    }
+}
@@ -3,7 +3,7 @@ use std::{collections::HashMap, path::PathBuf, sync::{atomic::{AtomicBool, Order
 use anyhow::Context;
 use database::{db_backup::{backup_sqlite_database, restore_sqlite_database}, migration_solver::MigrationGraphModule, sqlite_foreign_key_check, DB};
 use lib_clapshot_grpc::{proto::org::{self, Migration}, GrpcBindAddr};
-use crate::{api_server::server_state::ServerState, grpc::{caller::OrganizerCaller, grpc_client::OrganizerURI}};
+use crate::{api_server::server_state::ServerState, grpc::{caller::OrganizerCaller, grpc_client::{OrganizerPlugin, OrganizerURI}, org_events::OrgEvent}};
 
 use anyhow::bail;
 
@@ -12,6 +12,7 @@ pub mod api_server;
 pub mod database;
 pub mod tests;
 pub mod grpc;
+pub mod timecode;
 
 pub const PKG_VERSION: &'static str = env!("CARGO_PKG_VERSION");
 pub const PKG_NAME: &'static str = env!("CARGO_PKG_NAME");
@@ -35,10 +36,19 @@ impl ClapshotInit {
         cors_origins: Vec<String>,
         bind_api: String,
         port: u16,
-        organizer_uri: Option<OrganizerURI>,
+        organizers: Vec<OrganizerPlugin>,
         grpc_server_bind: GrpcBindAddr,
         n_workers: usize,
+        max_jobs_per_user: usize,
         target_bitrate: u32,
+        transcode_profiles: video_pipeline::transcode_profiles::TranscodeProfiles,
+        adaptive_streaming: video_pipeline::AdaptiveStreaming,
+        upload_expiry: chrono::Duration,
+        upload_limits: video_pipeline::upload_limits::UploadLimits,
+        auth: api_server::auth::AuthConfig,
+        file_offload: api_server::media_serving::FileOffload,
+        webhooks: api_server::webhooks::Webhooks,
+        email: Option<api_server::email_notifier::EmailConfig>,
         poll_interval: f32,
         default_user: String,
         resubmit_delay: f32,
@@ -66,50 +76,76 @@ impl ClapshotInit {
         let db_was_missing = !db_file.exists();
 
         if migrate || db_was_missing {
-            migrate_db(&db_file, &organizer_uri)?;
+            migrate_db(&db_file, &organizers)?;
         }
 
         let grpc_srv_listening_flag = Arc::new(AtomicBool::new(false));
         let db: Arc<DB> = Arc::new(database::DB::open_db_file(&db_file).unwrap());
 
+        // Subtitle cues are indexed for search by the server, not by DB triggers
+        match database::search::index_missing_subtitles(&db, &data_dir.join("videos")) {
+            Ok(0) => {},
+            Ok(n) => tracing::info!(count=n, "Indexed subtitles missing from search index."),
+            Err(e) => tracing::error!(details=%e, "Failed to index subtitles for search."),
+        }
+
+        // Frame numbers for comments that only have a timecode string (e.g. added by an older version)
+        match db.conn().and_then(|mut conn| database::models::Comment::fill_missing_frames(&mut conn)) {
+            Ok(0) => {},
+            Ok(n) => tracing::info!(count=n, "Filled in comment frame numbers."),
+            Err(e) => tracing::error!(details=%e, "Failed to fill in comment frame numbers."),
+        }
+
         // Run API server
         let (user_msg_tx, user_msg_rx) = unbounded::<api_server::UserMessage>();
         let (upload_tx, upload_rx) = unbounded::<video_pipeline::IncomingFile>();
+        let (pipeline_tx, pipeline_rx) = unbounded::<video_pipeline::PipelineCmd>();
+        let (org_event_tx, org_event_rx) = match organizers.iter().any(|o| o.roles.events) || !webhooks.is_empty() {
+            true => { let (tx, rx) = unbounded::<OrgEvent>(); (Some(tx), Some(rx)) },
+            false => (None, None),
+        };
         let api_thread = Some({
             let server = ServerState::new( db.clone(),
                 &data_dir.join("videos"),
                 &data_dir.join("upload"),
+                upload_expiry,
+                upload_limits.clone(),
+                auth,
+                file_offload,
                 &url_base,
-                organizer_uri.clone(),
+                organizers.clone(),
+                org_event_tx.clone(),
+                webhooks,
+                email,
                 grpc_srv_listening_flag.clone(),
                 default_user,
-                terminate_flag.clone());
-            let grpc_srv = if (&organizer_uri).is_some() { Some(grpc_server_bind.clone()) } else { None };
+                terminate_flag.clone(),
+                pipeline_tx);
+            let grpc_srv = if !organizers.is_empty() { Some(grpc_server_bind.clone()) } else { None };
             let ub = url_base.clone();
-            thread::spawn(move || { api_server::run_forever(user_msg_rx, grpc_srv, upload_tx, bind_api.to_string(), ub, cors_origins, server, port) })
+            thread::spawn(move || { api_server::run_forever(user_msg_rx, org_event_rx, grpc_srv, upload_tx, bind_api.to_string(), ub, cors_origins, server, port) })
         });
 
-        // Handshake Organizer if configured
-        match &organizer_uri {
-            Some(ouri) => {
-                // Wait for our gRPC server thread to bind before handshaking with organizer
-                let start_time = std::time::Instant::now();
-                while !grpc_srv_listening_flag.load(Ordering::Relaxed) {
-                    thread::sleep(std::time::Duration::from_millis(10));
-                    if start_time.elapsed().as_secs() > 3 {
-                        anyhow::bail!("gRPC server failed to start within 3 seconds.");
-                    }
+        // Handshake Organizers if configured
+        if organizers.is_empty() {
+            tracing::debug!("No Organizer URI provided, skipping gRPC.");
+        } else {
+            // Wait for our gRPC server thread to bind before handshaking with organizers
+            let start_time = std::time::Instant::now();
+            while !grpc_srv_listening_flag.load(Ordering::Relaxed) {
+                thread::sleep(std::time::Duration::from_millis(10));
+                if start_time.elapsed().as_secs() > 3 {
+                    anyhow::bail!("gRPC server failed to start within 3 seconds.");
                 }
-                // Ok, organizer should be able to connect back to us now, so handshake
-                let org = OrganizerCaller::new(&ouri);
-                tracing::info!("Connecting gRPC srv->org...");
-                org.blocking_handshake_organizer(&data_dir, &url_base, &db_file, &grpc_server_bind)?;
-                tracing::debug!("srv->org handshake done.");
             }
-            None => {
-                tracing::debug!("No Organizer URI provided, skipping gRPC.");
+            // Ok, organizers should be able to connect back to us now, so handshake
+            for (i, plugin) in organizers.iter().enumerate() {
+                let org = OrganizerCaller::new(&plugin.uri);
+                tracing::info!(plugin=i+1, "Connecting gRPC srv->org...");
+                org.blocking_handshake_organizer(&data_dir, &url_base, &db_file, &grpc_server_bind)?;
+                tracing::debug!(plugin=i+1, "srv->org handshake done.");
             }
-        };
+        }
 
         // Run media file processing pipeline
         let tf = Arc::clone(&terminate_flag);
@@ -117,7 +153,7 @@ impl ClapshotInit {
         let vpp_thread = Some({
             let db = db.clone();
             thread::spawn(move || { video_pipeline::run_forever(
-                db, tf.clone(), dd, user_msg_tx, poll_interval, resubmit_delay, target_bitrate, upload_rx, n_workers)})
+                db, tf.clone(), dd, user_msg_tx, org_event_tx, poll_interval, resubmit_delay, target_bitrate, transcode_profiles, adaptive_streaming, upload_rx, pipeline_rx, upload_limits, n_workers, max_jobs_per_user)})
         });
 
 
@@ -145,9 +181,9 @@ impl ClapshotInit {
 
 
 
-/// Find migrations from server and organizer, solve their dependencies, and apply them.
+/// Find migrations from server and organizers, solve their dependencies, and apply them.
 /// Backup before starting, and restore if foreign key checks fail after applying the migrations.
-fn migrate_db( db_file: &PathBuf, org_uri: &Option<OrganizerURI>) -> anyhow::Result<()>
+fn migrate_db( db_file: &PathBuf, organizers: &[OrganizerPlugin]) -> anyhow::Result<()>
 {
     use lib_clapshot_grpc::proto::org::CheckMigrationsRequest;
     let _span = tracing::info_span!("migrate_db").entered();
@@ -195,18 +231,23 @@ fn migrate_db( db_file: &PathBuf, org_uri: &Option<OrganizerURI>) -> anyhow::Res
             anyhow::anyhow!("Sqlite path is not valid UTF-8"))?.into()
     });
 
-    // Add Organizer and its migrations, if available
-    if let Some(uri) = org_uri {
-        let caller = OrganizerCaller::new(uri);
+    // Add Organizers and their migrations, if available
+    let mut module_to_org: HashMap<String, OrganizerURI> = HashMap::new();
+    for plugin in organizers {
+        let caller = OrganizerCaller::new(&plugin.uri);
         let (rt, mut org_conn) = caller.tokio_connect().context("Error connecting to Organizer")?;
-        tracing::debug!("Calling check_migrations on Organizer.");
+        tracing::debug!(org=?plugin.uri, "Calling check_migrations on Organizer.");
 
         match rt.block_on(org_conn.check_migrations(CheckMigrationsRequest { db: org_db_info.clone() })) {
             Ok(cm_res) => {
                 let migrations = cm_res.get_ref().pending_migrations.clone();
-                tracing::debug!("Organizer has {} pending migrations.", migrations.len());
+                let name = cm_res.get_ref().name.clone();
+                tracing::debug!("Organizer '{}' has {} pending migrations.", name, migrations.len());
+                if name == SERVER_MODULE_NAME || module_to_org.insert(name.clone(), plugin.uri.clone()).is_some() {
+                    bail!("Duplicate migration module name '{}' from Organizer {:?}", name, plugin.uri);
+                }
                 migration_modules.push(MigrationGraphModule {
-                    name: cm_res.get_ref().name.clone(),
+                    name,
                     cur_version: Some(cm_res.get_ref().current_schema_ver.clone()),
                     migrations,
                 });
@@ -254,7 +295,7 @@ fn migrate_db( db_file: &PathBuf, org_uri: &Option<OrganizerURI>) -> anyhow::Res
             let db_backup_file = backup_sqlite_database(db_file.into())?;
 
             let db: Arc<DB> = Arc::new(database::DB::open_db_file(&db_file).context("Error opening DB file")?);
-            match apply_migrations(&migration_modules, &order, &db, db_file, org_uri,org_db_info.clone())
+            match apply_migrations(&migration_modules, &order, &db, db_file, &module_to_org, org_db_info.clone())
                 .and_then(|_| { db.conn().context("Error opening DB connection after migrations") })
                 .and_then(|mut conn| { sqlite_foreign_key_check(&mut conn, true).context("Foreign key checks failed after migrations") })
             {
@@ -292,7 +333,7 @@ fn apply_migrations(
     plan: &Vec<Migration>,
     db: &Arc<DB>,
     db_file: &PathBuf,
-    org_uri: &Option<OrganizerURI>,
+    module_to_org: &HashMap<String, OrganizerURI>,
     org_db_info: Option<org::Database>
 ) -> Result<(), anyhow::Error>
 {
@@ -317,7 +358,7 @@ fn apply_migrations(
                 let _span = tracing::info_span!("apply org migration", name=mig.uuid, new_ver=mig.version, org=module_name).entered();
                 tracing::info!("Applying on Organizer...");
 
-                if let Some(uri) = org_uri {
+                if let Some(uri) = module_to_org.get(module_name) {
                     let (rt, mut org_conn) = OrganizerCaller::new(uri).tokio_connect()
                         .context("Error connecting to organizer for migrations")?;
                     rt.block_on(org_conn.apply_migration(ApplyMigrationRequest {
@@ -345,10 +386,19 @@ pub fn run_clapshot(
     cors_origins: Vec<String>,
     bind_api: String,
     port: u16,
-    organizer_uri: Option<OrganizerURI>,
+    organizers: Vec<OrganizerPlugin>,
     grpc_server_bind: GrpcBindAddr,
     n_workers: usize,
+    max_jobs_per_user: usize,
     target_bitrate: u32,
+    transcode_profiles: video_pipeline::transcode_profiles::TranscodeProfiles,
+    adaptive_streaming: video_pipeline::AdaptiveStreaming,
+    upload_expiry: chrono::Duration,
+    upload_limits: video_pipeline::upload_limits::UploadLimits,
+    auth: api_server::auth::AuthConfig,
+    file_offload: api_server::media_serving::FileOffload,
+    webhooks: api_server::webhooks::Webhooks,
+    email: Option<api_server::email_notifier::EmailConfig>,
     default_user: String,
     poll_interval: f32,
     resubmit_delay: f32
@@ -364,10 +414,19 @@ pub fn run_clapshot(
         cors_origins,
         bind_api,
         port,
-        organizer_uri,
+        organizers,
         grpc_server_bind,
         n_workers,
+        max_jobs_per_user,
         target_bitrate,
+        transcode_profiles,
+        adaptive_streaming,
+        upload_expiry,
+        upload_limits,
+        auth,
+        file_offload,
+        webhooks,
+        email,
         poll_interval,
         default_user,
         resubmit_delay,
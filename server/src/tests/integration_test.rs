@@ -25,7 +25,7 @@ mod integration_test
     use crate::api_server::tests::expect_user_msg;
     use crate::database::schema::media_files::{thumb_sheet_cols, thumb_sheet_rows};
     use crate::{expect_client_cmd, send_server_cmd};
-    use crate::grpc::grpc_client::prepare_organizer;
+    use crate::grpc::grpc_client::prepare_organizers;
     use crate::video_pipeline::{metadata_reader, IncomingFile};
     use crate::api_server::test_utils::{connect_client_ws, open_media_file, write};
     use lib_clapshot_grpc::{GrpcBindAddr, proto};
@@ -63,6 +63,7 @@ mod integration_test
             file_path: PathBuf::from_str(data_dir.join("NASA_Red_Lettuce_excerpt.mov").to_str().unwrap())?,
             user_id: "nobody".to_string(),
             cookies: HashMap::new(),
+            priority: Default::default(),
         };
         arg_sender.send(args.clone())?;
 
@@ -116,7 +117,8 @@ mod integration_test
                 let target_bitrate = $bitrate;
 
                 let grpc_server_bind = crate::grpc::grpc_server::make_grpc_server_bind(&None, &$data_dir)?;
-                let (org_uri, _org_hdl) = prepare_organizer(&None, &$org_cmd, tracing::Level::DEBUG, false, &$data_dir.path())?;
+                let org_cmds: Vec<String> = $org_cmd.into_iter().collect();
+                let (organizers, _org_hdls) = prepare_organizers(&[], &org_cmds, &[], tracing::Level::DEBUG, false, &$data_dir.path())?;
 
                 let terminate_flag = Arc::new(AtomicBool::new(false));
 
@@ -124,10 +126,10 @@ mod integration_test
                     let poll_interval = 0.1;
                     let data_dir = $data_dir.path().to_path_buf();
                     let url_base = url_base.clone();
-                    let org_uri = org_uri.clone();
+                    let organizers = organizers.clone();
                     let tf = terminate_flag.clone();
                     thread::spawn(move || {
-                        let mut clapshot = crate::ClapshotInit::init_and_spawn_workers(data_dir, true, url_base, vec![], "127.0.0.1".into(), port, org_uri.clone(), grpc_server_bind, 4, target_bitrate, poll_interval, "anonymous".to_string(), poll_interval*5.0, tf)?;
+                        let mut clapshot = crate::ClapshotInit::init_and_spawn_workers(data_dir, true, url_base, vec![], "127.0.0.1".into(), port, organizers, grpc_server_bind, 4, 0, target_bitrate, Default::default(), crate::video_pipeline::AdaptiveStreaming::Off, chrono::Duration::hours(24), Default::default(), Default::default(), Default::default(), Default::default(), None, poll_interval, "anonymous".to_string(), poll_interval*5.0, tf)?;
                         clapshot.wait_for_termination()
                 })};
 
@@ -137,8 +139,8 @@ mod integration_test
                     // Connect client
                     let cur_process_user = whoami::username();
                     let mut $ws = connect_client_ws(&ws_url, &cur_process_user).await;
-                    let $org_conn = match org_uri.clone() {
-                        Some(org_uri) => Some(crate::grpc::grpc_client::connect(org_uri.clone()).await.expect("Failed to connect to organizer")),
+                    let $org_conn = match organizers.first() {
+                        Some(org) => Some(crate::grpc::grpc_client::connect(org.uri.clone()).await.expect("Failed to connect to organizer")),
                         None => None,
                     };
                     { $($body)* }
@@ -0,0 +1,479 @@
+// SMPTE timecodes, and mapping them to frame numbers of a media file.
+//
+// A `Timecode` is a frame count from 00:00:00:00 at some `Rate`, labeled in one of three styles:
+// - Non-drop-frame `HH:MM:SS:FF`: nominal (rounded) frames per timecode second. What NLEs use at integer
+//   rates and at 23.976, where timecode slowly drifts from wall clock time.
+// - Drop-frame `HH:MM:SS;FF`, at 29.97 and 59.94 fps only: frame labels 0-1 (0-3 at 59.94) are skipped at
+//   the start of every minute except every tenth, which keeps timecode in step with the wall clock.
+// - Wall clock `HH:MM:SS:FF`: whole seconds of real time plus frames into the second, as the web client
+//   (`VideoFrame.toSMPTE()`) writes `comments.timecode`. Same as non-drop-frame at integer rates.
+// A `;` before the frames field always means drop-frame.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::database::models;
+
+/// Used if the media file has no (valid) frame rate, e.g. for audio files and images
+pub const DEFAULT_FPS: f64 = 25.0;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum TimecodeError {
+    #[error("Malformed timecode '{0}'")]
+    Malformed(String),
+    #[error("Timecode '{0}' out of range at {1} fps")]
+    OutOfRange(String, f64),
+    #[error("Timecode '{0}' doesn't exist in drop-frame counting")]
+    Dropped(String),
+    #[error("Drop-frame timecode needs 29.97 or 59.94 fps, not {0}")]
+    NotDropFrameRate(f64),
+    #[error("Invalid frame rate '{0}'")]
+    BadRate(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcStyle {
+    NonDrop,
+    DropFrame,
+    WallClock,
+}
+
+/// Frame rate, for timecode math
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    fps: f64,
+}
+
+impl Rate {
+    pub fn new(fps: f64) -> Result<Self, TimecodeError> {
+        if fps.is_finite() && fps > 0.0 {
+            Ok(Rate { fps })
+        } else {
+            Err(TimecodeError::BadRate(fps.to_string()))
+        }
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    /// Frames per timecode second, e.g. 30 at 29.97 fps
+    pub fn nominal(&self) -> u64 {
+        (self.fps.round() as u64).max(1)
+    }
+
+    /// NTSC style 1000/1001 rate, like 23.976 or 29.97
+    pub fn is_ntsc(&self) -> bool {
+        (self.fps - self.nominal() as f64).abs() >= 0.001 && (self.fps * 1.001 - (self.fps * 1.001).round()).abs() < 0.01
+    }
+
+    pub fn has_drop_frame(&self) -> bool {
+        self.is_ntsc() && matches!(self.nominal(), 30 | 60)
+    }
+
+    /// Frame labels skipped at the start of each minute (except every tenth) in drop-frame counting
+    fn dropped_per_minute(&self) -> u64 {
+        self.nominal() / 15
+    }
+
+    /// Frame duration as a rational number of seconds (numerator, denominator), e.g. (1001, 30000) at 29.97
+    pub fn frame_duration(&self) -> (u64, u64) {
+        let n = self.nominal();
+        if (self.fps - n as f64).abs() < 0.001 {
+            (1, n)
+        } else if self.is_ntsc() {
+            (1001, (self.fps * 1.001).round() as u64 * 1000)
+        } else {
+            (1000, (self.fps * 1000.0).round() as u64)
+        }
+    }
+}
+
+impl FromStr for Rate {
+    type Err = TimecodeError;
+
+    /// Parse decimal ("29.970") or fractional ("30000/1001") frame rate
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || TimecodeError::BadRate(s.to_string());
+        let num = |x: &str| x.trim().parse::<f64>().map_err(|_| bad());
+        let fps = match s.split_once('/') {
+            Some((n, d)) => num(n)? / num(d)?,
+            None => num(s)?,
+        };
+        Rate::new(fps).map_err(|_| bad())
+    }
+}
+
+
+/// Position as a frame count from 00:00:00:00, with the style to label it in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timecode {
+    frame: u64,
+    rate: Rate,
+    style: TcStyle,
+}
+
+/// Split `HH:MM:SS:FF` (or `HH:MM:SS`) into numeric fields, and tell if it's drop-frame (has a `;`)
+fn split_fields(tc: &str) -> Option<([u64; 4], bool)> {
+    let drop_frame = tc.contains(';');
+    let fields = tc.split([':', ';'])
+        .map(|p| p.bytes().all(|b| b.is_ascii_digit()).then(|| p.parse::<u32>().ok().map(u64::from)).flatten())
+        .collect::<Option<Vec<_>>>()?;
+    match fields[..] {
+        [h, m, s, f] => Some(([h, m, s, f], drop_frame)),
+        [h, m, s] if !drop_frame => Some(([h, m, s, 0], false)),
+        _ => None,
+    }
+}
+
+impl Timecode {
+    /// Timecode of a frame number. Fails if drop-frame is requested at a rate that doesn't have it.
+    pub fn from_frame(frame: u64, rate: Rate, style: TcStyle) -> Result<Self, TimecodeError> {
+        if style == TcStyle::DropFrame && !rate.has_drop_frame() {
+            return Err(TimecodeError::NotDropFrameRate(rate.fps));
+        }
+        Ok(Timecode { frame, rate, style })
+    }
+
+    /// Parse and validate a timecode.
+    ///
+    /// # Arguments
+    /// * `tc` - `HH:MM:SS:FF`, `HH:MM:SS;FF` (drop-frame) or `HH:MM:SS`
+    /// * `rate` - Frame rate the timecode counts at
+    /// * `colon_style` - How to count if there's no `;`: `NonDrop` or `WallClock` (or `DropFrame`, for sources that
+    ///   write drop-frame timecodes with colons)
+    pub fn parse(tc: &str, rate: Rate, colon_style: TcStyle) -> Result<Self, TimecodeError> {
+        let tc = tc.trim();
+        let ([h, m, s, f], semicolon) = split_fields(tc).ok_or_else(|| TimecodeError::Malformed(tc.to_string()))?;
+        let style = if semicolon { TcStyle::DropFrame } else { colon_style };
+        let n = rate.nominal();
+
+        // The client rounds frames into the second, which can reach `n` at fractional rates
+        let max_f = if style == TcStyle::WallClock { n } else { n - 1 };
+        if m >= 60 || s >= 60 || f > max_f {
+            return Err(TimecodeError::OutOfRange(tc.to_string(), rate.fps));
+        }
+        let secs = (h * 60 + m) * 60 + s;
+        let frame = match style {
+            TcStyle::NonDrop => secs * n + f,
+            TcStyle::WallClock => (secs as f64 * rate.fps + f as f64).round() as u64,
+            TcStyle::DropFrame => {
+                if !rate.has_drop_frame() {
+                    return Err(TimecodeError::NotDropFrameRate(rate.fps));
+                }
+                let d = rate.dropped_per_minute();
+                if s == 0 && f < d && m % 10 != 0 {
+                    return Err(TimecodeError::Dropped(tc.to_string()));
+                }
+                let mins = h * 60 + m;
+                secs * n + f - d * (mins - mins / 10)
+            },
+        };
+        Ok(Timecode { frame, rate, style })
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn rate(&self) -> Rate {
+        self.rate
+    }
+
+    pub fn style(&self) -> TcStyle {
+        self.style
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.frame as f64 / self.rate.fps
+    }
+
+    /// Timecode `frames` later, in the same style
+    pub fn add_frames(&self, frames: u64) -> Self {
+        Timecode { frame: self.frame + frames, ..*self }
+    }
+
+    /// (hours, minutes, seconds, frames) of the label
+    fn fields(&self) -> (u64, u64, u64, u64) {
+        let n = self.rate.nominal();
+        let (secs, f) = match self.style {
+            TcStyle::WallClock => {
+                let frame = self.frame as f64;
+                ((frame / self.rate.fps).floor() as u64, (frame % self.rate.fps).round() as u64)
+            },
+            TcStyle::NonDrop => (self.frame / n, self.frame % n),
+            TcStyle::DropFrame => {
+                let d = self.rate.dropped_per_minute();
+                let per_10min = n * 600 - 9 * d;
+                let per_min = n * 60 - d;
+                let (tens, rem) = (self.frame / per_10min, self.frame % per_10min);
+                let skipped = 9 * d * tens + if rem > d { d * ((rem - d) / per_min) } else { 0 };
+                let label = self.frame + skipped;
+                (label / n, label % n)
+            },
+        };
+        (secs / 3600, (secs / 60) % 60, secs % 60, f)
+    }
+}
+
+impl fmt::Display for Timecode {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (h, m, s, f) = self.fields();
+        let sep = if self.style == TcStyle::DropFrame { ';' } else { ':' };
+        write!(fmt, "{:02}:{:02}:{:02}{}{:02}", h, m, s, sep, f)
+    }
+}
+
+
+/// Frame rate, length and start timecode of a media file.
+/// Converts between frame numbers (counted from the first frame of the file) and timecodes.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRate {
+    rate: Rate,
+    total_frames: Option<u64>,
+    start: Option<Timecode>,
+}
+
+impl FrameRate {
+    pub fn of(mf: &models::MediaFile) -> Self {
+        let rate = mf.fps.as_deref().and_then(|s| Rate::from_str(s).ok());
+        let fr = Self::new(rate.map(|r| r.fps()).unwrap_or(DEFAULT_FPS), mf.total_frames.filter(|n| *n > 0).map(|n| n as u64));
+        let start = mf.start_timecode.as_deref().and_then(|tc| Timecode::parse(tc, fr.rate, TcStyle::NonDrop).ok());
+        fr.with_start(start)
+    }
+
+    pub fn new(fps: f64, total_frames: Option<u64>) -> Self {
+        let rate = Rate::new(fps).or_else(|_| Rate::new(DEFAULT_FPS)).expect("valid default fps");
+        FrameRate { rate, total_frames: total_frames.filter(|n| *n > 0), start: None }
+    }
+
+    pub fn with_start(self, start: Option<Timecode>) -> Self {
+        FrameRate { start, ..self }
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.rate.fps()
+    }
+
+    pub fn total_frames(&self) -> Option<u64> {
+        self.total_frames
+    }
+
+    /// Source timecode of the first frame, if the file has one
+    pub fn start(&self) -> Option<Timecode> {
+        self.start
+    }
+
+    /// Frames per timecode second
+    pub fn nominal(&self) -> u64 {
+        self.rate.nominal()
+    }
+
+    /// Source timecodes are drop-frame (as the start timecode is)
+    pub fn is_drop_frame(&self) -> bool {
+        matches!(self.start, Some(tc) if tc.style() == TcStyle::DropFrame)
+    }
+
+    fn source_style(&self) -> TcStyle {
+        if self.is_drop_frame() { TcStyle::DropFrame } else { TcStyle::NonDrop }
+    }
+
+    /// Limit frame number to the media file's length
+    pub fn clamp(&self, frame: u64) -> u64 {
+        match self.total_frames {
+            Some(n) => frame.min(n - 1),
+            None => frame,
+        }
+    }
+
+    /// Parse client's `HH:MM:SS:FF` (or `HH:MM:SS`) timecode into a frame number.
+    /// The client counts seconds at the actual frame rate (see `client_timecode()`). Drop-frame (`;`) is accepted, too.
+    pub fn parse(&self, tc: &str) -> Option<u64> {
+        Timecode::parse(tc, self.rate, TcStyle::WallClock).ok().map(|t| self.clamp(t.frame()))
+    }
+
+    /// Parse a source timecode (as written by `nle_timecode()` and NLEs) into a frame number.
+    /// If the file has a start timecode, timecodes from it onwards are taken relative to it.
+    pub fn parse_nle(&self, tc: &str) -> Option<u64> {
+        let frame = Timecode::parse(tc, self.rate, self.source_style()).ok()?.frame();
+        let start = self.start.map(|s| s.frame()).filter(|s| *s <= frame).unwrap_or(0);
+        Some(self.clamp(frame - start))
+    }
+
+    pub fn from_seconds(&self, secs: f64) -> Option<u64> {
+        (secs.is_finite() && secs >= 0.0).then(|| self.clamp((secs * self.rate.fps()).round() as u64))
+    }
+
+    /// Format frame number like the client does (`VideoFrame.toSMPTE()`), for storing in `comments.timecode`
+    pub fn client_timecode(&self, frame: u64) -> String {
+        Timecode { frame, rate: self.rate, style: TcStyle::WallClock }.to_string()
+    }
+
+    /// Format frame number as a source timecode: offset by the start timecode, and drop-frame if it is
+    pub fn nle_timecode(&self, frame: u64) -> String {
+        match self.start {
+            Some(start) => start.add_frames(frame).to_string(),
+            None => Timecode { frame, rate: self.rate, style: TcStyle::NonDrop }.to_string(),
+        }
+    }
+
+    pub fn seconds(&self, frame: u64) -> f64 {
+        frame as f64 / self.rate.fps()
+    }
+
+    /// Frame duration as a rational number of seconds (numerator, denominator), for FCPXML
+    pub fn frame_duration(&self) -> (u64, u64) {
+        self.rate.frame_duration()
+    }
+}
+
+/// Frame number for a comment's timecode (as sent by the client), to store in `comments.frame`.
+/// None if there's no timecode, or it doesn't parse.
+pub fn comment_frame(mf: &models::MediaFile, timecode: Option<&str>) -> Option<i32> {
+    FrameRate::of(mf).parse(timecode?).and_then(|f| i32::try_from(f).ok())
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::make_test_media_file;
+
+    fn rate(fps: &str) -> Rate {
+        Rate::from_str(fps).unwrap()
+    }
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(rate("30000/1001").nominal(), 30);
+        assert!(rate("29.970").has_drop_frame());
+        assert!(rate("59.94").has_drop_frame());
+        assert!(rate("23.976").is_ntsc());
+        assert!(!rate("23.976").has_drop_frame());
+        assert!(!rate("30").is_ntsc());
+        assert!(!rate("25").has_drop_frame());
+        assert_eq!(rate("29.97").frame_duration(), (1001, 30000));
+        assert_eq!(rate("23.976").frame_duration(), (1001, 24000));
+        assert_eq!(rate("25.000").frame_duration(), (1, 25));
+        assert_eq!(rate("12.5").frame_duration(), (1000, 12500));
+        assert!(Rate::from_str("0").is_err());
+        assert!(Rate::from_str("1/0").is_err());
+        assert!(Rate::from_str("").is_err());
+    }
+
+    #[test]
+    fn test_non_drop_frame() {
+        let r = rate("25");
+        let tc = Timecode::parse("01:00:00:00", r, TcStyle::NonDrop).unwrap();
+        assert_eq!(tc.frame(), 90000);
+        assert_eq!(tc.add_frames(62 * 25 + 3).to_string(), "01:01:02:03");
+        assert_eq!(Timecode::parse("10:00:00", r, TcStyle::NonDrop).unwrap().frame(), 36000 * 25);
+
+        assert!(matches!(Timecode::parse("00:00:00:25", r, TcStyle::NonDrop), Err(TimecodeError::OutOfRange(..))));
+        assert!(matches!(Timecode::parse("00:60:00:00", r, TcStyle::NonDrop), Err(TimecodeError::OutOfRange(..))));
+        assert!(matches!(Timecode::parse("00:00:01;00", r, TcStyle::NonDrop), Err(TimecodeError::NotDropFrameRate(_))));
+        for bad in ["", "bad", "00:00:00:00:00", "00:00", "00:-1:00:00", "00:+1:00:00", "00:00:00;", "00;00;00"] {
+            assert!(matches!(Timecode::parse(bad, r, TcStyle::NonDrop), Err(TimecodeError::Malformed(_))), "{}", bad);
+        }
+
+        // 29.97 NDF counts 30 labels per second, drifting from the wall clock
+        let tc = Timecode::parse("00:00:33:10", rate("29.97"), TcStyle::NonDrop).unwrap();
+        assert_eq!(tc.frame(), 1000);
+        assert!(Timecode::parse("00:00:01:30", rate("29.97"), TcStyle::NonDrop).is_err());
+    }
+
+    #[test]
+    fn test_drop_frame() {
+        let r = rate("29.97");
+        let df = |s: &str| Timecode::parse(s, r, TcStyle::NonDrop).map(|t| t.frame());
+        let label = |f: u64| Timecode::from_frame(f, r, TcStyle::DropFrame).unwrap().to_string();
+
+        assert_eq!(df("00:00:59;29"), Ok(1799));
+        assert_eq!(df("00:01:00;02"), Ok(1800));
+        assert_eq!(df("00:10:00;00"), Ok(17982));
+        assert_eq!(df("01:00:00;00"), Ok(107892));
+        assert!(matches!(df("00:01:00;00"), Err(TimecodeError::Dropped(_))));
+        assert!(matches!(df("00:01:00;01"), Err(TimecodeError::Dropped(_))));
+
+        assert_eq!(label(1799), "00:00:59;29");
+        assert_eq!(label(1800), "00:01:00;02");
+        assert_eq!(label(17981), "00:09:59;29");
+        assert_eq!(label(17982), "00:10:00;00");
+        assert_eq!(label(107892), "01:00:00;00");
+
+        // Round trip through a few hours of labels
+        for f in (0..400_000).step_by(997).chain(1795..1805) {
+            assert_eq!(df(&label(f)), Ok(f), "frame {}", f);
+        }
+
+        // 59.94 drops 4 labels per minute
+        let r = rate("60000/1001");
+        let tc = Timecode::parse("00:01:00;04", r, TcStyle::NonDrop).unwrap();
+        assert_eq!(tc.frame(), 3600);
+        assert_eq!(tc.to_string(), "00:01:00;04");
+        assert!(Timecode::parse("00:01:00;03", r, TcStyle::NonDrop).is_err());
+        assert!(Timecode::from_frame(0, rate("25"), TcStyle::DropFrame).is_err());
+    }
+
+    #[test]
+    fn test_wall_clock() {
+        let r = rate("29.970");
+        let tc = Timecode::parse("00:00:33:11", r, TcStyle::WallClock).unwrap();
+        assert_eq!(tc.frame(), 1000);   // As the client's toSMPTE(1000) at 29.97
+        assert_eq!(tc.to_string(), "00:00:33:11");
+        assert!((tc.seconds() - 33.367).abs() < 0.001);
+        assert!(Timecode::parse("00:00:01:30", r, TcStyle::WallClock).is_ok());
+        assert!(Timecode::parse("00:00:01:31", r, TcStyle::WallClock).is_err());
+    }
+
+    #[test]
+    fn test_frame_rate() {
+        let mut mf = models::MediaFile {
+            total_frames: Some(10000),
+            fps: Some("29.970".into()),
+            ..make_test_media_file()
+        };
+        let r = FrameRate::of(&mf);
+        assert_eq!(r.nominal(), 30);
+        assert_eq!(r.parse("00:00:33:11"), Some(1000));
+        assert_eq!(r.parse("01:00:00:00"), Some(9999));   // Clamped to last frame
+        assert_eq!(r.parse("00:00:01:31"), None);
+        assert_eq!(r.parse("bad"), None);
+        assert_eq!(r.parse(""), None);
+        assert_eq!(r.client_timecode(1000), "00:00:33:11");
+        assert_eq!(r.nle_timecode(1000), "00:00:33:10");
+        assert_eq!(r.parse_nle("00:00:33:10"), Some(1000));
+        assert_eq!(r.parse_nle("00:00:01:30"), None);
+        assert_eq!(r.from_seconds(1.0), Some(30));
+        assert_eq!(r.frame_duration(), (1001, 30000));
+        assert_eq!(comment_frame(&mf, Some("00:00:33:11")), Some(1000));
+        assert_eq!(comment_frame(&mf, Some("")), None);
+        assert_eq!(comment_frame(&mf, None), None);
+
+        // Drop-frame start timecode: source timecodes are offset by it, and drop-frame too
+        mf.start_timecode = Some("00:59:59;28".into());
+        let r = FrameRate::of(&mf);
+        assert!(r.is_drop_frame());
+        assert_eq!(r.nle_timecode(0), "00:59:59;28");
+        assert_eq!(r.nle_timecode(2), "01:00:00;00");
+        assert_eq!(r.parse_nle("01:00:00;00"), Some(2));
+        assert_eq!(r.parse_nle("01:00:00:00"), Some(2));    // Colons, but counted as drop-frame
+        assert_eq!(r.parse_nle("00:00:01;00"), Some(30));   // Before start, so taken as relative
+        assert_eq!(r.parse("00:00:33:11"), Some(1000));     // Client timecodes are always from the first frame
+
+        mf.fps = Some("25.000".into());
+        mf.total_frames = Some(0);
+        let r = FrameRate::of(&mf);
+        assert_eq!(r.parse("00:01:02:03"), Some(62 * 25 + 3));
+        assert_eq!(r.nle_timecode(62 * 25 + 3), "00:01:02:03");
+        assert_eq!(r.parse("10:00:00"), Some(36000 * 25));
+        assert_eq!(r.frame_duration(), (1, 25));
+
+        mf.start_timecode = Some("garbage".into());
+        assert!(FrameRate::of(&mf).start().is_none());
+        mf.fps = Some("".into());
+        assert_eq!(FrameRate::of(&mf).fps(), DEFAULT_FPS);
+    }
+}
@@ -0,0 +1,194 @@
+// Reuse of processed artifacts between media files with identical contents.
+// If someone uploads a file that's already in the library (maybe under another
+// name or by another user), the transcoded video, thumbnails and adaptive ladder
+// of the earlier copy are hard linked into the new media dir instead of redoing them.
+// Transcoded video and adaptive streams are only shared if they were made with the
+// same transcoding profile (and, for the video, the same target bitrate).
+
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{anyhow, bail};
+use tracing;
+
+use crate::database::{DB, models};
+
+/// Upload cookie: if set (to anything but "0" or "false"), an upload that is identical to a
+/// media file the uploader already has is dropped instead of added as a second copy.
+pub const SKIP_DUPLICATES_COOKIE: &str = "skip_duplicates";
+
+
+/// Which artifacts were shared from an identical media file
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SharedArtifacts {
+    pub transcoded: bool,
+    pub thumbs: bool,
+    pub adaptive: bool,
+}
+
+impl SharedArtifacts {
+    pub fn any(&self) -> bool {
+        self.transcoded || self.thumbs || self.adaptive
+    }
+}
+
+/// Did the uploader ask to drop uploads they already have? (See `SKIP_DUPLICATES_COOKIE`.)
+pub fn skip_duplicates(upload_cookies: &HashMap<String, String>) -> bool
+{
+    upload_cookies.get(SKIP_DUPLICATES_COOKIE).map(|s| s.trim().to_lowercase())
+        .is_some_and(|s| !s.is_empty() && s != "0" && s != "false")
+}
+
+/// Link finished artifacts of `donor` into the dir of media file `dst_id`, and mark them done in DB.
+/// Unfinished (or failed) artifacts, and ones made with different settings, are left for the normal pipeline to produce.
+///
+/// # Arguments
+/// * `donor` - Earlier media file with identical contents
+/// * `dst_id` - ID of the new media file (must already be in DB, and have its dir created)
+/// * `profile` - Name of the transcoding profile picked for the new file
+/// * `want_bitrate` - Target bitrate of the new file's transcode, or None if it plays as-is (no need to share the transcode)
+/// * `media_files_dir` - Directory where all media files are stored
+pub fn share_artifacts(donor: &models::MediaFile, dst_id: &str, profile: &str, want_bitrate: Option<u32>, media_files_dir: &Path, db: &DB) -> SharedArtifacts
+{
+    let src_dir = media_files_dir.join(&donor.id);
+    let dst_dir = media_files_dir.join(dst_id);
+    let mut res = SharedArtifacts::default();
+
+    let log_err = |what: &str, e: anyhow::Error| {
+        tracing::warn!(donor=%donor.id, media_file=%dst_id, details=%e, "Failed to share {what} from identical media file. Will process it normally.");
+        false
+    };
+
+    let same_profile = donor.transcode_profile.as_deref() == Some(profile);
+    let same_bitrate = want_bitrate.is_some_and(|br| donor.transcode_bitrate == Some(br as i32));
+
+    if same_profile && same_bitrate && donor.recompression_done.is_some() {
+        res.transcoded = (|| -> anyhow::Result<()> {
+            link_symlinked(&src_dir, &dst_dir, "video.mp4")?;
+            models::MediaFile::set_recompressed(&mut db.conn()?, dst_id, donor.transcode_bitrate)?;
+            Ok(())
+        })().map_or_else(|e| log_err("transcoded video", e), |_| true);
+    }
+
+    if donor.thumbs_done.is_some() {
+        res.thumbs = (|| -> anyhow::Result<()> {
+            let conn = &mut db.conn()?;
+            if matches!(donor.has_thumbnail, Some(true)) {
+                link_or_copy(&src_dir.join("thumbs"), &dst_dir.join("thumbs"))?;
+                models::MediaFile::set_has_thumb(conn, dst_id, true)?;
+                if let (Some(cols), Some(rows)) = (donor.thumb_sheet_cols, donor.thumb_sheet_rows) {
+                    models::MediaFile::set_thumb_sheet_dimensions(conn, dst_id, cols as u32, rows as u32)?;
+                }
+            }
+            models::MediaFile::set_thumbs_done(conn, dst_id)?;
+            Ok(())
+        })().map_or_else(|e| log_err("thumbnails", e), |_| true);
+    }
+
+    if same_profile && donor.adaptive_done.is_some() {
+        res.adaptive = (|| -> anyhow::Result<()> {
+            link_symlinked(&src_dir, &dst_dir, "adaptive")?;
+            models::MediaFile::set_adaptive_done(&mut db.conn()?, dst_id, matches!(donor.adaptive_has_dash, Some(true)))?;
+            Ok(())
+        })().map_or_else(|e| log_err("adaptive streams", e), |_| true);
+    }
+
+    if res.any() {
+        tracing::info!(donor=%donor.id, media_file=%dst_id, shared=?res, "Shared processed artifacts from identical media file.");
+    }
+    res
+}
+
+/// Share an artifact that is exposed through a relative symlink (e.g. `video.mp4 -> transcoded_xxx.mp4`):
+/// link the target into `dst_dir` under the same name, and create the same symlink there.
+fn link_symlinked(src_dir: &Path, dst_dir: &Path, link_name: &str) -> anyhow::Result<()>
+{
+    let target = std::fs::read_link(src_dir.join(link_name))?;
+    let target_name = target.file_name().ok_or(anyhow!("Bad symlink target {:?}", target))?;
+    link_or_copy(&src_dir.join(target_name), &dst_dir.join(target_name))?;
+    std::os::unix::fs::symlink(target_name, dst_dir.join(link_name))?;
+    Ok(())
+}
+
+/// Hard link a file, or recursively all files in a directory, from `src` to `dst`.
+/// Falls back to copying if hard linking fails (e.g. across filesystems).
+/// Hard links keep the shared data alive even if the other media file is deleted.
+pub fn link_or_copy(src: &Path, dst: &Path) -> anyhow::Result<()>
+{
+    if src.is_dir() {
+        std::fs::create_dir_all(dst)?;
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            link_or_copy(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else if src.is_file() {
+        if std::fs::hard_link(src, dst).is_err() {
+            std::fs::copy(src, dst)?;
+        }
+    } else {
+        bail!("Not found: {:?}", src);
+    }
+    Ok(())
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+    use crate::database::{DbBasicQuery, DbUpdate};
+    use crate::database::tests::make_test_db;
+
+    #[test]
+    fn test_skip_duplicates_cookie() {
+        let cookies = |v: &str| HashMap::from([(SKIP_DUPLICATES_COOKIE.to_string(), v.to_string())]);
+        assert!(!skip_duplicates(&HashMap::new()));
+        assert!(skip_duplicates(&cookies("1")) && skip_duplicates(&cookies("true")));
+        assert!(!skip_duplicates(&cookies("false")) && !skip_duplicates(&cookies("0")) && !skip_duplicates(&cookies("")));
+    }
+
+    #[test]
+    fn test_share_artifacts() {
+        let (db, data_dir, media_files, _com) = make_test_db();
+        let media_files_dir = data_dir.join("videos");
+
+        // Donor (from test DB) is transcoded and thumbnailed, but has no adaptive ladder
+        let donor = &models::MediaFile { transcode_profile: Some("default".into()), transcode_bitrate: Some(123), ..media_files[0].clone() };
+        let src_dir = media_files_dir.join(&donor.id);
+        std::fs::create_dir_all(src_dir.join("thumbs")).unwrap();
+        std::fs::write(src_dir.join("transcoded_br123_abc.mp4"), "video").unwrap();
+        std::os::unix::fs::symlink("transcoded_br123_abc.mp4", src_dir.join("video.mp4")).unwrap();
+        std::fs::write(src_dir.join("thumbs").join("thumb.webp"), "thumb").unwrap();
+
+        // New media file with identical contents
+        let dst = &media_files[1];
+        let dst_dir = media_files_dir.join(&dst.id);
+        std::fs::create_dir_all(&dst_dir).unwrap();
+        let conn = &mut db.conn().unwrap();
+        models::MediaFile::update_many(conn, &[models::MediaFile {
+            recompression_done: None, thumbs_done: None, has_thumbnail: None, thumb_sheet_cols: None, thumb_sheet_rows: None,
+            ..dst.clone() }]).unwrap();
+
+        // Transcode made with another profile or bitrate is not shared
+        let other_dir = media_files_dir.join(&media_files[2].id);
+        std::fs::create_dir_all(&other_dir).unwrap();
+        assert!(!share_artifacts(donor, &media_files[2].id, "default", Some(456), &media_files_dir, &db).transcoded);
+        assert!(!share_artifacts(donor, &media_files[2].id, "dailies", Some(123), &media_files_dir, &db).transcoded);
+        assert!(!other_dir.join("video.mp4").exists());
+
+        let res = share_artifacts(donor, &dst.id, "default", Some(123), &media_files_dir, &db);
+        assert_eq!(res, SharedArtifacts { transcoded: true, thumbs: true, adaptive: false });
+
+        // Files are hard links, not copies
+        let transcoded = dst_dir.join("transcoded_br123_abc.mp4");
+        assert_eq!(std::fs::read_to_string(dst_dir.join("video.mp4")).unwrap(), "video");
+        assert_eq!(transcoded.metadata().unwrap().ino(), src_dir.join("transcoded_br123_abc.mp4").metadata().unwrap().ino());
+        assert!(dst_dir.join("thumbs").join("thumb.webp").is_file());
+
+        let v = models::MediaFile::get(conn, &dst.id).unwrap();
+        assert!(v.recompression_done.is_some() && v.thumbs_done.is_some() && v.adaptive_done.is_none());
+        assert_eq!(v.transcode_bitrate, Some(123));
+        assert_eq!((v.has_thumbnail, v.thumb_sheet_cols, v.thumb_sheet_rows), (Some(true), donor.thumb_sheet_cols, donor.thumb_sheet_rows));
+    }
+}
@@ -1,24 +1,40 @@
 use std::{process::Command, io::BufRead};
-use std::path::PathBuf;
-use crossbeam_channel::{Sender, Receiver};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use crossbeam_channel::{Sender, Receiver, select, unbounded};
 use rust_decimal::Decimal;
 use tracing;
 use threadpool::ThreadPool;
+use serde::{Deserialize, Serialize};
 
 use super::metadata_reader::MediaType;
+use super::job_scheduler::JobScheduler;
+use super::job_queue;
+use crate::database::DB;
+use super::JobPriority;
+use super::transcode_profiles::TranscodeProfile;
 use super::DetailedMsg;
+use super::review_report::{self, ReportAssets, ReportFormat, ReviewReport};
 use rust_decimal::prelude::ToPrimitive;
 
 pub type ProgressSender = crossbeam_channel::Sender<(String, String, String, Option<f32>)>;
 
+/// Segment length for adaptive (HLS/DASH) streams. Keyframes are forced at this interval.
+const LADDER_SEGMENT_SECS: u32 = 4;
+
 
 // Input to the FFMPEG processor
+// (Serializable, so it can be persisted in the `jobs` table and resumed after restart)
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CmprInput {
     Transcode {
         video_dst: PathBuf,
         video_bitrate: u32,
+        profile: TranscodeProfile,
         src: CmprInputSource,
     },
     Thumbs {
@@ -26,16 +42,67 @@ pub enum CmprInput {
         thumb_sheet_dims: (u32, u32),   // cols, rows: how many thumbnails in the sheet
         thumb_size: (u32, u32),         // width, height: resolution of a single thumbnail
         src: CmprInputSource,
+    },
+    TranscodeLadder {
+        ladder_dir: PathBuf,            // Where to write master playlist, variant playlists and segments
+        renditions: Vec<LadderRendition>,
+        with_audio: bool,
+        with_dash: bool,                // Also write a DASH manifest (segments are shared with HLS)
+        #[serde(default)]
+        profile: TranscodeProfile,      // For audio settings
+        src: CmprInputSource,
+    },
+    Report {
+        report: ReviewReport,
+        format: ReportFormat,
+        report_dst: PathBuf,            // Final .tar.gz / .pdf file
+        drawings_dir: PathBuf,          // Media file's `drawings/` dir
+        src: CmprInputSource,           // Video (or image) to take still frames from
     }
 }
 
-#[derive(Debug, Clone)]
+/// One variant stream of an adaptive bitrate ladder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LadderRendition {
+    pub height: u32,        // Max height; smaller sources are not upscaled
+    pub video_bitrate: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CmprInputSource {
     pub user_id: String,
     pub media_file_id: String,
     pub media_type: MediaType,
     pub path: PathBuf,
     pub duration: Decimal,
+    #[serde(default)]
+    pub priority: JobPriority,
+    #[serde(skip)]
+    pub job_id: Option<i32>,    // Row in `jobs` table, if this is a persisted job
+}
+
+impl CmprInput {
+    pub fn src(&self) -> &CmprInputSource {
+        match self {
+            CmprInput::Transcode { src, .. } | CmprInput::Thumbs { src, .. } | CmprInput::TranscodeLadder { src, .. } | CmprInput::Report { src, .. } => src,
+        }
+    }
+
+    pub fn src_mut(&mut self) -> &mut CmprInputSource {
+        match self {
+            CmprInput::Transcode { src, .. } | CmprInput::Thumbs { src, .. } | CmprInput::TranscodeLadder { src, .. } | CmprInput::Report { src, .. } => src,
+        }
+    }
+
+    /// Job type name, as stored in the `jobs` table
+    pub fn job_type(&self) -> &'static str {
+        match self {
+            CmprInput::Transcode { .. } => "transcode",
+            CmprInput::Thumbs { .. } => "thumbs",
+            CmprInput::TranscodeLadder { .. } => "ladder",
+            CmprInput::Report { .. } => "report",
+        }
+    }
 }
 
 
@@ -45,6 +112,7 @@ pub struct CmprInputSource {
 pub enum CmprOutput {
     TranscodeSuccess {
         video_dst: PathBuf,
+        video_bitrate: u32,
         logs: CmprLogs
     },
     ThumbsSuccess {
@@ -52,14 +120,26 @@ pub enum CmprOutput {
         thumb_sheet_dims: Option<(u32, u32)>,   // cols, rows
         logs: CmprLogs
     },
+    LadderSuccess {
+        ladder_dir: PathBuf,
+        has_dash: bool,
+        logs: CmprLogs
+    },
+    ReportSuccess {
+        report_dst: PathBuf,
+        logs: CmprLogs
+    },
     TranscodeFailure { logs: CmprLogs },
-    ThumbsFailure { logs: CmprLogs }
+    ThumbsFailure { logs: CmprLogs },
+    LadderFailure { logs: CmprLogs },
+    ReportFailure { logs: CmprLogs }
 }
 
 #[derive(Debug, Clone)]
 pub struct CmprLogs {
     pub media_file_id: String,
     pub user_id: String,
+    pub job_id: Option<i32>,
     pub stdout: String,
     pub stderr: String,
     pub dmsg: DetailedMsg,
@@ -67,17 +147,90 @@ pub struct CmprLogs {
 
 
 
+/// Keeps track of cancelled jobs and the FFMpeg processes running for each job,
+/// so that a job can be stopped mid-run. Shared by the pipeline and the worker threads.
+#[derive(Default)]
+pub struct JobCanceller {
+    state: Mutex<CancelState>,
+}
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: HashSet<i32>,
+    pids: HashMap<i32, Vec<u32>>,   // job_id -> running child processes
+}
+
+impl JobCanceller {
+
+    /// Mark job as cancelled, and kill its running processes (if any).
+    /// Workers won't start a cancelled job that is still waiting in the queue.
+    pub fn cancel(&self, job_id: i32) {
+        let mut st = self.state.lock().unwrap();
+        st.cancelled.insert(job_id);
+        for pid in st.pids.get(&job_id).into_iter().flatten() {
+            kill_process(*pid);
+        }
+    }
+
+    pub fn is_cancelled(&self, job_id: Option<i32>) -> bool {
+        job_id.is_some_and(|id| self.state.lock().unwrap().cancelled.contains(&id))
+    }
+
+    /// Forget a cancelled job, after its output has been handled
+    pub fn forget(&self, job_id: i32) {
+        let mut st = self.state.lock().unwrap();
+        st.cancelled.remove(&job_id);
+        st.pids.remove(&job_id);
+    }
+
+    /// Track a child process of a job. Kills it right away if the job was already cancelled.
+    fn register(&self, job_id: i32, pid: u32) {
+        let mut st = self.state.lock().unwrap();
+        if st.cancelled.contains(&job_id) {
+            kill_process(pid);
+        }
+        st.pids.entry(job_id).or_default().push(pid);
+    }
+
+    fn unregister(&self, job_id: i32, pid: u32) {
+        let mut st = self.state.lock().unwrap();
+        if let Some(pids) = st.pids.get_mut(&job_id) {
+            pids.retain(|p| *p != pid);
+            if pids.is_empty() { st.pids.remove(&job_id); }
+        }
+    }
+}
+
+fn kill_process(pid: u32) {
+    tracing::info!(pid=pid, "Killing process of a cancelled job.");
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        tracing::warn!(pid=pid, details=%std::io::Error::last_os_error(), "Failed to kill process.");
+    }
+}
+
+/// Like `Command::output()`, but registers the child process with `canceller`
+/// while it runs, so that cancelling the job kills it.
+fn run_cancellable(cmd: &mut Command, job_id: Option<i32>, canceller: &JobCanceller) -> std::io::Result<std::process::Output>
+{
+    let child = cmd.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    let pid = child.id();
+    if let Some(job_id) = job_id { canceller.register(job_id, pid); }
+    let res = child.wait_with_output();
+    if let Some(job_id) = job_id { canceller.unregister(job_id, pid); }
+    res
+}
+
+
 fn err2cout<E: std::fmt::Debug>(msg_txt: &str, err: E, args: &CmprInput) -> CmprOutput {
     let details_str = format!("{:?}", err);
     tracing::error!(details=&details_str, "err2cout: {}", msg_txt);
 
-    let src = match args {
-        CmprInput::Transcode { src, .. } | CmprInput::Thumbs { src, .. } => src,
-    };
+    let src = args.src();
 
     let logs = CmprLogs {
         media_file_id: src.media_file_id.clone(),
         user_id: src.user_id.clone(),
+        job_id: src.job_id,
         stdout: "".into(),
         stderr: "".into(),
         dmsg: DetailedMsg {
@@ -89,7 +242,9 @@ fn err2cout<E: std::fmt::Debug>(msg_txt: &str, err: E, args: &CmprInput) -> Cmpr
     };
     match args {
         CmprInput::Transcode { .. } => { CmprOutput::TranscodeFailure { logs } },
-        CmprInput::Thumbs { .. } => { CmprOutput::ThumbsFailure { logs } }
+        CmprInput::Thumbs { .. } => { CmprOutput::ThumbsFailure { logs } },
+        CmprInput::TranscodeLadder { .. } => { CmprOutput::LadderFailure { logs } },
+        CmprInput::Report { .. } => { CmprOutput::ReportFailure { logs } }
     }
 }
 
@@ -97,10 +252,14 @@ fn err2cout<E: std::fmt::Debug>(msg_txt: &str, err: E, args: &CmprInput) -> Cmpr
 /// Send progress updates to the progress channel.
 ///
 /// # Arguments
-/// * `args` - what to compress and where to put the result
+/// * `src` - what to transcode
+/// * `video_dst` - where to put the result
+/// * `video_bitrate` - target bitrate for video
+/// * `profile` - transcoding profile (codecs, audio settings, ffmpeg options for videos)
 /// * `progress` - channel to send progress updates to
+/// * `canceller` - for killing FFMpeg if the job gets cancelled
 ///
-fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate: u32, progress: ProgressSender ) -> CmprOutput
+fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate: u32, profile: &TranscodeProfile, progress: ProgressSender, canceller: &Arc<JobCanceller>) -> CmprOutput
 {
     let _span = tracing::info_span!("run_ffmpeg_transcode",
         media_file = %src.media_file_id,
@@ -137,24 +296,13 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
 
     let ffmpeg_options: Vec<String> = match src.media_type {
         MediaType::Video => {
-            // Max 1080p, stereo 128kbps audio AAC
-            vec![
-                "-map", "0",
-                "-dn",
-                "-vcodec", "libx264",
-                "-vf", "scale=1920:-8",
-                "-preset", "faster",
-                "-acodec", "aac",
-                "-ac", "2",
-                "-strict", "experimental",
-                "-b:v", &bitrate,
-                "-b:a", "128000"
-            ]
+            // Options from the profile template (by default: scale to 1920 wide, stereo 128kbps audio AAC)
+            profile.expand_video_args(video_bitrate)
         },
         MediaType::Audio => {
             frame_count = (src.duration * Decimal::from(60)).floor().to_u32();
             if frame_count.is_none() {
-                return err2cout("Failed to parse audio duration", src.duration, &CmprInput::Transcode { video_dst, video_bitrate, src: src.clone() });
+                return err2cout("Failed to parse audio duration", src.duration, &CmprInput::Transcode { video_dst, video_bitrate, profile: profile.clone(), src: src.clone() });
             }
             vec![
                 "-dn",
@@ -165,9 +313,9 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
                 "-strict", "experimental",
                 "-vcodec", "libx264",
                 "-b:v", &bitrate,
-                "-acodec", "aac",
-                "-b:a", "384k"
-            ]
+            ].iter().map(|s| s.to_string())
+                .chain(profile.audio_only_args())
+                .collect()
         },
         MediaType::Image => {
             frame_count = Some(1*24);
@@ -181,17 +329,194 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
                 "-r", "24",
                 "-pix_fmt", "yuv422p",
                 "-b:v", &bitrate,
-            ]
+            ].iter().map(|s| s.to_string()).collect()
         }
-    }.iter().map(|s| s.to_string()).collect();
+    };
 
     tracing::info!(bitrate=video_bitrate, media_type=?src.media_type, "Transcoder called.");
 
+    let ppipe_path = video_dst.with_extension("progress").with_extension("pipe");
+    let mut output_args: Vec<OsString> = ffmpeg_options.into_iter().map(OsString::from).collect();
+    output_args.push(video_dst.clone().into_os_string());
+
+    let (err_msg, stdout, stderr) = run_ffmpeg_with_progress(src, output_args, &ppipe_path, frame_count, "Transcoding", progress, canceller);
+
+    let logs = CmprLogs {
+        media_file_id: src.media_file_id.clone(),
+        user_id: src.user_id.clone(),
+        job_id: src.job_id,
+        stdout,
+        stderr,
+        dmsg: DetailedMsg {
+            msg: if err_msg.is_some() { "Transcoding failed" } else { "Transcoding complete" }.to_string(),
+            details: format!("Error in FFMPEG: {:?}", err_msg.clone().unwrap_or_default()),
+            src_file: src.path.clone(),
+            user_id: src.user_id.clone()
+        }
+    };
+    match err_msg {
+        Some(_) => CmprOutput::TranscodeFailure { logs },
+        None => CmprOutput::TranscodeSuccess { video_dst, video_bitrate, logs }
+    }
+}
+
+
+/// Transcode a video into an adaptive bitrate ladder: one HLS variant stream per rendition
+/// (plus a shared audio rendition), and a master playlist `master.m3u8` in `ladder_dir`.
+///
+/// If `with_dash` is set, segments are written as fragmented MP4 instead, and a DASH
+/// manifest `manifest.mpd` is written next to the HLS playlists, so both share the same segments.
+///
+/// All renditions are encoded in a single FFMpeg run, so progress reports cover the whole ladder.
+///
+/// # Arguments
+/// * `src` - what to transcode
+/// * `ladder_dir` - directory to write playlists and segments into (created if missing)
+/// * `renditions` - output heights and bitrates, highest first
+/// * `audio` - transcoding profile for audio settings, if the source has an audio track to include
+/// * `with_dash` - also produce a DASH manifest
+/// * `progress` - channel to send progress updates to
+/// * `canceller` - for killing FFMpeg if the job gets cancelled
+fn run_ffmpeg_ladder(src: &CmprInputSource, ladder_dir: PathBuf, renditions: Vec<LadderRendition>, audio: Option<&TranscodeProfile>, with_dash: bool, progress: ProgressSender, canceller: &Arc<JobCanceller>) -> CmprOutput
+{
+    let _span = tracing::info_span!("run_ffmpeg_ladder",
+        media_file = %src.media_file_id,
+        user = %src.user_id,
+        thread = ?std::thread::current().id()).entered();
+
+    let with_audio = audio.is_some();
+    let as_input = || CmprInput::TranscodeLadder { ladder_dir: ladder_dir.clone(), renditions: renditions.clone(), with_audio, with_dash,
+        profile: audio.cloned().unwrap_or_default(), src: src.clone() };
+
+    if !matches!(src.media_type, MediaType::Video) {
+        return err2cout("Adaptive ladder is only supported for video", &src.media_type, &as_input());
+    }
+    if renditions.is_empty() {
+        return err2cout("Adaptive ladder has no renditions", &renditions, &as_input());
+    }
+
+    let n = renditions.len();
+    let n_streams = if with_audio { n + 1 } else { n };
+    if !with_dash {
+        // HLS muxer writes each variant stream into its own subdirectory
+        for i in 0..n_streams {
+            if let Err(e) = std::fs::create_dir_all(ladder_dir.join(format!("stream_{i}"))) {
+                return err2cout("Failed to create adaptive ladder directory", e.to_string(), &as_input());
+            }
+        }
+    } else if let Err(e) = std::fs::create_dir_all(&ladder_dir) {
+        return err2cout("Failed to create adaptive ladder directory", e.to_string(), &as_input());
+    }
+
+    // Split the video into one scaled stream per rendition. Never upscale, and keep height even for libx264.
+    let filter_complex = format!("[0:v]split={n}{outs};{scales}",
+        outs = (0..n).map(|i| format!("[s{i}]")).collect::<String>(),
+        scales = renditions.iter().enumerate()
+            .map(|(i, r)| format!("[s{i}]scale=w=-2:h='trunc(min({h},ih)/2)*2',format=yuv420p[v{i}]", h=r.height))
+            .collect::<Vec<_>>().join(";"));
+
+    let mut opts: Vec<String> = vec!["-filter_complex".into(), filter_complex];
+    for i in 0..n { opts.extend(["-map".into(), format!("[v{i}]")]); }
+    if with_audio { opts.extend(["-map".into(), "0:a:0".into()]); }
+
+    // Keyframes at segment boundaries, so that all renditions can be switched between
+    opts.extend([
+        "-dn", "-sn",
+        "-c:v", "libx264",
+        "-preset", "faster",
+        "-sc_threshold", "0",
+    ].iter().map(|s| s.to_string()));
+    opts.extend(["-force_key_frames".into(), format!("expr:gte(t,n_forced*{LADDER_SEGMENT_SECS})")]);
+
+    for (i, r) in renditions.iter().enumerate() {
+        opts.extend([
+            format!("-b:v:{i}"), r.video_bitrate.to_string(),
+            format!("-maxrate:v:{i}"), (r.video_bitrate / 10 * 11).to_string(),
+            format!("-bufsize:v:{i}"), (r.video_bitrate * 2).to_string(),
+        ]);
+    }
+    if let Some(p) = audio {
+        opts.extend(["-c:a".into(), p.audio_codec.clone(), "-ac".into(), p.audio_channels.to_string(), "-b:a".into(), p.audio_bitrate.to_string()]);
+    }
+
+    let mut output_args: Vec<OsString> = opts.into_iter().map(OsString::from).collect();
+    if with_dash {
+        let adaptation_sets = if with_audio { "id=0,streams=v id=1,streams=a" } else { "id=0,streams=v" };
+        output_args.extend([
+            "-f", "dash",
+            "-seg_duration", &LADDER_SEGMENT_SECS.to_string(),
+            "-use_template", "1",
+            "-use_timeline", "1",
+            "-hls_playlist", "1",   // Also write master.m3u8 + media playlists for the same segments
+            "-adaptation_sets", adaptation_sets,
+        ].iter().map(OsString::from));
+        output_args.push(ladder_dir.join("manifest.mpd").into_os_string());
+    } else {
+        let var_stream_map = (0..n).map(|i| if with_audio { format!("v:{i},agroup:aud") } else { format!("v:{i}") })
+            .chain(with_audio.then(|| "a:0,agroup:aud".to_string()))
+            .collect::<Vec<_>>().join(" ");
+        output_args.extend([
+            "-f", "hls",
+            "-hls_time", &LADDER_SEGMENT_SECS.to_string(),
+            "-hls_playlist_type", "vod",
+            "-hls_flags", "independent_segments",
+            "-master_pl_name", "master.m3u8",
+            "-var_stream_map", &var_stream_map,
+            "-hls_segment_filename",
+        ].iter().map(OsString::from));
+        output_args.push(ladder_dir.join("stream_%v").join("seg_%05d.ts").into_os_string());
+        output_args.push(ladder_dir.join("stream_%v").join("playlist.m3u8").into_os_string());
+    }
+
+    tracing::info!(renditions=?renditions, with_audio=with_audio, with_dash=with_dash, "Adaptive ladder transcoder called.");
+
+    let ppipe_path = ladder_dir.with_extension("pipe");
+    let (mut err_msg, stdout, stderr) = run_ffmpeg_with_progress(src, output_args, &ppipe_path, None, "Transcoding adaptive streams", progress, canceller);
+
+    if err_msg.is_none() && !ladder_dir.join("master.m3u8").is_file() {
+        err_msg = Some("FFMPEG did not write master playlist".to_string());
+    }
+
+    let logs = CmprLogs {
+        media_file_id: src.media_file_id.clone(),
+        user_id: src.user_id.clone(),
+        job_id: src.job_id,
+        stdout,
+        stderr,
+        dmsg: DetailedMsg {
+            msg: if err_msg.is_some() { "Adaptive transcoding failed" } else { "Adaptive transcoding complete" }.to_string(),
+            details: format!("Error in FFMPEG: {:?}", err_msg.clone().unwrap_or_default()),
+            src_file: src.path.clone(),
+            user_id: src.user_id.clone()
+        }
+    };
+    match err_msg {
+        Some(_) => CmprOutput::LadderFailure { logs },
+        None => CmprOutput::LadderSuccess { ladder_dir, has_dash: with_dash, logs }
+    }
+}
+
+
+/// Run FFMpeg on the source file with given output options, and send progress updates
+/// parsed from a named pipe to the progress channel while it runs.
+///
+/// # Arguments
+/// * `src` - source media file
+/// * `output_args` - ffmpeg arguments after the input file, including output path(s)
+/// * `ppipe_path` - where to create the named pipe for progress reports
+/// * `frame_count` - number of frames expected in output, or None to count video frames with ffprobe
+/// * `op_name` - operation name for progress messages, e.g. "Transcoding"
+/// * `progress` - channel to send progress updates to
+/// * `canceller` - for killing FFMpeg if the job gets cancelled
+///
+/// # Returns
+/// * (error message if failed, stdout, stderr)
+fn run_ffmpeg_with_progress(src: &CmprInputSource, output_args: Vec<OsString>, ppipe_path: &Path, mut frame_count: Option<u32>, op_name: &'static str, progress: ProgressSender, canceller: &Arc<JobCanceller>) -> (Option<String>, String, String)
+{
     // Open a named pipe for ffmpeg to write progress reports to.
     // If this fails, ignore it and just don't show progress.
     let ppipe_fname = {
-        let fname = video_dst.with_extension("progress").with_extension("pipe");
-        match fname.to_str() {
+        match ppipe_path.to_str() {
             None => { Err("Invalid dst path".to_string()) }
             Some(fname) => unix_named_pipe::create(&fname, None)
                 .map(|_| fname.to_string())
@@ -287,7 +612,7 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
                                             "progress" => {
                                                 match val {
                                                     "end" => {
-                                                        msg = Some("Transcoding done.".to_string());
+                                                        msg = Some(format!("{op_name} done."));
                                                         done_ratio = Some(1.0);
                                                     },
                                                     _ => {
@@ -300,10 +625,10 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
                                                         match (frame_i, frame_count) {
                                                             (Some(frame), Some(n_frames)) => {
                                                                 let ratio = frame as f32 / n_frames as f32;
-                                                                msg = Some(format!("Transcoding... {:.1}% done{speed_str}", (ratio * 100f32) as i32));
+                                                                msg = Some(format!("{op_name}... {:.1}% done{speed_str}", (ratio * 100f32) as i32));
                                                                 done_ratio = Some(ratio);
                                                             },
-                                                            _ => { msg = Some(format!("Transcoding...{speed_str}")); }
+                                                            _ => { msg = Some(format!("{op_name}...{speed_str}")); }
                                             }}}},
                                             other => {
                                                 tracing::trace!(key=other, val=%val, "Ignoring unsupported key.");
@@ -321,8 +646,8 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
 
     // Start transcoder thread (writes to the progress pipe)
     let ffmpeg_thread = {
+        let (job_id, canceller) = (src.job_id, canceller.clone());
         let src = src.path.clone();
-        let dst = video_dst.clone();
 
         std::thread::spawn(move || {
             let _span = tracing::info_span!("ffmpeg_transcode",
@@ -334,11 +659,11 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
             // Add proggress reporting
             if let Some(pfn) = ppipe_fname { cmd = cmd.args(&["-progress", &pfn]); }
 
-            // Add media type specific options
-            cmd = cmd.args(&ffmpeg_options).arg(&dst);
+            // Add operation specific options and output(s)
+            cmd = cmd.args(&output_args);
 
             tracing::debug!(cmd=?cmd, "Invoking ffmpeg.");
-            match cmd.output() {
+            match run_cancellable(cmd, job_id, &canceller) {
                 Ok(res) => {
                     tracing::info!("ffmpeg finished");
                     (if res.status.success() {None} else {Some("FFMPEG exited with error".to_string())},
@@ -368,22 +693,7 @@ fn run_ffmpeg_transcode(src: &CmprInputSource, video_dst: PathBuf, video_bitrate
     }
     tracing::debug!("FFMPEG progress thread joined.");
 
-    let logs = CmprLogs {
-        media_file_id: src.media_file_id.clone(),
-        user_id: src.user_id.clone(),
-        stdout: stdout,
-        stderr: stderr,
-        dmsg: DetailedMsg {
-            msg: if err_msg.is_some() { "Transcoding failed" } else { "Transcoding complete" }.to_string(),
-            details: format!("Error in FFMPEG: {:?}", err_msg.clone().unwrap_or_default()),
-            src_file: src.path.clone(),
-            user_id: src.user_id.clone()
-        }
-    };
-    match err_msg {
-        Some(_) => CmprOutput::TranscodeFailure { logs },
-        None => CmprOutput::TranscodeSuccess { video_dst, logs }
-    }
+    (err_msg, stdout, stderr)
 }
 
 
@@ -427,8 +737,9 @@ fn count_video_frames( src: &PathBuf ) -> Option<u32>
 ///
 /// # Arguments
 /// * `args` - what to process and where to put the result
+/// * `canceller` - for killing FFMpeg if the job gets cancelled
 ///
-fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_sheet_dims: (u32, u32), src: CmprInputSource ) -> CmprOutput
+fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_sheet_dims: (u32, u32), src: CmprInputSource, canceller: &Arc<JobCanceller> ) -> CmprOutput
 {
     let _span = tracing::info_span!("run_ffmpeg_thumbnailer",
         media_file = %src.media_file_id,
@@ -457,6 +768,7 @@ fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_shee
     let single_thumb_thread = {
         let src_path = src.path.clone();
         let thumb_dir = thumb_dir.clone();
+        let (job_id, canceller) = (src.job_id, canceller.clone());
         std::thread::spawn(move || {
             let _span = tracing::info_span!("ffmpeg_thumb_poster",
                 thread = ?std::thread::current().id()).entered();
@@ -480,7 +792,7 @@ fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_shee
             ]).arg(thumb_dir.join("thumb.webp"));
 
             tracing::debug!(cmd=?cmd, "Invoking ffmpeg.");
-            match cmd.output() {
+            match run_cancellable(cmd, job_id, &canceller) {
                 Ok(res) => {
                     tracing::info!("ffmpeg finished");
                     (if res.status.success() {None} else {Some("FFMPEG exited with error".to_string())},
@@ -499,6 +811,7 @@ fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_shee
     let sheet_thread = {
         let src_path = src.path.clone();
         let thumb_dir = thumb_dir.clone();
+        let (job_id, canceller) = (src.job_id, canceller.clone());
         std::thread::spawn(move || {
             let _span = tracing::info_span!("ffmpeg_thumbsheet",
                 thread = ?std::thread::current().id()).entered();
@@ -536,7 +849,7 @@ fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_shee
             ]).arg(thumb_dir.join(format!("sheet-{thumb_sheet_cols}x{thumb_sheet_rows}.webp")));
 
             tracing::debug!(cmd=?cmd, "Invoking ffmpeg.");
-            match cmd.output() {
+            match run_cancellable(cmd, job_id, &canceller) {
                 Ok(res) => {
                     tracing::info!("ffmpeg finished");
                     (if res.status.success() {None} else {Some("FFMPEG exited with error".to_string())},
@@ -578,6 +891,7 @@ fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_shee
     let logs = CmprLogs {
         media_file_id: src.media_file_id.clone(),
         user_id: src.user_id.clone(),
+        job_id: src.job_id,
         stdout: comb_stdout,
         stderr: comb_stderr,
         dmsg: DetailedMsg {
@@ -598,6 +912,129 @@ fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_shee
 }
 
 
+/// Render a review report: extract a still frame for each comment thread and convert drawings
+/// to PNG with FFMpeg, write the HTML, and pack it into a .tar.gz or print it to PDF.
+/// Frames or drawings that fail to convert are left out of the report, but a missing FFMpeg
+/// (or PDF converter) fails the job.
+///
+/// # Arguments
+/// * `report` - Comment threads to render
+/// * `format` - HTML bundle or PDF
+/// * `report_dst` - Where to write the result
+/// * `drawings_dir` - Media file's `drawings/` dir
+/// * `src` - Video (or image) to take still frames from
+/// * `canceller` - for killing FFMpeg if the job gets cancelled
+fn run_report(report: ReviewReport, format: ReportFormat, report_dst: PathBuf, drawings_dir: PathBuf, src: CmprInputSource, canceller: &Arc<JobCanceller>) -> CmprOutput
+{
+    use anyhow::{bail, Context};
+    let _span = tracing::info_span!("run_report",
+        media_file = %src.media_file_id,
+        user = %src.user_id,
+        thread = ?std::thread::current().id()).entered();
+
+    let work_dir = review_report::work_dir(&report_dst);
+    let mut stderr = String::new();
+
+    let mut run_ffmpeg = |input: &Path, seek: Option<String>, vf: Option<String>, dst: &Path| -> anyhow::Result<bool> {
+        let mut cmd = Command::new("nice");
+        cmd.arg("-n").arg("10").arg("--").arg("ffmpeg").arg("-y").arg("-nostats");
+        if let Some(secs) = seek { cmd.arg("-ss").arg(secs); }
+        cmd.arg("-i").arg(input).args(["-frames:v", "1"]);
+        if let Some(vf) = vf { cmd.arg("-vf").arg(vf).args(["-q:v", "3"]); }
+        cmd.arg(dst);
+        tracing::debug!(cmd=?cmd, "Invoking ffmpeg.");
+        let res = run_cancellable(&mut cmd, src.job_id, canceller).context("ffmpeg exec failed")?;
+        if canceller.is_cancelled(src.job_id) { bail!("Job was cancelled"); }
+        stderr.push_str(&String::from_utf8_lossy(&res.stderr));
+        Ok(res.status.success() && dst.is_file())
+    };
+
+    let res = (|| -> anyhow::Result<()> {
+        std::fs::create_dir_all(work_dir.join("frames")).context("Failed to create report directory")?;
+        std::fs::create_dir_all(work_dir.join("drawings")).context("Failed to create report directory")?;
+        let mut assets = ReportAssets::default();
+
+        // Still frames, one per distinct timecode
+        let mut by_time: HashMap<String, String> = HashMap::new();
+        for (i, t) in report.threads.iter().enumerate() {
+            let Some(secs) = t.seconds else { continue };
+            let secs = format!("{:.3}", secs);
+            if let Some(rel) = by_time.get(&secs) {
+                assets.frames.insert(i, rel.clone());
+                continue;
+            }
+            let rel = format!("frames/{:04}.jpg", by_time.len() + 1);
+            let vf = format!("scale={}:-2", review_report::REPORT_FRAME_W);
+            if run_ffmpeg(&src.path, Some(secs.clone()), Some(vf), &work_dir.join(&rel))? {
+                by_time.insert(secs, rel.clone());
+                assets.frames.insert(i, rel);
+            } else {
+                tracing::warn!(at=%secs, "Failed to extract still frame for report. Leaving it out.");
+            }
+        }
+
+        // Drawings (webp) to PNG, which PDF converters can read
+        for name in review_report::drawing_names(&report) {
+            let drw_src = drawings_dir.join(name);
+            if !drw_src.is_file() {
+                tracing::warn!(file=?drw_src, "Drawing file missing. Leaving it out of report.");
+                continue;
+            }
+            let rel = format!("drawings/{}.png", Path::new(name).file_stem().unwrap_or_default().to_string_lossy());
+            if run_ffmpeg(&drw_src, None, None, &work_dir.join(&rel))? {
+                assets.drawings.insert(name.to_string(), rel);
+            } else {
+                tracing::warn!(file=?drw_src, "Failed to convert drawing for report. Leaving it out.");
+            }
+        }
+
+        let html_path = work_dir.join("index.html");
+        std::fs::write(&html_path, review_report::render_html(&report, &assets)).context("Failed to write report HTML")?;
+
+        match format {
+            ReportFormat::Html => review_report::pack_bundle(&work_dir, &report_dst)?,
+            ReportFormat::Pdf => {
+                let mut cmd = Command::new("wkhtmltopdf");
+                cmd.args(["--quiet", "--enable-local-file-access", "--page-size", "A4", "--title", &report.title])
+                    .arg(&html_path).arg(&report_dst);
+                tracing::debug!(cmd=?cmd, "Invoking wkhtmltopdf.");
+                let res = run_cancellable(&mut cmd, src.job_id, canceller).context("wkhtmltopdf exec failed (is it installed?)")?;
+                if !res.status.success() || !report_dst.is_file() {
+                    bail!("wkhtmltopdf failed: {}", String::from_utf8_lossy(&res.stderr));
+                }
+            },
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = std::fs::remove_dir_all(&work_dir) {
+        tracing::warn!(dir=?work_dir, details=%e, "Failed to remove report work dir.");
+    }
+
+    match res {
+        Ok(()) => {
+            tracing::info!(file=?report_dst, "Review report done.");
+            CmprOutput::ReportSuccess {
+                logs: CmprLogs {
+                    media_file_id: src.media_file_id.clone(),
+                    user_id: src.user_id.clone(),
+                    job_id: src.job_id,
+                    stdout: "".into(),
+                    stderr,
+                    dmsg: DetailedMsg {
+                        msg: "Review report complete".into(),
+                        details: "".into(),
+                        src_file: src.path.clone(),
+                        user_id: src.user_id.clone()
+                    }
+                },
+                report_dst,
+            }
+        },
+        Err(e) => err2cout("Review report generation failed", e, &CmprInput::Report { report, format, report_dst, drawings_dir, src }),
+    }
+}
+
 /// Listen to incoming transcoding/thumbnailing requests and spawn a thread (from a pool) to handle each one.
 /// Calls FFMpeg CLI to do the actual work, and sends progress updates to the given channel.
 ///
@@ -606,55 +1043,118 @@ fn run_ffmpeg_thumbnailer( thumb_dir: PathBuf, thumb_size: (u32,u32), thumb_shee
 /// * `outq` - Channel to send results
 /// * `progress` - Channel to send transcoding progress updates. Tuple: (media_file_id, progress_msg)
 /// * `n_workers` - Number of worker threads to spawn for processing. This should be at most the number of CPU cores.
+/// * `max_jobs_per_user` - Max number of jobs one user can have running at once (0 = no limit)
+/// * `canceller` - Cancelled jobs are skipped, and their FFMpeg processes killed
+/// * `db` - Persisted jobs are marked as running in the DB when a worker starts them
 pub fn run_forever(
     inq: Receiver<CmprInput>,
     outq: Sender<CmprOutput>,
     progress: ProgressSender,
-    n_workers: usize)
+    n_workers: usize,
+    max_jobs_per_user: usize,
+    canceller: Arc<JobCanceller>,
+    db: Arc<DB>)
 {
     let _span = tracing::info_span!("COMPR").entered();
-    tracing::debug!(n_workers = n_workers, "Starting.");
+    tracing::debug!(n_workers = n_workers, max_jobs_per_user = max_jobs_per_user, "Starting.");
 
+    // Jobs wait in the scheduler (not in the thread pool's FIFO) until a worker is free,
+    // so priority and per-user fairness are decided at the last possible moment.
     let pool = ThreadPool::new(n_workers);
+    let mut sched = JobScheduler::<CmprInput>::new(max_jobs_per_user);
+    let (done_tx, done_rx) = unbounded::<String>();
+
+    /// Tells scheduler that a worker finished a job for given user (even if it panicked)
+    struct DoneGuard(Sender<String>, String);
+    impl Drop for DoneGuard {
+        fn drop(&mut self) { self.0.send(std::mem::take(&mut self.1)).ok(); }
+    }
+
     loop {
-        match inq.recv() {
-            Ok(args) => {
-                //tracing::info!("Got message: {:?}", args);
-                match &args {
-                    CmprInput::Transcode { src, .. } => {
-                        tracing::info!(id=%src.media_file_id, r#type=?src.media_type,
-                            user=%src.user_id, file=%(src.path.file_name().unwrap_or_default().to_string_lossy()),
-                            "Media file transcode request.");
-                    },
-                    CmprInput::Thumbs { src, .. } => {
-                        tracing::info!(id=%src.media_file_id, r#type=?src.media_type,
-                            user=%src.user_id, file=%(src.path.file_name().unwrap_or_default().to_string_lossy()),
-                            "Media file thumbnail request.");
-                    },
-                }
-                tracing::debug!(details=?args, "Spawning worker thread.");
-
-                let outq = outq.clone();
-                let prgr_sender = progress.clone();
-                pool.execute(move || {
-                    match args {
-                        CmprInput::Transcode { video_dst, video_bitrate, src } => {
-                            if let Err(e) = outq.send(run_ffmpeg_transcode(&src, video_dst, video_bitrate, prgr_sender)) {
-                                tracing::error!("Transcode result send failed! Aborting. -- {:?}", e);
-                            }
+        select! {
+            recv(inq) -> msg => match msg {
+                Ok(args) => {
+                    //tracing::info!("Got message: {:?}", args);
+                    match &args {
+                        CmprInput::Transcode { src, .. } => {
+                            tracing::info!(id=%src.media_file_id, r#type=?src.media_type,
+                                user=%src.user_id, file=%(src.path.file_name().unwrap_or_default().to_string_lossy()),
+                                priority=?src.priority, "Media file transcode request.");
                         },
-                        CmprInput::Thumbs { thumb_dir, thumb_sheet_dims, thumb_size, src } => {
-                            if let Err(e) = outq.send(run_ffmpeg_thumbnailer(thumb_dir, thumb_size, thumb_sheet_dims, src)) {
-                                tracing::error!("Thumbnail result send failed! Aborting. -- {:?}", e);
-                            }
+                        CmprInput::Thumbs { src, .. } => {
+                            tracing::info!(id=%src.media_file_id, r#type=?src.media_type,
+                                user=%src.user_id, file=%(src.path.file_name().unwrap_or_default().to_string_lossy()),
+                                priority=?src.priority, "Media file thumbnail request.");
+                        },
+                        CmprInput::TranscodeLadder { src, renditions, .. } => {
+                            tracing::info!(id=%src.media_file_id, r#type=?src.media_type,
+                                user=%src.user_id, file=%(src.path.file_name().unwrap_or_default().to_string_lossy()),
+                                priority=?src.priority, renditions=renditions.len(), "Media file adaptive ladder request.");
+                        },
+                        CmprInput::Report { src, format, report, .. } => {
+                            tracing::info!(id=%src.media_file_id, user=%src.user_id, format=?format,
+                                threads=report.threads.len(), priority=?src.priority, "Review report request.");
                         },
                     }
-                });
+                    let (priority, user_id) = (args.src().priority, args.src().user_id.clone());
+                    sched.push(priority, &user_id, args);
+                },
+                Err(e) => {
+                    tracing::info!(details=%e, "Input queue closed.");
+                    break;
+                }
             },
-            Err(e) => {
-                tracing::info!(details=%e, "Input queue closed.");
-                break;
-            }
+            recv(done_rx) -> msg => {
+                if let Ok(user_id) = msg {
+                    sched.finished(&user_id);
+                }
+            },
+        }
+
+        // Start queued jobs while there are free workers
+        while sched.n_running() < n_workers {
+            let (user_id, args) = match sched.pop() {
+                Some(j) => j,
+                None => break,
+            };
+            tracing::debug!(details=?args, queued=sched.n_queued(), "Spawning worker thread.");
+
+            let outq = outq.clone();
+            let prgr_sender = progress.clone();
+            let done = DoneGuard(done_tx.clone(), user_id);
+            let canceller = canceller.clone();
+            let db = db.clone();
+            pool.execute(move || {
+                let _done = done;
+                let job_id = args.src().job_id;
+                if canceller.is_cancelled(job_id) || job_id.is_some_and(|id| !job_queue::mark_started(&db, id)) {
+                    tracing::info!(job=?args.src().job_id, "Job was cancelled before it started. Skipping.");
+                    outq.send(err2cout("Cancelled", "Job was cancelled", &args)).ok();
+                    return;
+                }
+                match args {
+                    CmprInput::Transcode { video_dst, video_bitrate, profile, src } => {
+                        if let Err(e) = outq.send(run_ffmpeg_transcode(&src, video_dst, video_bitrate, &profile, prgr_sender, &canceller)) {
+                            tracing::error!("Transcode result send failed! Aborting. -- {:?}", e);
+                        }
+                    },
+                    CmprInput::Thumbs { thumb_dir, thumb_sheet_dims, thumb_size, src } => {
+                        if let Err(e) = outq.send(run_ffmpeg_thumbnailer(thumb_dir, thumb_size, thumb_sheet_dims, src, &canceller)) {
+                            tracing::error!("Thumbnail result send failed! Aborting. -- {:?}", e);
+                        }
+                    },
+                    CmprInput::TranscodeLadder { ladder_dir, renditions, with_audio, with_dash, profile, src } => {
+                        if let Err(e) = outq.send(run_ffmpeg_ladder(&src, ladder_dir, renditions, with_audio.then_some(&profile), with_dash, prgr_sender, &canceller)) {
+                            tracing::error!("Adaptive ladder result send failed! Aborting. -- {:?}", e);
+                        }
+                    },
+                    CmprInput::Report { report, format, report_dst, drawings_dir, src } => {
+                        if let Err(e) = outq.send(run_report(report, format, report_dst, drawings_dir, src, &canceller)) {
+                            tracing::error!("Review report result send failed! Aborting. -- {:?}", e);
+                        }
+                    },
+                }
+            });
         }
     }
 
@@ -0,0 +1,309 @@
+// Persistent queue for the FFMPEG worker pool.
+// Every processing request (`CmprInput`) is stored in the `jobs` table before it's
+// handed to the workers, so work in flight survives a crash or restart.
+// Failed jobs are retried with exponential backoff, up to `MAX_ATTEMPTS` times.
+// Cancelled jobs are never retried, and their partial outputs are removed.
+
+use std::path::Path;
+use std::time::Duration;
+use anyhow::Context;
+use chrono::Local;
+use crossbeam_channel::Sender;
+use tracing;
+
+use crate::database::{DB, models, DbBasicQuery};
+use super::ffmpeg_processor::{CmprInput, CmprOutput, JobCanceller};
+
+/// How many times to try a job before giving up on it
+pub const MAX_ATTEMPTS: i32 = 4;
+
+/// How often to check for queued jobs whose retry backoff has passed
+pub const RETRY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Delay before the first retry. Doubled for every subsequent one.
+const RETRY_BASE_DELAY_SECS: i64 = 30;
+
+/// Successfully finished jobs are pruned from the table after this many days
+const KEEP_DONE_DAYS: i64 = 30;
+
+
+/// What happened to a job, after worker output was recorded by `finish()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobOutcome {
+    Untracked,      // Not a persisted job
+    Done,
+    Retry(i64),     // Failed, will be retried in this many seconds
+    Failed,         // Failed, and out of attempts
+    Cancelled,      // Cancelled while running. Output (if any) was discarded.
+}
+
+
+/// Store a new job in the DB, and hand it to the worker pool.
+pub fn submit(db: &DB, cmpr_tx: &Sender<CmprInput>, job: CmprInput) -> anyhow::Result<()>
+{
+    let src = job.src();
+    let row = models::Job::insert(&mut db.conn()?, &models::JobInsert {
+        media_file_id: src.media_file_id.clone(),
+        user_id: src.user_id.clone(),
+        job_type: job.job_type().into(),
+        state: models::Job::QUEUED.into(),
+        payload: serde_json::to_string(&job).context("Error serializing job")?,
+    })?;
+    tracing::debug!(job=row.id, media_file=%row.media_file_id, job_type=%row.job_type, "Job queued.");
+    start(db, cmpr_tx, row.id, job)
+}
+
+/// Send job to the workers. It stays "queued" until a worker picks it up (see `mark_started()`).
+/// If sending fails, the job is resumed on next startup.
+fn start(db: &DB, cmpr_tx: &Sender<CmprInput>, job_id: i32, mut job: CmprInput) -> anyhow::Result<()>
+{
+    job.src_mut().job_id = Some(job_id);
+    models::Job::set_dispatched(&mut db.conn()?, job_id)?;
+    cmpr_tx.send(job).context("Error sending job to worker pool")
+}
+
+/// Mark job as running, and count the attempt. Called by a worker just before it starts the job.
+///
+/// # Returns
+/// * `false` if the job should not be run (e.g. it was cancelled while waiting)
+pub fn mark_started(db: &DB, job_id: i32) -> bool
+{
+    match db.conn().and_then(|mut conn| models::Job::set_running(&mut conn, job_id)) {
+        Ok(started) => started,
+        Err(e) => {
+            tracing::error!(job=job_id, details=%e, "Failed to mark job as running. Running it anyway.");
+            true
+        }
+    }
+}
+
+/// Put jobs that were interrupted by a shutdown back in the queue, and prune old finished ones.
+/// Call once on startup, before `dispatch_due()`.
+pub fn recover(db: &DB) -> anyhow::Result<()>
+{
+    let conn = &mut db.conn()?;
+    let n = models::Job::requeue_interrupted(conn)?;
+    if n > 0 {
+        tracing::info!(count=n, "Resuming interrupted processing jobs.");
+    }
+    models::Job::delete_done_before(conn, Local::now().naive_local() - chrono::Duration::days(KEEP_DONE_DAYS))?;
+    Ok(())
+}
+
+/// Start all queued jobs whose retry backoff (if any) has passed.
+pub fn dispatch_due(db: &DB, cmpr_tx: &Sender<CmprInput>) -> anyhow::Result<()>
+{
+    let due = models::Job::get_due(&mut db.conn()?, Local::now().naive_local())?;
+    for j in due {
+        match serde_json::from_str::<CmprInput>(&j.payload) {
+            Ok(job) => {
+                tracing::info!(job=j.id, media_file=%j.media_file_id, job_type=%j.job_type, attempt=j.attempts+1, "Starting queued job.");
+                start(db, cmpr_tx, j.id, job)?;
+            },
+            Err(e) => {
+                tracing::error!(job=j.id, details=%e, "Bad job payload in DB. Marking job as failed.");
+                models::Job::set_failed(&mut db.conn()?, j.id, &format!("Bad job payload: {e}"), None)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Record the outcome of a job from worker output.
+/// Failures are scheduled for a retry, unless the job has used up its attempts.
+/// If the job was cancelled while it ran, its partial outputs are removed instead.
+pub fn finish(db: &DB, canceller: &JobCanceller, res: &CmprOutput) -> anyhow::Result<JobOutcome>
+{
+    use CmprOutput::*;
+    let (logs, success) = match res {
+        TranscodeSuccess { logs, .. } | ThumbsSuccess { logs, .. } | LadderSuccess { logs, .. } | ReportSuccess { logs, .. } => (logs, true),
+        TranscodeFailure { logs } | ThumbsFailure { logs } | LadderFailure { logs } | ReportFailure { logs } => (logs, false),
+    };
+    let job_id = match logs.job_id {
+        Some(id) => id,
+        None => return Ok(JobOutcome::Untracked),
+    };
+
+    let conn = &mut db.conn()?;
+    let job = models::Job::get(conn, &job_id)?;
+    if job.state == models::Job::CANCELLED {
+        tracing::info!(job=job_id, media_file=%job.media_file_id, "Cancelled job stopped. Removing its outputs.");
+        canceller.forget(job_id);
+        remove_partial_outputs(db, &job);
+        return Ok(JobOutcome::Cancelled);
+    }
+    if success {
+        models::Job::set_done(conn, job_id)?;
+        return Ok(JobOutcome::Done);
+    }
+
+    let err_text = format!("{}: {}", logs.dmsg.msg, logs.dmsg.details);
+    if job.attempts < MAX_ATTEMPTS {
+        let delay = retry_delay_secs(job.attempts);
+        tracing::info!(job=job_id, attempt=job.attempts, delay_secs=delay, "Job failed. Will retry.");
+        models::Job::set_failed(conn, job_id, &err_text, Some(Local::now().naive_local() + chrono::Duration::seconds(delay)))?;
+        Ok(JobOutcome::Retry(delay))
+    } else {
+        tracing::warn!(job=job_id, attempts=job.attempts, "Job failed. Giving up.");
+        models::Job::set_failed(conn, job_id, &err_text, None)?;
+        Ok(JobOutcome::Failed)
+    }
+}
+
+/// Cancel unfinished jobs of a media file.
+///
+/// Running jobs have their FFMpeg processes killed, and their partial outputs are
+/// removed when the worker reports back (in `finish()`). Queued jobs are cleaned up
+/// right away, and skipped if a worker later picks them up.
+///
+/// # Arguments
+/// * `vid` - ID of the media file
+/// * `job_type` - Only cancel jobs of this type (e.g. "transcode"), or None for all
+///
+/// # Returns
+/// * Number of jobs cancelled
+pub fn cancel(db: &DB, canceller: &JobCanceller, vid: &str, job_type: Option<&str>) -> anyhow::Result<usize>
+{
+    let conn = &mut db.conn()?;
+    let jobs = models::Job::get_pending(conn, vid, job_type)?;
+    for j in &jobs {
+        tracing::info!(job=j.id, media_file=%vid, job_type=%j.job_type, state=%j.state, "Cancelling job.");
+        models::Job::set_cancelled(conn, j.id)?;
+        if j.state == models::Job::RUNNING {
+            canceller.cancel(j.id);
+        } else {
+            remove_partial_outputs(db, j);
+        }
+    }
+    Ok(jobs.len())
+}
+
+/// Delete whatever an unfinished job may have written to disk.
+/// Thumbnails are only removed if the media file never had any, so an
+/// interrupted re-run doesn't leave the file without them.
+fn remove_partial_outputs(db: &DB, job: &models::Job)
+{
+    let rm_file = |p: &Path| if p.exists() {
+        if let Err(e) = std::fs::remove_file(p) { tracing::warn!(file=?p, details=%e, "Failed to remove partial output."); }
+    };
+    let rm_dir = |p: &Path| if p.exists() {
+        if let Err(e) = std::fs::remove_dir_all(p) { tracing::warn!(dir=?p, details=%e, "Failed to remove partial output."); }
+    };
+
+    match serde_json::from_str::<CmprInput>(&job.payload) {
+        Ok(CmprInput::Transcode { video_dst, .. }) => {
+            rm_file(&video_dst);
+            rm_file(&video_dst.with_extension("pipe"));
+        },
+        Ok(CmprInput::TranscodeLadder { ladder_dir, .. }) => {
+            rm_dir(&ladder_dir);
+            rm_file(&ladder_dir.with_extension("pipe"));
+        },
+        Ok(CmprInput::Thumbs { thumb_dir, .. }) => {
+            let had_thumbs = db.conn()
+                .and_then(|mut conn| models::MediaFile::get(&mut conn, &job.media_file_id))
+                .map(|v| v.thumbs_done.is_some())
+                .unwrap_or(true);
+            if !had_thumbs { rm_dir(&thumb_dir); }
+        },
+        Ok(CmprInput::Report { report_dst, .. }) => {
+            rm_file(&report_dst);
+            rm_dir(&super::review_report::work_dir(&report_dst));
+        },
+        Err(e) => {
+            tracing::warn!(job=job.id, details=%e, "Bad job payload in DB. Can't remove partial outputs.");
+        }
+    }
+}
+
+/// Backoff before the next attempt, after `attempts` failed ones
+fn retry_delay_secs(attempts: i32) -> i64 {
+    RETRY_BASE_DELAY_SECS * 2i64.pow((attempts.max(1) - 1) as u32)
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::database::tests::make_test_db;
+    use crate::video_pipeline::ffmpeg_processor::{CmprInputSource, CmprLogs};
+    use crate::video_pipeline::metadata_reader::MediaType;
+    use crate::video_pipeline::DetailedMsg;
+
+    fn insert_transcode_job(db: &DB, video_dst: std::path::PathBuf) -> i32 {
+        let job = CmprInput::Transcode {
+            video_dst,
+            video_bitrate: 1_000_000,
+            profile: Default::default(),
+            src: CmprInputSource {
+                user_id: "user.num1".into(),
+                media_file_id: "B1DE0".into(),
+                media_type: MediaType::Video,
+                path: "orig.mov".into(),
+                duration: Default::default(),
+                priority: Default::default(),
+                job_id: None,
+            },
+        };
+        models::Job::insert(&mut db.conn().unwrap(), &models::JobInsert {
+            media_file_id: "B1DE0".into(),
+            user_id: "user.num1".into(),
+            job_type: job.job_type().into(),
+            state: models::Job::QUEUED.into(),
+            payload: serde_json::to_string(&job).unwrap(),
+        }).unwrap().id
+    }
+
+    #[test]
+    fn test_cancel_jobs() {
+        let (db, data_dir, _vid, _com) = make_test_db();
+        let canceller = JobCanceller::default();
+
+        // Queued job (e.g. waiting for retry): outputs are removed right away
+        let queued_dst = data_dir.join("queued.mp4");
+        std::fs::write(&queued_dst, "partial").unwrap();
+        let queued = insert_transcode_job(&db, queued_dst.clone());
+
+        // Running job: processes are killed, outputs removed when worker reports back
+        let running_dst = data_dir.join("running.mp4");
+        std::fs::write(&running_dst, "partial").unwrap();
+        let running = insert_transcode_job(&db, running_dst.clone());
+        assert!(mark_started(&db, running));
+
+        assert_eq!(cancel(&db, &canceller, "B1DE0", Some("thumbs")).unwrap(), 0);
+        assert_eq!(cancel(&db, &canceller, "B1DE0", None).unwrap(), 2);
+
+        let conn = &mut db.conn().unwrap();
+        assert_eq!(models::Job::get(conn, &queued).unwrap().state, models::Job::CANCELLED);
+        assert_eq!(models::Job::get(conn, &running).unwrap().state, models::Job::CANCELLED);
+        assert!(!queued_dst.exists());
+        assert!(running_dst.exists());
+        assert!(canceller.is_cancelled(Some(running)));
+        assert!(!canceller.is_cancelled(Some(queued)));
+
+        // Worker output for the killed job is discarded, not retried
+        let logs = CmprLogs {
+            media_file_id: "B1DE0".into(),
+            user_id: "user.num1".into(),
+            job_id: Some(running),
+            stdout: "".into(),
+            stderr: "".into(),
+            dmsg: DetailedMsg { msg: "Transcoding failed".into(), details: "".into(), src_file: "orig.mov".into(), user_id: "user.num1".into() },
+        };
+        assert_eq!(finish(&db, &canceller, &CmprOutput::TranscodeFailure { logs }).unwrap(), JobOutcome::Cancelled);
+        assert_eq!(models::Job::get(conn, &running).unwrap().state, models::Job::CANCELLED);
+        assert!(!running_dst.exists());
+        assert!(!canceller.is_cancelled(Some(running)));
+        assert!(models::Job::get_pending(conn, "B1DE0", None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_retry_delay_doubles() {
+        assert_eq!(retry_delay_secs(1), RETRY_BASE_DELAY_SECS);
+        assert_eq!(retry_delay_secs(2), RETRY_BASE_DELAY_SECS * 2);
+        assert_eq!(retry_delay_secs(3), RETRY_BASE_DELAY_SECS * 4);
+    }
+}
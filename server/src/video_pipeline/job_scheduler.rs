@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use super::JobPriority;
+
+
+/// Decides which job the FFMPEG worker pool runs next.
+///
+/// Highest priority class goes first. Within a class, users take turns (round-robin),
+/// so one user's bulk drop doesn't starve everyone else. Users already running
+/// `max_per_user` jobs are skipped until one of their jobs finishes.
+pub struct JobScheduler<T> {
+    queues: BTreeMap<JobPriority, VecDeque<(String, VecDeque<T>)>>,    // Per class: (user_id, jobs) in turn order
+    running: HashMap<String, usize>,
+    max_per_user: usize,    // 0 = no limit
+}
+
+impl<T> JobScheduler<T> {
+
+    pub fn new(max_per_user: usize) -> Self {
+        Self { queues: BTreeMap::new(), running: HashMap::new(), max_per_user }
+    }
+
+    /// Add a job to the end of user's queue in given priority class.
+    pub fn push(&mut self, priority: JobPriority, user_id: &str, job: T) {
+        let users = self.queues.entry(priority).or_default();
+        match users.iter_mut().find(|(u, _)| u == user_id) {
+            Some((_, jobs)) => jobs.push_back(job),
+            None => users.push_back((user_id.to_string(), VecDeque::from([job]))),
+        }
+    }
+
+    /// Take the next runnable job, and count it as running for its user.
+    /// Returns None if there are no jobs, or all users with jobs are at their cap.
+    pub fn pop(&mut self) -> Option<(String, T)> {
+        for users in self.queues.values_mut().rev() {
+            for _ in 0..users.len() {
+                let (user_id, mut jobs) = users.pop_front()?;
+                let n_running = self.running.get(&user_id).copied().unwrap_or(0);
+                if self.max_per_user != 0 && n_running >= self.max_per_user {
+                    users.push_back((user_id, jobs));
+                    continue;
+                }
+                if let Some(job) = jobs.pop_front() {
+                    if !jobs.is_empty() {
+                        users.push_back((user_id.clone(), jobs));
+                    }
+                    self.running.insert(user_id.clone(), n_running + 1);
+                    return Some((user_id, job));
+                }
+            }
+        }
+        None
+    }
+
+    /// Mark one of user's running jobs as finished.
+    pub fn finished(&mut self, user_id: &str) {
+        if let Some(n) = self.running.get_mut(user_id) {
+            *n = n.saturating_sub(1);
+            if *n == 0 {
+                self.running.remove(user_id);
+            }
+        }
+    }
+
+    /// Total number of jobs currently running
+    pub fn n_running(&self) -> usize {
+        self.running.values().sum()
+    }
+
+    /// Total number of jobs waiting to run
+    pub fn n_queued(&self) -> usize {
+        self.queues.values().flat_map(|users| users.iter().map(|(_, jobs)| jobs.len())).sum()
+    }
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop_all<T>(s: &mut JobScheduler<T>) -> Vec<(String, T)> {
+        let mut res = vec![];
+        while let Some((u, j)) = s.pop() {
+            s.finished(&u);
+            res.push((u, j));
+        }
+        res
+    }
+
+    #[test]
+    fn test_priority_before_fifo() {
+        let mut s = JobScheduler::new(0);
+        s.push(JobPriority::Bulk, "alice", 1);
+        s.push(JobPriority::Background, "alice", 2);
+        s.push(JobPriority::Interactive, "bob", 3);
+        s.push(JobPriority::Bulk, "alice", 4);
+        let order: Vec<_> = pop_all(&mut s).into_iter().map(|(_, j)| j).collect();
+        assert_eq!(order, vec![3, 1, 4, 2]);
+    }
+
+    #[test]
+    fn test_round_robin_between_users() {
+        let mut s = JobScheduler::new(0);
+        for i in 0..4 { s.push(JobPriority::Bulk, "alice", i); }
+        s.push(JobPriority::Bulk, "bob", 10);
+        s.push(JobPriority::Bulk, "carol", 20);
+        s.push(JobPriority::Bulk, "bob", 11);
+        let order: Vec<_> = pop_all(&mut s).into_iter().map(|(_, j)| j).collect();
+        assert_eq!(order, vec![0, 10, 20, 1, 11, 2, 3]);
+    }
+
+    #[test]
+    fn test_per_user_cap() {
+        let mut s = JobScheduler::new(2);
+        for i in 0..5 { s.push(JobPriority::Bulk, "alice", i); }
+
+        // Alice can only run two at a time...
+        assert_eq!(s.pop().map(|(_, j)| j), Some(0));
+        assert_eq!(s.pop().map(|(_, j)| j), Some(1));
+        assert!(s.pop().is_none());
+        assert_eq!((s.n_running(), s.n_queued()), (2, 3));
+
+        // ...but others still get through
+        s.push(JobPriority::Bulk, "bob", 10);
+        assert_eq!(s.pop(), Some(("bob".to_string(), 10)));
+
+        s.finished("alice");
+        assert_eq!(s.pop().map(|(_, j)| j), Some(2));
+        assert!(s.pop().is_none());
+    }
+}
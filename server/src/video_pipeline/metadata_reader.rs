@@ -1,16 +1,19 @@
 use std::{collections::HashMap, process::Command};
+use std::io::Read;
 use std::sync::atomic::Ordering;
 use threadpool::ThreadPool;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use sha2::{Sha256, Digest};
 use serde_json;
+use serde::{Deserialize, Serialize};
 use crossbeam_channel::{Sender, Receiver, RecvError};
 use tracing;
 use rust_decimal::prelude::*;
 use std::sync::atomic::AtomicBool;
 use std::str::FromStr;
-use super::{IncomingFile, DetailedMsg};
+use super::{IncomingFile, DetailedMsg, JobPriority};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MediaType {
     Video,
     Audio,
@@ -48,8 +51,13 @@ pub struct Metadata {
     pub orig_codec: String,
     pub fps: Decimal,
     pub bitrate: u32,
+    pub height: Option<u32>,    // Video frame height in pixels, if known
+    pub has_audio: bool,
     pub metadata_all: String,
-    pub upload_cookies: HashMap<String, String>    // Cookies from the upload, not read from the file
+    pub upload_cookies: HashMap<String, String>,   // Cookies from the upload, not read from the file
+    pub priority: JobPriority,
+    pub content_hash: String,   // SHA-256 of the whole file (hex)
+    pub start_timecode: Option<String>,     // Embedded source timecode of the first frame, if any
 }
 
 pub type MetadataResult = Result<Metadata, DetailedMsg>;
@@ -123,6 +131,7 @@ fn extract_variables<F>(json: serde_json::Value, args: &IncomingFile, get_file_s
     where F: FnOnce() -> Result<u64, String>
 {
     let tracks = json["media"]["track"].as_array().ok_or("No media tracks found")?;
+    let has_audio = tracks.iter().any(|t| t["@type"] == "Audio");
 
     // Video file
     if let Some(video_track) = tracks.iter().find(|t| t["@type"] == "Video") {
@@ -139,6 +148,8 @@ fn extract_variables<F>(json: serde_json::Value, args: &IncomingFile, get_file_s
                     ((get_file_size()? as f32) * 8.0 / duration) as u32
                 }}};
 
+        let fps = Decimal::from_str(video_track["FrameRate"].as_str().ok_or("FPS not found")?).map_err(|_| "Invalid FPS".to_string())?;
+
         Ok(Metadata {
             src_file: args.file_path.clone(),
             user_id: args.user_id.clone(),
@@ -146,10 +157,15 @@ fn extract_variables<F>(json: serde_json::Value, args: &IncomingFile, get_file_s
             duration,
             media_type: MediaType::Video,
             orig_codec: video_track["Format"].as_str().ok_or("No codec found")?.to_string(),
-            fps: Decimal::from_str(video_track["FrameRate"].as_str().ok_or("FPS not found")?).map_err(|_| "Invalid FPS".to_string())?,
+            fps,
             bitrate,
+            height: video_track["Height"].as_str().and_then(|h| h.parse().ok()),
+            has_audio,
             metadata_all: json.to_string(),
-            upload_cookies: args.cookies.clone()
+            upload_cookies: args.cookies.clone(),
+            priority: args.priority,
+            content_hash: String::new(),
+            start_timecode: start_timecode(tracks, &fps),
         })
     }
 
@@ -164,8 +180,13 @@ fn extract_variables<F>(json: serde_json::Value, args: &IncomingFile, get_file_s
             orig_codec: audio_track["Format"].as_str().ok_or("No codec found")?.to_string(),
             fps: Decimal::from_u8(0).unwrap(),
             bitrate: audio_track["BitRate"].as_str().ok_or("Bitrate not found")?.parse().map_err(|_| "Invalid bitrate".to_string())?,
+            height: None,
+            has_audio,
             metadata_all: json.to_string(),
-            upload_cookies: args.cookies.clone()
+            upload_cookies: args.cookies.clone(),
+            priority: args.priority,
+            content_hash: String::new(),
+            start_timecode: None,
         })
     }
 
@@ -180,19 +201,85 @@ fn extract_variables<F>(json: serde_json::Value, args: &IncomingFile, get_file_s
             orig_codec: image_track["Format"].as_str().ok_or("No codec found")?.to_string(),
             fps: Decimal::from_u8(0).unwrap(),
             bitrate: 0,
+            height: image_track["Height"].as_str().and_then(|h| h.parse().ok()),
+            has_audio,
             metadata_all: json.to_string(),
-            upload_cookies: args.cookies.clone()
+            upload_cookies: args.cookies.clone(),
+            priority: args.priority,
+            content_hash: String::new(),
+            start_timecode: None,
         })
     } else {
         return Err("No video, audio or image track found".to_string());
     }
 }
 
-/// Run mediainfo and extract the metadata
+/// Find the embedded start timecode in mediainfo tracks: video track (e.g. MXF), QuickTime / MP4
+/// timecode track, or container. Drop-frame timecodes are written with `;` before frames.
+///
+/// # Returns
+/// * Validated timecode, normalized to `HH:MM:SS:FF` / `HH:MM:SS;FF`. None if there's none, or it's invalid for the frame rate.
+fn start_timecode(tracks: &[serde_json::Value], fps: &Decimal) -> Option<String>
+{
+    use crate::timecode::{Rate, TcStyle, Timecode};
+
+    let track_types = [
+        |t: &serde_json::Value| t["@type"] == "Video",
+        |t: &serde_json::Value| t["@type"] == "Other" && t["Type"] == "Time code",
+        |t: &serde_json::Value| t["@type"] == "General",
+    ];
+    let (tc, track) = track_types.iter()
+        .find_map(|is_type| tracks.iter().filter(|t| is_type(t)).find_map(|t| t["TimeCode_FirstFrame"].as_str().map(|tc| (tc, t))))?;
+
+    let rate = Rate::new(fps.to_f64()?).ok()?;
+    let colon_style = if track["TimeCode_DropFrame"] == "Yes" { TcStyle::DropFrame } else { TcStyle::NonDrop };
+    match Timecode::parse(tc, rate, colon_style) {
+        Ok(tc) => Some(tc.to_string()),
+        Err(e) => {
+            tracing::warn!(details=%e, "Ignoring invalid start timecode.");
+            None
+        }
+    }
+}
+
+/// Read video frame height and audio presence back from stored mediainfo JSON
+/// (`Metadata::metadata_all`), for re-processing an already ingested file.
+///
+/// # Returns
+/// * (height, has_audio)
+pub fn height_and_audio(metadata_all: &str) -> (Option<u32>, bool)
+{
+    let json: serde_json::Value = serde_json::from_str(metadata_all).unwrap_or_default();
+    let tracks = json["media"]["track"].as_array().cloned().unwrap_or_default();
+    let height = tracks.iter().find(|t| t["@type"] == "Video").and_then(|t| t["Height"].as_str()).and_then(|h| h.parse().ok());
+    (height, tracks.iter().any(|t| t["@type"] == "Audio"))
+}
+
+/// Calculate SHA-256 of the whole file, reading it in chunks.
+///
+/// # Returns
+/// * Hex encoded hash
+pub fn hash_file_contents(file_path: &Path) -> std::io::Result<String>
+{
+    let mut file = std::fs::File::open(file_path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024*1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Run mediainfo and extract the metadata. Also hash the file contents.
 fn read_metadata_from_file(args: &IncomingFile) -> Result<Metadata, String>
 {
     let json = run_mediainfo(&args.file_path)?;
-    extract_variables(json, args, || Ok(args.file_path.metadata().map_err(|e| format!("Failed to get file size: {:?}", e))?.len()))
+    let mut md = extract_variables(json, args, || Ok(args.file_path.metadata().map_err(|e| format!("Failed to get file size: {:?}", e))?.len()))?;
+    md.content_hash = hash_file_contents(&args.file_path).map_err(|e| format!("Failed to hash file contents: {:?}", e))?;
+    Ok(md)
 }
 
 /// Listens to inq for new files to scan for metadata with Mediainfo shell command.
@@ -257,14 +344,15 @@ fn test_fixture(has_bitrate: bool, has_fps: bool) -> (IncomingFile, serde_json::
     let json = serde_json::from_str(&format!(r#"{{
         "media": {{ "track": [ {{
                     "@type": "Video",  "FrameCount": "100",
-                    "Duration": "5.0", "Format": "H264"
+                    "Duration": "5.0", "Format": "H264", "Height": "720"
                     {}{}
                 }} ] }} }}"#, bitrate, fps)).unwrap();
 
     let args = IncomingFile {
         file_path: PathBuf::from("test.mp4"),
         user_id: "test_user".to_string(),
-        cookies: Default::default()
+        cookies: Default::default(),
+        priority: Default::default(),
     };
 
     (args, json)
@@ -280,6 +368,9 @@ fn test_extract_variables_ok()
     assert_eq!(metadata.orig_codec, "H264");
     assert_eq!(metadata.fps, Decimal::from_str("30.000").unwrap());
     assert_eq!(metadata.bitrate, 1000);
+    assert_eq!(metadata.height, Some(720));
+    assert!(!metadata.has_audio);
+    assert_eq!(height_and_audio(&metadata.metadata_all), (Some(720), false));
 }
 
 #[test]
@@ -298,3 +389,41 @@ fn test_extract_variables_fail_missing_fps()
     assert!(metadata.is_err());
     assert!(metadata.unwrap_err().to_lowercase().contains("fps"));
 }
+
+#[test]
+fn test_extract_start_timecode()
+{
+    let (args, mut json) = test_fixture(true, true);
+    assert_eq!(extract_variables(json.clone(), &args, || Ok(1000)).unwrap().start_timecode, None);
+
+    // QuickTime timecode track
+    json["media"]["track"].as_array_mut().unwrap().push(serde_json::json!({
+        "@type": "Other", "Type": "Time code", "Format": "QuickTime TC", "TimeCode_FirstFrame": "01:00:00:00"}));
+    assert_eq!(extract_variables(json.clone(), &args, || Ok(1000)).unwrap().start_timecode, Some("01:00:00:00".into()));
+
+    // Video track takes precedence. Invalid for the frame rate (30 fps has no drop-frame), so ignored.
+    json["media"]["track"][0]["TimeCode_FirstFrame"] = "00:59:59;28".into();
+    assert_eq!(extract_variables(json.clone(), &args, || Ok(1000)).unwrap().start_timecode, None);
+
+    json["media"]["track"][0]["FrameRate"] = "29.970".into();
+    assert_eq!(extract_variables(json.clone(), &args, || Ok(1000)).unwrap().start_timecode, Some("00:59:59;28".into()));
+}
+
+#[test]
+fn test_hash_file_contents()
+{
+    let dir = assert_fs::TempDir::new().unwrap();
+    let (a, b, c) = (dir.path().join("a.bin"), dir.path().join("b.bin"), dir.path().join("c.bin"));
+
+    // Same header, different tail. Longer than the read buffer.
+    let mut data = vec![7u8; 3*1024*1024 + 5];
+    std::fs::write(&a, &data).unwrap();
+    std::fs::write(&b, &data).unwrap();
+    *data.last_mut().unwrap() = 8;
+    std::fs::write(&c, &data).unwrap();
+
+    let ha = hash_file_contents(&a).unwrap();
+    assert_eq!(ha.len(), 64);
+    assert_eq!(ha, hash_file_contents(&b).unwrap());
+    assert_ne!(ha, hash_file_contents(&c).unwrap());
+}
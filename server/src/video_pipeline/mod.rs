@@ -5,7 +5,6 @@
 #![allow(unused_parens)]
 
 use std::collections::HashMap;
-use std::io::Read;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::AtomicBool;
@@ -24,15 +23,26 @@ use hex;
 
 pub mod incoming_monitor;
 pub mod metadata_reader;
+pub mod review_report;
+pub mod transcode_profiles;
+pub mod upload_limits;
 
 mod cleanup_rejected;
+mod dedup;
 mod ffmpeg_processor;
+mod job_queue;
+mod job_scheduler;
 
 use metadata_reader::MetadataResult;
 use crate::api_server::{UserMessage, UserMessageTopic};
+use crate::api_server::media_versions::VERSION_OF_COOKIE;
+use crate::grpc::org_events::{self, OrgEvent};
+use lib_clapshot_grpc::proto;
 use crate::database::error::DBError;
 use crate::video_pipeline::metadata_reader::MediaType;
 use cleanup_rejected::clean_up_rejected_file;
+use transcode_profiles::TranscodeProfiles;
+use upload_limits::UploadLimits;
 use crate::database::{DB, models, DbBasicQuery};
 
 pub const THUMB_SHEET_COLS: u32 = 10;
@@ -40,12 +50,57 @@ pub const THUMB_SHEET_ROWS: u32 = 10;
 pub const THUMB_W: u32 = 160;
 pub const THUMB_H: u32 = 90;
 
+/// User message sent when transcoding finishes (also matched by the e-mail notifier)
+pub const MEDIA_TRANSCODED_MSG: &str = "Media transcoded.";
+
+/// Adaptive ladder renditions: (max height, fraction of target bitrate)
+const ADAPTIVE_LADDER: [(u32, f32); 4] = [(1080, 1.0), (720, 0.55), (480, 0.3), (360, 0.18)];
+
+
+/// Adaptive bitrate streams to produce for videos, in addition to the progressive .mp4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdaptiveStreaming {
+    Off,
+    Hls,
+    HlsAndDash,
+}
+
+
+/// Scheduling class for media processing jobs. Higher classes run first
+/// when workers are busy; users take turns within a class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
+pub enum JobPriority {
+    Background,     // Maintenance, e.g. thumbnailing media from older versions
+    Bulk,           // Files dropped in the incoming folder
+    #[default]
+    Interactive,    // HTTP uploads; someone is likely waiting for them
+}
+
+/// Media processing step that can be re-run on an existing media file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingStep {
+    Transcode,
+    Thumbnails,
+    Adaptive,
+}
+
+/// Requests to the pipeline from API server and Organizer, about already ingested media files
+#[derive(Debug, Clone)]
+pub enum PipelineCmd {
+    /// Stop all unfinished processing jobs of a media file, and remove their partial outputs
+    Cancel { media_file_id: String, user_id: String },
+    /// Re-queue transcoding (optionally with a different bitrate), thumbnailing or the adaptive ladder
+    Rerun { media_file_id: String, user_id: String, step: ProcessingStep, video_bitrate: Option<u32> },
+    /// Generate a review report of the media file's comments (see `review_report`)
+    Report { media_file_id: String, user_id: String, format: review_report::ReportFormat },
+}
 
 #[derive (Clone, Debug)]
 pub struct IncomingFile {
     pub file_path: PathBuf,
     pub user_id: String,
-    pub cookies: HashMap<String, String>  // Cookies from client, if this was an HTTP upload
+    pub cookies: HashMap<String, String>,  // Cookies from client, if this was an HTTP upload
+    pub priority: JobPriority,
 }
 
 #[derive(Debug, Clone)]
@@ -58,8 +113,8 @@ pub struct DetailedMsg {
 
 
 /// Calculate hash identifier (media_file_id) for the submitted files,
-/// based on filename, user_id, size and sample of the file contents.
-fn calc_media_file_id(file_path: &PathBuf, user_id: &str, upload_cookies: HashMap<String, String>) -> anyhow::Result<String> {
+/// based on filename, user_id, upload cookies and hash of the full file contents.
+fn calc_media_file_id(file_path: &PathBuf, user_id: &str, upload_cookies: HashMap<String, String>, content_hash: &str) -> anyhow::Result<String> {
     let mut file_hash = Sha256::new();
     let fname = file_path.file_name()
         .ok_or(anyhow!("Bad filename: {:?}", file_path))?.to_str()
@@ -80,17 +135,27 @@ fn calc_media_file_id(file_path: &PathBuf, user_id: &str, upload_cookies: HashMa
         }
     }
 
-    // Read max 32k of contents
-    let file = std::fs::File::open(file_path)?;
-    let mut buf = Vec::with_capacity(32*1024);
-    file.take(32768u64).read_to_end(&mut buf)?;
-    file_hash.update(&buf);
+    file_hash.update(content_hash.as_bytes());
 
     let hash = hex::encode(file_hash.finalize());
     assert!(hash.len() >= 8);
     Ok(hash[0..8].to_string())
 }
 
+/// Pick adaptive ladder renditions for a video. Renditions taller than the source
+/// are skipped (except the smallest one), so small videos don't get upscaled duplicates.
+fn adaptive_renditions(src_height: Option<u32>, target_bitrate: u32) -> Vec<ffmpeg_processor::LadderRendition> {
+    let mut res = ADAPTIVE_LADDER.iter()
+        .filter(|(h, _)| src_height.is_none_or(|sh| *h <= sh))
+        .map(|(h, f)| ffmpeg_processor::LadderRendition { height: *h, video_bitrate: (target_bitrate as f32 * f) as u32 })
+        .collect::<Vec<_>>();
+    if res.is_empty() {
+        let (h, f) = ADAPTIVE_LADDER[ADAPTIVE_LADDER.len()-1];
+        res.push(ffmpeg_processor::LadderRendition { height: h, video_bitrate: (target_bitrate as f32 * f) as u32 });
+    }
+    res
+}
+
 /// Process new file after metadata reader has finished.
 /// Move the file to the appropriate directory, and update the database.
 /// See if the file is a duplicate, and submit it for transcoding if necessary.
@@ -100,8 +165,11 @@ fn ingest_media_file(
         data_dir: &Path,
         media_files_dir: &Path,
         target_bitrate: u32,
+        profiles: &TranscodeProfiles,
+        adaptive: AdaptiveStreaming,
         db: &DB,
         user_msg_tx: &crossbeam_channel::Sender<UserMessage>,
+        org_event_tx: &Option<crossbeam_channel::Sender<OrgEvent>>,
         cmpr_tx: &crossbeam_channel::Sender<ffmpeg_processor::CmprInput>)
             -> anyhow::Result<bool>
 {
@@ -155,6 +223,47 @@ fn ingest_media_file(
     }
     assert!(!dir_for_media_file.exists()); // Should have been deleted above
 
+    // Same contents already in the library, maybe under another name or owner?
+    // Tell the uploader before anything is moved, and drop the upload if they asked to skip duplicates.
+    let identical = models::MediaFile::get_by_content_hash(&mut db.conn()?, &md.content_hash)?;
+    if let Some(own) = identical.iter().find(|v| v.user_id == md.user_id) {
+        let own_title = own.title.clone().unwrap_or(own.id.clone());
+        if dedup::skip_duplicates(&md.upload_cookies) {
+            tracing::info!(existing=%own.id, "User already has a media file with identical contents. Skipping upload as requested.");
+            user_msg_tx.send(UserMessage {
+                topic: UserMessageTopic::Ok,
+                msg: format!("Upload skipped: identical file is already in your library, as '{own_title}'"),
+                details: Some(format!("Existing media file ID: {}", own.id)),
+                user_id: Some(md.user_id.clone()),
+                media_file_id: None,  // Don't pass media file id here, otherwise the pre-existing media would be deleted!
+                ..Default::default()
+            }).ok();
+            clean_up_rejected_file(data_dir, &src, Some(media_id.into())).unwrap_or_else(|e| {
+                tracing::error!(details=?e, "Cleanup failed.");
+            });
+            return Ok(false);
+        }
+        tracing::info!(existing=%own.id, "User already has a media file with identical contents. Adding anyway.");
+        user_msg_tx.send(UserMessage {
+            topic: UserMessageTopic::Ok,
+            msg: format!("Note: identical file is already in your library, as '{own_title}'"),
+            details: Some(format!("Existing media file ID: {}", own.id)),
+            user_id: Some(md.user_id.clone()),
+            media_file_id: Some(own.id.clone()),
+            ..Default::default()
+        }).ok();
+    } else if !identical.is_empty() {
+        // Don't reveal whose file it is
+        tracing::info!(existing=%identical[0].id, "Another user has a media file with identical contents. Adding anyway.");
+        user_msg_tx.send(UserMessage {
+            topic: UserMessageTopic::Ok,
+            msg: "Note: another user has already uploaded an identical file".to_string(),
+            details: Some("Your copy is added separately, but its processed files may be reused.".to_string()),
+            user_id: Some(md.user_id.clone()),
+            ..Default::default()
+        }).ok();
+    }
+
     // Move src file to orig/
     tracing::debug!(dir=%dir_for_media_file.display(), "Creating media file dir.");
     std::fs::create_dir(&dir_for_media_file)?;
@@ -169,6 +278,23 @@ fn ingest_media_file(
 
     let orig_filename = src.file_name().ok_or(anyhow!("Bad filename: {:?}", src))?.to_string_lossy().into_owned();
 
+    // Pick transcoding profile for this upload
+    let profile = match profiles.select(&md.upload_cookies) {
+        Ok(p) => p,
+        Err((p, msg)) => {
+            tracing::warn!(details=%msg, "Bad transcoding profile in upload cookies.");
+            user_msg_tx.send(UserMessage {
+                topic: UserMessageTopic::Error,
+                msg,
+                user_id: Some(md.user_id.clone()),
+                media_file_id: Some(media_id.to_string()),
+                ..Default::default()
+            }).ok();
+            p
+        }
+    };
+    tracing::debug!(profile=%profile.name, "Using transcoding profile.");
+
     // Add to DB
     tracing::debug!("Adding media file to DB.");
     models::MediaFile::insert(&mut db.conn()?, &models::MediaFileInsert {
@@ -187,28 +313,29 @@ fn ingest_media_file(
         fps: Some(md.fps.to_string()),
         raw_metadata_all: Some(md.metadata_all.clone()),
         default_subtitle_id: None,
+        adaptive_done: None,
+        adaptive_has_dash: None,
+        content_hash: Some(md.content_hash.clone()),
+        start_timecode: md.start_timecode.clone(),
+        transcode_profile: Some(profile.name.clone()),
+        transcode_bitrate: None,
     })?;
 
-
-    // Check if it needs recompressing
-    fn needs_transcoding(md: &metadata_reader::Metadata, target_max_bitrate: u32) -> Option<(String, u32)> {
-        match md.media_type {
-            metadata_reader::MediaType::Audio => Some(("client cannot playback audio only".to_string(), target_max_bitrate)),
-            metadata_reader::MediaType::Image => Some(("client cannot 'playback' still images".to_string(), target_max_bitrate)),
-            metadata_reader::MediaType::Video => {
-                let new_bitrate = std::cmp::max(md.bitrate/2, std::cmp::min(md.bitrate, target_max_bitrate));
-                let ext = md.src_file.extension().unwrap_or(std::ffi::OsStr::new("")).to_string_lossy().to_lowercase();
-                {
-                    let bitrate_fine = (new_bitrate >= md.bitrate || (md.bitrate as f32) <= 1.2 * (target_max_bitrate as f32));
-                    let codec_fine = ["h264", "avc", "hevc", "h265"].contains(&md.orig_codec.to_lowercase().as_str());
-                    let container_fine = ["mp4", "mkv"].contains(&ext.as_str());
-
-                    if !container_fine { Some(format!("container '{}' not supported", md.src_file.extension().unwrap_or_default().to_string_lossy())) }
-                    else if !codec_fine { Some(format!("codec '{}' not supported", md.orig_codec)) }
-                    else if !bitrate_fine { Some(format!("bitrate is too high: old {} > new {}", md.bitrate, new_bitrate)) }
-                    else { None }
-                }.map(|reason| (reason, new_bitrate))
-            },
+    // Uploaded as a new version of an existing media file? (Permission was checked at upload time.)
+    if let Some(prev_id) = md.upload_cookies.get(VERSION_OF_COOKIE).map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        match models::MediaFileVersion::add_version(&mut db.conn()?, prev_id, media_id) {
+            Ok(v) => tracing::info!(stack_id=%v.stack_id, version=v.version, "Added as a new version."),
+            Err(e) => {
+                tracing::warn!(prev_id, details=%e, "Failed to add media file as a new version.");
+                user_msg_tx.send(UserMessage {
+                    topic: UserMessageTopic::Error,
+                    msg: "Failed to add as a new version".to_string(),
+                    details: Some(format!("Media file was added on its own. {}", e)),
+                    user_id: Some(md.user_id.clone()),
+                    media_file_id: Some(media_id.to_string()),
+                    ..Default::default()
+                }).ok();
+            }
         }
     }
 
@@ -218,14 +345,23 @@ fn ingest_media_file(
         media_type: md.media_type.clone(),
         path: src_moved.clone(),
         duration: md.duration,
+        priority: md.priority,
+        job_id: None,
     };
 
-    let transcode_req = match needs_transcoding(md, target_bitrate) {
+    // Reuse whatever was already processed for an identical file (preferably one made with the same profile)
+    let needs_transcoding = profile.needs_transcoding(md, target_bitrate);
+    let shared = identical.iter().find(|v| v.transcode_profile.as_deref() == Some(profile.name.as_str())).or(identical.first())
+        .map(|donor| dedup::share_artifacts(donor, media_id, &profile.name, needs_transcoding.as_ref().map(|(_, br)| *br), media_files_dir, db))
+        .unwrap_or_default();
+
+    let transcode_req = match needs_transcoding.filter(|_| !shared.transcoded) {
         Some((reason, new_bitrate)) => {
             let video_dst = dir_for_media_file.join(format!("transcoded_br{}_{}.mp4", new_bitrate, uuid::Uuid::new_v4()));
-            cmpr_tx.send(ffmpeg_processor::CmprInput::Transcode {
+            job_queue::submit(db, cmpr_tx, ffmpeg_processor::CmprInput::Transcode {
                 video_dst,
                 video_bitrate: new_bitrate,
+                profile: profile.clone(),
                 src: src.clone()
             }).map(|_| (true, reason)).context("Error sending file to transcoding")
         },
@@ -236,19 +372,13 @@ fn ingest_media_file(
     };
 
     // Also invoke thumbnail generator unless there was a problem with the file
-    if let Ok(_) = &transcode_req {
+    if transcode_req.is_ok() && !shared.thumbs {
         let thumb_dir = dir_for_media_file.join("thumbs");
-        if let Err(e) = cmpr_tx.send(ffmpeg_processor::CmprInput::Thumbs {
+        if let Err(e) = job_queue::submit(db, cmpr_tx, ffmpeg_processor::CmprInput::Thumbs {
             thumb_dir,
             thumb_sheet_dims: (THUMB_SHEET_COLS, THUMB_SHEET_ROWS),
             thumb_size: (THUMB_W, THUMB_H),
-            src: ffmpeg_processor::CmprInputSource {
-                user_id: md.user_id.clone(),
-                media_file_id: media_id.to_string(),
-                media_type: md.media_type.clone(),
-                path: src_moved.clone(),
-                duration: md.duration,
-            }
+            src: src.clone()
         }) {
             tracing::error!(details=?e, "Failed to send file to thumbnailing");
             if let Err(e) = user_msg_tx.send(UserMessage {
@@ -263,6 +393,21 @@ fn ingest_media_file(
         };
     };
 
+    // Also make an adaptive bitrate ladder for videos, if enabled
+    if transcode_req.is_ok() && !shared.adaptive && adaptive != AdaptiveStreaming::Off && matches!(md.media_type, MediaType::Video) {
+        let ladder_dir = dir_for_media_file.join(format!("adaptive_{}", uuid::Uuid::new_v4()));
+        if let Err(e) = job_queue::submit(db, cmpr_tx, ffmpeg_processor::CmprInput::TranscodeLadder {
+            ladder_dir,
+            renditions: adaptive_renditions(md.height, target_bitrate),
+            with_audio: md.has_audio,
+            with_dash: adaptive == AdaptiveStreaming::HlsAndDash,
+            profile: profile.clone(),
+            src: src.clone()
+        }) {
+            tracing::error!(details=?e, "Failed to send file to adaptive ladder transcoding");
+        }
+    }
+
     // Tell user about the processing
     match transcode_req {
         Ok((do_transcode, reason)) => {
@@ -276,11 +421,14 @@ fn ingest_media_file(
                 subtitle_id: None,
                 progress: None,
             })?;
+            org_events::emit(org_event_tx, OrgEvent::MediaFileAdded { media_file_id: media_id.to_string(), upload_cookies: md.upload_cookies.clone() });
             // Tell user in text also
             tracing::debug!(transcode=do_transcode, reason=reason, "Media added to DB. Transcode");
             user_msg_tx.send(UserMessage {
                 topic: UserMessageTopic::Ok,
-                msg: "Media added.".to_string() + if do_transcode {" Transcoding..."} else {""},
+                msg: "Media added.".to_string()
+                    + if shared.any() {" Reusing processed files of an identical upload."} else {""}
+                    + if do_transcode {" Transcoding..."} else {""},
                 details: if do_transcode { Some(format!("Transcoding because {reason}")) } else { None },
                 user_id: Some(md.user_id.clone()),
                 media_file_id: Some(media_id.to_string()),
@@ -306,18 +454,161 @@ fn ingest_media_file(
 }
 
 
+/// Pipeline state that `rerun_processing()` needs to queue new jobs
+struct RerunContext<'a> {
+    media_files_dir: &'a Path,
+    target_bitrate: u32,
+    profiles: &'a TranscodeProfiles,
+    adaptive: AdaptiveStreaming,
+    db: &'a DB,
+    canceller: &'a ffmpeg_processor::JobCanceller,
+    cmpr_tx: &'a crossbeam_channel::Sender<ffmpeg_processor::CmprInput>,
+}
 
+/// Re-queue transcoding, thumbnailing or the adaptive ladder of an already ingested media file, from its original.
+/// Unfinished jobs of the same step are cancelled first, so they don't race with the new one.
+/// Uses the transcoding profile that was picked when the file was uploaded.
+///
+/// # Arguments
+/// * `media_file_id` - Media file to reprocess
+/// * `step` - What to re-run
+/// * `video_bitrate` - Target bitrate for transcoding. None = profile's max bitrate or `target_bitrate`
+///
+/// # Returns
+/// * Message to show to the user
+fn rerun_processing(media_file_id: &str, step: ProcessingStep, video_bitrate: Option<u32>, ctx: &RerunContext) -> anyhow::Result<String>
+{
+    let RerunContext { media_files_dir, target_bitrate, profiles, adaptive, db, canceller, cmpr_tx } = *ctx;
+    let v = models::MediaFile::get(&mut db.conn()?, &media_file_id.into())?;
+    let media_type = v.media_type.as_ref().and_then(|mt| MediaType::from_str(mt).ok())
+        .ok_or(anyhow!("Unknown media type: {:?}", v.media_type))?;
+    let orig_filename = v.orig_filename.as_ref().ok_or(anyhow!("Original filename missing"))?;
+
+    let dir_for_media_file = media_files_dir.join(&v.id);
+    let orig_path = dir_for_media_file.join("orig").join(orig_filename);
+    if !orig_path.is_file() { bail!("Original file not found") }
+
+    let profile = profiles.by_name(v.transcode_profile.as_deref());
+    if v.transcode_profile.as_ref().is_some_and(|n| n != &profile.name) {
+        tracing::warn!(media_file=%v.id, profile=?v.transcode_profile, "Transcoding profile no longer configured. Using default.");
+    }
+
+    let src = ffmpeg_processor::CmprInputSource {
+        user_id: v.user_id.clone(),
+        media_file_id: v.id.clone(),
+        media_type: media_type.clone(),
+        path: orig_path,
+        duration: Decimal::from_f32(v.duration.unwrap_or(0.0)).unwrap_or_default(),
+        priority: JobPriority::Interactive,
+        job_id: None,
+    };
+
+    let (req, msg) = match step {
+        ProcessingStep::Transcode => {
+            let bitrate = video_bitrate.unwrap_or(profile.max_bitrate.unwrap_or(target_bitrate));
+            (ffmpeg_processor::CmprInput::Transcode {
+                video_dst: dir_for_media_file.join(format!("transcoded_br{}_{}.mp4", bitrate, uuid::Uuid::new_v4())),
+                video_bitrate: bitrate,
+                profile: profile.clone(),
+                src,
+            }, format!("Re-transcoding at {:.1} Mbps...", bitrate as f32 / 1_000_000.0))
+        },
+        ProcessingStep::Thumbnails => {
+            (ffmpeg_processor::CmprInput::Thumbs {
+                thumb_dir: dir_for_media_file.join("thumbs"),
+                thumb_sheet_dims: (THUMB_SHEET_COLS, THUMB_SHEET_ROWS),
+                thumb_size: (THUMB_W, THUMB_H),
+                src,
+            }, "Re-generating thumbnails...".to_string())
+        },
+        ProcessingStep::Adaptive => {
+            if adaptive == AdaptiveStreaming::Off { bail!("Adaptive streaming is not enabled on this server") }
+            if !matches!(media_type, MediaType::Video) { bail!("Adaptive streaming is only available for videos") }
+            let (height, has_audio) = metadata_reader::height_and_audio(v.raw_metadata_all.as_deref().unwrap_or_default());
+            (ffmpeg_processor::CmprInput::TranscodeLadder {
+                ladder_dir: dir_for_media_file.join(format!("adaptive_{}", uuid::Uuid::new_v4())),
+                renditions: adaptive_renditions(height, target_bitrate),
+                with_audio: has_audio,
+                with_dash: adaptive == AdaptiveStreaming::HlsAndDash,
+                profile: profile.clone(),
+                src,
+            }, "Re-generating adaptive streams...".to_string())
+        },
+    };
+
+    let n = job_queue::cancel(db, canceller, &v.id, Some(req.job_type()))?;
+    if n > 0 { tracing::info!(media_file=%v.id, cancelled=n, "Cancelled earlier run(s) before re-run."); }
+
+    job_queue::submit(db, cmpr_tx, req)?;
+    Ok(msg)
+}
+
+
+/// Collect comment threads of a media file into a review report, and queue it for the workers.
+/// Still frames are taken from the transcoded video if there is one, otherwise from the original.
+fn queue_review_report(
+        media_file_id: &str,
+        user_id: &str,
+        format: review_report::ReportFormat,
+        media_files_dir: &Path,
+        db: &DB,
+        cmpr_tx: &crossbeam_channel::Sender<ffmpeg_processor::CmprInput>)
+            -> anyhow::Result<String>
+{
+    use crate::database::{DbQueryByMediaFile, DBPaging};
+    let conn = &mut db.conn()?;
+    let v = models::MediaFile::get(conn, &media_file_id.into())?;
+    let comments = models::Comment::get_by_media_file(conn, &v.id, DBPaging::default())?;
+    let media_type = v.media_type.as_ref().and_then(|mt| MediaType::from_str(mt).ok())
+        .ok_or(anyhow!("Unknown media type: {:?}", v.media_type))?;
+
+    let dir_for_media_file = media_files_dir.join(&v.id);
+    let transcoded = dir_for_media_file.join("video.mp4");
+    let src_path = if transcoded.is_file() { transcoded } else {
+        let orig_filename = v.orig_filename.as_ref().ok_or(anyhow!("Original filename missing"))?;
+        dir_for_media_file.join("orig").join(orig_filename)
+    };
+    if !src_path.is_file() && !matches!(media_type, MediaType::Audio) { bail!("Media file not found") }
+
+    let now = chrono::Local::now().naive_local();
+    let reports_dir = dir_for_media_file.join(review_report::REPORTS_DIR);
+    std::fs::create_dir_all(&reports_dir).context("Failed to create reports dir")?;
+
+    let req = ffmpeg_processor::CmprInput::Report {
+        report: review_report::collect(&v, &comments, now),
+        format,
+        report_dst: reports_dir.join(format!("review_{}_{}{}", v.id, now.format("%Y%m%d-%H%M%S"), format.file_suffix())),
+        drawings_dir: dir_for_media_file.join("drawings"),
+        src: ffmpeg_processor::CmprInputSource {
+            user_id: user_id.to_string(),
+            media_file_id: v.id.clone(),
+            media_type,
+            path: src_path,
+            duration: Decimal::from_f32(v.duration.unwrap_or(0.0)).unwrap_or_default(),
+            priority: JobPriority::Interactive,
+            job_id: None,
+        },
+    };
+    job_queue::submit(db, cmpr_tx, req)?;
+    Ok("Generating review report...".to_string())
+}
 
 pub fn run_forever(
     db: Arc<DB>,
     terminate_flag: Arc<AtomicBool>,
     data_dir: PathBuf,
     user_msg_tx: crossbeam_channel::Sender<UserMessage>,
+    org_event_tx: Option<crossbeam_channel::Sender<OrgEvent>>,
     poll_interval: f32,
     resubmit_delay: f32,
     target_bitrate: u32,
+    profiles: TranscodeProfiles,
+    adaptive: AdaptiveStreaming,
     upload_rx: Receiver<IncomingFile>,
-    n_workers: usize)
+    ctl_rx: Receiver<PipelineCmd>,
+    limits: UploadLimits,
+    n_workers: usize,
+    max_jobs_per_user: usize)
 {
     tracing::debug!("Starting media file processing pipeline.");
 
@@ -361,9 +652,13 @@ pub fn run_forever(
     let (cmpr_in_tx, cmpr_in_rx) = unbounded::<ffmpeg_processor::CmprInput>();
     let (cmpr_out_tx, cmpr_out_rx) = unbounded::<ffmpeg_processor::CmprOutput>();
     let (cmpr_prog_tx, cmpr_prog_rx) = unbounded::<(String, String, String, Option<f32>)>();
-    thread::spawn(move || {
-        ffmpeg_processor::run_forever(cmpr_in_rx, cmpr_out_tx, cmpr_prog_tx, n_workers);
-    });
+    let canceller = Arc::new(ffmpeg_processor::JobCanceller::default());
+    {
+        let (canceller, db) = (canceller.clone(), db.clone());
+        thread::spawn(move || {
+            ffmpeg_processor::run_forever(cmpr_in_rx, cmpr_out_tx, cmpr_prog_tx, n_workers, max_jobs_per_user, canceller, db);
+        });
+    }
 
     // Migration from older version: find a media file that is missing thumbnail sheet
     fn legacy_thumbnail_next_media_file(db: &DB, videos_dir: &PathBuf, cmpr_in: &mut crossbeam_channel::Sender<ffmpeg_processor::CmprInput>) -> Option<String> {
@@ -372,7 +667,12 @@ pub fn run_forever(
             .and_then(|mut conn| models::MediaFile::get_all_with_missing_thumbnails(&mut conn))
             .map_err(|e| { tracing::error!(details=?e, "DB: Failed to get media files without thumbnails."); }).ok()?;
 
-        if let Some(v) = candidates.first() {
+        // Skip files that already have a thumbnailing job queued (e.g. resumed after restart)
+        let thumbs_pending = |vid: &str| db.conn()
+            .and_then(|mut conn| models::Job::has_pending(&mut conn, vid, "thumbs"))
+            .unwrap_or(false);
+
+        if let Some(v) = candidates.iter().find(|v| !thumbs_pending(&v.id)) {
             tracing::info!(id=%v.id, "Found legacy media file that needs thumbnailing.");
 
             let media_file_path = if v.recompression_done.is_some() {
@@ -401,9 +701,11 @@ pub fn run_forever(
                             media_type,
                             path: file_path,
                             duration: Decimal::from_f32(v.duration.unwrap_or(0.0)).unwrap_or_default(),
+                            priority: JobPriority::Background,
+                            job_id: None,
                         },
                     };
-                    cmpr_in.send(req).unwrap_or_else(|e| {
+                    job_queue::submit(db, cmpr_in, req).unwrap_or_else(|e| {
                             tracing::error!(details=?e, "Error sending legacy thumbnailing request to compressor.");
                         });
                     return Some(v.id.clone());
@@ -415,6 +717,12 @@ pub fn run_forever(
         }
         None
     }
+    // Resume jobs that were queued or running when the server last stopped
+    if let Err(e) = job_queue::recover(&db).and_then(|_| job_queue::dispatch_due(&db, &cmpr_in_tx)) {
+        tracing::error!(details=?e, "Failed to resume processing jobs from DB.");
+    }
+    let job_retry_ticker = crossbeam_channel::tick(job_queue::RETRY_POLL_INTERVAL);
+
     let mut legacy_media_file_now_thumnailing = legacy_thumbnail_next_media_file(&db, &media_files_dir, &mut cmpr_in_tx.clone());
 
 
@@ -426,8 +734,9 @@ pub fn run_forever(
                 match msg {
                     Ok(msg) => {
                         tracing::debug!("Got upload result. Submitting it for processing. {:?}", msg);
-                        to_md.send(IncomingFile {file_path: msg.file_path.clone(),user_id: msg.user_id, cookies: msg.cookies }).unwrap_or_else(|e| {
+                        to_md.send(IncomingFile {file_path: msg.file_path.clone(),user_id: msg.user_id, cookies: msg.cookies, priority: msg.priority }).unwrap_or_else(|e| {
                                 tracing::error!("Error sending file to metadata reader: {:?}", e);
+                                limits.pending.release(&msg.file_path);
                                 clean_up_rejected_file(&data_dir, &msg.file_path, None).unwrap_or_else(|e| {
                                     tracing::error!("Cleanup of '{:?}' failed: {:?}", &msg.file_path, e);
                                 });
@@ -444,8 +753,22 @@ pub fn run_forever(
                         let (vid, ing_res) = match md_res {
                             MetadataResult::Ok(md) => {
                                 tracing::debug!("Got metadata for {:?}", md.src_file);
-                                match calc_media_file_id(&md.src_file, &md.user_id, md.upload_cookies.clone()) {
+                                // Final check for type and quota (incoming folder files haven't been checked at all yet).
+                                // Upload's reservation is replaced by its actual size here, and by the media file dir after ingest.
+                                limits.pending.release(&md.src_file);
+                                let limits_res = limits.check_type(&md.src_file.file_name().unwrap_or_default().to_string_lossy(), None)
+                                    .and_then(|_| limits.check_quota(&db, &media_files_dir, &md.user_id,
+                                        std::fs::metadata(&md.src_file).map(|m| m.len()).unwrap_or(0)));
+                                match limits_res.map(|_| calc_media_file_id(&md.src_file, &md.user_id, md.upload_cookies.clone(), &md.content_hash)) {
                                     Err(e) => {
+                                        (None, Err(DetailedMsg {
+                                            msg: "Upload rejected".into(),
+                                            details: e.to_string(),
+                                            src_file: md.src_file.clone(),
+                                            user_id: md.user_id.clone(),
+                                        }))
+                                    },
+                                    Ok(Err(e)) => {
                                         (None, Err(DetailedMsg {
                                             msg: "Media file hashing error".into(),
                                             details: e.to_string(),
@@ -453,8 +776,8 @@ pub fn run_forever(
                                             user_id: md.user_id.clone(),
                                         }))
                                     },
-                                    Ok(vid) => {
-                                        let ing_res = ingest_media_file(&vid, &md, &data_dir, &media_files_dir, target_bitrate, &db, &user_msg_tx, &cmpr_in_tx).map_err(|e| {
+                                    Ok(Ok(vid)) => {
+                                        let ing_res = ingest_media_file(&vid, &md, &data_dir, &media_files_dir, target_bitrate, &profiles, adaptive, &db, &user_msg_tx, &org_event_tx, &cmpr_in_tx).map_err(|e| {
                                             DetailedMsg {
                                                 msg: "Media ingestion failed".into(),
                                                 details: e.to_string(),
@@ -465,7 +788,10 @@ pub fn run_forever(
                                     },
                                 }
                             }
-                            MetadataResult::Err(e) => (None, Err(e))
+                            MetadataResult::Err(e) => {
+                                limits.pending.release(&e.src_file);
+                                (None, Err(e))
+                            }
                         };
                         // Relay errors, if any.
                         // No need to send ok message here, variations of it are sent from ingest_media_file().
@@ -476,7 +802,7 @@ pub fn run_forever(
                                     Ok(()) => { "".into() } };
                             user_msg_tx.send(UserMessage {
                                     topic: UserMessageTopic::Error,
-                                    msg: "Error reading media file metadata.".into(),
+                                    msg: format!("{}.", e.msg),
                                     details: Some(format!("'{}': ", e.src_file.file_name().unwrap_or_default().to_string_lossy()) + &e.details + &cleanup_err),
                                     user_id: Some(e.user_id),
                                     media_file_id: vid,
@@ -492,6 +818,8 @@ pub fn run_forever(
             recv(from_mon) -> msg => {
                 match msg {
                     Ok(new_file) => {
+                        // Count it against quota while waiting, like HTTP uploads
+                        limits.pending.reserve(&new_file.file_path, &new_file.user_id, std::fs::metadata(&new_file.file_path).map(|m| m.len()).unwrap_or(0));
                         // Relay to metadata reader
                         to_md.send(new_file).unwrap_or_else(|e| {
                             tracing::error!("FATAL. Error sending file to metadata reader: {:?}", e);
@@ -520,14 +848,61 @@ pub fn run_forever(
                     Err(e) => { tracing::warn!("Transcoder is dead ('{:?}'). Exit.", e); break; },
                 }
             },
+            // Cancel / re-run requests from API server and Organizer
+            recv(ctl_rx) -> msg => {
+                let (media_file_id, user_id, res) = match msg {
+                    Ok(PipelineCmd::Cancel { media_file_id, user_id }) => {
+                        let res = job_queue::cancel(&db, &canceller, &media_file_id, None).map(|n| match n {
+                            0 => "Nothing to cancel; media is not being processed.".to_string(),
+                            n => format!("Processing cancelled ({n} job(s))."),
+                        });
+                        (media_file_id, user_id, res)
+                    },
+                    Ok(PipelineCmd::Rerun { media_file_id, user_id, step, video_bitrate }) => {
+                        let ctx = RerunContext { media_files_dir: &media_files_dir, target_bitrate, profiles: &profiles, adaptive, db: &db, canceller: &canceller, cmpr_tx: &cmpr_in_tx };
+                        let res = rerun_processing(&media_file_id, step, video_bitrate, &ctx);
+                        (media_file_id, user_id, res)
+                    },
+                    Ok(PipelineCmd::Report { media_file_id, user_id, format }) => {
+                        let res = queue_review_report(&media_file_id, &user_id, format, &media_files_dir, &db, &cmpr_in_tx);
+                        (media_file_id, user_id, res)
+                    },
+                    Err(_) => { break; }
+                };
+                let um = match res {
+                    Ok(msg) => UserMessage { topic: UserMessageTopic::Ok, msg, ..Default::default() },
+                    Err(e) => {
+                        tracing::error!(media_file=%media_file_id, details=?e, "Pipeline command failed.");
+                        UserMessage { topic: UserMessageTopic::Error, msg: "Processing request failed".into(), details: Some(e.to_string()), ..Default::default() }
+                    }
+                };
+                user_msg_tx.send(UserMessage { user_id: Some(user_id), media_file_id: Some(media_file_id), ..um })
+                    .unwrap_or_else(|e| { tracing::error!("Error sending user message: {:?}", e); });
+            },
+            // Start queued jobs whose retry backoff has passed
+            recv(job_retry_ticker) -> _ => {
+                if let Err(e) = job_queue::dispatch_due(&db, &cmpr_in_tx) {
+                    tracing::error!(details=?e, "Failed to start queued processing jobs.");
+                }
+            },
             // Transcoder output
             recv(cmpr_out_rx) -> msg => {
-                use ffmpeg_processor::CmprOutput::{TranscodeSuccess, ThumbsSuccess, TranscodeFailure, ThumbsFailure};
+                use ffmpeg_processor::CmprOutput::{TranscodeSuccess, ThumbsSuccess, LadderSuccess, ReportSuccess, TranscodeFailure, ThumbsFailure, LadderFailure, ReportFailure};
+
+                // Update job state in DB (and schedule a retry, if it failed)
+                let outcome = msg.as_ref().ok().map(|res| job_queue::finish(&db, &canceller, res).unwrap_or_else(|e| {
+                    tracing::error!(details=?e, "Failed to update job state in DB.");
+                    job_queue::JobOutcome::Untracked
+                }));
+
                 match msg {
                     Err(e) => { tracing::warn!("Transcoder is dead ('{:?}'). Exit.", e); break; },
+                    Ok(_) if outcome == Some(job_queue::JobOutcome::Cancelled) => {
+                        // Outputs were already removed by job_queue::finish(). User was told when the cancel was requested.
+                    },
                     Ok(res) => match &res {
 
-                        TranscodeSuccess { video_dst, logs } =>
+                        TranscodeSuccess { video_dst, video_bitrate, logs } =>
                         {
                             let videos_dir = media_files_dir.clone();
                             let vid = logs.media_file_id.clone();
@@ -551,6 +926,7 @@ pub fn run_forever(
                             let user_id = logs.dmsg.clone().user_id;
                             let utx = user_msg_tx.clone();
                             let db = db.clone();
+                            let bitrate = *video_bitrate as i32;
                             let linked_ok = (move || {
                                 let vh_dir = videos_dir.join(&vid);
                                 if !vh_dir.exists() {
@@ -563,12 +939,27 @@ pub fn run_forever(
                                     Err(e) => { tracing::error!("{:?}", e); return false; }
                                 };
                                 let symlink_path = vh_dir.join("video.mp4");
-                                if let Err(e) = std::os::unix::fs::symlink(dst_filename, &symlink_path) {
+
+                                // Re-run replaces an earlier transcode
+                                let prev_dst = std::fs::read_link(&symlink_path).ok();
+                                if prev_dst.is_some() {
+                                    if let Err(e) = std::fs::remove_file(&symlink_path) {
+                                        tracing::error!(details=%e, "Failed to remove old symlink {:?}", symlink_path);
+                                        return false;
+                                    }
+                                }
+                                if let Err(e) = std::os::unix::fs::symlink(&dst_filename, &symlink_path) {
                                     tracing::error!(details=%e, "Failed to create symlink {:?} -> {:?}", symlink_path, video_dst);
                                     return false;
                                 }
+                                if let Some(prev) = prev_dst.filter(|p| p.as_os_str() != dst_filename.as_str()) {
+                                    tracing::info!(file=?prev, "Removing previously transcoded file.");
+                                    std::fs::remove_file(vh_dir.join(&prev)).unwrap_or_else(|e| {
+                                        tracing::warn!(details=%e, "Failed to remove old transcoded file {:?}", prev);
+                                    });
+                                }
 
-                                if let Err(e) = db.conn().and_then(|mut conn| models::MediaFile::set_recompressed(&mut conn, &vid)) {
+                                if let Err(e) = db.conn().and_then(|mut conn| models::MediaFile::set_recompressed(&mut conn, &vid, Some(bitrate))) {
                                     tracing::error!(details=%e, "Error marking media file as recompressed in DB");
                                     return false;
                                 } else {
@@ -586,10 +977,16 @@ pub fn run_forever(
                                 true
                             })();
 
+                            org_events::emit(&org_event_tx, OrgEvent::MediaFileProcessed {
+                                media_file_id: logs.media_file_id.clone(),
+                                step: proto::MediaProcessingStep::Transcode,
+                                success: linked_ok,
+                                details: if linked_ok { None } else { Some("Linking or DB update failed".into()) } });
+
                             // Send success message
                             user_msg_tx.send(UserMessage {
                                     topic: if linked_ok {UserMessageTopic::Ok} else {UserMessageTopic::Error},
-                                    msg: "Media transcoded.".to_string() + if linked_ok {""} else {" But linking or DB failed."},
+                                    msg: MEDIA_TRANSCODED_MSG.to_string() + if linked_ok {""} else {" But linking or DB failed."},
                                     details: None,
                                     user_id: Some(logs.dmsg.user_id.clone()),
                                     media_file_id: Some(logs.media_file_id.clone()),
@@ -655,15 +1052,117 @@ pub fn run_forever(
                                     tracing::error!(details=%e, "Error storing thumbs_done in DB");
                                 }
                             }
+                            org_events::emit(&org_event_tx, OrgEvent::MediaFileProcessed {
+                                media_file_id: vid,
+                                step: proto::MediaProcessingStep::Thumbnails,
+                                success: !db_errors,
+                                details: if db_errors { Some("DB update failed".into()) } else { None } });
+                        },
+
+                        LadderSuccess { ladder_dir, has_dash, logs } =>
+                        {
+                            let vid = logs.media_file_id.clone();
+
+                            // Write out stdout/stderr to separate files
+                            for (name, data) in [("stdout", &logs.stdout), ("stderr", &logs.stderr)].iter() {
+                                let path = ladder_dir.join(format!("{}.txt", name));
+                                tracing::debug!(media_file=%vid, file=?path, "Writing {} from adaptive transcoder", name);
+                                if let Err(e) = std::fs::write(&path, data) {
+                                    tracing::error!(file=?path, details=%e, "Error writing {:?}", name);
+                                }}
+
+                            // Symlink `adaptive` to the ladder dir, and mark it done in DB
+                            let linked_ok = (|| {
+                                let dir_name = match ladder_dir.file_name() {
+                                    Some(d) => d.to_owned(),
+                                    None => { tracing::error!("Bad adaptive ladder dir {:?}", ladder_dir); return false; }
+                                };
+                                let symlink_path = media_files_dir.join(&vid).join("adaptive");
+
+                                // Re-run (or retry) replaces an earlier ladder
+                                let prev_dir = std::fs::read_link(&symlink_path).ok();
+                                if prev_dir.is_some() {
+                                    if let Err(e) = std::fs::remove_file(&symlink_path) {
+                                        tracing::error!(details=%e, "Failed to remove old symlink {:?}", symlink_path);
+                                        return false;
+                                    }
+                                }
+                                if let Err(e) = std::os::unix::fs::symlink(&dir_name, &symlink_path) {
+                                    tracing::error!(details=%e, "Failed to create symlink {:?} -> {:?}", symlink_path, ladder_dir);
+                                    return false;
+                                }
+                                if let Some(prev) = prev_dir.filter(|p| p.as_os_str() != dir_name) {
+                                    tracing::info!(dir=?prev, "Removing previous adaptive ladder.");
+                                    std::fs::remove_dir_all(media_files_dir.join(&vid).join(&prev)).unwrap_or_else(|e| {
+                                        tracing::warn!(details=%e, "Failed to remove old adaptive ladder {:?}", prev);
+                                    });
+                                }
+                                if let Err(e) = db.conn().and_then(|mut conn| models::MediaFile::set_adaptive_done(&mut conn, &vid, *has_dash)) {
+                                    tracing::error!(details=%e, "Error marking adaptive streams done in DB");
+                                    return false;
+                                }
+                                user_msg_tx.send(UserMessage {
+                                    topic: UserMessageTopic::MediaFileUpdated,
+                                    msg: "Adaptive streams ready".into(),
+                                    user_id: Some(logs.user_id.clone()),
+                                    media_file_id: Some(vid.clone()),
+                                    ..Default::default()
+                                }).ok();
+                                true
+                            })();
+
+                            user_msg_tx.send(UserMessage {
+                                    topic: if linked_ok {UserMessageTopic::Ok} else {UserMessageTopic::Error},
+                                    msg: "Adaptive streams transcoded.".to_string() + if linked_ok {""} else {" But linking or DB failed."},
+                                    user_id: Some(logs.dmsg.user_id.clone()),
+                                    media_file_id: Some(vid),
+                                    progress: Some(1.0),
+                                    ..Default::default()
+                                }).unwrap_or_else(|e| { tracing::error!(details=%e, "Error sending user message"); });
+                        },
+
+                        ReportSuccess { report_dst, logs } =>
+                        {
+                            // Path under `/videos`, which checks the user's access to the media file
+                            let url = format!("/videos/{}/{}/{}", logs.media_file_id, review_report::REPORTS_DIR,
+                                report_dst.file_name().unwrap_or_default().to_string_lossy());
+                            user_msg_tx.send(UserMessage {
+                                    topic: UserMessageTopic::Ok,
+                                    msg: "Review report ready.".into(),
+                                    details: Some(url),
+                                    user_id: Some(logs.user_id.clone()),
+                                    media_file_id: Some(logs.media_file_id.clone()),
+                                    ..Default::default()
+                                }).unwrap_or_else(|e| { tracing::error!(details=%e, "Error sending user message"); });
                         },
 
                         TranscodeFailure { logs, .. } |
-                        ThumbsFailure { logs } =>
+                        ThumbsFailure { logs } |
+                        LadderFailure { logs } |
+                        ReportFailure { logs } =>
                         {
-                            let op = if matches!(&res, TranscodeFailure {..}) { "transcoding" } else { "thumbnailing" };
-                            let msg = format!("Media {op} failed");
+                            let op = match &res {
+                                TranscodeFailure {..} => "Media transcoding",
+                                LadderFailure {..} => "Adaptive stream transcoding",
+                                ReportFailure {..} => "Review report",
+                                _ => "Media thumbnailing" };
+                            let msg = match outcome {
+                                Some(job_queue::JobOutcome::Retry(secs)) => format!("{op} failed. Retrying in {secs} seconds."),
+                                _ => format!("{op} failed"),
+                            };
                             let logs = logs.clone();
                             tracing::error!(video=logs.media_file_id, details=?logs.dmsg, msg);
+                            let step = match &res {
+                                TranscodeFailure {..} => Some(proto::MediaProcessingStep::Transcode),
+                                ThumbsFailure {..} => Some(proto::MediaProcessingStep::Thumbnails),
+                                _ => None };
+                            if let (Some(step), false) = (step, matches!(outcome, Some(job_queue::JobOutcome::Retry(_)))) {
+                                org_events::emit(&org_event_tx, OrgEvent::MediaFileProcessed {
+                                    media_file_id: logs.media_file_id.clone(),
+                                    step,
+                                    success: false,
+                                    details: Some(logs.dmsg.details.clone()) });
+                            }
                             user_msg_tx.send(UserMessage {
                                     topic: UserMessageTopic::Error,
                                     msg: msg,
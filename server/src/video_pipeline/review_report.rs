@@ -0,0 +1,322 @@
+// Printable review summary of a media file: every comment thread with its timecode, author and replies,
+// a still frame at the thread's timecode, and the comment's drawing overlaid on it.
+// The pipeline collects the report from DB (`collect()`), and a worker renders it (`ffmpeg_processor::run_report`):
+// stills are extracted from the transcoded video and drawings converted to PNG with FFMpeg, and the
+// result is written either as an HTML bundle (.tar.gz with `index.html` and images) or as a PDF.
+// PDFs are printed from the same HTML with `wkhtmltopdf`, which must be installed on the server.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::Context;
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::api_server::comment_export::{self, xml_escape};
+use crate::timecode::FrameRate;
+use crate::database::models;
+
+/// Subdirectory of the media file dir for finished reports. Served (with access control) by `/videos`.
+pub const REPORTS_DIR: &str = "reports";
+
+/// Width of still frames in the report, in pixels
+pub const REPORT_FRAME_W: u32 = 640;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReportFormat {
+    Html,   // .tar.gz bundle
+    Pdf,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "html" | "htm" => Ok(ReportFormat::Html),
+            "pdf" => Ok(ReportFormat::Pdf),
+            _ => anyhow::bail!("Unknown report format '{}'. Use html or pdf.", s),
+        }
+    }
+}
+
+impl ReportFormat {
+    pub fn file_suffix(&self) -> &'static str {
+        match self {
+            ReportFormat::Html => ".tar.gz",
+            ReportFormat::Pdf => ".pdf",
+        }
+    }
+}
+
+/// Comment threads of a media file, ready for rendering.
+/// Serializable, as it is part of the worker job (`CmprInput::Report`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReviewReport {
+    pub media_file_id: String,
+    pub title: String,
+    pub generated: String,
+    pub threads: Vec<ReportThread>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportThread {
+    pub timecode: Option<String>,
+    pub seconds: Option<f64>,           // Where to take the still frame from, if any
+    pub comments: Vec<ReportComment>,   // Top-level comment first, then replies (oldest first)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReportComment {
+    pub author: String,
+    pub created: String,
+    pub text: String,
+    pub drawing: Option<String>,        // Filename in the media file's `drawings/` dir
+}
+
+/// Images available to the HTML, relative to the bundle root
+#[derive(Debug, Default)]
+pub struct ReportAssets {
+    pub frames: HashMap<usize, String>,     // Thread index -> still frame
+    pub drawings: HashMap<String, String>,  // Drawing filename (from DB) -> converted image
+}
+
+
+/// Gather a media file's comment threads into a report, in timecode order.
+///
+/// # Arguments
+/// * `mf` - Media file (for title, type and frame rate)
+/// * `comments` - All comments of the media file
+/// * `generated` - Report creation time, to print on it
+pub fn collect(mf: &models::MediaFile, comments: &[models::Comment], generated: chrono::NaiveDateTime) -> ReviewReport {
+    let rate = FrameRate::of(mf);
+    let media_type = mf.media_type.as_deref().unwrap_or("video");
+    let report_comment = |c: &models::Comment| ReportComment {
+        author: comment_export::author(c).to_string(),
+        created: c.created.format("%Y-%m-%d %H:%M").to_string(),
+        text: c.comment.clone(),
+        drawing: c.drawing.clone().filter(|d| is_plain_filename(d)),
+    };
+
+    let threads = comment_export::build_threads(comments, &rate).iter().map(|t| ReportThread {
+        timecode: t.frame.map(|f| rate.client_timecode(f)),
+        seconds: match media_type {
+            "audio" => None,
+            "image" => Some(0.0),
+            _ => t.frame.map(|f| rate.seconds(f)),
+        },
+        comments: std::iter::once(t.comment).chain(t.replies.iter().copied()).map(report_comment).collect(),
+    }).collect();
+
+    ReviewReport {
+        media_file_id: mf.id.clone(),
+        title: comment_export::media_title(mf),
+        generated: generated.format("%Y-%m-%d %H:%M").to_string(),
+        threads,
+    }
+}
+
+/// Drawing names come from DB, but make sure they can't point outside the drawings dir
+fn is_plain_filename(name: &str) -> bool {
+    !name.is_empty() && Path::new(name).file_name().is_some_and(|f| f == name) && name != ".."
+}
+
+/// Temporary directory for assembling a report, next to its final destination
+pub fn work_dir(report_dst: &Path) -> PathBuf {
+    let name = report_dst.file_name().unwrap_or_default().to_string_lossy();
+    report_dst.with_file_name(format!("{}.tmp", name))
+}
+
+/// Render the report as a standalone HTML page. Images are referenced relative to the page,
+/// and left out if they are missing from `assets` (e.g. frame extraction failed).
+pub fn render_html(report: &ReviewReport, assets: &ReportAssets) -> String {
+    let n_comments: usize = report.threads.iter().map(|t| t.comments.len()).sum();
+    let mut out = format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Review: {title}</title>
+<style>
+body {{ font-family: sans-serif; font-size: 11pt; color: #222; margin: 2em; }}
+h1 {{ font-size: 16pt; margin-bottom: 0.2em; }}
+.meta {{ color: #666; margin-bottom: 1.5em; }}
+.thread {{ page-break-inside: avoid; border-top: 1px solid #ccc; padding: 0.8em 0; overflow: hidden; }}
+.still {{ float: left; position: relative; width: {frame_w}px; margin: 0 1em 0.5em 0; background: #eee; }}
+.still img {{ display: block; width: 100%; }}
+.still img.overlay {{ position: absolute; top: 0; left: 0; height: 100%; }}
+.tc {{ font-family: monospace; font-weight: bold; font-size: 12pt; margin-bottom: 0.4em; }}
+.comment {{ margin-bottom: 0.6em; }}
+.reply {{ margin-left: 1.5em; }}
+.author {{ font-weight: bold; }}
+.when {{ color: #888; font-size: 9pt; }}
+.text {{ white-space: pre-wrap; }}
+img.drawing {{ display: block; width: 160px; margin-top: 0.3em; background: #eee; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<div class="meta">Review report generated {generated} &middot; {n_threads} threads, {n_comments} comments</div>
+"#,
+        title = xml_escape(&report.title),
+        frame_w = REPORT_FRAME_W / 2,
+        generated = xml_escape(&report.generated),
+        n_threads = report.threads.len());
+
+    let drawing_src = |c: &ReportComment| c.drawing.as_ref().and_then(|d| assets.drawings.get(d));
+
+    for (i, t) in report.threads.iter().enumerate() {
+        out += "<div class=\"thread\">\n";
+
+        // Still frame with the top-level comment's drawing on top (or just the drawing, if there's no frame)
+        let first_drawing = t.comments.first().and_then(drawing_src);
+        match (assets.frames.get(&i), first_drawing) {
+            (Some(frame), drw) => {
+                out += &format!("<div class=\"still\"><img src=\"{}\" alt=\"\">", xml_escape(frame));
+                if let Some(d) = drw {
+                    out += &format!("<img class=\"overlay\" src=\"{}\" alt=\"\">", xml_escape(d));
+                }
+                out += "</div>\n";
+            },
+            (None, Some(d)) => out += &format!("<div class=\"still\"><img src=\"{}\" alt=\"\"></div>\n", xml_escape(d)),
+            (None, None) => {},
+        }
+
+        out += &format!("<div class=\"tc\">{}</div>\n", xml_escape(t.timecode.as_deref().unwrap_or("No timecode")));
+        for (n, c) in t.comments.iter().enumerate() {
+            out += &format!("<div class=\"comment{}\"><span class=\"author\">{}</span> <span class=\"when\">{}</span><div class=\"text\">{}</div>",
+                if n > 0 { " reply" } else { "" },
+                xml_escape(&c.author), xml_escape(&c.created), xml_escape(&c.text));
+            if let Some(d) = drawing_src(c).filter(|_| n > 0) {
+                out += &format!("<img class=\"drawing\" src=\"{}\" alt=\"\">", xml_escape(d));
+            }
+            out += "</div>\n";
+        }
+        out += "</div>\n";
+    }
+    if report.threads.is_empty() {
+        out += "<p>No comments.</p>\n";
+    }
+    out += "</body>\n</html>\n";
+    out
+}
+
+/// Pack an assembled report directory into a .tar.gz, with everything under a top-level folder
+/// named after the archive (so it extracts cleanly).
+pub fn pack_bundle(src_dir: &Path, dst: &Path) -> anyhow::Result<()> {
+    let name = dst.file_name().context("Bad report path")?.to_string_lossy();
+    let prefix = name.trim_end_matches(ReportFormat::Html.file_suffix());
+
+    let gzip_writer = GzEncoder::new(File::create(dst).context("Error creating report file")?, Compression::default());
+    let mut tar_builder = tar::Builder::new(gzip_writer);
+    tar_builder.append_dir_all(prefix, src_dir).context("Error adding report files to archive")?;
+    tar_builder.into_inner().context("Error finishing report archive")?
+        .finish().context("Error finishing report archive")?;
+    Ok(())
+}
+
+/// Filenames of all drawings referenced in the report
+pub fn drawing_names(report: &ReviewReport) -> HashSet<&str> {
+    report.threads.iter().flat_map(|t| t.comments.iter()).filter_map(|c| c.drawing.as_deref()).collect()
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::{make_test_comment, make_test_media_file};
+
+    fn mf(media_type: &str) -> models::MediaFile {
+        models::MediaFile {
+            media_type: Some(media_type.into()),
+            orig_filename: Some("cut3.mov".into()),
+            title: Some("Cut <3>".into()),
+            total_frames: Some(1000),
+            fps: Some("25".into()),
+            ..make_test_media_file()
+        }
+    }
+
+    fn comment(id: i32, parent_id: Option<i32>, tc: Option<&str>, drawing: Option<&str>, text: &str) -> models::Comment {
+        models::Comment {
+            parent_id,
+            username_ifnull: "Alice".into(),
+            comment: text.into(),
+            timecode: tc.map(|s| s.into()),
+            drawing: drawing.map(|s| s.into()),
+            ..make_test_comment(id)
+        }
+    }
+
+    fn sample_comments() -> Vec<models::Comment> {
+        vec![
+            comment(1, None, Some("00:00:10:00"), Some("abc.webp"), "Too dark"),
+            comment(2, Some(1), None, Some("../../etc/passwd"), "Fixed <b>"),
+            comment(3, None, Some("00:00:02:05"), None, "Jump cut"),
+            comment(4, None, None, None, "General note"),
+        ]
+    }
+
+    #[test]
+    fn test_collect() {
+        let now = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc();
+        let r = collect(&mf("video"), &sample_comments(), now);
+        assert_eq!(r.title, "Cut <3>");
+        assert_eq!(r.threads.len(), 3);
+        assert_eq!(r.threads[0].timecode.as_deref(), Some("00:00:02:05"));
+        assert_eq!(r.threads[0].seconds, Some(2.2));
+        assert_eq!(r.threads[1].seconds, Some(10.0));
+        assert_eq!(r.threads[1].comments.len(), 2);
+        assert_eq!(r.threads[1].comments[0].drawing.as_deref(), Some("abc.webp"));
+        assert_eq!(r.threads[1].comments[1].drawing, None);     // Path traversal dropped
+        assert_eq!(r.threads[2].timecode, None);
+        assert_eq!(r.threads[2].seconds, None);
+        assert_eq!(drawing_names(&r), HashSet::from(["abc.webp"]));
+
+        // Images have a single still for all threads, audio none
+        assert!(collect(&mf("image"), &sample_comments(), now).threads.iter().all(|t| t.seconds == Some(0.0)));
+        assert!(collect(&mf("audio"), &sample_comments(), now).threads.iter().all(|t| t.seconds.is_none()));
+    }
+
+    #[test]
+    fn test_render_html() {
+        let now = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc();
+        let r = collect(&mf("video"), &sample_comments(), now);
+        let assets = ReportAssets {
+            frames: HashMap::from([(1, "frames/0001.jpg".to_string())]),
+            drawings: HashMap::from([("abc.webp".to_string(), "drawings/abc.png".to_string())]),
+        };
+        let html = render_html(&r, &assets);
+        assert!(html.contains("<title>Review: Cut &lt;3&gt;</title>"));
+        assert!(html.contains("3 threads, 4 comments"));
+        assert!(html.contains("<img src=\"frames/0001.jpg\" alt=\"\"><img class=\"overlay\" src=\"drawings/abc.png\" alt=\"\">"));
+        assert!(html.contains("Fixed &lt;b&gt;"));
+        assert!(html.contains("No timecode"));
+        assert_eq!(html.matches("<img").count(), 2);
+        assert!(html.find("Jump cut").unwrap() < html.find("Too dark").unwrap());
+
+        // Drawing without a still frame is shown by itself
+        let html = render_html(&r, &ReportAssets { frames: HashMap::new(), drawings: assets.drawings });
+        assert!(html.contains("<div class=\"still\"><img src=\"drawings/abc.png\" alt=\"\"></div>"));
+    }
+
+    #[test]
+    fn test_pack_bundle() {
+        let tmp = assert_fs::TempDir::new().unwrap();
+        let dst = tmp.path().join("review_B1DE0.tar.gz");
+        let src = work_dir(&dst);
+        std::fs::create_dir_all(src.join("frames")).unwrap();
+        std::fs::write(src.join("index.html"), "<html></html>").unwrap();
+        std::fs::write(src.join("frames").join("0001.jpg"), "jpg").unwrap();
+        pack_bundle(&src, &dst).unwrap();
+
+        let mut tar = tar::Archive::new(flate2::read::GzDecoder::new(File::open(&dst).unwrap()));
+        let names: HashSet<String> = tar.entries().unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().trim_end_matches('/').to_string())
+            .collect();
+        assert!(names.contains("review_B1DE0/index.html"));
+        assert!(names.contains("review_B1DE0/frames/0001.jpg"));
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+
+use super::metadata_reader::{Metadata, MediaType};
+
+/// Upload cookie that selects a transcoding profile by name.
+/// Organizers can set it on the client with `client_set_cookies`, e.g. per folder.
+pub const PROFILE_COOKIE_NAME: &str = "transcode_profile";
+
+/// Placeholders that can be used in `video_args` templates
+const TEMPLATE_VARS: [&str; 8] = ["video_codec", "video_bitrate", "audio_codec", "audio_bitrate", "audio_channels", "max_width", "max_height", "scale_filter"];
+
+
+/// Rules for deciding if a media file needs transcoding, and the FFMpeg options to do it with.
+/// Missing fields in a config file get the same values as the built-in default profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TranscodeProfile {
+    pub name: String,
+
+    pub accepted_codecs: Vec<String>,       // Video codecs (as reported by mediainfo) that clients can play as-is
+    pub accepted_containers: Vec<String>,   // File extensions that clients can play as-is
+    pub max_bitrate: Option<u32>,           // Bitrate ceiling in bps. None = use server's `--bitrate`
+    pub bitrate_tolerance: f32,             // Don't transcode if bitrate is at most this many times the ceiling
+    pub max_width: u32,
+    pub max_height: Option<u32>,            // Transcode (and scale down) taller videos. None = no limit
+
+    pub video_codec: String,
+    pub audio_codec: String,
+    pub audio_bitrate: u32,
+    pub audio_channels: u32,
+    pub audio_only_bitrate: u32,            // For audio-only files (waveform video + audio)
+    pub audio_only_channels: Option<u32>,   // None = keep source channel layout
+
+    /// FFMpeg options for transcoding videos (between input and output file).
+    /// May contain placeholders like `{video_bitrate}`, see TEMPLATE_VARS.
+    pub video_args: Vec<String>,
+}
+
+impl Default for TranscodeProfile {
+    fn default() -> Self {
+        let strs = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        TranscodeProfile {
+            name: "default".into(),
+            accepted_codecs: strs(&["h264", "avc", "hevc", "h265"]),
+            accepted_containers: strs(&["mp4", "mkv"]),
+            max_bitrate: None,
+            bitrate_tolerance: 1.2,
+            max_width: 1920,
+            max_height: None,
+            video_codec: "libx264".into(),
+            audio_codec: "aac".into(),
+            audio_bitrate: 128000,
+            audio_channels: 2,
+            audio_only_bitrate: 384000,
+            audio_only_channels: None,
+            video_args: strs(&[
+                "-map", "0",
+                "-dn",
+                "-vcodec", "{video_codec}",
+                "-vf", "{scale_filter}",
+                "-preset", "faster",
+                "-acodec", "{audio_codec}",
+                "-ac", "{audio_channels}",
+                "-strict", "experimental",
+                "-b:v", "{video_bitrate}",
+                "-b:a", "{audio_bitrate}",
+            ]),
+        }
+    }
+}
+
+impl TranscodeProfile {
+
+    /// Check if media file needs transcoding under this profile.
+    ///
+    /// # Arguments
+    /// * `md` - Metadata of the media file
+    /// * `default_max_bitrate` - Bitrate ceiling to use if profile doesn't set one
+    ///
+    /// # Returns
+    /// * None if media can be played as-is, otherwise (reason, new video bitrate)
+    pub fn needs_transcoding(&self, md: &Metadata, default_max_bitrate: u32) -> Option<(String, u32)> {
+        let target_max_bitrate = self.max_bitrate.unwrap_or(default_max_bitrate);
+        match md.media_type {
+            MediaType::Audio => Some(("client cannot playback audio only".to_string(), target_max_bitrate)),
+            MediaType::Image => Some(("client cannot 'playback' still images".to_string(), target_max_bitrate)),
+            MediaType::Video => {
+                let new_bitrate = std::cmp::max(md.bitrate/2, std::cmp::min(md.bitrate, target_max_bitrate));
+                let ext = md.src_file.extension().unwrap_or(std::ffi::OsStr::new("")).to_string_lossy().to_lowercase();
+                {
+                    let bitrate_fine = new_bitrate >= md.bitrate || (md.bitrate as f32) <= self.bitrate_tolerance * (target_max_bitrate as f32);
+                    let codec_fine = self.accepted_codecs.iter().any(|c| c.eq_ignore_ascii_case(&md.orig_codec));
+                    let container_fine = self.accepted_containers.iter().any(|c| c.eq_ignore_ascii_case(&ext));
+                    let size_fine = match (md.height, self.max_height) { (Some(h), Some(max_h)) => h <= max_h, _ => true };
+
+                    if !container_fine { Some(format!("container '{}' not supported", md.src_file.extension().unwrap_or_default().to_string_lossy())) }
+                    else if !codec_fine { Some(format!("codec '{}' not supported", md.orig_codec)) }
+                    else if !bitrate_fine { Some(format!("bitrate is too high: old {} > new {}", md.bitrate, new_bitrate)) }
+                    else if !size_fine { Some(format!("resolution is too high: {} > {} lines", md.height.unwrap_or_default(), self.max_height.unwrap_or_default())) }
+                    else { None }
+                }.map(|reason| (reason, new_bitrate))
+            },
+        }
+    }
+
+    /// FFMpeg scale filter for `{scale_filter}`. Without `max_height`, just scales to `max_width`
+    /// (the traditional `scale=1920:-8`), otherwise fits in both limits without upscaling.
+    fn scale_filter(&self) -> String {
+        match self.max_height {
+            None => format!("scale={}:-8", self.max_width),
+            Some(h) => format!("scale=w='min({},iw)':h='min({},ih)':force_original_aspect_ratio=decrease:force_divisible_by=2", self.max_width, h),
+        }
+    }
+
+    /// Expand `video_args` template into FFMpeg options
+    pub fn expand_video_args(&self, video_bitrate: u32) -> Vec<String> {
+        let vars: HashMap<&str, String> = HashMap::from([
+            ("video_codec", self.video_codec.clone()),
+            ("video_bitrate", video_bitrate.to_string()),
+            ("audio_codec", self.audio_codec.clone()),
+            ("audio_bitrate", self.audio_bitrate.to_string()),
+            ("audio_channels", self.audio_channels.to_string()),
+            ("max_width", self.max_width.to_string()),
+            ("max_height", self.max_height.map(|h| h.to_string()).unwrap_or("ih".into())),
+            ("scale_filter", self.scale_filter()),
+        ]);
+        self.video_args.iter().map(|a| {
+            vars.iter().fold(a.clone(), |acc, (k, v)| acc.replace(&format!("{{{k}}}"), v))
+        }).collect()
+    }
+
+    /// FFMpeg audio options for transcoding audio-only files
+    pub fn audio_only_args(&self) -> Vec<String> {
+        let mut args = vec!["-acodec".to_string(), self.audio_codec.clone()];
+        if let Some(ch) = self.audio_only_channels {
+            args.extend(["-ac".into(), ch.to_string()]);
+        }
+        args.extend(["-b:a".into(), self.audio_only_bitrate.to_string()]);
+        args
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() { bail!("Profile name is empty"); }
+        if self.video_args.is_empty() { bail!("Profile '{}' has no video_args", self.name); }
+        if self.max_width == 0 || self.max_height == Some(0) { bail!("Profile '{}' has zero max_width or max_height", self.name); }
+        let placeholder_re = regex::Regex::new(r"\{([a-z_]+)\}").expect("bad regex");
+        for arg in &self.video_args {
+            for cap in placeholder_re.captures_iter(arg) {
+                if !TEMPLATE_VARS.contains(&&cap[1]) {
+                    bail!("Profile '{}' uses unknown placeholder '{{{}}}' in video_args", self.name, &cap[1]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Config file format for transcoding profiles (JSON)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesConfig {
+    default: String,
+    profiles: Vec<TranscodeProfile>,
+}
+
+/// Set of transcoding profiles, one of which is the default
+#[derive(Debug, Clone)]
+pub struct TranscodeProfiles {
+    profiles: Vec<TranscodeProfile>,
+    default_idx: usize,
+}
+
+impl Default for TranscodeProfiles {
+    fn default() -> Self {
+        TranscodeProfiles { profiles: vec![TranscodeProfile::default()], default_idx: 0 }
+    }
+}
+
+impl TranscodeProfiles {
+
+    /// Load profiles from a JSON config file. Example:
+    ///
+    /// ```json
+    /// {
+    ///   "default": "dailies",
+    ///   "profiles": [
+    ///     { "name": "dailies", "max_bitrate": 1500000, "max_height": 720 },
+    ///     { "name": "archival", "accepted_codecs": ["prores"], "accepted_containers": ["mov"], "bitrate_tolerance": 1000 }
+    ///   ]
+    /// }
+    /// ```
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read transcoding profiles from {:?}", path))?;
+        Self::from_json(&json).with_context(|| format!("Bad transcoding profiles file {:?}", path))
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let cfg: ProfilesConfig = serde_json::from_str(json)?;
+        for (i, p) in cfg.profiles.iter().enumerate() {
+            p.validate()?;
+            if cfg.profiles[..i].iter().any(|o| o.name == p.name) {
+                bail!("Duplicate transcoding profile name '{}'", p.name);
+            }
+        }
+        let default_idx = cfg.profiles.iter().position(|p| p.name == cfg.default)
+            .ok_or(anyhow!("Default transcoding profile '{}' not found", cfg.default))?;
+        Ok(TranscodeProfiles { profiles: cfg.profiles, default_idx })
+    }
+
+    pub fn default_profile(&self) -> &TranscodeProfile {
+        &self.profiles[self.default_idx]
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.profiles.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    /// Find profile by name. Falls back to the default profile if name is None
+    /// or no longer configured (e.g. profile was removed from the config file).
+    pub fn by_name(&self, name: Option<&str>) -> &TranscodeProfile {
+        name.and_then(|n| self.profiles.iter().find(|p| p.name == n)).unwrap_or(self.default_profile())
+    }
+
+    /// Pick profile for an upload, based on its cookies.
+    ///
+    /// # Returns
+    /// * Err(default profile, error message) if the cookie names an unknown profile
+    pub fn select(&self, upload_cookies: &HashMap<String, String>) -> Result<&TranscodeProfile, (&TranscodeProfile, String)> {
+        match upload_cookies.get(PROFILE_COOKIE_NAME).map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            None => Ok(self.default_profile()),
+            Some(name) => self.profiles.iter().find(|p| p.name == name).ok_or_else(|| {
+                (self.default_profile(), format!("Unknown transcoding profile '{}'. Using '{}'.", name, self.default_profile().name))
+            }),
+        }
+    }
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+fn test_metadata(codec: &str, file: &str, bitrate: u32, height: u32) -> Metadata
+{
+    Metadata {
+        src_file: file.into(),
+        user_id: "test_user".into(),
+        total_frames: 100,
+        duration: rust_decimal::Decimal::from(5),
+        media_type: MediaType::Video,
+        orig_codec: codec.into(),
+        fps: rust_decimal::Decimal::from(25),
+        bitrate,
+        height: Some(height),
+        has_audio: true,
+        metadata_all: "{}".into(),
+        upload_cookies: HashMap::new(),
+        priority: Default::default(),
+        content_hash: String::new(),
+        start_timecode: None,
+    }
+}
+
+#[test]
+fn test_default_profile_rules()
+{
+    let p = TranscodeProfile::default();
+    assert!(p.needs_transcoding(&test_metadata("AVC", "a.mp4", 2_000_000, 1080), 2_500_000).is_none());
+    assert!(p.needs_transcoding(&test_metadata("AVC", "a.mp4", 2_900_000, 1080), 2_500_000).is_none());   // within 1.2x
+    assert!(p.needs_transcoding(&test_metadata("AVC", "a.mp4", 9_000_000, 1080), 2_500_000).unwrap().0.contains("bitrate"));
+    assert!(p.needs_transcoding(&test_metadata("ProRes", "a.mov", 2_000_000, 1080), 2_500_000).unwrap().0.contains("container"));
+    assert!(p.needs_transcoding(&test_metadata("ProRes", "a.mkv", 2_000_000, 1080), 2_500_000).unwrap().0.contains("codec"));
+
+    // No resolution limit by default (4K plays as-is), same scaling as before profiles existed
+    assert!(p.needs_transcoding(&test_metadata("AVC", "a.mp4", 2_000_000, 2160), 2_500_000).is_none());
+    assert!(p.expand_video_args(2_500_000).windows(2).any(|w| w == ["-vf", "scale=1920:-8"]));
+
+    // Audio-only files keep the traditional high bitrate and source channel layout
+    assert_eq!(p.audio_only_args(), vec!["-acodec", "aac", "-b:a", "384000"]);
+    let mono = TranscodeProfile { audio_only_bitrate: 96000, audio_only_channels: Some(1), ..TranscodeProfile::default() };
+    assert_eq!(mono.audio_only_args(), vec!["-acodec", "aac", "-ac", "1", "-b:a", "96000"]);
+}
+
+#[test]
+fn test_profiles_from_json()
+{
+    let profiles = TranscodeProfiles::from_json(r#"{
+        "default": "dailies",
+        "profiles": [
+            { "name": "dailies", "max_bitrate": 1000000, "max_height": 720 },
+            { "name": "archival", "accepted_codecs": ["ProRes"], "accepted_containers": ["mov"], "bitrate_tolerance": 1000 }
+        ]}"#).unwrap();
+    assert_eq!(profiles.names(), vec!["dailies", "archival"]);
+
+    // Select by cookie, fall back to default on missing or unknown name
+    let cookies = |v: &str| HashMap::from([(PROFILE_COOKIE_NAME.to_string(), v.to_string())]);
+    assert_eq!(profiles.select(&HashMap::new()).unwrap().name, "dailies");
+    assert_eq!(profiles.select(&cookies("archival")).unwrap().name, "archival");
+    assert_eq!(profiles.select(&cookies("nonexistent")).unwrap_err().0.name, "dailies");
+    assert_eq!(profiles.by_name(Some("archival")).name, "archival");
+    assert_eq!(profiles.by_name(Some("removed")).name, "dailies");
+    assert_eq!(profiles.by_name(None).name, "dailies");
+
+    // ProRes proxies are kept as-is in archival profile, but not in dailies
+    let prores = test_metadata("ProRes", "a.mov", 100_000_000, 1080);
+    assert!(profiles.select(&cookies("archival")).unwrap().needs_transcoding(&prores, 2_500_000).is_none());
+    assert_eq!(profiles.default_profile().needs_transcoding(&prores, 2_500_000).unwrap().1, 50_000_000);
+
+    // Template expansion
+    let args = profiles.default_profile().expand_video_args(123);
+    assert!(args.contains(&"123".to_string()));
+    assert!(args.iter().any(|a| a.contains("min(720,ih)")));
+    assert!(profiles.default_profile().needs_transcoding(&test_metadata("AVC", "a.mp4", 500_000, 1080), 2_500_000).unwrap().0.contains("resolution"));
+
+    // Bad configs
+    assert!(TranscodeProfiles::from_json(r#"{"default": "x", "profiles": [{"name": "y"}]}"#).is_err());
+    assert!(TranscodeProfiles::from_json(r#"{"default": "y", "profiles": [{"name": "y"}, {"name": "y"}]}"#).is_err());
+    assert!(TranscodeProfiles::from_json(r#"{"default": "y", "profiles": [{"name": "y", "video_args": ["{nope}"]}]}"#).is_err());
+    assert!(TranscodeProfiles::from_json(r#"{"default": "y", "profiles": [{"name": "y", "typo_field": 1}]}"#).is_err());
+}
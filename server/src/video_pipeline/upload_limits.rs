@@ -0,0 +1,262 @@
+// Storage quotas and file type restrictions for new media files.
+// Checked by the HTTP upload handlers before writing anything to disk (as far as
+// the request tells), and again by the pipeline before ingesting, which also covers the
+// incoming folder. Usage is computed from `media_files` rows and the size of media file dirs,
+// plus uploads that have been accepted but not ingested yet (see `PendingUploads`).
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::Context;
+
+use crate::database::{DB, models};
+
+
+/// Configurable upload limits. Default = no limits.
+#[derive(Debug, Clone, Default)]
+pub struct UploadLimits {
+    pub user_max_bytes: Option<u64>,
+    pub user_max_files: Option<u64>,
+    pub total_max_bytes: Option<u64>,
+    pub total_max_files: Option<u64>,
+    /// Allowed MIME types (lowercase, `type/*` wildcards allowed). Empty = any.
+    pub allowed_mime_types: Vec<String>,
+    /// Allowed file extensions (lowercase, without dot). Empty = any.
+    pub allowed_extensions: Vec<String>,
+    /// Uploads in progress or waiting for ingestion. Shared between clones.
+    pub pending: PendingUploads,
+}
+
+/// Uploads that count against quotas before they're ingested: being received over HTTP (multipart or
+/// resumable), or complete and waiting for the pipeline. Keyed by upload path, value is (owner, bytes).
+/// Without this, parallel uploads would each pass the quota check and together exceed it.
+#[derive(Debug, Clone, Default)]
+pub struct PendingUploads(Arc<parking_lot::Mutex<BTreeMap<PathBuf, (String, u64)>>>);
+
+impl PendingUploads {
+
+    /// Reserve (or update the reservation of) `bytes` for an upload at `path`
+    pub fn reserve(&self, path: &Path, user_id: &str, bytes: u64) {
+        self.0.lock().insert(path.to_path_buf(), (user_id.to_string(), bytes));
+    }
+
+    /// Drop reservation, when upload was ingested, rejected or abandoned
+    pub fn release(&self, path: &Path) {
+        self.0.lock().remove(path);
+    }
+
+    /// Move reservation to a new path, e.g. when a completed upload is moved for processing
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let mut map = self.0.lock();
+        if let Some(v) = map.remove(from) {
+            map.insert(to.to_path_buf(), v);
+        }
+    }
+
+    /// Reserved storage of a user (or everyone, if `user_id` is None)
+    pub fn usage(&self, user_id: Option<&str>) -> Usage {
+        self.0.lock().values().filter(|(u, _)| user_id.is_none_or(|id| id == u))
+            .fold(Usage::default(), |acc, (_, b)| Usage { bytes: acc.bytes + b, files: acc.files + 1 })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LimitError {
+    #[error("File type not allowed: {0}")]
+    TypeNotAllowed(String),
+    #[error("Storage quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("Failed to check storage usage: {0}")]
+    Other(#[from] anyhow::Error),
+}
+
+/// Storage used by media files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+fn fmt_bytes(b: u64) -> String {
+    format!("{:.2} GB", b as f64 / (1024.0*1024.0*1024.0))
+}
+
+/// Total size of files under a dir (or size of a file). Symlinks are not followed.
+pub fn disk_usage(path: &Path) -> u64 {
+    let md = match std::fs::symlink_metadata(path) {
+        Ok(md) => md,
+        Err(_) => return 0,
+    };
+    if md.is_dir() {
+        std::fs::read_dir(path).map(|entries| entries.flatten().map(|e| disk_usage(&e.path())).sum()).unwrap_or(0)
+    } else if md.is_file() {
+        md.len()
+    } else {
+        0
+    }
+}
+
+/// Compute storage usage of a user (or everyone, if `user_id` is None)
+pub fn usage(db: &DB, media_files_dir: &Path, user_id: Option<&str>) -> anyhow::Result<Usage>
+{
+    let ids = models::MediaFile::get_ids(&mut db.conn()?, user_id).context("Listing media files")?;
+    let bytes = match user_id {
+        Some(_) => ids.iter().map(|id| disk_usage(&media_files_dir.join(id))).sum(),
+        None => disk_usage(media_files_dir),
+    };
+    Ok(Usage { bytes, files: ids.len() as u64 })
+}
+
+impl UploadLimits {
+
+    /// Storage usage of a user (or everyone), including pending uploads
+    fn usage_with_pending(&self, db: &DB, media_files_dir: &Path, user_id: Option<&str>) -> anyhow::Result<Usage> {
+        let (stored, pending) = (usage(db, media_files_dir, user_id)?, self.pending.usage(user_id));
+        Ok(Usage { bytes: stored.bytes + pending.bytes, files: stored.files + pending.files })
+    }
+
+    pub fn has_quotas(&self) -> bool {
+        self.user_max_bytes.is_some() || self.user_max_files.is_some() || self.total_max_bytes.is_some() || self.total_max_files.is_some()
+    }
+
+    /// Check filename extension and (if known) MIME type against the allow lists.
+    ///
+    /// # Arguments
+    /// * `filename` - Name of the file (path is ignored)
+    /// * `mime` - MIME type as reported by the uploader, if any
+    pub fn check_type(&self, filename: &str, mime: Option<&str>) -> Result<(), LimitError>
+    {
+        if !self.allowed_extensions.is_empty() {
+            let ext = Path::new(filename).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            if !self.allowed_extensions.contains(&ext) {
+                return Err(LimitError::TypeNotAllowed(format!("extension '{}' (allowed: {})", ext, self.allowed_extensions.join(", "))));
+            }
+        }
+        if let (false, Some(mime)) = (self.allowed_mime_types.is_empty(), mime) {
+            let mime = mime.split(';').next().unwrap_or("").trim().to_lowercase();
+            let major = mime.split('/').next().unwrap_or("");
+            let ok = self.allowed_mime_types.iter().any(|a| a == &mime || a.strip_suffix("/*") == Some(major));
+            if !ok {
+                return Err(LimitError::TypeNotAllowed(format!("MIME type '{}' (allowed: {})", mime, self.allowed_mime_types.join(", "))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if adding a new file of `new_bytes` would exceed user or global quotas.
+    /// Pending uploads count as used, so release the new file's own reservation (if any) first.
+    ///
+    /// # Arguments
+    /// * `db` - Database
+    /// * `media_files_dir` - Directory where all media files are stored
+    /// * `user_id` - Owner of the new file
+    /// * `new_bytes` - Size of the new file
+    pub fn check_quota(&self, db: &DB, media_files_dir: &Path, user_id: &str, new_bytes: u64) -> Result<(), LimitError>
+    {
+        let check = |usage: Usage, max_bytes: Option<u64>, max_files: Option<u64>, whose: &str| -> Result<(), LimitError> {
+            if let Some(max) = max_files {
+                if usage.files + 1 > max {
+                    return Err(LimitError::QuotaExceeded(format!("{} media file limit ({}) reached", whose, max)));
+                }
+            }
+            if let Some(max) = max_bytes {
+                if usage.bytes + new_bytes > max {
+                    return Err(LimitError::QuotaExceeded(format!("{} storage limit is {}, {} used, new file is {}",
+                        whose, fmt_bytes(max), fmt_bytes(usage.bytes), fmt_bytes(new_bytes))));
+                }
+            }
+            Ok(())
+        };
+        if self.user_max_bytes.is_some() || self.user_max_files.is_some() {
+            check(self.usage_with_pending(db, media_files_dir, Some(user_id))?, self.user_max_bytes, self.user_max_files, "Your")?;
+        }
+        if self.total_max_bytes.is_some() || self.total_max_files.is_some() {
+            check(self.usage_with_pending(db, media_files_dir, None)?, self.total_max_bytes, self.total_max_files, "Server")?;
+        }
+        Ok(())
+    }
+
+    /// How many more bytes can `user_id` store, or None if unlimited.
+    /// Used to cut off uploads of unknown size.
+    pub fn remaining_bytes(&self, db: &DB, media_files_dir: &Path, user_id: &str) -> Result<Option<u64>, LimitError>
+    {
+        let mut res: Option<u64> = None;
+        if let Some(max) = self.user_max_bytes {
+            res = Some(max.saturating_sub(self.usage_with_pending(db, media_files_dir, Some(user_id))?.bytes));
+        }
+        if let Some(max) = self.total_max_bytes {
+            let left = max.saturating_sub(self.usage_with_pending(db, media_files_dir, None)?.bytes);
+            res = Some(res.map_or(left, |r| r.min(left)));
+        }
+        Ok(res)
+    }
+}
+
+
+// Unit tests =====================================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::make_test_db;
+
+    #[test]
+    fn test_check_type() {
+        let lim = UploadLimits {
+            allowed_mime_types: vec!["video/*".into(), "image/png".into()],
+            allowed_extensions: vec!["mp4".into(), "mov".into(), "png".into()],
+            ..Default::default()
+        };
+        assert!(lim.check_type("clip.MOV", Some("video/quicktime")).is_ok());
+        assert!(lim.check_type("pic.png", Some("image/png; charset=binary")).is_ok());
+        assert!(lim.check_type("clip.mp4", None).is_ok());
+        assert!(matches!(lim.check_type("evil.exe", Some("video/mp4")), Err(LimitError::TypeNotAllowed(_))));
+        assert!(matches!(lim.check_type("noext", None), Err(LimitError::TypeNotAllowed(_))));
+        assert!(matches!(lim.check_type("pic.png", Some("image/jpeg")), Err(LimitError::TypeNotAllowed(_))));
+        assert!(UploadLimits::default().check_type("anything.exe", Some("application/x-foo")).is_ok());
+    }
+
+    #[test]
+    fn test_check_quota() {
+        let (db, data_dir, media_files, _com) = make_test_db();
+        let media_files_dir = data_dir.join("videos");
+        let owner = &media_files[0].user_id;
+        let n_owned = media_files.iter().filter(|v| &v.user_id == owner).count() as u64;
+
+        // Test DB has some comment drawings on disk already
+        let base_user = usage(&db, &media_files_dir, Some(owner)).unwrap().bytes;
+        let base_total = usage(&db, &media_files_dir, None).unwrap().bytes;
+        assert!(media_files[1].user_id != *owner);
+        for (v, sz) in [(&media_files[0], 1000), (&media_files[1], 500)] {
+            std::fs::create_dir_all(media_files_dir.join(&v.id)).unwrap();
+            std::fs::write(media_files_dir.join(&v.id).join("orig.mp4"), vec![0u8; sz]).unwrap();
+        }
+
+        assert_eq!(usage(&db, &media_files_dir, Some(owner)).unwrap(), Usage { bytes: base_user + 1000, files: n_owned });
+        assert_eq!(usage(&db, &media_files_dir, None).unwrap(), Usage { bytes: base_total + 1500, files: media_files.len() as u64 });
+
+        let lim = UploadLimits { user_max_bytes: Some(base_user + 1500), ..Default::default() };
+        assert!(lim.check_quota(&db, &media_files_dir, owner, 500).is_ok());
+        assert!(matches!(lim.check_quota(&db, &media_files_dir, owner, 501), Err(LimitError::QuotaExceeded(_))));
+        assert_eq!(lim.remaining_bytes(&db, &media_files_dir, owner).unwrap(), Some(500));
+
+        let lim = UploadLimits { user_max_files: Some(n_owned), ..Default::default() };
+        assert!(matches!(lim.check_quota(&db, &media_files_dir, owner, 1), Err(LimitError::QuotaExceeded(_))));
+        assert!(lim.check_quota(&db, &media_files_dir, "nobody", 1).is_ok());
+
+        let lim = UploadLimits { user_max_bytes: Some(10_000), total_max_bytes: Some(base_total + 1600), ..Default::default() };
+        assert!(matches!(lim.check_quota(&db, &media_files_dir, "nobody", 200), Err(LimitError::QuotaExceeded(_))));
+        assert_eq!(lim.remaining_bytes(&db, &media_files_dir, owner).unwrap(), Some(100));
+        assert_eq!(UploadLimits::default().remaining_bytes(&db, &media_files_dir, owner).unwrap(), None);
+
+        // Pending uploads count as used until released
+        let lim = UploadLimits { user_max_bytes: Some(base_user + 1500), user_max_files: Some(n_owned + 2), ..Default::default() };
+        lim.clone().pending.reserve(Path::new("/upload/a/clip.mp4"), owner, 300);
+        assert!(matches!(lim.check_quota(&db, &media_files_dir, owner, 201), Err(LimitError::QuotaExceeded(_))));
+        assert_eq!(lim.remaining_bytes(&db, &media_files_dir, owner).unwrap(), Some(200));
+        assert!(lim.check_quota(&db, &media_files_dir, "nobody", 1000).is_ok());
+        lim.pending.rename(Path::new("/upload/a/clip.mp4"), Path::new("/upload/b/clip.mp4"));
+        lim.pending.release(Path::new("/upload/b/clip.mp4"));
+        assert!(lim.check_quota(&db, &media_files_dir, owner, 500).is_ok());
+    }
+}